@@ -2,10 +2,28 @@ BEGIN;
 
 CREATE TYPE role_enum AS ENUM ('admin', 'company_it_head', 'company_it', 'default');
 
+CREATE TYPE license_type_enum AS ENUM ('named', 'floating');
+
+CREATE TYPE currency_enum AS ENUM ('USD', 'EUR', 'NOK');
+
+/* A company is priced, billed and invoiced in its billing currency */
 CREATE TABLE company (
     company_id SERIAL PRIMARY KEY,
     company_name TEXT NOT NULL,
-    company_address TEXT NOT NULL
+    company_address TEXT NOT NULL,
+    billing_currency currency_enum NOT NULL DEFAULT 'USD'
+);
+
+/* Where a company is billed, the country decides the tax on its orders.
+The country is an ISO 3166-1 alpha-2 code, the VAT number includes its country prefix */
+CREATE TABLE billing_address (
+    company_id INT PRIMARY KEY,
+    street TEXT NOT NULL,
+    postal_code TEXT NOT NULL,
+    city TEXT NOT NULL,
+    country CHAR(2) NOT NULL CHECK (country ~ '^[A-Z]{2}$'),
+    vat_number TEXT,
+    FOREIGN KEY (company_id) REFERENCES company(company_id) ON DELETE CASCADE
 );
 
 CREATE TABLE register_company_user (
@@ -51,13 +69,86 @@ CREATE TABLE cookies (
     FOREIGN KEY (user_id) REFERENCES app_user(user_id) ON DELETE CASCADE
 );
 
+/* Prices and amounts are in minor units (cents) of the currency next to them */
+/* A product is drafted, optionally scheduled to be published at publish_at, published and archived.
+Only published products are available for purchase */
+CREATE TYPE publish_status_enum AS ENUM ('draft', 'scheduled', 'published', 'archived');
+
 CREATE TABLE product (
     product_id TEXT PRIMARY KEY,
     display_name TEXT NOT NULL,
-    price_per_user REAL NOT NULL,
+    price_per_user BIGINT NOT NULL CHECK (price_per_user >= 0),
+    currency currency_enum NOT NULL DEFAULT 'USD',
     short_description VARCHAR(256) NOT NULL,
     main_image TEXT NOT NULL,
-    available BOOLEAN NOT NULL
+    publish_status publish_status_enum NOT NULL DEFAULT 'draft',
+    publish_at TIMESTAMPTZ,
+    available BOOLEAN NOT NULL GENERATED ALWAYS AS (publish_status = 'published') STORED,
+    /* Searched text of the name and short description, matches in the name rank higher */
+    search_document tsvector NOT NULL GENERATED ALWAYS AS (
+        setweight(to_tsvector('english', display_name), 'A')
+        || setweight(to_tsvector('english', short_description), 'B')
+    ) STORED,
+    CHECK ((publish_status = 'scheduled') = (publish_at IS NOT NULL))
+);
+
+CREATE INDEX product_search_document_idx ON product USING GIN (search_document);
+
+/* Price lists of a product in currencies other than its own.
+A product is sold in its own currency and in the currencies it has a price list in,
+prices are never converted between currencies */
+CREATE TABLE product_price (
+    product_id TEXT NOT NULL,
+    currency currency_enum NOT NULL,
+    price_per_user BIGINT NOT NULL CHECK (price_per_user >= 0),
+    PRIMARY KEY (product_id, currency),
+    FOREIGN KEY (product_id) REFERENCES product(product_id) ON DELETE CASCADE
+);
+
+/* Volume pricing, a purchase of at least min_seats seats costs price_per_user for every seat,
+in the currency of the tier */
+CREATE TABLE product_price_tier (
+    product_id TEXT NOT NULL,
+    currency currency_enum NOT NULL DEFAULT 'USD',
+    min_seats INT NOT NULL CHECK (min_seats > 0),
+    price_per_user BIGINT NOT NULL CHECK (price_per_user >= 0),
+    PRIMARY KEY (product_id, currency, min_seats),
+    FOREIGN KEY (product_id) REFERENCES product(product_id) ON DELETE CASCADE
+);
+
+CREATE TYPE discount_kind_enum AS ENUM ('percentage', 'fixed');
+
+/* Discount codes for campaigns. A percentage discount takes percent_off basis points off
+the price, a fixed discount takes amount_off off the price in its currency.
+Without a product the code applies to all products, without companies to all companies */
+CREATE TABLE discount_code (
+    discount_code_id SERIAL PRIMARY KEY,
+    code TEXT NOT NULL UNIQUE,
+    kind discount_kind_enum NOT NULL,
+    percent_off INT CHECK (percent_off > 0 AND percent_off <= 10000),
+    amount_off BIGINT CHECK (amount_off > 0),
+    currency currency_enum,
+    product_id TEXT,
+    max_uses INT CHECK (max_uses > 0),
+    times_used INT NOT NULL DEFAULT 0 CHECK (times_used >= 0),
+    valid_from timestamptz,
+    valid_until timestamptz,
+    active BOOLEAN NOT NULL DEFAULT true,
+    CHECK (
+        (kind = 'percentage' AND percent_off IS NOT NULL AND amount_off IS NULL AND currency IS NULL)
+        OR (kind = 'fixed' AND amount_off IS NOT NULL AND currency IS NOT NULL AND percent_off IS NULL)
+    ),
+    CHECK (valid_from IS NULL OR valid_until IS NULL OR valid_from < valid_until),
+    FOREIGN KEY (product_id) REFERENCES product(product_id) ON DELETE CASCADE
+);
+
+/* Companies a discount code is restricted to */
+CREATE TABLE discount_code_company (
+    discount_code_id INT NOT NULL,
+    company_id INT NOT NULL,
+    PRIMARY KEY (discount_code_id, company_id),
+    FOREIGN KEY (discount_code_id) REFERENCES discount_code(discount_code_id) ON DELETE CASCADE,
+    FOREIGN KEY (company_id) REFERENCES company(company_id) ON DELETE CASCADE
 );
 
 CREATE TABLE license (
@@ -65,9 +156,11 @@ CREATE TABLE license (
     valid BOOLEAN NOT NULL,
     start_date timestamptz NOT NULL,
     end_date timestamptz NOT NULL,
-    amount INT NOT NULL,
+    amount INT NOT NULL CHECK (amount >= 0),
     company_id INT NOT NULL,
     product_id TEXT NOT NULL,
+    license_type license_type_enum NOT NULL DEFAULT 'named',
+    trial BOOLEAN NOT NULL DEFAULT FALSE,
     FOREIGN KEY (company_id) REFERENCES company(company_id),
     FOREIGN KEY (product_id) REFERENCES product(product_id)
 );
@@ -146,6 +239,336 @@ CREATE TRIGGER enforce_valid_user_license
     BEFORE INSERT OR UPDATE ON user_license
     FOR EACH ROW EXECUTE PROCEDURE enforce_valid_user_license();
 
+/* Licenses whose seats are assigned to company users automatically when they register */
+CREATE TABLE license_auto_assign (
+    license_id INT PRIMARY KEY,
+    FOREIGN KEY (license_id) REFERENCES license(license_id) ON DELETE CASCADE
+);
+
+/* Trial offered for a product, a product without a row here has no trial */
+CREATE TABLE product_trial (
+    product_id TEXT PRIMARY KEY,
+    duration_days INT NOT NULL CHECK (duration_days > 0),
+    seats INT NOT NULL CHECK (seats > 0),
+    FOREIGN KEY (product_id) REFERENCES product(product_id) ON DELETE CASCADE
+);
+
+/* Trials started by companies, a company can only start one trial per product */
+CREATE TABLE company_trial (
+    company_id INT NOT NULL,
+    product_id TEXT NOT NULL,
+    license_id INT NOT NULL,
+    started_at timestamptz NOT NULL DEFAULT now(),
+    converted_at timestamptz,
+    reminder_sent BOOLEAN NOT NULL DEFAULT FALSE,
+    PRIMARY KEY (company_id, product_id),
+    UNIQUE (license_id),
+    FOREIGN KEY (company_id) REFERENCES company(company_id),
+    FOREIGN KEY (product_id) REFERENCES product(product_id),
+    FOREIGN KEY (license_id) REFERENCES license(license_id) ON DELETE CASCADE
+);
+
+/* Seats currently checked out on floating (concurrent-use) licenses */
+CREATE TABLE license_checkout (
+    license_id INT NOT NULL,
+    user_id INT NOT NULL,
+    checked_out_at timestamptz NOT NULL DEFAULT now(),
+    last_heartbeat timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (license_id, user_id),
+    FOREIGN KEY (license_id) REFERENCES license(license_id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES app_user(user_id) ON DELETE CASCADE
+);
+
+CREATE OR REPLACE FUNCTION enforce_license_max_checkouts()
+RETURNS TRIGGER AS $$
+DECLARE
+    max_users INTEGER := 0;
+    current_checkout_count INTEGER := 0;
+    license_company_id INTEGER;
+    user_company_id INTEGER;
+BEGIN
+    LOCK TABLE license_checkout IN EXCLUSIVE MODE;
+
+    SELECT amount, company_id INTO max_users, license_company_id
+    FROM license
+    WHERE license_id = NEW.license_id;
+
+    SELECT company_id INTO user_company_id
+    FROM app_user
+    WHERE user_id = NEW.user_id;
+
+    IF (license_company_id != user_company_id) THEN
+        RAISE EXCEPTION 'Cannot check out a license that is not owned by the user''s company.';
+    END IF;
+
+    SELECT COUNT(*) INTO current_checkout_count
+    FROM license_checkout
+    WHERE license_id = NEW.license_id;
+
+    IF (current_checkout_count >= max_users) THEN
+        RAISE EXCEPTION 'Cannot check out more than % seat(s) for the license.', max_users;
+    END IF;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER enforce_license_max_checkouts
+    BEFORE INSERT ON license_checkout
+    FOR EACH ROW EXECUTE PROCEDURE enforce_license_max_checkouts();
+
+CREATE TABLE license_reminder (
+    license_id INT NOT NULL,
+    end_date timestamptz NOT NULL,
+    days_before INT NOT NULL CHECK (days_before > 0),
+    sent_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (license_id, end_date, days_before),
+    FOREIGN KEY (license_id) REFERENCES license(license_id) ON DELETE CASCADE
+);
+
+/* Daily seat usage of licenses, the peak observed that day */
+CREATE TABLE license_usage_snapshot (
+    license_id INT NOT NULL,
+    snapshot_date DATE NOT NULL,
+    seats_used INT NOT NULL,
+    amount INT NOT NULL,
+    PRIMARY KEY (license_id, snapshot_date),
+    FOREIGN KEY (license_id) REFERENCES license(license_id) ON DELETE CASCADE
+);
+
+CREATE TYPE order_type_enum AS ENUM ('purchase', 'renewal', 'seat_change', 'subscription_renewal', 'early_renewal', 'trial_conversion');
+
+CREATE TYPE order_status_enum AS ENUM ('pending', 'paid', 'failed');
+
+CREATE TYPE tax_treatment_enum AS ENUM ('domestic', 'eu_consumer', 'reverse_charge', 'export');
+
+CREATE TYPE payment_status_enum AS ENUM ('pending', 'succeeded', 'failed');
+
+/* Last invoice number handed out per year, invoice numbers are sequential within a year */
+CREATE TABLE invoice_counter (
+    year INT PRIMARY KEY,
+    last_number INT NOT NULL
+);
+
+/* What a company paid, one order per license purchase, renewal or seat change.
+An order is applied to its licenses once it is paid */
+CREATE TABLE "order" (
+    order_id SERIAL PRIMARY KEY,
+    invoice_number TEXT NOT NULL UNIQUE,
+    order_type order_type_enum NOT NULL,
+    status order_status_enum NOT NULL DEFAULT 'pending',
+    company_id INT NOT NULL,
+    user_id INT,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    currency currency_enum NOT NULL,
+    /* Discount code used, the code is kept if the discount code is deleted */
+    discount_code_id INT,
+    discount_code TEXT,
+    /* Taken off the sum of the lines */
+    discount BIGINT NOT NULL DEFAULT 0 CHECK (discount >= 0),
+    /* Sum of the lines less the discount, before tax */
+    subtotal BIGINT NOT NULL,
+    /* Tax rate in basis points, 2500 is 25% */
+    tax_rate INT NOT NULL CHECK (tax_rate >= 0),
+    tax_treatment tax_treatment_enum NOT NULL,
+    tax BIGINT NOT NULL,
+    /* Subtotal and tax */
+    total BIGINT NOT NULL,
+    /* Country and VAT number the company was billed with */
+    billing_country CHAR(2),
+    vat_number TEXT,
+    FOREIGN KEY (company_id) REFERENCES company(company_id),
+    FOREIGN KEY (user_id) REFERENCES app_user(user_id) ON DELETE SET NULL,
+    FOREIGN KEY (discount_code_id) REFERENCES discount_code(discount_code_id) ON DELETE SET NULL
+);
+
+/* Lines are in the currency of their order */
+CREATE TABLE order_line (
+    order_line_id SERIAL PRIMARY KEY,
+    order_id INT NOT NULL,
+    license_id INT NOT NULL,
+    product_id TEXT NOT NULL,
+    description TEXT NOT NULL,
+    seats INT NOT NULL,
+    unit_price BIGINT NOT NULL,
+    period_start timestamptz NOT NULL,
+    period_end timestamptz NOT NULL,
+    amount BIGINT NOT NULL,
+    /* Seats of the license once a seat change is applied, none for other lines */
+    license_seats INT,
+    FOREIGN KEY (order_id) REFERENCES "order"(order_id) ON DELETE CASCADE,
+    FOREIGN KEY (license_id) REFERENCES license(license_id),
+    FOREIGN KEY (product_id) REFERENCES product(product_id)
+);
+
+/* Payment of an order at a payment provider, in the currency of the order */
+CREATE TABLE payment (
+    payment_id SERIAL PRIMARY KEY,
+    order_id INT NOT NULL,
+    provider TEXT NOT NULL,
+    intent_id TEXT NOT NULL,
+    status payment_status_enum NOT NULL DEFAULT 'pending',
+    amount BIGINT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    updated_at timestamptz NOT NULL DEFAULT now(),
+    UNIQUE (provider, intent_id),
+    FOREIGN KEY (order_id) REFERENCES "order"(order_id) ON DELETE CASCADE
+);
+
+/* Last credit note number handed out per year, numbered like invoices */
+CREATE TABLE credit_note_counter (
+    year INT PRIMARY KEY,
+    last_number INT NOT NULL
+);
+
+CREATE TYPE license_action_enum AS ENUM ('keep', 'shorten', 'revoke');
+
+/* Money paid back for an order through its payment provider, documented by a credit note.
+An order can be refunded several times, up to its total */
+CREATE TABLE refund (
+    refund_id SERIAL PRIMARY KEY,
+    order_id INT NOT NULL,
+    credit_note_number TEXT NOT NULL UNIQUE,
+    provider TEXT NOT NULL,
+    provider_refund_id TEXT NOT NULL,
+    /* Paid back, tax included, in the currency of the order */
+    amount BIGINT NOT NULL CHECK (amount > 0),
+    /* Part of the amount that is tax */
+    tax BIGINT NOT NULL,
+    reason TEXT,
+    /* What was done to the licenses of the order */
+    license_action license_action_enum NOT NULL,
+    /* End date the licenses were shortened to */
+    license_end_date timestamptz,
+    created_by INT,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    /* Order crediting the company that the refund pays back, none for refunds made by an admin */
+    credit_order_id INT,
+    FOREIGN KEY (order_id) REFERENCES "order"(order_id),
+    FOREIGN KEY (created_by) REFERENCES app_user(user_id) ON DELETE SET NULL,
+    FOREIGN KEY (credit_order_id) REFERENCES "order"(order_id)
+);
+
+/* Licenses a user is about to buy, bought together in one order at checkout */
+CREATE TABLE cart_item (
+    cart_item_id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL,
+    product_id TEXT NOT NULL,
+    seats INT NOT NULL CHECK (seats > 0),
+    term_years INT NOT NULL CHECK (term_years > 0),
+    license_type license_type_enum NOT NULL DEFAULT 'named',
+    added_at timestamptz NOT NULL DEFAULT now(),
+    UNIQUE (user_id, product_id),
+    FOREIGN KEY (user_id) REFERENCES app_user(user_id) ON DELETE CASCADE,
+    FOREIGN KEY (product_id) REFERENCES product(product_id) ON DELETE CASCADE
+);
+
+CREATE TYPE purchase_request_status_enum AS ENUM ('pending', 'approved', 'rejected');
+
+/* A company user asking the IT heads of the company to buy a license.
+An approved request is bought with the order it links to */
+CREATE TABLE purchase_request (
+    purchase_request_id SERIAL PRIMARY KEY,
+    company_id INT NOT NULL,
+    requested_by INT,
+    product_id TEXT NOT NULL,
+    seats INT NOT NULL CHECK (seats > 0),
+    term_years INT NOT NULL CHECK (term_years > 0),
+    license_type license_type_enum NOT NULL DEFAULT 'named',
+    comment TEXT,
+    status purchase_request_status_enum NOT NULL DEFAULT 'pending',
+    created_at timestamptz NOT NULL DEFAULT now(),
+    decided_by INT,
+    decided_at timestamptz,
+    decision_note TEXT,
+    order_id INT,
+    FOREIGN KEY (company_id) REFERENCES company(company_id) ON DELETE CASCADE,
+    FOREIGN KEY (requested_by) REFERENCES app_user(user_id) ON DELETE SET NULL,
+    FOREIGN KEY (product_id) REFERENCES product(product_id) ON DELETE CASCADE,
+    FOREIGN KEY (decided_by) REFERENCES app_user(user_id) ON DELETE SET NULL,
+    FOREIGN KEY (order_id) REFERENCES "order"(order_id)
+);
+
+CREATE TYPE billing_interval_enum AS ENUM ('monthly', 'yearly');
+
+CREATE TYPE subscription_status_enum AS ENUM ('active', 'past_due', 'canceled');
+
+/* A license renewed automatically at the end of each billing period.
+The license ends at the end of the period paid for, or of the grace period
+while a failed renewal is waiting to be paid */
+CREATE TABLE subscription (
+    subscription_id SERIAL PRIMARY KEY,
+    license_id INT NOT NULL UNIQUE,
+    company_id INT NOT NULL,
+    billing_interval billing_interval_enum NOT NULL,
+    status subscription_status_enum NOT NULL DEFAULT 'active',
+    current_period_end timestamptz NOT NULL,
+    cancel_at_period_end BOOLEAN NOT NULL DEFAULT FALSE,
+    /* Order renewing the subscription for the next period, until it is paid */
+    renewal_order_id INT,
+    grace_until timestamptz,
+    created_by INT,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    canceled_at timestamptz,
+    /* Payment method saved at the payment provider, renewals are charged to it */
+    payment_method_id TEXT,
+    FOREIGN KEY (license_id) REFERENCES license(license_id) ON DELETE CASCADE,
+    FOREIGN KEY (company_id) REFERENCES company(company_id) ON DELETE CASCADE,
+    FOREIGN KEY (renewal_order_id) REFERENCES "order"(order_id) ON DELETE SET NULL,
+    FOREIGN KEY (created_by) REFERENCES app_user(user_id) ON DELETE SET NULL
+);
+
+/* Last quote number handed out per year, numbered like invoices */
+CREATE TABLE quote_counter (
+    year INT PRIMARY KEY,
+    last_number INT NOT NULL
+);
+
+CREATE TYPE quote_status_enum AS ENUM ('draft', 'sent', 'accepted', 'withdrawn');
+
+/* A formal offer to a company, at prices set by an admin.
+An IT head of the company accepts it before it is no longer valid,
+which buys its lines with the order it links to */
+CREATE TABLE quote (
+    quote_id SERIAL PRIMARY KEY,
+    quote_number TEXT NOT NULL UNIQUE,
+    company_id INT NOT NULL,
+    currency currency_enum NOT NULL,
+    status quote_status_enum NOT NULL DEFAULT 'draft',
+    valid_until timestamptz NOT NULL,
+    /* Terms of the offer, for the company */
+    note TEXT,
+    created_by INT,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    sent_at timestamptz,
+    accepted_by INT,
+    accepted_at timestamptz,
+    order_id INT,
+    FOREIGN KEY (company_id) REFERENCES company(company_id) ON DELETE CASCADE,
+    FOREIGN KEY (created_by) REFERENCES app_user(user_id) ON DELETE SET NULL,
+    FOREIGN KEY (accepted_by) REFERENCES app_user(user_id) ON DELETE SET NULL,
+    FOREIGN KEY (order_id) REFERENCES "order"(order_id)
+);
+
+/* A license offered in a quote */
+CREATE TABLE quote_line (
+    quote_id INT NOT NULL,
+    line_number INT NOT NULL,
+    product_id TEXT NOT NULL,
+    seats INT NOT NULL CHECK (seats > 0),
+    term_years INT NOT NULL CHECK (term_years > 0),
+    license_type license_type_enum NOT NULL DEFAULT 'named',
+    /* Price per user per year offered, in the currency of the quote */
+    unit_price BIGINT NOT NULL CHECK (unit_price >= 0),
+    /* Price per user per year of the product when the quote was made */
+    list_price BIGINT NOT NULL,
+    /* Price of all seats for the whole term */
+    amount BIGINT NOT NULL,
+    PRIMARY KEY (quote_id, line_number),
+    FOREIGN KEY (quote_id) REFERENCES quote(quote_id) ON DELETE CASCADE,
+    FOREIGN KEY (product_id) REFERENCES product(product_id) ON DELETE CASCADE
+);
+
 CREATE TABLE category (
     category_id SERIAL PRIMARY KEY,
     name TEXT NOT NULL,
@@ -160,6 +583,38 @@ CREATE TABLE product_category (
     FOREIGN KEY (category_id) REFERENCES category(category_id) ON DELETE CASCADE
 );
 
+/* Released versions of a product, with where to download them.
+A license covers the releases released before its end date */
+CREATE TABLE product_release (
+    release_id SERIAL PRIMARY KEY,
+    product_id TEXT NOT NULL,
+    version TEXT NOT NULL,
+    release_date timestamptz NOT NULL DEFAULT now(),
+    changelog TEXT NOT NULL,
+    /* The download of the release, all NULL if it has none */
+    file_name TEXT,
+    file_size BIGINT CHECK (file_size >= 0),
+    sha256 TEXT CHECK (sha256 ~ '^[0-9a-f]{64}$'),
+    /* Key of the uploaded file in file storage, NULL if no file is uploaded */
+    storage_key TEXT,
+    FOREIGN KEY (product_id) REFERENCES product(product_id) ON DELETE CASCADE,
+    UNIQUE (product_id, version),
+    CHECK ((file_name IS NULL) = (file_size IS NULL) AND (file_name IS NULL) = (sha256 IS NULL)),
+    CHECK (storage_key IS NULL OR file_name IS NOT NULL)
+);
+
+/* A download of the file of a release, through a license the user holds a seat on */
+CREATE TABLE download_event (
+    event_id SERIAL PRIMARY KEY,
+    release_id INT NOT NULL,
+    license_id INT NOT NULL,
+    user_id INT,
+    downloaded_at timestamptz NOT NULL DEFAULT now(),
+    FOREIGN KEY (release_id) REFERENCES product_release(release_id) ON DELETE CASCADE,
+    FOREIGN KEY (license_id) REFERENCES license(license_id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES app_user(user_id) ON DELETE SET NULL
+);
+
 CREATE TABLE testimonial (
     testimonial_id SERIAL PRIMARY KEY,
     author TEXT NOT NULL,
@@ -178,9 +633,16 @@ CREATE TABLE product_image (
 CREATE TABLE product_text (
     text_id SERIAL PRIMARY KEY,
     text_title TEXT NOT NULL,
-    paragraph TEXT NOT NULL
+    paragraph TEXT NOT NULL,
+    /* Searched text of the title and paragraph, ranking below the name of the product */
+    search_document tsvector NOT NULL GENERATED ALWAYS AS (
+        setweight(to_tsvector('english', text_title), 'B')
+        || setweight(to_tsvector('english', paragraph), 'C')
+    ) STORED
 );
 
+CREATE INDEX product_text_search_document_idx ON product_text USING GIN (search_document);
+
 CREATE TABLE description_component (
     component_id SERIAL PRIMARY KEY,
     priority INT NOT NULL,
@@ -1,9 +1,14 @@
 BEGIN;
 
-INSERT INTO company (company_name, company_address)
-VALUES ('Proflex', 'Sandbergvegen 98 6009 Ålesund'),
-        ('Enterprise Solutions Inc.', 'Cupertino, California'),
-        ('Business Solutions LLC', 'Redmond, Washington');
+INSERT INTO company (company_name, company_address, billing_currency)
+VALUES ('Proflex', 'Sandbergvegen 98 6009 Ålesund', 'NOK'),
+        ('Enterprise Solutions Inc.', 'Cupertino, California', 'USD'),
+        ('Business Solutions LLC', 'Redmond, Washington', 'EUR');
+
+INSERT INTO billing_address (company_id, street, postal_code, city, country, vat_number)
+VALUES (1, 'Sandbergvegen 98', '6009', 'Ålesund', 'NO', 'NO999999999MVA'),
+        (2, '1 Infinite Loop', '95014', 'Cupertino, California', 'US', NULL),
+        (3, '1 Microsoft Way', '98052', 'Redmond, Washington', 'US', NULL);
 
 INSERT INTO app_user (email, pass_hash, company_id, role)
 VALUES ('admin_proflex@gmail.com', '$argon2id$v=19$m=4096,t=3,p=1$zGDWbzz553WuAOr9wAscPw$scsw+3Q2pW19Jqz8uXQpzikahS3a8CNubaC1EQH3Fa4', 1, 'admin'),
@@ -12,11 +17,35 @@ VALUES ('admin_proflex@gmail.com', '$argon2id$v=19$m=4096,t=3,p=1$zGDWbzz553WuAO
         ('it_manager_2@enterprisesolutions.com', 'pass', 2, 'company_it_head'),
         ('bs_it_head@businesssolutions.com', 'pass', 3, 'company_it_head');
 
-INSERT INTO product ("product_id", "display_name", "price_per_user", "short_description", "main_image", "available") VALUES
-('online_scheduling_software',	'Online Scheduling Software',	200,	'Streamline your scheduling process with Proflex Solutions'' Online Scheduling Software. Automate appointments, manage resources, and track performance effortlessly. Boost productivity and save time today!',	'/resources/images/online_scheduling_software/Oransj_calender_software_on_monitor_ab65cb91-6bb0-4891-896b-1e52813dfd49.png',	'1'),
-('proflex_bpa_solutions',	'Proflex BPA Solutions',	500,	'Automate and optimize your business processes with ProFlex BPA Solutions. Streamline operations, save time and money, and focus on business growth with our secure and efficient software.',	'/resources/images/proflex_bpa_solutions/Oransj_automation_software_on_monitor_f69ffed8-f8db-4aa3-b416-57c66d238db9.png',	'1'),
-('proflex_tax_solutions',	'ProFlex Tax Solutions',	300,	'Streamline your tax preparation and filing process with ProFlex Tax Solutions. Enter data quickly and accurately, automate workflows, and focus on your business while ensuring the security and efficiency of your tax-related data.',	'/resources/images/proflex_tax_solutions/Product-4.jpg',	'1');
+INSERT INTO product ("product_id", "display_name", "price_per_user", "short_description", "main_image", "publish_status") VALUES
+('online_scheduling_software',	'Online Scheduling Software',	20000,	'Streamline your scheduling process with Proflex Solutions'' Online Scheduling Software. Automate appointments, manage resources, and track performance effortlessly. Boost productivity and save time today!',	'/resources/images/online_scheduling_software/Oransj_calender_software_on_monitor_ab65cb91-6bb0-4891-896b-1e52813dfd49.png',	'published'),
+('proflex_bpa_solutions',	'Proflex BPA Solutions',	50000,	'Automate and optimize your business processes with ProFlex BPA Solutions. Streamline operations, save time and money, and focus on business growth with our secure and efficient software.',	'/resources/images/proflex_bpa_solutions/Oransj_automation_software_on_monitor_f69ffed8-f8db-4aa3-b416-57c66d238db9.png',	'published'),
+('proflex_tax_solutions',	'ProFlex Tax Solutions',	30000,	'Streamline your tax preparation and filing process with ProFlex Tax Solutions. Enter data quickly and accurately, automate workflows, and focus on your business while ensuring the security and efficiency of your tax-related data.',	'/resources/images/proflex_tax_solutions/Product-4.jpg',	'published');
+
+
+INSERT INTO product_price_tier (product_id, min_seats, price_per_user)
+VALUES ('online_scheduling_software', 10, 18000),
+       ('online_scheduling_software', 50, 16000),
+       ('proflex_bpa_solutions', 25, 45000),
+       ('proflex_bpa_solutions', 100, 40000),
+       ('proflex_tax_solutions', 20, 27000);
 
+INSERT INTO product_price (product_id, currency, price_per_user)
+VALUES ('online_scheduling_software', 'EUR', 18500),
+       ('online_scheduling_software', 'NOK', 210000),
+       ('proflex_bpa_solutions', 'EUR', 46000),
+       ('proflex_bpa_solutions', 'NOK', 520000),
+       ('proflex_tax_solutions', 'EUR', 28000),
+       ('proflex_tax_solutions', 'NOK', 315000);
+
+INSERT INTO product_price_tier (product_id, currency, min_seats, price_per_user)
+VALUES ('online_scheduling_software', 'EUR', 10, 16500),
+       ('proflex_tax_solutions', 'EUR', 20, 25000),
+       ('proflex_tax_solutions', 'NOK', 20, 285000);
+
+INSERT INTO discount_code (code, kind, percent_off, amount_off, currency, product_id, max_uses)
+VALUES ('WELCOME10', 'percentage', 1000, NULL, NULL, NULL, NULL),
+       ('TAX100', 'fixed', NULL, 10000, 'USD', 'proflex_tax_solutions', 100);
 
 INSERT INTO license (valid, start_date, end_date, amount, company_id, product_id)
 VALUES (false, '2022-01-01', '2022-12-31', 100, 2, 'online_scheduling_software'),
@@ -74,4 +103,11 @@ INSERT INTO product_category ("product_id", "category_id") VALUES
 ('proflex_tax_solutions', 2),
 ('proflex_tax_solutions', 3);
 
+INSERT INTO product_release ("product_id", "version", "release_date", "changelog", "file_name", "file_size", "sha256") VALUES
+('proflex_tax_solutions', '2.0.0', '2022-09-01', 'New tax forms for 2022 and a redesigned filing wizard.', 'proflex_tax_solutions-2.0.0.zip', 104857600, 'a3f1c2d4e5b60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f90'),
+('proflex_tax_solutions', '2.1.0', '2023-03-15', 'Faster imports from accounting systems.', 'proflex_tax_solutions-2.1.0.zip', 106954752, 'b4e2d3c5f6a71829304b5c6d7e8f9a01b2c3d4e5f60718293a4b5c6d7e8f9a01'),
+('proflex_tax_solutions', '3.0.0', '2024-02-01', 'Tax forms for 2024 and e-filing to more countries.', 'proflex_tax_solutions-3.0.0.zip', 115343360, 'c5f3e4d6a7b8293a415c6d7e8f9a0b12c3d4e5f60718293a4b5c6d7e8f9a0b12'),
+('proflex_bpa_solutions', '1.0.0', '2023-01-10', 'First release.', 'proflex_bpa_solutions-1.0.0.zip', 52428800, 'd6a4f5e7b8c93a4b526d7e8f9a0b1c23d4e5f60718293a4b5c6d7e8f9a0b1c23'),
+('online_scheduling_software', '4.2.0', '2023-05-20', 'Calendar sync with more providers.', NULL, NULL, NULL);
+
 COMMIT;
\ No newline at end of file
@@ -3,13 +3,16 @@ BEGIN;
 INSERT INTO company (company_name, company_address)
 VALUES ('Proflex', 'Sandbergvegen 98 6009 Ålesund');
 
+INSERT INTO billing_address (company_id, street, postal_code, city, country, vat_number)
+VALUES (1, 'Sandbergvegen 98', '6009', 'Ålesund', 'NO', 'NO999999999MVA');
+
 INSERT INTO app_user (email, pass_hash, company_id, role)
 VALUES ('admin_proflex@gmail.com', '$argon2id$v=19$m=4096,t=3,p=1$zGDWbzz553WuAOr9wAscPw$scsw+3Q2pW19Jqz8uXQpzikahS3a8CNubaC1EQH3Fa4', 1, 'admin');
 
-INSERT INTO product ("product_id", "display_name", "price_per_user", "short_description", "main_image", "available") VALUES
-('online_scheduling_software',	'Online Scheduling Software',	200,	'Streamline your scheduling process with Proflex Solutions'' Online Scheduling Software. Automate appointments, manage resources, and track performance effortlessly. Boost productivity and save time today!',	'/resources/images/online_scheduling_software/Oransj_calender_software_on_monitor_ab65cb91-6bb0-4891-896b-1e52813dfd49.png',	'1'),
-('proflex_bpa_solutions',	'Proflex BPA Solutions',	500,	'Automate and optimize your business processes with ProFlex BPA Solutions. Streamline operations, save time and money, and focus on business growth with our secure and efficient software.',	'/resources/images/proflex_bpa_solutions/Oransj_automation_software_on_monitor_f69ffed8-f8db-4aa3-b416-57c66d238db9.png',	'1'),
-('proflex_tax_solutions',	'ProFlex Tax Solutions',	300,	'Streamline your tax preparation and filing process with ProFlex Tax Solutions. Enter data quickly and accurately, automate workflows, and focus on your business while ensuring the security and efficiency of your tax-related data.',	'/resources/images/proflex_tax_solutions/Product-4.jpg',	'1');
+INSERT INTO product ("product_id", "display_name", "price_per_user", "short_description", "main_image", "publish_status") VALUES
+('online_scheduling_software',	'Online Scheduling Software',	20000,	'Streamline your scheduling process with Proflex Solutions'' Online Scheduling Software. Automate appointments, manage resources, and track performance effortlessly. Boost productivity and save time today!',	'/resources/images/online_scheduling_software/Oransj_calender_software_on_monitor_ab65cb91-6bb0-4891-896b-1e52813dfd49.png',	'published'),
+('proflex_bpa_solutions',	'Proflex BPA Solutions',	50000,	'Automate and optimize your business processes with ProFlex BPA Solutions. Streamline operations, save time and money, and focus on business growth with our secure and efficient software.',	'/resources/images/proflex_bpa_solutions/Oransj_automation_software_on_monitor_f69ffed8-f8db-4aa3-b416-57c66d238db9.png',	'published'),
+('proflex_tax_solutions',	'ProFlex Tax Solutions',	30000,	'Streamline your tax preparation and filing process with ProFlex Tax Solutions. Enter data quickly and accurately, automate workflows, and focus on your business while ensuring the security and efficiency of your tax-related data.',	'/resources/images/proflex_tax_solutions/Product-4.jpg',	'published');
 
 INSERT INTO product_image ("image_path", "alt_text") VALUES
 ('/resources/images/online_scheduling_software/Product-1.jpg',	'mobile calender interface'),
@@ -6,9 +6,13 @@ import {
   MeUser,
   PartialUser,
   Product,
+  ProductPreview,
   Testimonial,
   User,
   InviteInfo,
+  PriceQuote,
+  Purchase,
+  BillingCurrency,
 } from "./Interfaces";
 
 let baseUrl = import.meta.env.VITE_URL + ":" + import.meta.env.VITE_PORT;
@@ -43,6 +47,32 @@ export class FetchError extends Error {
   }
 }
 
+/**
+ * Get every page of a paginated list.
+ * List endpoints return one page at a time, with a link to the next page
+ * in the `Link` header of the response.
+ *
+ * @param path The path of the list, e.g. `/api/products`.
+ * @param errorMessage The message of the error thrown if a page could not be fetched.
+ * @returns The items on all pages of the list.
+ */
+const fetchAllPages = async <T,>(path: string, errorMessage: string) => {
+  const items: T[] = [];
+  let next: string | undefined = path;
+  while (next) {
+    const response = await fetch(`${baseUrl}${next}`);
+    if (!response.ok) {
+      throw new Error(errorMessage);
+    }
+    const page: T[] = await response.json();
+    items.push(...page);
+    next = response.headers
+      .get("Link")
+      ?.match(/<([^>]*)>; rel="next"/)?.[1];
+  }
+  return items;
+};
+
 /**
  * Get a specific user.
  *
@@ -200,13 +230,10 @@ export const fetchLicense = async (licenseId: string) => {
  * @returns All licenses' information.
  */
 export const fetchLicensesFullInfo = async () => {
-  const response = await fetch(`${baseUrl}/api/priv/licenses_full`);
-  if (response.ok) {
-    const data: FullLicenseInfo[] = await response.json();
-    return data;
-  } else {
-    throw new Error("Could not fetch licenses.");
-  }
+  return fetchAllPages<FullLicenseInfo>(
+    "/api/priv/licenses_full",
+    "Could not fetch licenses."
+  );
 };
 
 /**
@@ -293,20 +320,120 @@ export const postLicense = async (license: License) => {
 };
 
 /**
- * Get all companies.
+ * Purchase a license for the user's company.
+ * The price is calculated by the server.
  *
- * @returns All companies.
+ * @param productId The ID of the product.
+ * @param seats The number of seats.
+ * @param termYears The number of years the license is valid for.
+ * @param discountCode The discount code to apply, if any.
+ * @returns The created license, the price, the order and the payment to pay it with.
+ * @throws FetchError if the request fails.
  */
-export const fetchCompanies = async () => {
-  const response = await fetch(`${baseUrl}/api/priv/companies`);
+export const postPurchase = async (
+  productId: string,
+  seats: number,
+  termYears: number,
+  discountCode?: string
+) => {
+  const response = await fetch(`${baseUrl}/api/priv/licenses/purchase`, {
+    method: "POST",
+    headers: {
+      Accept: "application/json",
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify({
+      product_id: productId,
+      seats: seats,
+      term_years: termYears,
+      discount_code: discountCode || null,
+    }),
+    credentials: "include",
+  });
+  if (response.status === 201) {
+    const data: Purchase = await response.json();
+    return data;
+  } else {
+    throw new FetchError(
+      "Could not purchase license.",
+      response.status,
+      response.statusText
+    );
+  }
+};
+
+/**
+ * Get the price of a license for a product.
+ *
+ * @param productId The ID of the product.
+ * @param seats The number of seats.
+ * @param termYears The number of years the license is valid for.
+ * @param discountCode The discount code to apply, if any.
+ * @param currency The currency to price in, the currency of the product if none.
+ * @returns The price quote.
+ * @throws FetchError if the request fails.
+ */
+export const fetchQuote = async (
+  productId: string,
+  seats: number,
+  termYears: number,
+  discountCode?: string,
+  currency?: string
+) => {
+  const discount = discountCode
+    ? `&discount_code=${encodeURIComponent(discountCode)}`
+    : "";
+  const inCurrency = currency ? `&currency=${currency}` : "";
+  const response = await fetch(
+    `${baseUrl}/api/products/${productId}/quote?seats=${seats}&term_years=${termYears}${discount}${inCurrency}`
+  );
   if (response.ok) {
-    const data: Company[] = await response.json();
+    const data: PriceQuote = await response.json();
     return data;
   } else {
-    throw new Error("Could not fetch companies.");
+    throw new FetchError(
+      "Could not fetch price.",
+      response.status,
+      response.statusText
+    );
+  }
+};
+
+/**
+ * Get the currency a company is billed in.
+ *
+ * @param companyId The ID of the company.
+ * @returns The currency code.
+ * @throws FetchError if the request fails.
+ */
+export const fetchBillingCurrency = async (companyId: number) => {
+  const response = await fetch(
+    `${baseUrl}/api/priv/companies/${companyId}/billing_currency`
+  );
+  if (response.ok) {
+    const data: BillingCurrency = await response.json();
+    return data.billing_currency;
+  } else {
+    throw new FetchError(
+      "Could not fetch billing currency.",
+      response.status,
+      response.statusText
+    );
   }
 };
 
+/**
+ * Get all companies.
+ *
+ * @returns All companies.
+ */
+export const fetchCompanies = async () => {
+  return fetchAllPages<Company>(
+    "/api/priv/companies",
+    "Could not fetch companies."
+  );
+};
+
 /**
  * Get a specific product.
  *
@@ -328,33 +455,47 @@ export const fetchProduct = async (productId: string) => {
 };
 
 /**
- * Get all available products.
+ * Get a product and its descriptions as an admin, whether it is published or not.
  *
- * @returns All available products.
+ * @param productId The ID of the product.
+ * @returns The product, its publish status and its descriptions.
+ * @throws FetchError if the request fails.
  */
-export const fetchAvailableProducts = async () => {
-  const response = await fetch(`${baseUrl}/api/products`);
+export const fetchProductPreview = async (productId: string) => {
+  const response = await fetch(
+    `${baseUrl}/api/priv/products/${productId}/preview`
+  );
   if (response.ok) {
-    const data: Product[] = await response.json();
+    const data: ProductPreview = await response.json();
     return data;
   } else {
-    throw new Error("Could not fetch products.");
+    throw new FetchError(
+      "Could not fetch product.",
+      response.status,
+      response.statusText
+    );
   }
 };
 
+/**
+ * Get all available products.
+ *
+ * @returns All available products.
+ */
+export const fetchAvailableProducts = async () => {
+  return fetchAllPages<Product>("/api/products", "Could not fetch products.");
+};
+
 /**
  * Get all products.
  *
  * @returns All products.
  */
 export const fetchAllProducts = async () => {
-  const response = await fetch(`${baseUrl}/api/priv/products`);
-  if (response.ok) {
-    const data: Product[] = await response.json();
-    return data;
-  } else {
-    throw new Error("Could not fetch products.");
-  }
+  return fetchAllPages<Product>(
+    "/api/priv/products",
+    "Could not fetch products."
+  );
 };
 
 /**
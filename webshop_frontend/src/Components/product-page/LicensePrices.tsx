@@ -1,5 +1,4 @@
 export type LicensePricesProps = {
-  price: number;
   refs: {
     price: React.RefObject<HTMLSelectElement>;
   };
@@ -7,18 +6,16 @@ export type LicensePricesProps = {
 };
 
 /**
- * Represents a select element with the license prices as options.
+ * Represents a select element with the number of seats as options.
+ * The price of the selected plan is calculated by the server.
  *
  * @returns A License Prices component.
  */
 export default function LicensePrices(props: LicensePricesProps) {
-  const prices = {
-    priceTiers: [
-      1, 2, 3, 4, 5, 10, 15, 20, 25, 30, 35, 40, 45, 50, 60, 70, 80, 90, 100,
-      150, 200, 250, 300,
-    ],
-    price: props.price,
-  };
+  const seatOptions = [
+    1, 2, 3, 4, 5, 10, 15, 20, 25, 30, 35, 40, 45, 50, 60, 70, 80, 90, 100, 150,
+    200, 250, 300,
+  ];
 
   return (
     <>
@@ -33,15 +30,9 @@ export default function LicensePrices(props: LicensePricesProps) {
         <option key="0" value="0" disabled hidden>
           Please choose a plan
         </option>
-        {prices.priceTiers.map((tier: number) => (
-          <option
-            key={tier}
-            value={Math.round(tier * prices.price * 100) / 100}
-          >
-            {tier +
-              " user(s) — $" +
-              Math.round(tier * prices.price * 100) / 100 +
-              " / year"}
+        {seatOptions.map((seats: number) => (
+          <option key={seats} value={seats}>
+            {seats + " user(s)"}
           </option>
         ))}
       </select>
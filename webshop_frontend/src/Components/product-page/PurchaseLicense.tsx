@@ -1,13 +1,15 @@
 import { ChangeEvent, useEffect, useRef, useState } from "react";
 import { Link, useNavigate, useParams } from "react-router-dom";
 import Spinner from "../utils/utils";
-import { License, MeUser, Product } from "../../Interfaces";
+import { MeUser, PriceQuote, Product, Purchase } from "../../Interfaces";
 import LicensePrices from "./LicensePrices";
 import {
   FetchError,
+  fetchBillingCurrency,
   fetchMe,
   fetchProduct,
-  postLicense,
+  fetchQuote,
+  postPurchase,
 } from "../../ApiController";
 import TermsOfService from "../profile/register/TermsOfService";
 import { ErrorMessage } from "../ErrorMessage";
@@ -23,26 +25,61 @@ export default function PurchaseLicense() {
   const navigate = useNavigate();
 
   const [user, setUser] = useState<MeUser>();
+  const [currency, setCurrency] = useState<string>();
   const [loadingUser, setLoadingUser] = useState(true);
   const [loadingProd, setLoadingProd] = useState(true);
 
   const [error, setError] = useState<Error | null>(null);
   const [purchased, setPurchased] = useState<boolean>(false);
+  const [paymentPending, setPaymentPending] = useState<boolean>(false);
 
   const { productId } = useParams();
   const [product, setProduct] = useState<Product>();
-  const [totalPrice, setTotalPrice] = useState<number>(0);
+  const [quote, setQuote] = useState<PriceQuote>();
 
   const price = useRef<HTMLSelectElement>(null);
+  const discountCode = useRef<HTMLInputElement>(null);
   const [formAlert, setFormAlert] = useState<string>("");
 
   /**
-   * Update the total price in the object's state.
+   * Fetch the price of the selected plan in the billing currency of the
+   * company, with the discount code if one is entered, and update the
+   * object's state.
+   *
+   * @param seats The number of seats of the selected plan.
+   */
+  const updateQuote = (seats: number) => {
+    const code = discountCode.current?.value.trim();
+    fetchQuote(productId!, seats, 1, code, currency)
+      .then((quote: PriceQuote) => {
+        setQuote(quote);
+        setFormAlert("");
+      })
+      .catch((error: FetchError) => {
+        if (code && [400, 404, 409].includes(error.status)) {
+          setFormAlert("This discount code cannot be used");
+        } else {
+          setError(error);
+        }
+      });
+  };
+
+  /**
+   * Fetch the price of the selected plan and update the object's state.
    *
    * @param event The user event.
    */
   const updatePrice = (event: ChangeEvent<HTMLSelectElement>) => {
-    setTotalPrice(parseInt(event.target.value));
+    updateQuote(parseInt(event.target.value));
+  };
+
+  /**
+   * Apply the entered discount code to the price of the selected plan.
+   */
+  const applyDiscount = () => {
+    if (price.current && parseInt(price.current.value) > 0) {
+      updateQuote(parseInt(price.current.value));
+    }
   };
 
   /**
@@ -70,21 +107,15 @@ export default function PurchaseLicense() {
     event.preventDefault();
 
     if (validateForm() && user && product) {
-      let license: License = {
-        license_id: NaN,
-        company_id: user.company_id,
-        product_id: product.product_id,
-        product_name: product.display_name,
-        start_date: new Date(),
-        end_date: new Date(
-          new Date().setFullYear(new Date().getFullYear() + 1)
-        ),
-        amount: Math.round(totalPrice / product.price_per_user),
-        valid: true,
-      };
       setLoadingProd(true);
-      postLicense(license)
-        .then(() => {
+      postPurchase(
+        product.product_id,
+        parseInt(price.current!.value),
+        1,
+        discountCode.current?.value.trim()
+      )
+        .then((purchase: Purchase) => {
+          setPaymentPending(purchase.payment !== null);
           setPurchased(true);
           setLoadingProd(false);
         })
@@ -116,8 +147,11 @@ export default function PurchaseLicense() {
       });
 
     fetchMe()
-      .then((user: MeUser) => {
+      .then(async (user: MeUser) => {
         setUser(user);
+        if (user.role !== "Default") {
+          setCurrency(await fetchBillingCurrency(user.company_id));
+        }
         setLoadingUser(false);
       })
       .catch((error: FetchError) => {
@@ -155,12 +189,30 @@ export default function PurchaseLicense() {
                       cancel it.
                     </p>
                     <LicensePrices
-                      price={product.price_per_user}
                       updatePrice={(event) => updatePrice(event)}
                       refs={{ price }}
                     />
 
-                    <p className="total-price">TOTAL: ${totalPrice}</p>
+                    <br />
+                    <label htmlFor="discount-code">Discount code: </label>
+                    <input
+                      type="text"
+                      id="discount-code"
+                      name="discount-code"
+                      ref={discountCode}
+                      onBlur={applyDiscount}
+                    />
+                    {quote && quote.discount && (
+                      <p>
+                        Discount ({quote.discount.code}): -
+                        {quote.discount.amount + " " + quote.currency}
+                      </p>
+                    )}
+
+                    <p className="total-price">
+                      TOTAL: {quote ? quote.total + " " + quote.currency : "0.00"}
+                    </p>
+                    <p>VAT is added based on your company's billing address.</p>
 
                     <TermsOfService />
 
@@ -177,10 +229,20 @@ export default function PurchaseLicense() {
         )}
         {purchased && !loadingProd && (
           <>
-            <h2>License successfully purchased!</h2>
+            <h2>
+              {paymentPending
+                ? "License ordered!"
+                : "License successfully purchased!"}
+            </h2>
             <p>
               Thank you for purchasing a license for {product!.display_name}!
               <br />
+              {paymentPending && (
+                <>
+                  The license becomes valid once the order is paid.
+                  <br />
+                </>
+              )}
               You can view your licenses on your{" "}
               <a href="" onClick={() => navigate("/profile")}>
                 profile page
@@ -8,11 +8,11 @@ import {
   Description,
   Product,
   LocalDescription,
+  ProductPreview,
   Testimonial,
 } from "../../../../Interfaces";
 import {
-  fetchDescriptionComponents,
-  fetchProduct,
+  fetchProductPreview,
   fetchTestimonials,
 } from "../../../../ApiController";
 import { AccordionSectionProps } from "./Accordion/AccordionSection";
@@ -69,14 +69,11 @@ export default function ManageProductPage() {
     fetchTestimonials(productId!)
       .then((testimonials: Testimonial[]) => setTestimonials(testimonials))
       .catch(handleBreakingErrors);
-    fetchDescriptionComponents(productId!)
-      .then((descriptions: Description[]) =>
-        initializeSections(assignImageState(descriptions))
-      )
-      .catch(handleBreakingErrors);
-    fetchProduct(productId!)
-      .then((product: Product) => {
-        setProductInfo(product);
+    // the preview has drafts, which the public endpoints do not
+    fetchProductPreview(productId!)
+      .then((preview: ProductPreview) => {
+        initializeSections(assignImageState(preview.descriptions));
+        setProductInfo(preview.product);
       })
       .catch(handleBreakingErrors);
   };
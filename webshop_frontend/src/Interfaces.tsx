@@ -48,17 +48,65 @@ export interface Company {
   company_id: number;
   company_name: string;
   company_address: string;
+  billing_currency: string;
+}
+
+export interface BillingCurrency {
+  billing_currency: string;
 }
 
 export interface Product {
   product_id: string;
   display_name: string;
-  price_per_user: number;
+  price_per_user: string;
+  currency: string;
   short_description: string;
   main_image: string;
   available: boolean;
 }
 
+export interface Publishing {
+  product_id: string;
+  status: "draft" | "scheduled" | "published" | "archived";
+  publish_at: string | null;
+}
+
+export interface ProductPreview {
+  product: Product;
+  publishing: Publishing;
+  descriptions: Description[];
+}
+
+export interface PriceQuote {
+  product_id: string;
+  seats: number;
+  term_years: number;
+  currency: string;
+  unit_price: string;
+  tier_min_seats: number | null;
+  subtotal: string;
+  discount: AppliedDiscount | null;
+  total: string;
+}
+
+export interface AppliedDiscount {
+  discount_code_id: number;
+  code: string;
+  amount: string;
+}
+
+export interface PaymentIntent {
+  intent_id: string;
+  client_secret: string;
+  status: string;
+  amount: string;
+  currency: string;
+}
+
+export interface Purchase {
+  payment: PaymentIntent | null;
+}
+
 export interface Text {
   text_title: string;
   paragraph: string;
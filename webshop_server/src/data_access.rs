@@ -5,11 +5,19 @@
 //! e.g. product descriptions have separate tables for different description types, but are grouped together in the descriptions module.)
 use sqlx::{Pool, Postgres};
 pub mod auth;
+pub mod cart;
 pub mod category;
 pub mod company;
+pub mod discount;
 pub mod error_handling;
 pub mod license;
+pub mod order;
+pub mod pagination;
+pub mod payment;
 pub mod product;
+pub mod purchase_request;
+pub mod quote;
+pub mod refund;
 pub mod testimonial;
 pub mod user;
 
@@ -0,0 +1,139 @@
+//! Data access for carts, the licenses a user is about to buy.
+//!
+//! Each user has one cart, with at most one item per product.
+//! All items of a cart are bought together in one order at checkout.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Pool, Postgres, Transaction};
+use utoipa::ToSchema;
+
+use crate::data_access::license::LicenseType;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CartItem {
+    pub cart_item_id: i32,
+    pub product_id: String,
+    /// Number of seats.
+    pub seats: i32,
+    /// Length of the license in years.
+    pub term_years: i32,
+    pub license_type: LicenseType,
+    pub added_at: DateTime<Utc>,
+}
+
+/// Returns the items in the cart of a user, in the order they were added.
+pub async fn get_cart(pool: &Pool<Postgres>, user_id: &i32) -> Result<Vec<CartItem>, sqlx::Error> {
+    query_as!(
+        CartItem,
+        r#"SELECT cart_item_id, product_id, seats, term_years,
+        license_type as "license_type: _", added_at
+        FROM cart_item
+        WHERE user_id = $1
+        ORDER BY cart_item_id"#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Adds a product to the cart of a user.
+pub async fn add_cart_item(
+    pool: &Pool<Postgres>,
+    user_id: &i32,
+    product_id: &str,
+    seats: i32,
+    term_years: i32,
+    license_type: LicenseType,
+) -> Result<CartItem, sqlx::Error> {
+    query_as!(
+        CartItem,
+        r#"INSERT INTO cart_item (user_id, product_id, seats, term_years, license_type)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING cart_item_id, product_id, seats, term_years,
+        license_type as "license_type: _", added_at"#,
+        user_id,
+        product_id,
+        seats,
+        term_years,
+        license_type as _
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Changes the seats, term and license type of an item in the cart of a user.
+pub async fn update_cart_item(
+    pool: &Pool<Postgres>,
+    user_id: &i32,
+    cart_item_id: &i32,
+    seats: i32,
+    term_years: i32,
+    license_type: LicenseType,
+) -> Result<CartItem, sqlx::Error> {
+    query_as!(
+        CartItem,
+        r#"UPDATE cart_item
+        SET seats = $3, term_years = $4, license_type = $5
+        WHERE user_id = $1 AND cart_item_id = $2
+        RETURNING cart_item_id, product_id, seats, term_years,
+        license_type as "license_type: _", added_at"#,
+        user_id,
+        cart_item_id,
+        seats,
+        term_years,
+        license_type as _
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Removes an item from the cart of a user.
+///
+/// # Returns
+/// false if the item was not in the cart.
+pub async fn remove_cart_item(
+    pool: &Pool<Postgres>,
+    user_id: &i32,
+    cart_item_id: &i32,
+) -> Result<bool, sqlx::Error> {
+    let removed = query!(
+        r#"DELETE FROM cart_item WHERE user_id = $1 AND cart_item_id = $2"#,
+        user_id,
+        cart_item_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(removed.rows_affected() > 0)
+}
+
+/// Removes all items from the cart of a user.
+pub async fn clear_cart(pool: &Pool<Postgres>, user_id: &i32) -> Result<(), sqlx::Error> {
+    query!(r#"DELETE FROM cart_item WHERE user_id = $1"#, user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Empties the cart of a user at checkout.
+///
+/// # Returns
+/// The items that were in the cart, in the order they were added,
+/// so they can be checked against the items priced.
+pub async fn take_cart(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: &i32,
+) -> Result<Vec<CartItem>, sqlx::Error> {
+    let mut items = query_as!(
+        CartItem,
+        r#"DELETE FROM cart_item
+        WHERE user_id = $1
+        RETURNING cart_item_id, product_id, seats, term_years,
+        license_type as "license_type: _", added_at"#,
+        user_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    items.sort_by_key(|item| item.cart_item_id);
+    Ok(items)
+}
@@ -1,9 +1,10 @@
 use serde::{Deserialize, Serialize};
 use sqlx::{
-    query_as, {Pool, Postgres},
+    query, query_as, {Pool, Postgres},
 };
+use utoipa::ToSchema;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct Category {
     category_id: i32,
     name: String,
@@ -29,3 +30,86 @@ pub async fn get_category_by_id(pool: &Pool<Postgres>, id: &i32) -> Result<Categ
     .await?;
     Ok(category)
 }
+
+/// Creates a category.
+pub async fn create_category(
+    pool: &Pool<Postgres>,
+    name: &str,
+    description: &str,
+) -> Result<Category, sqlx::Error> {
+    let category = query_as!(
+        Category,
+        r#"INSERT INTO category (name, description) VALUES ($1, $2) RETURNING *"#,
+        name,
+        description
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(category)
+}
+
+/// Updates the name and description of a category.
+/// Returns `RowNotFound` if the category does not exist.
+pub async fn update_category(
+    pool: &Pool<Postgres>,
+    id: &i32,
+    name: &str,
+    description: &str,
+) -> Result<Category, sqlx::Error> {
+    let category = query_as!(
+        Category,
+        r#"UPDATE category SET name = $2, description = $3
+        WHERE category_id = $1
+        RETURNING *"#,
+        id,
+        name,
+        description
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(category)
+}
+
+/// Deletes a category, its products are no longer in it.
+/// Returns false if the category does not exist.
+pub async fn delete_category(pool: &Pool<Postgres>, id: &i32) -> Result<bool, sqlx::Error> {
+    let deleted = query!(r#"DELETE FROM category WHERE category_id = $1"#, id)
+        .execute(pool)
+        .await?;
+    Ok(deleted.rows_affected() > 0)
+}
+
+/// Puts a product in a category.
+/// Returns false if the product was in the category already.
+pub async fn add_product(
+    pool: &Pool<Postgres>,
+    category_id: &i32,
+    product_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let added = query!(
+        r#"INSERT INTO product_category (product_id, category_id) VALUES ($1, $2)
+        ON CONFLICT DO NOTHING"#,
+        product_id,
+        category_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(added.rows_affected() > 0)
+}
+
+/// Takes a product out of a category.
+/// Returns false if the product was not in the category.
+pub async fn remove_product(
+    pool: &Pool<Postgres>,
+    category_id: &i32,
+    product_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let removed = query!(
+        r#"DELETE FROM product_category WHERE product_id = $1 AND category_id = $2"#,
+        product_id,
+        category_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(removed.rows_affected() > 0)
+}
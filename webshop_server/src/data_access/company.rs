@@ -1,21 +1,106 @@
 use serde::{Deserialize, Serialize};
-use sqlx::{query_as, Pool, Postgres};
+use sqlx::{query, query_as, Executor, Pool, Postgres};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    data_access::pagination::{Page, PageRequest, SortField},
+    money::Currency,
+};
 
 #[derive(Deserialize, Serialize)]
 pub struct Company {
     pub company_id: i32,
     pub company_name: String,
     pub company_address: String,
+    /// Currency the company is priced, billed and invoiced in.
+    pub billing_currency: Currency,
+}
+
+/// Where a company is billed, decides the tax on its orders.
+#[derive(Deserialize, Serialize, ToSchema, Debug, Clone)]
+pub struct BillingAddress {
+    pub company_id: i32,
+    pub street: String,
+    pub postal_code: String,
+    pub city: String,
+    /// ISO 3166-1 alpha-2 country code.
+    #[schema(example = "NO")]
+    pub country: String,
+    /// VAT number with its country prefix, none if the company has none.
+    #[schema(example = "NO999999999MVA")]
+    pub vat_number: Option<String>,
+}
+
+/// Fields a list of companies can be sorted by, by id if not given.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum CompanySort {
+    #[default]
+    CompanyId,
+    CompanyName,
+}
+
+impl SortField for CompanySort {
+    const NAMES: &'static [&'static str] = &["company_id", "company_name"];
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "company_id" => Some(CompanySort::CompanyId),
+            "company_name" => Some(CompanySort::CompanyName),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            CompanySort::CompanyId => "company_id",
+            CompanySort::CompanyName => "company_name",
+        }
+    }
 }
 
-/// Get all companies from the database.
-/// returns a vector of all companies.
+/// Filters for a list of companies, all companies if none are given.
+#[derive(Deserialize, Debug, Default, IntoParams)]
+pub struct CompanyFilter {
+    /// Only companies with this in their name, in any case
+    pub name: Option<String>,
+}
+
+/// Get a page of the companies matching the filter.
 /// returns an error if the database query failed.
-pub async fn get_all_companies(pool: &Pool<Postgres>) -> Result<Vec<Company>, sqlx::Error> {
-    let companies = query_as!(Company, "SELECT * FROM company")
-        .fetch_all(pool)
-        .await?;
-    Ok(companies)
+pub async fn get_companies(
+    pool: &Pool<Postgres>,
+    filter: &CompanyFilter,
+    page: &PageRequest<CompanySort>,
+) -> Result<Page<Company>, sqlx::Error> {
+    let items = query_as!(
+        Company,
+        r#"SELECT company_id, company_name, company_address,
+        billing_currency as "billing_currency: _"
+        FROM company
+        WHERE ($1::TEXT IS NULL OR company_name ILIKE '%' || $1 || '%')
+        ORDER BY
+            CASE WHEN $2 = 'company_name' AND NOT $3 THEN company_name END ASC,
+            CASE WHEN $2 = 'company_name' AND $3 THEN company_name END DESC,
+            CASE WHEN $2 = 'company_id' AND $3 THEN company_id END DESC,
+            company_id
+        LIMIT $4 OFFSET $5"#,
+        filter.name,
+        page.sort_name(),
+        page.descending(),
+        page.limit,
+        page.offset
+    )
+    .fetch_all(pool)
+    .await?;
+    let total = query!(
+        r#"SELECT COUNT(*) as "total!" FROM company
+        WHERE ($1::TEXT IS NULL OR company_name ILIKE '%' || $1 || '%')"#,
+        filter.name
+    )
+    .fetch_one(pool)
+    .await?
+    .total;
+    Ok(Page { items, total })
 }
 
 /// Get a company by id.
@@ -28,7 +113,10 @@ pub async fn get_company_by_id(
 ) -> Result<Company, sqlx::Error> {
     let company = query_as!(
         Company,
-        "SELECT * FROM company WHERE company_id = $1",
+        r#"SELECT company_id, company_name, company_address,
+        billing_currency as "billing_currency: _"
+        FROM company
+        WHERE company_id = $1"#,
         company_id
     )
     .fetch_one(pool)
@@ -60,7 +148,9 @@ pub async fn create_company(
     }
     let company = query_as!(
         Company,
-        "INSERT INTO company (company_name, company_address) VALUES ($1, $2) RETURNING *",
+        r#"INSERT INTO company (company_name, company_address) VALUES ($1, $2)
+        RETURNING company_id, company_name, company_address,
+        billing_currency as "billing_currency: _""#,
         company_name,
         company_address
     )
@@ -83,3 +173,85 @@ pub async fn delete_company(pool: &Pool<Postgres>, company_id: &i32) -> Result<b
     }
     Ok(true)
 }
+
+/// Get the billing address of a company.
+/// returns none if the company has no billing address.
+pub async fn get_billing_address<'e, E>(
+    executor: E,
+    company_id: &i32,
+) -> Result<Option<BillingAddress>, sqlx::Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let address = query_as!(
+        BillingAddress,
+        r#"SELECT company_id, street, postal_code, city, country, vat_number
+        FROM billing_address
+        WHERE company_id = $1"#,
+        company_id
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(address)
+}
+
+/// Set the billing address of a company, replacing the one it had.
+pub async fn set_billing_address(
+    pool: &Pool<Postgres>,
+    address: &BillingAddress,
+) -> Result<BillingAddress, sqlx::Error> {
+    let address = query_as!(
+        BillingAddress,
+        r#"INSERT INTO billing_address (company_id, street, postal_code, city, country, vat_number)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (company_id) DO UPDATE
+        SET street = $2, postal_code = $3, city = $4, country = $5, vat_number = $6
+        RETURNING company_id, street, postal_code, city, country, vat_number"#,
+        address.company_id,
+        address.street,
+        address.postal_code,
+        address.city,
+        address.country,
+        address.vat_number
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(address)
+}
+
+/// Get the currency a company is billed in.
+/// returns an error if the company was not found.
+pub async fn get_billing_currency<'e, E>(
+    executor: E,
+    company_id: &i32,
+) -> Result<Currency, sqlx::Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let company = query!(
+        r#"SELECT billing_currency as "billing_currency: Currency"
+        FROM company
+        WHERE company_id = $1"#,
+        company_id
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(company.billing_currency)
+}
+
+/// Set the currency a company is billed in.
+/// returns false if the company was not found.
+pub async fn set_billing_currency(
+    pool: &Pool<Postgres>,
+    company_id: &i32,
+    currency: Currency,
+) -> Result<bool, sqlx::Error> {
+    let updated = query!(
+        r#"UPDATE company SET billing_currency = $2 WHERE company_id = $1"#,
+        company_id,
+        currency as _
+    )
+    .execute(pool)
+    .await?;
+    Ok(updated.rows_affected() > 0)
+}
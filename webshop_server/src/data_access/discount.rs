@@ -0,0 +1,335 @@
+//! Data access for discount codes.
+//!
+//! A discount code takes a percentage or a fixed amount off the price of an order.
+//! It can be limited to a product, to some companies, to a number of uses and to a period.
+//! A use is counted when an order is placed with the code, and given back if its payment fails.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Pool, Postgres, Transaction};
+use utoipa::ToSchema;
+
+use crate::money::{Currency, Money};
+
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[sqlx(type_name = "discount_kind_enum", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DiscountKind {
+    /// Takes a percentage off the price.
+    Percentage,
+    /// Takes a fixed amount off the price.
+    Fixed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DiscountCode {
+    pub discount_code_id: i32,
+    /// The code customers enter, in upper case.
+    #[schema(example = "WELCOME10")]
+    pub code: String,
+    pub kind: DiscountKind,
+    /// Percentage off in basis points, 1000 is 10%. Only for percentage discounts.
+    #[schema(example = 1000)]
+    pub percent_off: Option<i32>,
+    /// Amount off the price of an order. Only for fixed discounts.
+    #[schema(value_type = Option<String>, example = "100.00")]
+    pub amount_off: Option<Money>,
+    /// Currency of the amount off, the code only applies to prices in it.
+    pub currency: Option<Currency>,
+    /// Product the code applies to, none for all products.
+    pub product_id: Option<String>,
+    /// Companies that may use the code, empty for all companies.
+    pub company_ids: Vec<i32>,
+    /// How many times the code can be used, none for no limit.
+    pub max_uses: Option<i32>,
+    pub times_used: i32,
+    pub valid_from: Option<DateTime<Utc>>,
+    pub valid_until: Option<DateTime<Utc>>,
+    pub active: bool,
+}
+
+/// Discount code without its companies, as stored in the `discount_code` table.
+struct DiscountCodeRow {
+    discount_code_id: i32,
+    code: String,
+    kind: DiscountKind,
+    percent_off: Option<i32>,
+    amount_off: Option<Money>,
+    currency: Option<Currency>,
+    product_id: Option<String>,
+    max_uses: Option<i32>,
+    times_used: i32,
+    valid_from: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
+    active: bool,
+}
+
+impl DiscountCodeRow {
+    fn with_companies(self, company_ids: Vec<i32>) -> DiscountCode {
+        DiscountCode {
+            discount_code_id: self.discount_code_id,
+            code: self.code,
+            kind: self.kind,
+            percent_off: self.percent_off,
+            amount_off: self.amount_off,
+            currency: self.currency,
+            product_id: self.product_id,
+            company_ids,
+            max_uses: self.max_uses,
+            times_used: self.times_used,
+            valid_from: self.valid_from,
+            valid_until: self.valid_until,
+            active: self.active,
+        }
+    }
+}
+
+/// Discount code to create or update, the number of uses is kept by the server.
+pub struct PartialDiscountCode {
+    pub code: String,
+    pub kind: DiscountKind,
+    pub percent_off: Option<i32>,
+    pub amount_off: Option<Money>,
+    pub currency: Option<Currency>,
+    pub product_id: Option<String>,
+    pub company_ids: Vec<i32>,
+    pub max_uses: Option<i32>,
+    pub valid_from: Option<DateTime<Utc>>,
+    pub valid_until: Option<DateTime<Utc>>,
+    pub active: bool,
+}
+
+async fn attach_companies(
+    pool: &Pool<Postgres>,
+    codes: Vec<DiscountCodeRow>,
+) -> Result<Vec<DiscountCode>, sqlx::Error> {
+    let ids: Vec<i32> = codes.iter().map(|code| code.discount_code_id).collect();
+    let rows = query!(
+        r#"SELECT discount_code_id, company_id
+        FROM discount_code_company
+        WHERE discount_code_id = ANY($1)
+        ORDER BY company_id"#,
+        &ids
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut companies: HashMap<i32, Vec<i32>> = HashMap::new();
+    for row in rows {
+        companies
+            .entry(row.discount_code_id)
+            .or_default()
+            .push(row.company_id);
+    }
+    Ok(codes
+        .into_iter()
+        .map(|code| {
+            let company_ids = companies.remove(&code.discount_code_id).unwrap_or_default();
+            code.with_companies(company_ids)
+        })
+        .collect())
+}
+
+/// Returns all discount codes, newest first.
+pub async fn get_discount_codes(pool: &Pool<Postgres>) -> Result<Vec<DiscountCode>, sqlx::Error> {
+    let codes = query_as!(
+        DiscountCodeRow,
+        r#"SELECT discount_code_id, code, kind as "kind: _", percent_off,
+        amount_off as "amount_off: _", currency as "currency: _", product_id, max_uses,
+        times_used, valid_from, valid_until, active
+        FROM discount_code
+        ORDER BY discount_code_id DESC"#
+    )
+    .fetch_all(pool)
+    .await?;
+    attach_companies(pool, codes).await
+}
+
+/// Returns a discount code by its id.
+pub async fn get_discount_code_by_id(
+    pool: &Pool<Postgres>,
+    discount_code_id: &i32,
+) -> Result<DiscountCode, sqlx::Error> {
+    let code = query_as!(
+        DiscountCodeRow,
+        r#"SELECT discount_code_id, code, kind as "kind: _", percent_off,
+        amount_off as "amount_off: _", currency as "currency: _", product_id, max_uses,
+        times_used, valid_from, valid_until, active
+        FROM discount_code
+        WHERE discount_code_id = $1"#,
+        discount_code_id
+    )
+    .fetch_one(pool)
+    .await?;
+    let mut codes = attach_companies(pool, vec![code]).await?;
+    Ok(codes.remove(0))
+}
+
+/// Returns a discount code by the code customers enter, ignoring case.
+pub async fn get_discount_code(
+    pool: &Pool<Postgres>,
+    code: &str,
+) -> Result<Option<DiscountCode>, sqlx::Error> {
+    let code = query_as!(
+        DiscountCodeRow,
+        r#"SELECT discount_code_id, code, kind as "kind: _", percent_off,
+        amount_off as "amount_off: _", currency as "currency: _", product_id, max_uses,
+        times_used, valid_from, valid_until, active
+        FROM discount_code
+        WHERE code = upper($1)"#,
+        code.trim()
+    )
+    .fetch_optional(pool)
+    .await?;
+    match code {
+        Some(code) => Ok(attach_companies(pool, vec![code]).await?.pop()),
+        None => Ok(None),
+    }
+}
+
+async fn set_companies(
+    transaction: &mut Transaction<'_, Postgres>,
+    discount_code_id: &i32,
+    company_ids: &[i32],
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"DELETE FROM discount_code_company WHERE discount_code_id = $1"#,
+        discount_code_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    query!(
+        r#"INSERT INTO discount_code_company (discount_code_id, company_id)
+        SELECT $1, company_id FROM UNNEST($2::int[]) AS company_id"#,
+        discount_code_id,
+        company_ids
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+/// Creates a discount code, the code is stored in upper case.
+pub async fn create_discount_code(
+    pool: &Pool<Postgres>,
+    code: &PartialDiscountCode,
+) -> Result<DiscountCode, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let row = query_as!(
+        DiscountCodeRow,
+        r#"INSERT INTO discount_code
+        (code, kind, percent_off, amount_off, currency, product_id, max_uses,
+        valid_from, valid_until, active)
+        VALUES (upper($1), $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        RETURNING discount_code_id, code, kind as "kind: _", percent_off,
+        amount_off as "amount_off: _", currency as "currency: _", product_id, max_uses,
+        times_used, valid_from, valid_until, active"#,
+        code.code,
+        code.kind as _,
+        code.percent_off,
+        code.amount_off as _,
+        code.currency as _,
+        code.product_id,
+        code.max_uses,
+        code.valid_from,
+        code.valid_until,
+        code.active
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+    set_companies(&mut transaction, &row.discount_code_id, &code.company_ids).await?;
+    transaction.commit().await?;
+    Ok(row.with_companies(code.company_ids.clone()))
+}
+
+/// Updates a discount code, keeping how many times it has been used.
+pub async fn update_discount_code(
+    pool: &Pool<Postgres>,
+    discount_code_id: &i32,
+    code: &PartialDiscountCode,
+) -> Result<DiscountCode, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let row = query_as!(
+        DiscountCodeRow,
+        r#"UPDATE discount_code
+        SET code = upper($2), kind = $3, percent_off = $4, amount_off = $5, currency = $6,
+        product_id = $7, max_uses = $8, valid_from = $9, valid_until = $10, active = $11
+        WHERE discount_code_id = $1
+        RETURNING discount_code_id, code, kind as "kind: _", percent_off,
+        amount_off as "amount_off: _", currency as "currency: _", product_id, max_uses,
+        times_used, valid_from, valid_until, active"#,
+        discount_code_id,
+        code.code,
+        code.kind as _,
+        code.percent_off,
+        code.amount_off as _,
+        code.currency as _,
+        code.product_id,
+        code.max_uses,
+        code.valid_from,
+        code.valid_until,
+        code.active
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+    set_companies(&mut transaction, discount_code_id, &code.company_ids).await?;
+    transaction.commit().await?;
+    Ok(row.with_companies(code.company_ids.clone()))
+}
+
+/// Deletes a discount code, orders keep the code they were placed with.
+///
+/// # Returns
+/// false if the discount code was not found.
+pub async fn delete_discount_code(
+    pool: &Pool<Postgres>,
+    discount_code_id: &i32,
+) -> Result<bool, sqlx::Error> {
+    let deleted = query!(
+        r#"DELETE FROM discount_code WHERE discount_code_id = $1"#,
+        discount_code_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(deleted.rows_affected() > 0)
+}
+
+/// Counts a use of a discount code, if it has uses left.
+///
+/// # Returns
+/// false if the code has been deleted, deactivated or used up meanwhile.
+pub async fn redeem(
+    transaction: &mut Transaction<'_, Postgres>,
+    discount_code_id: &i32,
+) -> Result<bool, sqlx::Error> {
+    let redeemed = query!(
+        r#"UPDATE discount_code
+        SET times_used = times_used + 1
+        WHERE discount_code_id = $1 AND active
+        AND (max_uses IS NULL OR times_used < max_uses)"#,
+        discount_code_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(redeemed.rows_affected() > 0)
+}
+
+/// Gives back the use of the discount code of an order whose payment failed.
+pub async fn release(
+    transaction: &mut Transaction<'_, Postgres>,
+    order_id: &i32,
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"UPDATE discount_code
+        SET times_used = times_used - 1
+        FROM "order"
+        WHERE "order".discount_code_id = discount_code.discount_code_id
+        AND "order".order_id = $1 AND discount_code.times_used > 0"#,
+        order_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
@@ -1,9 +1,30 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Months, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{
-    query, query_as, Executor, {Pool, Postgres},
+    query, query_as, Executor, Transaction, {Pool, Postgres},
 };
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::data_access::pagination::{Page, PageRequest, SortField};
+
+pub mod assignment;
+pub mod checkout;
+pub mod reminder;
+pub mod subscription;
+pub mod trial;
+pub mod usage;
+
+/// How the seats (`amount`) of a license are handed out.
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, ToSchema)]
+#[sqlx(type_name = "license_type_enum", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum LicenseType {
+    /// Seats are assigned to specific users through `user_license`.
+    #[default]
+    Named,
+    /// Any company user can check out a seat, `amount` caps concurrent checkouts.
+    Floating,
+}
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct License {
@@ -14,9 +35,39 @@ pub struct License {
     amount: i32,
     pub company_id: i32,
     product_id: String,
+    license_type: LicenseType,
+    trial: bool,
+}
+impl License {
+    pub fn license_id(&self) -> i32 {
+        self.license_id
+    }
+    pub fn license_type(&self) -> LicenseType {
+        self.license_type
+    }
+    pub fn amount(&self) -> i32 {
+        self.amount
+    }
+    pub fn end_date(&self) -> DateTime<Utc> {
+        self.end_date
+    }
+    pub fn product_id(&self) -> &str {
+        &self.product_id
+    }
+    pub fn valid(&self) -> bool {
+        self.valid
+    }
+    pub fn trial(&self) -> bool {
+        self.trial
+    }
+    /// Returns true if the license is marked valid and today is within its start and end date.
+    pub fn is_active(&self) -> bool {
+        let now = Utc::now();
+        self.valid && self.start_date <= now && now <= self.end_date
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct PartialLicense {
     valid: bool,
     start_date: DateTime<Utc>,
@@ -24,10 +75,53 @@ pub struct PartialLicense {
     amount: i32,
     company_id: i32,
     product_id: String,
+    #[serde(default)]
+    license_type: LicenseType,
 }
 impl PartialLicense {
-    pub fn company_id(&self) -> i32 {
-        self.company_id
+    pub fn new(
+        valid: bool,
+        start_date: DateTime<Utc>,
+        end_date: DateTime<Utc>,
+        amount: i32,
+        company_id: i32,
+        product_id: &str,
+        license_type: LicenseType,
+    ) -> Self {
+        Self {
+            valid,
+            start_date,
+            end_date,
+            amount,
+            company_id,
+            product_id: product_id.to_string(),
+            license_type,
+        }
+    }
+
+    /// A license being bought, starting now and lasting for the term.
+    /// It is not valid until the order paying for it is paid.
+    ///
+    /// # Returns
+    /// None if the end of the term is out of range.
+    pub fn purchase(
+        seats: i32,
+        term_years: i32,
+        company_id: i32,
+        product_id: &str,
+        license_type: LicenseType,
+    ) -> Option<Self> {
+        let start_date = Utc::now();
+        let end_date = start_date.checked_add_months(Months::new(12 * term_years as u32))?;
+        Some(Self::new(
+            false,
+            start_date,
+            end_date,
+            seats,
+            company_id,
+            product_id,
+            license_type,
+        ))
     }
 }
 
@@ -40,6 +134,8 @@ pub struct FullLicenseInfo {
     amount: i32,
     company_id: i32,
     product_id: String,
+    license_type: LicenseType,
+    trial: bool,
     company_name: String,
     display_name: String,
     active_users: Option<i32>,
@@ -55,7 +151,8 @@ pub struct LicenseValidation {
 pub async fn get_licenses(pool: &Pool<Postgres>) -> Result<Vec<License>, sqlx::Error> {
     let licenses = query_as!(
         License,
-        r#"SELECT *
+        r#"SELECT license_id, valid, start_date, end_date, amount, company_id, product_id,
+        license_type as "license_type: _", trial
         FROM license"#
     )
     .fetch_all(pool)
@@ -63,11 +160,68 @@ pub async fn get_licenses(pool: &Pool<Postgres>) -> Result<Vec<License>, sqlx::E
     Ok(licenses)
 }
 
-/// Returns full info from all licenses
-pub async fn get_licenses_full(pool: &Pool<Postgres>) -> Result<Vec<FullLicenseInfo>, sqlx::Error> {
-    let licenses = query_as!(
+/// Fields a list of licenses can be sorted by, by id if not given.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum LicenseSort {
+    #[default]
+    LicenseId,
+    StartDate,
+    EndDate,
+    CompanyName,
+    DisplayName,
+}
+
+impl SortField for LicenseSort {
+    const NAMES: &'static [&'static str] = &[
+        "license_id",
+        "start_date",
+        "end_date",
+        "company_name",
+        "display_name",
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "license_id" => Some(LicenseSort::LicenseId),
+            "start_date" => Some(LicenseSort::StartDate),
+            "end_date" => Some(LicenseSort::EndDate),
+            "company_name" => Some(LicenseSort::CompanyName),
+            "display_name" => Some(LicenseSort::DisplayName),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            LicenseSort::LicenseId => "license_id",
+            LicenseSort::StartDate => "start_date",
+            LicenseSort::EndDate => "end_date",
+            LicenseSort::CompanyName => "company_name",
+            LicenseSort::DisplayName => "display_name",
+        }
+    }
+}
+
+/// Filters for a list of licenses, all licenses if none are given.
+#[derive(Deserialize, Debug, Default, IntoParams)]
+pub struct LicenseFilter {
+    /// Only licenses of this company
+    pub company_id: Option<i32>,
+    /// Only licenses for this product
+    pub product_id: Option<String>,
+    /// Only licenses that are, or are not, valid
+    pub valid: Option<bool>,
+}
+
+/// Returns a page of full info of the licenses matching the filter
+pub async fn get_licenses_full(
+    pool: &Pool<Postgres>,
+    filter: &LicenseFilter,
+    page: &PageRequest<LicenseSort>,
+) -> Result<Page<FullLicenseInfo>, sqlx::Error> {
+    let items = query_as!(
         FullLicenseInfo,
-        r#"SELECT license_id, valid, start_date, end_date, amount, company_id, product_id, company_name, display_name, coalesce(cast(active_users as INTEGER), 0) AS active_users
+        r#"SELECT license_id, valid, start_date, end_date, amount, company_id, product_id, license_type as "license_type: _", trial, company_name, display_name, coalesce(cast(active_users as INTEGER), 0) AS active_users
         FROM license
         JOIN product USING (product_id)
         JOIN company USING (company_id)
@@ -75,11 +229,45 @@ pub async fn get_licenses_full(pool: &Pool<Postgres>) -> Result<Vec<FullLicenseI
             SELECT count(*) AS active_users, license_id
             FROM user_license
             GROUP BY license_id
-        ) AS count USING (license_id)"#
+        ) AS count USING (license_id)
+        WHERE ($1::INT IS NULL OR company_id = $1)
+        AND ($2::TEXT IS NULL OR product_id = $2)
+        AND ($3::BOOLEAN IS NULL OR valid = $3)
+        ORDER BY
+            CASE WHEN $4 = 'start_date' AND NOT $5 THEN start_date END ASC,
+            CASE WHEN $4 = 'start_date' AND $5 THEN start_date END DESC,
+            CASE WHEN $4 = 'end_date' AND NOT $5 THEN end_date END ASC,
+            CASE WHEN $4 = 'end_date' AND $5 THEN end_date END DESC,
+            CASE WHEN $4 = 'company_name' AND NOT $5 THEN company_name END ASC,
+            CASE WHEN $4 = 'company_name' AND $5 THEN company_name END DESC,
+            CASE WHEN $4 = 'display_name' AND NOT $5 THEN display_name END ASC,
+            CASE WHEN $4 = 'display_name' AND $5 THEN display_name END DESC,
+            CASE WHEN $4 = 'license_id' AND $5 THEN license_id END DESC,
+            license_id
+        LIMIT $6 OFFSET $7"#,
+        filter.company_id,
+        filter.product_id,
+        filter.valid,
+        page.sort_name(),
+        page.descending(),
+        page.limit,
+        page.offset
     )
     .fetch_all(pool)
     .await?;
-    Ok(licenses)
+    let total = query!(
+        r#"SELECT COUNT(*) as "total!" FROM license
+        WHERE ($1::INT IS NULL OR company_id = $1)
+        AND ($2::TEXT IS NULL OR product_id = $2)
+        AND ($3::BOOLEAN IS NULL OR valid = $3)"#,
+        filter.company_id,
+        filter.product_id,
+        filter.valid
+    )
+    .fetch_one(pool)
+    .await?
+    .total;
+    Ok(Page { items, total })
 }
 
 /// Returns a license
@@ -89,7 +277,9 @@ pub async fn get_license_by_id(
 ) -> Result<License, sqlx::Error> {
     let license = query_as!(
         License,
-        r#"SELECT * FROM license WHERE license_id = $1"#,
+        r#"SELECT license_id, valid, start_date, end_date, amount, company_id, product_id,
+        license_type as "license_type: _", trial
+        FROM license WHERE license_id = $1"#,
         license_id
     )
     .fetch_one(pool)
@@ -104,7 +294,7 @@ pub async fn get_licenses_full_by_company(
 ) -> Result<Vec<FullLicenseInfo>, sqlx::Error> {
     let licenses = query_as!(
         FullLicenseInfo,
-        r#"SELECT license_id, valid, start_date, end_date, amount, company_id, product_id, company_name, display_name, coalesce(cast(active_users as INTEGER), 0) AS active_users
+        r#"SELECT license_id, valid, start_date, end_date, amount, company_id, product_id, license_type as "license_type: _", trial, company_name, display_name, coalesce(cast(active_users as INTEGER), 0) AS active_users
         FROM license
         JOIN product USING (product_id)
         JOIN company USING (company_id)
@@ -123,27 +313,89 @@ pub async fn get_licenses_full_by_company(
 }
 
 /// Create a license
-pub async fn create_license(
-    pool: &Pool<Postgres>,
+pub async fn create_license<'e, E>(
+    executor: E,
     license: &PartialLicense,
-) -> Result<License, sqlx::Error> {
+) -> Result<License, sqlx::Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
     query_as!(
         License,
         r#"INSERT INTO license
-        (valid, start_date, end_date, amount, company_id, product_id)
-        VALUES ($1, $2, $3, $4, $5, $6)
-        RETURNING *"#,
+        (valid, start_date, end_date, amount, company_id, product_id, license_type)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING license_id, valid, start_date, end_date, amount, company_id, product_id,
+        license_type as "license_type: _", trial"#,
         license.valid,
         license.start_date,
         license.end_date,
         license.amount,
         license.company_id,
         license.product_id,
+        license.license_type as _,
     )
-    .fetch_one(pool)
+    .fetch_one(executor)
     .await
 }
 
+/// Returns a license and locks it until the end of the transaction,
+/// so its seats and dates cannot change while an order is made for it.
+pub async fn lock_license(
+    transaction: &mut Transaction<'_, Postgres>,
+    license_id: &i32,
+) -> Result<License, sqlx::Error> {
+    query_as!(
+        License,
+        r#"SELECT license_id, valid, start_date, end_date, amount, company_id, product_id,
+        license_type as "license_type: _", trial
+        FROM license WHERE license_id = $1
+        FOR UPDATE"#,
+        license_id
+    )
+    .fetch_one(transaction)
+    .await
+}
+
+/// Returns the number of users assigned to a license.
+pub async fn get_assigned_seats(
+    transaction: &mut Transaction<'_, Postgres>,
+    license_id: &i32,
+) -> Result<i64, sqlx::Error> {
+    Ok(query!(
+        r#"SELECT count(*) AS "count!" FROM user_license WHERE license_id = $1"#,
+        license_id
+    )
+    .fetch_one(transaction)
+    .await?
+    .count)
+}
+
+/// Returns the number of seats in use on a license:
+/// assigned users for named licenses, checked out seats for floating licenses.
+/// Checkouts without a heartbeat within the checkout timeout are stale, and not counted.
+pub async fn get_used_seats(
+    transaction: &mut Transaction<'_, Postgres>,
+    license: &License,
+    checkout_timeout: Duration,
+) -> Result<i64, sqlx::Error> {
+    let used_seats = match license.license_type {
+        LicenseType::Named => get_assigned_seats(transaction, &license.license_id).await?,
+        LicenseType::Floating => {
+            query!(
+                r#"SELECT count(*) AS "count!" FROM license_checkout
+                WHERE license_id = $1 AND last_heartbeat >= $2"#,
+                license.license_id,
+                Utc::now() - checkout_timeout
+            )
+            .fetch_one(transaction)
+            .await?
+            .count
+        }
+    };
+    Ok(used_seats)
+}
+
 /// Update the validation of licenses
 pub async fn update_license_validations(
     pool: &Pool<Postgres>,
@@ -173,7 +425,7 @@ pub async fn get_licenses_for_user(
 ) -> Result<Vec<FullLicenseInfo>, sqlx::Error> {
     let licenses = query_as!(
         FullLicenseInfo,
-        r#"SELECT license_id, valid, start_date, end_date, amount, company_id, product_id, company_name, display_name, coalesce(cast(active_users as INTEGER), 0) AS active_users
+        r#"SELECT license_id, valid, start_date, end_date, amount, company_id, product_id, license_type as "license_type: _", trial, company_name, display_name, coalesce(cast(active_users as INTEGER), 0) AS active_users
         FROM license
         JOIN product USING (product_id)
         JOIN company USING (company_id)
@@ -195,6 +447,25 @@ pub async fn get_licenses_for_user(
     Ok(licenses)
 }
 
+/// Returns true if a user is assigned a seat on a license
+pub async fn user_has_seat(
+    pool: &Pool<Postgres>,
+    license_id: &i32,
+    user_id: &i32,
+) -> Result<bool, sqlx::Error> {
+    let has_seat = query!(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM user_license WHERE license_id = $1 AND user_id = $2
+        ) as "has_seat!""#,
+        license_id,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?
+    .has_seat;
+    Ok(has_seat)
+}
+
 /// Returns all of a user's company's licenses that the user has no access to
 pub async fn get_licenses_for_user_no_access(
     pool: &Pool<Postgres>,
@@ -203,7 +474,7 @@ pub async fn get_licenses_for_user_no_access(
 ) -> Result<Vec<FullLicenseInfo>, sqlx::Error> {
     let licenses = query_as!(
         FullLicenseInfo,
-        r#"SELECT license_id, valid, start_date, end_date, amount, company_id, product_id, company_name, display_name, coalesce(cast(active_users as INTEGER), 0) AS active_users
+        r#"SELECT license_id, valid, start_date, end_date, amount, company_id, product_id, license_type as "license_type: _", trial, company_name, display_name, coalesce(cast(active_users as INTEGER), 0) AS active_users
         FROM license
         JOIN product USING (product_id)
         JOIN company USING (company_id)
@@ -0,0 +1,207 @@
+//! Rule-based seat assignment for named licenses.
+//!
+//! Instead of listing `(user_id, license_id)` pairs, users can be assigned to a license by a rule,
+//! and a license can be set to automatically assign company users when they register.
+//! Each seat is inserted on its own, so the seat trigger on `user_license` rejects users
+//! beyond the license's `amount` without undoing the seats assigned before them.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{query, Pool, Postgres};
+use utoipa::ToSchema;
+
+use crate::data_access::user::{self, Role, User};
+
+/// Which users of the license's company to assign.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum AssignmentRule {
+    /// All users of the company.
+    AllCompanyUsers,
+    /// Users of the company with the given role.
+    Role { role: Role },
+}
+
+/// A seat assigned to a user.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Assignment {
+    license_id: i32,
+    user_id: i32,
+}
+
+/// A user that could not be assigned a seat, and why.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FailedAssignment {
+    license_id: i32,
+    user_id: i32,
+    reason: String,
+}
+
+/// Outcome of a rule-based assignment.
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct AssignmentResult {
+    /// Seats assigned by this request.
+    assigned: Vec<Assignment>,
+    /// Users matching the rule that already had a seat.
+    already_assigned: Vec<Assignment>,
+    /// Users matching the rule that could not be assigned a seat.
+    failed: Vec<FailedAssignment>,
+}
+
+impl AssignmentResult {
+    pub fn failed(&self) -> &[FailedAssignment] {
+        &self.failed
+    }
+
+    /// Assigns a seat on the license to the user, recording the outcome.
+    /// A seat rejected by a trigger is recorded as failed, any other error is returned.
+    async fn assign(
+        &mut self,
+        pool: &Pool<Postgres>,
+        license_id: i32,
+        user_id: i32,
+    ) -> Result<(), sqlx::Error> {
+        let insert = query!(
+            r#"INSERT INTO user_license (license_id, user_id)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING"#,
+            license_id,
+            user_id
+        )
+        .execute(pool)
+        .await;
+        match insert {
+            Ok(inserted) if inserted.rows_affected() == 0 => {
+                self.already_assigned.push(Assignment {
+                    license_id,
+                    user_id,
+                });
+                Ok(())
+            }
+            Ok(_) => {
+                self.assigned.push(Assignment {
+                    license_id,
+                    user_id,
+                });
+                Ok(())
+            }
+            // raise_exception, raised by the seat and company triggers on user_license
+            Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("P0001") => {
+                self.failed.push(FailedAssignment {
+                    license_id,
+                    user_id,
+                    reason: e.message().to_string(),
+                });
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Assigns seats on a license to all users of the company matching the rule.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `license_id` - Id of the license
+/// * `company_id` - Id of the company owning the license
+/// * `rule` - Which users of the company to assign
+pub async fn assign_by_rule(
+    pool: &Pool<Postgres>,
+    license_id: i32,
+    company_id: i32,
+    rule: &AssignmentRule,
+) -> Result<AssignmentResult, sqlx::Error> {
+    let users = user::get_users_by_company(pool, &company_id).await?;
+    // the seat trigger runs before the conflict check, so users with a seat on a full license
+    // would be rejected instead of skipped when inserting them again
+    let assigned_users: HashSet<i32> = user::get_users_by_license(pool, &license_id)
+        .await?
+        .iter()
+        .map(|user| user.user_id)
+        .collect();
+
+    let mut result = AssignmentResult::default();
+    for user in users.iter().filter(|user| match rule {
+        AssignmentRule::AllCompanyUsers => true,
+        AssignmentRule::Role { role } => user.role == *role,
+    }) {
+        if assigned_users.contains(&user.user_id) {
+            result.already_assigned.push(Assignment {
+                license_id,
+                user_id: user.user_id,
+            });
+        } else {
+            result.assign(pool, license_id, user.user_id).await?;
+        }
+    }
+    Ok(result)
+}
+
+/// Returns true if company users are automatically assigned to the license when they register.
+pub async fn get_auto_assign(pool: &Pool<Postgres>, license_id: &i32) -> Result<bool, sqlx::Error> {
+    let policy = query!(
+        r#"SELECT license_id FROM license_auto_assign WHERE license_id = $1"#,
+        license_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(policy.is_some())
+}
+
+/// Sets whether company users are automatically assigned to the license when they register.
+pub async fn set_auto_assign(
+    pool: &Pool<Postgres>,
+    license_id: &i32,
+    auto_assign: bool,
+) -> Result<(), sqlx::Error> {
+    if auto_assign {
+        query!(
+            r#"INSERT INTO license_auto_assign (license_id)
+            VALUES ($1)
+            ON CONFLICT DO NOTHING"#,
+            license_id
+        )
+        .execute(pool)
+        .await?;
+    } else {
+        query!(
+            r#"DELETE FROM license_auto_assign WHERE license_id = $1"#,
+            license_id
+        )
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Assigns a newly registered user to all active named licenses of their company
+/// that are set to auto-assign.
+pub async fn auto_assign_user(
+    pool: &Pool<Postgres>,
+    user: &User,
+) -> Result<AssignmentResult, sqlx::Error> {
+    let licenses = query!(
+        r#"SELECT license_id
+        FROM license
+        JOIN license_auto_assign USING (license_id)
+        WHERE company_id = $1
+        AND license_type = 'named'
+        AND valid = true
+        AND start_date <= now()
+        AND end_date > now()
+        ORDER BY license_id"#,
+        user.company_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut result = AssignmentResult::default();
+    for license in licenses {
+        result
+            .assign(pool, license.license_id, user.user_id)
+            .await?;
+    }
+    Ok(result)
+}
@@ -0,0 +1,193 @@
+//! Data access for seat checkouts on floating (concurrent-use) licenses.
+//!
+//! A checkout is held by a user for as long as they keep sending heartbeats.
+//! Checkouts whose last heartbeat is older than the checkout timeout are stale,
+//! they are removed by the reaper job and do not count towards the license's `amount`.
+//! The database trigger `enforce_license_max_checkouts` makes sure `amount` is never exceeded,
+//! even when several users try to check out the last seat at the same time.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Pool, Postgres};
+use utoipa::ToSchema;
+
+/// A seat checked out by a user on a floating license.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LicenseCheckout {
+    license_id: i32,
+    user_id: i32,
+    checked_out_at: DateTime<Utc>,
+    last_heartbeat: DateTime<Utc>,
+}
+
+/// Checks out a seat on a license for a user.
+/// If the user already holds a seat, its heartbeat is refreshed instead.
+///
+/// Stale checkouts of the license are removed first, so they do not block the seat.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `license_id` - ID of the floating license
+/// * `user_id` - ID of the user checking out a seat
+/// * `timeout` - How long a checkout is kept without a heartbeat
+///
+/// # Errors
+/// Database error with code `P0001` (`RaiseException`) if all seats are taken,
+/// or if the license is not owned by the user's company.
+pub async fn checkout_seat(
+    pool: &Pool<Postgres>,
+    license_id: &i32,
+    user_id: &i32,
+    timeout: Duration,
+) -> Result<LicenseCheckout, sqlx::Error> {
+    let now = Utc::now();
+    let mut transaction = pool.begin().await?;
+
+    // Take the lock the trigger would take up front, so concurrent checkouts queue up here
+    // instead of deadlocking when upgrading from the row locks taken below.
+    query!(r#"LOCK TABLE license_checkout IN EXCLUSIVE MODE"#)
+        .execute(&mut transaction)
+        .await?;
+
+    query!(
+        r#"DELETE FROM license_checkout
+        WHERE license_id = $1 AND last_heartbeat < $2"#,
+        license_id,
+        now - timeout
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    let existing = query_as!(
+        LicenseCheckout,
+        r#"UPDATE license_checkout
+        SET last_heartbeat = $3
+        WHERE license_id = $1 AND user_id = $2
+        RETURNING license_id, user_id, checked_out_at, last_heartbeat"#,
+        license_id,
+        user_id,
+        now
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    let checkout = match existing {
+        Some(checkout) => checkout,
+        None => query_as!(
+            LicenseCheckout,
+            r#"INSERT INTO license_checkout (license_id, user_id, checked_out_at, last_heartbeat)
+                VALUES ($1, $2, $3, $3)
+                RETURNING license_id, user_id, checked_out_at, last_heartbeat"#,
+            license_id,
+            user_id,
+            now
+        )
+        .fetch_one(&mut transaction)
+        .await?,
+    };
+    transaction.commit().await?;
+
+    Ok(checkout)
+}
+
+/// Keeps a checkout alive by updating its heartbeat.
+///
+/// # Errors
+/// `sqlx::Error::RowNotFound` if the user holds no seat on the license,
+/// or if the checkout has already timed out. The user has to check out a seat again.
+pub async fn heartbeat(
+    pool: &Pool<Postgres>,
+    license_id: &i32,
+    user_id: &i32,
+    timeout: Duration,
+) -> Result<LicenseCheckout, sqlx::Error> {
+    let now = Utc::now();
+    query_as!(
+        LicenseCheckout,
+        r#"UPDATE license_checkout
+        SET last_heartbeat = $3
+        WHERE license_id = $1 AND user_id = $2 AND last_heartbeat >= $4
+        RETURNING license_id, user_id, checked_out_at, last_heartbeat"#,
+        license_id,
+        user_id,
+        now,
+        now - timeout
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Releases a user's seat on a license.
+/// Returns true if a seat was released, false if the user held none.
+pub async fn release_seat(
+    pool: &Pool<Postgres>,
+    license_id: &i32,
+    user_id: &i32,
+) -> Result<bool, sqlx::Error> {
+    let delete = query!(
+        r#"DELETE FROM license_checkout
+        WHERE license_id = $1 AND user_id = $2"#,
+        license_id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(delete.rows_affected() > 0)
+}
+
+/// Returns all checkouts of a license that have not timed out.
+pub async fn get_active_checkouts(
+    pool: &Pool<Postgres>,
+    license_id: &i32,
+    timeout: Duration,
+) -> Result<Vec<LicenseCheckout>, sqlx::Error> {
+    query_as!(
+        LicenseCheckout,
+        r#"SELECT license_id, user_id, checked_out_at, last_heartbeat
+        FROM license_checkout
+        WHERE license_id = $1 AND last_heartbeat >= $2
+        ORDER BY checked_out_at ASC"#,
+        license_id,
+        Utc::now() - timeout
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns true if a user holds a checkout on a license that has not timed out.
+pub async fn holds_checkout(
+    pool: &Pool<Postgres>,
+    license_id: &i32,
+    user_id: &i32,
+    timeout: Duration,
+) -> Result<bool, sqlx::Error> {
+    let holds = query!(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM license_checkout
+            WHERE license_id = $1 AND user_id = $2 AND last_heartbeat >= $3
+        ) as "holds!""#,
+        license_id,
+        user_id,
+        Utc::now() - timeout
+    )
+    .fetch_one(pool)
+    .await?
+    .holds;
+    Ok(holds)
+}
+
+/// Removes all checkouts that have not received a heartbeat within the timeout.
+/// Returns the number of checkouts removed.
+pub async fn remove_stale_checkouts(
+    pool: &Pool<Postgres>,
+    timeout: Duration,
+) -> Result<u64, sqlx::Error> {
+    let delete = query!(
+        r#"DELETE FROM license_checkout
+        WHERE last_heartbeat < $1"#,
+        Utc::now() - timeout
+    )
+    .execute(pool)
+    .await?;
+    Ok(delete.rows_affected())
+}
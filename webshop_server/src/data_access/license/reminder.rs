@@ -0,0 +1,81 @@
+//! Data access for license expiry reminders.
+//!
+//! Reminders are sent at a number of days before a license's `end_date`.
+//! Each sent reminder is recorded in `license_reminder` with the end date it was sent for,
+//! so it is sent at most once per end date, and again once a license is renewed.
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{query, query_as, Pool, Postgres};
+
+/// Paid license that is about to expire, with its seat usage.
+#[derive(Debug)]
+pub struct ExpiringLicense {
+    pub license_id: i32,
+    pub company_id: i32,
+    pub display_name: String,
+    pub end_date: DateTime<Utc>,
+    pub amount: i32,
+    /// Assigned users for named licenses, checked out seats for floating licenses.
+    pub used_seats: i64,
+}
+
+/// Returns valid licenses ending within `days_before` days that are due a reminder.
+///
+/// A license is due a reminder if no reminder has been sent for its current end date
+/// at this or a smaller offset.
+/// Handling offsets from the smallest to the largest therefore sends a single reminder
+/// for a license that is already within several offsets, e.g. one bought 5 days before its end.
+/// Trial licenses are not included, they have their own reminder.
+pub async fn get_licenses_due_reminder(
+    pool: &Pool<Postgres>,
+    days_before: i32,
+) -> Result<Vec<ExpiringLicense>, sqlx::Error> {
+    query_as!(
+        ExpiringLicense,
+        r#"SELECT license_id, company_id, display_name, end_date, amount,
+        CASE WHEN license_type = 'floating'
+            THEN (SELECT count(*) FROM license_checkout WHERE license_checkout.license_id = license.license_id)
+            ELSE (SELECT count(*) FROM user_license WHERE user_license.license_id = license.license_id)
+        END AS "used_seats!"
+        FROM license
+        JOIN product USING (product_id)
+        WHERE valid = true
+        AND trial = false
+        AND end_date > now()
+        AND end_date <= $1
+        AND NOT EXISTS (
+            SELECT 1 FROM license_reminder
+            WHERE license_reminder.license_id = license.license_id
+            AND license_reminder.end_date = license.end_date
+            AND license_reminder.days_before <= $2
+        )
+        ORDER BY company_id, end_date"#,
+        Utc::now() + Duration::days(days_before as i64),
+        days_before
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Records that the reminder at `days_before` days has been sent for the licenses,
+/// for the end dates they were sent for.
+pub async fn set_reminders_sent(
+    pool: &Pool<Postgres>,
+    licenses: &[ExpiringLicense],
+    days_before: i32,
+) -> Result<(), sqlx::Error> {
+    let license_ids: Vec<i32> = licenses.iter().map(|license| license.license_id).collect();
+    let end_dates: Vec<DateTime<Utc>> = licenses.iter().map(|license| license.end_date).collect();
+    query!(
+        r#"INSERT INTO license_reminder (license_id, end_date, days_before)
+        SELECT license_id, end_date, $3
+        FROM UNNEST($1::int[], $2::timestamptz[]) AS sent(license_id, end_date)
+        ON CONFLICT DO NOTHING"#,
+        &license_ids,
+        &end_dates,
+        days_before
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,419 @@
+//! Data access for subscriptions, licenses renewed automatically every billing period.
+//!
+//! A company subscribes a paid license to a monthly or yearly plan. Shortly before the end
+//! of the period paid for, a renewal order for the next period is created and charged
+//! to the payment method saved when the company last paid a renewal itself.
+//! A subscription without a saved payment method is not charged, its renewal is paid
+//! by the company.
+//! Once paid, the license and the subscription are extended to the end of that period.
+//! If the period ends before the renewal is paid, the subscription is past due and the license
+//! stays usable until the end of a grace period, after which it is invalidated.
+//! A subscription canceled at period end is not renewed, and its license simply ends.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Pool, Postgres, Transaction};
+use utoipa::ToSchema;
+
+use crate::data_access::order::OrderStatus;
+
+/// How often a subscription is renewed.
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[sqlx(type_name = "billing_interval_enum", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum BillingInterval {
+    Monthly,
+    Yearly,
+}
+
+impl BillingInterval {
+    /// Length of a billing period in months.
+    pub fn months(&self) -> u32 {
+        match self {
+            BillingInterval::Monthly => 1,
+            BillingInterval::Yearly => 12,
+        }
+    }
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[sqlx(type_name = "subscription_status_enum", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    /// Paid until the end of the current period.
+    Active,
+    /// The renewal was not paid by the end of the period, the license is in its grace period.
+    PastDue,
+    /// No longer renewed.
+    Canceled,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Subscription {
+    pub subscription_id: i32,
+    pub license_id: i32,
+    pub company_id: i32,
+    pub billing_interval: BillingInterval,
+    pub status: SubscriptionStatus,
+    /// End of the period paid for.
+    pub current_period_end: DateTime<Utc>,
+    /// Whether the subscription ends at the end of the current period instead of renewing.
+    pub cancel_at_period_end: bool,
+    /// Order renewing the subscription for the next period, until it is paid.
+    pub renewal_order_id: Option<i32>,
+    /// When the license is invalidated if the renewal is still not paid, while past due.
+    pub grace_until: Option<DateTime<Utc>>,
+    /// The user who subscribed, none if the user has been deleted.
+    pub created_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub canceled_at: Option<DateTime<Utc>>,
+    /// Payment method saved at the payment provider that renewals are charged to,
+    /// none until the company has paid a renewal itself.
+    pub payment_method_id: Option<String>,
+}
+
+/// Subscribes a license, renewing it from its end date.
+pub async fn create_subscription(
+    pool: &Pool<Postgres>,
+    license_id: &i32,
+    billing_interval: BillingInterval,
+    created_by: &i32,
+) -> Result<Subscription, sqlx::Error> {
+    query_as!(
+        Subscription,
+        r#"INSERT INTO subscription (license_id, company_id, billing_interval, current_period_end,
+        created_by)
+        SELECT license_id, company_id, $2, end_date, $3
+        FROM license
+        WHERE license_id = $1
+        RETURNING subscription_id, license_id, company_id,
+        billing_interval as "billing_interval: _", status as "status: _", current_period_end,
+        cancel_at_period_end, renewal_order_id, grace_until, created_by, created_at,
+        canceled_at, payment_method_id"#,
+        license_id,
+        billing_interval as _,
+        created_by
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Returns all subscriptions, newest first.
+pub async fn get_subscriptions(pool: &Pool<Postgres>) -> Result<Vec<Subscription>, sqlx::Error> {
+    query_as!(
+        Subscription,
+        r#"SELECT subscription_id, license_id, company_id,
+        billing_interval as "billing_interval: _", status as "status: _", current_period_end,
+        cancel_at_period_end, renewal_order_id, grace_until, created_by, created_at,
+        canceled_at, payment_method_id
+        FROM subscription
+        ORDER BY subscription_id DESC"#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns the subscriptions of a company, newest first.
+pub async fn get_subscriptions_by_company(
+    pool: &Pool<Postgres>,
+    company_id: &i32,
+) -> Result<Vec<Subscription>, sqlx::Error> {
+    query_as!(
+        Subscription,
+        r#"SELECT subscription_id, license_id, company_id,
+        billing_interval as "billing_interval: _", status as "status: _", current_period_end,
+        cancel_at_period_end, renewal_order_id, grace_until, created_by, created_at,
+        canceled_at, payment_method_id
+        FROM subscription
+        WHERE company_id = $1
+        ORDER BY subscription_id DESC"#,
+        company_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns a subscription.
+pub async fn get_subscription_by_id(
+    pool: &Pool<Postgres>,
+    subscription_id: &i32,
+) -> Result<Subscription, sqlx::Error> {
+    query_as!(
+        Subscription,
+        r#"SELECT subscription_id, license_id, company_id,
+        billing_interval as "billing_interval: _", status as "status: _", current_period_end,
+        cancel_at_period_end, renewal_order_id, grace_until, created_by, created_at,
+        canceled_at, payment_method_id
+        FROM subscription
+        WHERE subscription_id = $1"#,
+        subscription_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Returns a subscription and locks it until the end of the transaction.
+pub async fn lock_subscription(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_id: &i32,
+) -> Result<Subscription, sqlx::Error> {
+    query_as!(
+        Subscription,
+        r#"SELECT subscription_id, license_id, company_id,
+        billing_interval as "billing_interval: _", status as "status: _", current_period_end,
+        cancel_at_period_end, renewal_order_id, grace_until, created_by, created_at,
+        canceled_at, payment_method_id
+        FROM subscription
+        WHERE subscription_id = $1
+        FOR UPDATE"#,
+        subscription_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+}
+
+/// Records the order renewing a subscription, until it is paid.
+pub async fn set_renewal_order(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_id: &i32,
+    order_id: &i32,
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"UPDATE subscription SET renewal_order_id = $2 WHERE subscription_id = $1"#,
+        subscription_id,
+        order_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+/// Saves the payment method a renewal order of a subscription was paid with,
+/// later renewals are charged to it.
+pub async fn set_payment_method(
+    transaction: &mut Transaction<'_, Postgres>,
+    order_id: &i32,
+    payment_method_id: &str,
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"UPDATE subscription SET payment_method_id = $2
+        FROM order_line
+        WHERE order_line.license_id = subscription.license_id AND order_line.order_id = $1"#,
+        order_id,
+        payment_method_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+/// Marks the pending renewal order of a subscription as failed, if it has one,
+/// so it can no longer be paid.
+async fn fail_renewal_order(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription: &Subscription,
+) -> Result<(), sqlx::Error> {
+    if let Some(order_id) = subscription.renewal_order_id {
+        query!(
+            r#"UPDATE "order" SET status = $1 WHERE order_id = $2 AND status = $3"#,
+            OrderStatus::Failed as _,
+            order_id,
+            OrderStatus::Pending as _
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Cancels a subscription.
+///
+/// An active subscription ends at the end of the period paid for.
+/// A past due subscription ends now: its license ends at the end of the period paid for
+/// instead of the grace period, and its renewal order can no longer be paid.
+///
+/// # Returns
+/// The canceled subscription, none if it was already canceled.
+pub async fn cancel(
+    pool: &Pool<Postgres>,
+    subscription_id: &i32,
+) -> Result<Option<Subscription>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let subscription = lock_subscription(&mut transaction, subscription_id).await?;
+    match subscription.status {
+        SubscriptionStatus::Active => {
+            query!(
+                r#"UPDATE subscription SET cancel_at_period_end = true
+                WHERE subscription_id = $1"#,
+                subscription_id
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        SubscriptionStatus::PastDue => {
+            query!(
+                r#"UPDATE subscription
+                SET status = $2, canceled_at = now(), grace_until = NULL
+                WHERE subscription_id = $1"#,
+                subscription_id,
+                SubscriptionStatus::Canceled as _
+            )
+            .execute(&mut transaction)
+            .await?;
+            query!(
+                r#"UPDATE license SET end_date = $2 WHERE license_id = $1"#,
+                subscription.license_id,
+                subscription.current_period_end
+            )
+            .execute(&mut transaction)
+            .await?;
+            fail_renewal_order(&mut transaction, &subscription).await?;
+        }
+        SubscriptionStatus::Canceled => return Ok(None),
+    }
+    let subscription = lock_subscription(&mut transaction, subscription_id).await?;
+    transaction.commit().await?;
+    Ok(Some(subscription))
+}
+
+/// Keeps renewing an active subscription that was canceled at period end.
+///
+/// # Returns
+/// The subscription, none if it is not active or not canceled.
+pub async fn resume(
+    pool: &Pool<Postgres>,
+    subscription_id: &i32,
+) -> Result<Option<Subscription>, sqlx::Error> {
+    query_as!(
+        Subscription,
+        r#"UPDATE subscription SET cancel_at_period_end = false
+        WHERE subscription_id = $1 AND status = $2 AND cancel_at_period_end
+        RETURNING subscription_id, license_id, company_id,
+        billing_interval as "billing_interval: _", status as "status: _", current_period_end,
+        cancel_at_period_end, renewal_order_id, grace_until, created_by, created_at,
+        canceled_at, payment_method_id"#,
+        subscription_id,
+        SubscriptionStatus::Active as _
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Returns the active subscriptions whose period ends before the given time,
+/// that have no renewal order yet, are not canceled at period end
+/// and have a saved payment method to charge.
+pub async fn get_subscriptions_due_renewal(
+    pool: &Pool<Postgres>,
+    before: DateTime<Utc>,
+) -> Result<Vec<Subscription>, sqlx::Error> {
+    query_as!(
+        Subscription,
+        r#"SELECT subscription_id, license_id, company_id,
+        billing_interval as "billing_interval: _", status as "status: _", current_period_end,
+        cancel_at_period_end, renewal_order_id, grace_until, created_by, created_at,
+        canceled_at, payment_method_id
+        FROM subscription
+        WHERE status = $1 AND NOT cancel_at_period_end AND renewal_order_id IS NULL
+        AND payment_method_id IS NOT NULL AND current_period_end <= $2
+        ORDER BY current_period_end"#,
+        SubscriptionStatus::Active as _,
+        before
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Ends the subscriptions canceled at period end whose period is over.
+///
+/// # Returns
+/// The ended subscriptions.
+pub async fn end_canceled(pool: &Pool<Postgres>) -> Result<Vec<Subscription>, sqlx::Error> {
+    query_as!(
+        Subscription,
+        r#"UPDATE subscription SET status = $2, canceled_at = now()
+        WHERE status = $1 AND cancel_at_period_end AND current_period_end <= now()
+        RETURNING subscription_id, license_id, company_id,
+        billing_interval as "billing_interval: _", status as "status: _", current_period_end,
+        cancel_at_period_end, renewal_order_id, grace_until, created_by, created_at,
+        canceled_at, payment_method_id"#,
+        SubscriptionStatus::Active as _,
+        SubscriptionStatus::Canceled as _
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Marks the active subscriptions whose period is over without the renewal being paid
+/// as past due, and keeps their licenses usable for the grace period.
+///
+/// # Returns
+/// The past due subscriptions.
+pub async fn start_grace_periods(
+    pool: &Pool<Postgres>,
+    grace_days: i32,
+) -> Result<Vec<Subscription>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let subscriptions = query_as!(
+        Subscription,
+        r#"UPDATE subscription
+        SET status = $2, grace_until = current_period_end + make_interval(days => $3)
+        WHERE status = $1 AND NOT cancel_at_period_end AND current_period_end <= now()
+        RETURNING subscription_id, license_id, company_id,
+        billing_interval as "billing_interval: _", status as "status: _", current_period_end,
+        cancel_at_period_end, renewal_order_id, grace_until, created_by, created_at,
+        canceled_at, payment_method_id"#,
+        SubscriptionStatus::Active as _,
+        SubscriptionStatus::PastDue as _,
+        grace_days
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    let subscription_ids: Vec<i32> = subscriptions
+        .iter()
+        .map(|subscription| subscription.subscription_id)
+        .collect();
+    query!(
+        r#"UPDATE license SET end_date = subscription.grace_until
+        FROM subscription
+        WHERE subscription.license_id = license.license_id
+        AND subscription.subscription_id = ANY($1)"#,
+        &subscription_ids
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(subscriptions)
+}
+
+/// Cancels the past due subscriptions whose grace period is over, and invalidates
+/// their licenses. Their renewal orders can no longer be paid.
+///
+/// # Returns
+/// The canceled subscriptions.
+pub async fn end_grace_periods(pool: &Pool<Postgres>) -> Result<Vec<Subscription>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let subscriptions = query_as!(
+        Subscription,
+        r#"UPDATE subscription SET status = $2, canceled_at = now()
+        WHERE status = $1 AND grace_until <= now()
+        RETURNING subscription_id, license_id, company_id,
+        billing_interval as "billing_interval: _", status as "status: _", current_period_end,
+        cancel_at_period_end, renewal_order_id, grace_until, created_by, created_at,
+        canceled_at, payment_method_id"#,
+        SubscriptionStatus::PastDue as _,
+        SubscriptionStatus::Canceled as _
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    for subscription in subscriptions.iter() {
+        query!(
+            r#"UPDATE license SET valid = false WHERE license_id = $1"#,
+            subscription.license_id
+        )
+        .execute(&mut transaction)
+        .await?;
+        fail_renewal_order(&mut transaction, subscription).await?;
+    }
+    transaction.commit().await?;
+    Ok(subscriptions)
+}
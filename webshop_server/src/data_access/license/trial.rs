@@ -0,0 +1,243 @@
+//! Data access for trial licenses.
+//!
+//! A product can offer a trial, configured by admins with a duration and a number of seats.
+//! A company can start the trial of a product once, which creates a license flagged as trial.
+//! The trial license can later be converted into a paid license, keeping its seat assignments,
+//! by paying the order converting it, see [`crate::data_access::order::convert_trial`].
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Pool, Postgres};
+use utoipa::ToSchema;
+
+use super::{License, LicenseType};
+
+/// Trial offered for a product.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ProductTrial {
+    product_id: String,
+    duration_days: i32,
+    seats: i32,
+}
+
+/// Trial started by a company.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CompanyTrial {
+    company_id: i32,
+    product_id: String,
+    license_id: i32,
+    started_at: DateTime<Utc>,
+    converted_at: Option<DateTime<Utc>>,
+    reminder_sent: bool,
+}
+
+/// Trial license that is about to expire, with what is needed to remind the company about it.
+#[derive(Debug)]
+pub struct ExpiringTrial {
+    pub license_id: i32,
+    pub company_id: i32,
+    pub display_name: String,
+    pub end_date: DateTime<Utc>,
+}
+
+/// Errors that can occur when starting a trial.
+#[derive(Debug)]
+pub enum TrialError {
+    /// Wraps a sqlx error.
+    SqlxError(sqlx::Error),
+    /// The product does not offer a trial, or is not available.
+    NotOffered,
+    /// The company has already started a trial of this product.
+    AlreadyStarted,
+}
+
+impl From<sqlx::Error> for TrialError {
+    fn from(e: sqlx::Error) -> Self {
+        TrialError::SqlxError(e)
+    }
+}
+
+/// Returns the trial offered for a product.
+///
+/// # Errors
+/// `sqlx::Error::RowNotFound` if the product offers no trial.
+pub async fn get_product_trial(
+    pool: &Pool<Postgres>,
+    product_id: &str,
+) -> Result<ProductTrial, sqlx::Error> {
+    query_as!(
+        ProductTrial,
+        r#"SELECT product_id, duration_days, seats
+        FROM product_trial
+        WHERE product_id = $1"#,
+        product_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Creates or replaces the trial offered for a product.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `product_id` - Id of the product
+/// * `duration_days` - How many days a trial lasts
+/// * `seats` - How many seats a trial license has
+pub async fn set_product_trial(
+    pool: &Pool<Postgres>,
+    product_id: &str,
+    duration_days: i32,
+    seats: i32,
+) -> Result<ProductTrial, sqlx::Error> {
+    query_as!(
+        ProductTrial,
+        r#"INSERT INTO product_trial (product_id, duration_days, seats)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (product_id)
+        DO UPDATE SET duration_days = EXCLUDED.duration_days, seats = EXCLUDED.seats
+        RETURNING product_id, duration_days, seats"#,
+        product_id,
+        duration_days,
+        seats
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Stops offering a trial for a product. Trials already started are not affected.
+/// Returns true if the product offered a trial, false otherwise.
+pub async fn delete_product_trial(
+    pool: &Pool<Postgres>,
+    product_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let delete = query!(
+        r#"DELETE FROM product_trial WHERE product_id = $1"#,
+        product_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(delete.rows_affected() > 0)
+}
+
+/// Returns all trials started by a company.
+pub async fn get_company_trials(
+    pool: &Pool<Postgres>,
+    company_id: &i32,
+) -> Result<Vec<CompanyTrial>, sqlx::Error> {
+    query_as!(
+        CompanyTrial,
+        r#"SELECT company_id, product_id, license_id, started_at, converted_at, reminder_sent
+        FROM company_trial
+        WHERE company_id = $1
+        ORDER BY started_at DESC"#,
+        company_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Starts the trial of a product for a company.
+/// Creates a valid trial license, starting now and lasting for the configured duration.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `company_id` - Id of the company starting the trial
+/// * `product_id` - Id of the product to try
+///
+/// # Returns
+/// The created trial license.
+pub async fn start_trial(
+    pool: &Pool<Postgres>,
+    company_id: &i32,
+    product_id: &str,
+) -> Result<License, TrialError> {
+    let mut transaction = pool.begin().await?;
+
+    let trial = query_as!(
+        ProductTrial,
+        r#"SELECT product_id, duration_days, seats
+        FROM product_trial
+        JOIN product USING (product_id)
+        WHERE product_id = $1 AND available = true"#,
+        product_id
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    let trial = match trial {
+        Some(trial) => trial,
+        None => return Err(TrialError::NotOffered),
+    };
+
+    let start_date = Utc::now();
+    let end_date = start_date + Duration::days(trial.duration_days as i64);
+    let license = query_as!(
+        License,
+        r#"INSERT INTO license
+        (valid, start_date, end_date, amount, company_id, product_id, license_type, trial)
+        VALUES (true, $1, $2, $3, $4, $5, $6, true)
+        RETURNING license_id, valid, start_date, end_date, amount, company_id, product_id,
+        license_type as "license_type: _", trial"#,
+        start_date,
+        end_date,
+        trial.seats,
+        company_id,
+        product_id,
+        LicenseType::Named as _,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    let inserted = query!(
+        r#"INSERT INTO company_trial (company_id, product_id, license_id, started_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (company_id, product_id) DO NOTHING"#,
+        company_id,
+        product_id,
+        license.license_id(),
+        start_date
+    )
+    .execute(&mut transaction)
+    .await?;
+    if inserted.rows_affected() == 0 {
+        // dropping the transaction rolls back the license created above
+        return Err(TrialError::AlreadyStarted);
+    }
+    transaction.commit().await?;
+
+    Ok(license)
+}
+
+/// Returns trial licenses ending before the given date that have not been reminded about yet.
+/// Trials that have already expired or been converted are not included.
+pub async fn get_trials_expiring_before(
+    pool: &Pool<Postgres>,
+    before: DateTime<Utc>,
+) -> Result<Vec<ExpiringTrial>, sqlx::Error> {
+    query_as!(
+        ExpiringTrial,
+        r#"SELECT license_id, license.company_id, display_name, end_date
+        FROM company_trial
+        JOIN license USING (license_id)
+        JOIN product ON product.product_id = license.product_id
+        WHERE license.trial = true
+        AND reminder_sent = false
+        AND end_date > now()
+        AND end_date <= $1"#,
+        before
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Marks that the expiry reminder for a trial license has been sent.
+pub async fn set_reminder_sent(pool: &Pool<Postgres>, license_id: &i32) -> Result<(), sqlx::Error> {
+    query!(
+        r#"UPDATE company_trial
+        SET reminder_sent = true
+        WHERE license_id = $1"#,
+        license_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,182 @@
+//! Data access for seat usage history of licenses.
+//!
+//! The usage snapshot job records, for every day a license is active, the number of seats in use:
+//! assigned users for named licenses, checked out seats for floating licenses.
+//! A snapshot is taken several times a day and keeps the peak, so usage between runs is not lost.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Pool, Postgres};
+use utoipa::ToSchema;
+
+/// Seats in use on a license on a day.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UsagePoint {
+    pub date: NaiveDate,
+    /// Peak number of seats in use that day.
+    pub seats_used: i32,
+    /// Number of seats of the license that day.
+    pub amount: i32,
+}
+
+/// Usage history of a license.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LicenseUsage {
+    pub license_id: i32,
+    pub product_id: String,
+    pub display_name: String,
+    pub start_date: DateTime<Utc>,
+    pub end_date: DateTime<Utc>,
+    /// One point per day with a snapshot, oldest first.
+    pub usage: Vec<UsagePoint>,
+}
+
+/// Usage history of all licenses of a company.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CompanyUsage {
+    pub company_id: i32,
+    pub licenses: Vec<LicenseUsage>,
+    /// Seats in use and seats summed over all licenses, per day, oldest first.
+    pub total: Vec<UsagePoint>,
+}
+
+/// Snapshot row joined with its license, as returned by the company report query.
+struct UsageRow {
+    license_id: i32,
+    product_id: String,
+    display_name: String,
+    start_date: DateTime<Utc>,
+    end_date: DateTime<Utc>,
+    date: NaiveDate,
+    seats_used: i32,
+    amount: i32,
+}
+
+/// Records the seats in use today on every active license.
+/// If today already has a snapshot, the higher number of seats in use is kept.
+///
+/// # Returns
+/// The number of licenses a snapshot was recorded for.
+pub async fn take_snapshot(pool: &Pool<Postgres>) -> Result<u64, sqlx::Error> {
+    let snapshot = query!(
+        r#"INSERT INTO license_usage_snapshot (license_id, snapshot_date, seats_used, amount)
+        SELECT license_id, (now() AT TIME ZONE 'UTC')::date,
+        CASE WHEN license_type = 'floating'
+            THEN (SELECT count(*) FROM license_checkout WHERE license_checkout.license_id = license.license_id)
+            ELSE (SELECT count(*) FROM user_license WHERE user_license.license_id = license.license_id)
+        END,
+        amount
+        FROM license
+        WHERE valid = true AND start_date <= now() AND end_date > now()
+        ON CONFLICT (license_id, snapshot_date) DO UPDATE
+        SET seats_used = GREATEST(license_usage_snapshot.seats_used, EXCLUDED.seats_used),
+        amount = EXCLUDED.amount"#
+    )
+    .execute(pool)
+    .await?;
+    Ok(snapshot.rows_affected())
+}
+
+/// Returns the usage history of a license between two dates, both included.
+///
+/// # Errors
+/// `sqlx::Error::RowNotFound` if the license does not exist.
+pub async fn get_license_usage(
+    pool: &Pool<Postgres>,
+    license_id: &i32,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<LicenseUsage, sqlx::Error> {
+    let license = query!(
+        r#"SELECT license_id, product_id, display_name, start_date, end_date
+        FROM license
+        JOIN product USING (product_id)
+        WHERE license_id = $1"#,
+        license_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let usage = query_as!(
+        UsagePoint,
+        r#"SELECT snapshot_date AS date, seats_used, amount
+        FROM license_usage_snapshot
+        WHERE license_id = $1 AND snapshot_date BETWEEN $2 AND $3
+        ORDER BY snapshot_date"#,
+        license_id,
+        from,
+        to
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(LicenseUsage {
+        license_id: license.license_id,
+        product_id: license.product_id,
+        display_name: license.display_name,
+        start_date: license.start_date,
+        end_date: license.end_date,
+        usage,
+    })
+}
+
+/// Returns the usage history of all licenses of a company between two dates, both included.
+/// Licenses without snapshots in the period are left out.
+pub async fn get_company_usage(
+    pool: &Pool<Postgres>,
+    company_id: &i32,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<CompanyUsage, sqlx::Error> {
+    let rows = query_as!(
+        UsageRow,
+        r#"SELECT license_id, product_id, display_name, start_date, end_date,
+        snapshot_date AS date, seats_used, license_usage_snapshot.amount
+        FROM license_usage_snapshot
+        JOIN license USING (license_id)
+        JOIN product USING (product_id)
+        WHERE company_id = $1 AND snapshot_date BETWEEN $2 AND $3
+        ORDER BY license_id, snapshot_date"#,
+        company_id,
+        from,
+        to
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut licenses: Vec<LicenseUsage> = Vec::new();
+    let mut total: BTreeMap<NaiveDate, UsagePoint> = BTreeMap::new();
+    for row in rows {
+        let day = total.entry(row.date).or_insert(UsagePoint {
+            date: row.date,
+            seats_used: 0,
+            amount: 0,
+        });
+        day.seats_used += row.seats_used;
+        day.amount += row.amount;
+
+        let point = UsagePoint {
+            date: row.date,
+            seats_used: row.seats_used,
+            amount: row.amount,
+        };
+        match licenses.last_mut() {
+            Some(license) if license.license_id == row.license_id => license.usage.push(point),
+            _ => licenses.push(LicenseUsage {
+                license_id: row.license_id,
+                product_id: row.product_id,
+                display_name: row.display_name,
+                start_date: row.start_date,
+                end_date: row.end_date,
+                usage: vec![point],
+            }),
+        }
+    }
+    Ok(CompanyUsage {
+        company_id: *company_id,
+        licenses,
+        total: total.into_values().collect(),
+    })
+}
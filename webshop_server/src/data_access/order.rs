@@ -0,0 +1,1339 @@
+//! Data access for orders, the record of what a company paid for its licenses.
+//!
+//! Every license purchase, renewal and seat change creates an order, and a purchase can buy
+//! several licenses at once. An order is applied to its licenses once it is paid:
+//! a bought license becomes valid, a renewal extends the license and
+//! a seat change changes its seats. An early renewal starts a new term from when it is paid,
+//! crediting the days that were left. Orders that cost nothing are paid, and applied, right away.
+//! Orders that credit the company are applied right away too, and paid once the credit
+//! is paid back. A license has at most one seat change waiting for payment at a time.
+//! A subscription renewal extends the license and its subscription to the end of the period paid for.
+//! A trial conversion turns a trial license into a paid one, starting its term when it is paid.
+//! An order placed with a discount code uses the code up once, and gives the use back
+//! if its payment fails.
+//! Each order gets an invoice number of the form `YYYY-NNNNNN`,
+//! numbered sequentially within the year it was created.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Datelike, Duration, Months, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Pool, Postgres, Transaction};
+use utoipa::ToSchema;
+
+use crate::{
+    data_access::{
+        cart::{self, CartItem},
+        company, discount,
+        license::{
+            self,
+            subscription::{self, BillingInterval, Subscription, SubscriptionStatus},
+            License, LicenseType, PartialLicense,
+        },
+        purchase_request::{self, PurchaseRequest, PurchaseRequestStatus},
+        quote::{self, Quote},
+        refund,
+    },
+    money::{Currency, Money},
+    pricing::{self, AppliedDiscount, CartQuote, PeriodQuote, PriceQuote, ProrationQuote},
+    tax::{self, TaxTreatment},
+};
+
+/// What an order was made for.
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[sqlx(type_name = "order_type_enum", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum OrderType {
+    Purchase,
+    Renewal,
+    SeatChange,
+    SubscriptionRenewal,
+    EarlyRenewal,
+    TrialConversion,
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[sqlx(type_name = "order_status_enum", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    /// Waiting for payment, not applied to the licenses yet.
+    Pending,
+    /// Paid and applied to the licenses.
+    Paid,
+    /// The payment failed, the order will not be applied.
+    Failed,
+}
+
+/// A license an order was made for, and what it cost.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OrderLine {
+    pub license_id: i32,
+    pub product_id: String,
+    pub description: String,
+    /// Seats paid for, negative for removed seats.
+    pub seats: i32,
+    /// Price per user per year.
+    #[schema(value_type = String, example = "179.00")]
+    pub unit_price: Money,
+    /// Period the line pays for.
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    #[schema(value_type = String, example = "5370.00")]
+    pub amount: Money,
+    /// Seats of the license once a seat change is applied, none for other lines.
+    pub license_seats: Option<i32>,
+}
+
+/// A line of a paid order for a license, with what was paid for the order.
+#[derive(Debug)]
+pub struct PaidLine {
+    pub amount: Money,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    /// Subtotal of the order, after its discount.
+    pub order_subtotal: Money,
+    pub order_discount: Money,
+    /// Part of the subtotal of the order an admin has paid back.
+    pub order_refunded: Money,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Order {
+    pub order_id: i32,
+    pub invoice_number: String,
+    pub order_type: OrderType,
+    pub status: OrderStatus,
+    pub company_id: i32,
+    /// The user who placed the order, none if the user has been deleted.
+    pub user_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    /// Currency of the amounts and of all lines.
+    pub currency: Currency,
+    /// Discount code the order was placed with.
+    #[schema(example = "WELCOME10")]
+    pub discount_code: Option<String>,
+    /// Amount the discount code took off the sum of the lines.
+    #[schema(value_type = String, example = "537.00")]
+    pub discount: Money,
+    /// Sum of the lines less the discount, before tax.
+    #[schema(value_type = String, example = "4833.00")]
+    pub subtotal: Money,
+    /// Tax rate in basis points, 2500 is 25%.
+    #[schema(example = 2500)]
+    pub tax_rate: i32,
+    pub tax_treatment: TaxTreatment,
+    #[schema(value_type = String, example = "1342.50")]
+    pub tax: Money,
+    /// Subtotal and tax, what the company pays.
+    #[schema(value_type = String, example = "6712.50")]
+    pub total: Money,
+    /// Country the company was billed in, none if it had no billing address.
+    pub billing_country: Option<String>,
+    /// VAT number the company was billed with.
+    pub vat_number: Option<String>,
+    pub lines: Vec<OrderLine>,
+}
+
+/// Order without its lines, as stored in the `order` table.
+struct OrderRow {
+    order_id: i32,
+    invoice_number: String,
+    order_type: OrderType,
+    status: OrderStatus,
+    company_id: i32,
+    user_id: Option<i32>,
+    created_at: DateTime<Utc>,
+    currency: Currency,
+    discount_code: Option<String>,
+    discount: Money,
+    subtotal: Money,
+    tax_rate: i32,
+    tax_treatment: TaxTreatment,
+    tax: Money,
+    total: Money,
+    billing_country: Option<String>,
+    vat_number: Option<String>,
+}
+
+/// Order line with the order it belongs to.
+struct OrderLineRow {
+    order_id: i32,
+    license_id: i32,
+    product_id: String,
+    description: String,
+    seats: i32,
+    unit_price: Money,
+    period_start: DateTime<Utc>,
+    period_end: DateTime<Utc>,
+    amount: Money,
+    license_seats: Option<i32>,
+}
+
+#[derive(Debug)]
+pub enum OrderError {
+    SqlxError(sqlx::Error),
+    LicenseNotFound,
+    /// The license has been invalidated, or is a trial.
+    LicenseNotPurchasable,
+    /// The seats of the license changed after the price was computed.
+    LicenseChanged,
+    /// A seat change of the license is already waiting for payment.
+    SeatChangePending,
+    /// The license has too little left to refund to pay back the credit.
+    CreditNotRefundable,
+    /// More seats are in use than the license would have after the change.
+    TooFewSeats,
+    /// The discount code was used up, or deactivated, after the price was computed.
+    DiscountUsedUp,
+    /// The items of the cart changed after the price was computed.
+    CartChanged,
+    /// The purchase request has already been approved or rejected.
+    RequestNotPending,
+    /// The subscription is canceled, or its renewal is already waiting for payment.
+    SubscriptionNotRenewable,
+    /// The quote has not been sent, is no longer valid, or has been accepted or withdrawn.
+    QuoteNotAcceptable,
+    /// The license is not a valid trial, or its conversion is already waiting for payment.
+    TrialNotConvertible,
+}
+
+impl From<sqlx::Error> for OrderError {
+    fn from(e: sqlx::Error) -> Self {
+        OrderError::SqlxError(e)
+    }
+}
+
+impl std::fmt::Display for OrderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OrderError::SqlxError(e) => write!(f, "{}", e),
+            OrderError::LicenseNotFound => write!(f, "License not found"),
+            OrderError::LicenseNotPurchasable => {
+                write!(f, "Invalid and trial licenses cannot be renewed or changed")
+            }
+            OrderError::LicenseChanged => write!(f, "The license changed, please try again"),
+            OrderError::SeatChangePending => {
+                write!(f, "A seat change of the license is waiting for payment")
+            }
+            OrderError::CreditNotRefundable => write!(
+                f,
+                "The credit cannot be paid back, the license has too little left to refund"
+            ),
+            OrderError::TooFewSeats => write!(f, "More seats are in use than the new amount"),
+            OrderError::DiscountUsedUp => write!(f, "Discount code is no longer available"),
+            OrderError::CartChanged => write!(f, "The cart changed, please try again"),
+            OrderError::RequestNotPending => {
+                write!(f, "The purchase request has already been decided")
+            }
+            OrderError::SubscriptionNotRenewable => {
+                write!(
+                    f,
+                    "The subscription is canceled, or its renewal is being paid"
+                )
+            }
+            OrderError::QuoteNotAcceptable => {
+                write!(f, "The quote can no longer be accepted")
+            }
+            OrderError::TrialNotConvertible => {
+                write!(
+                    f,
+                    "The license is not a valid trial, or its conversion is being paid"
+                )
+            }
+        }
+    }
+}
+
+impl OrderRow {
+    fn with_lines(self, lines: Vec<OrderLine>) -> Order {
+        Order {
+            order_id: self.order_id,
+            invoice_number: self.invoice_number,
+            order_type: self.order_type,
+            status: self.status,
+            company_id: self.company_id,
+            user_id: self.user_id,
+            created_at: self.created_at,
+            currency: self.currency,
+            discount_code: self.discount_code,
+            discount: self.discount,
+            subtotal: self.subtotal,
+            tax_rate: self.tax_rate,
+            tax_treatment: self.tax_treatment,
+            tax: self.tax,
+            total: self.total,
+            billing_country: self.billing_country,
+            vat_number: self.vat_number,
+            lines,
+        }
+    }
+}
+
+impl From<OrderLineRow> for OrderLine {
+    fn from(row: OrderLineRow) -> Self {
+        OrderLine {
+            license_id: row.license_id,
+            product_id: row.product_id,
+            description: row.description,
+            seats: row.seats,
+            unit_price: row.unit_price,
+            period_start: row.period_start,
+            period_end: row.period_end,
+            amount: row.amount,
+            license_seats: row.license_seats,
+        }
+    }
+}
+
+/// Returns the next invoice number of the current year.
+/// The counter row stays locked until the transaction ends, so numbers have no gaps.
+async fn next_invoice_number(
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<String, sqlx::Error> {
+    let year = Utc::now().year();
+    let number = query!(
+        r#"INSERT INTO invoice_counter (year, last_number)
+        VALUES ($1, 1)
+        ON CONFLICT (year) DO UPDATE
+        SET last_number = invoice_counter.last_number + 1
+        RETURNING last_number"#,
+        year
+    )
+    .fetch_one(&mut *transaction)
+    .await?
+    .last_number;
+    Ok(invoice_number(year, number))
+}
+
+/// Formats the invoice number of the `number`th order of a year, e.g. `2023-000042`.
+fn invoice_number(year: i32, number: i32) -> String {
+    format!("{}-{:06}", year, number)
+}
+
+/// Amounts of an order, computed from its lines.
+#[derive(Debug, PartialEq)]
+struct OrderAmounts {
+    discount: Money,
+    /// Sum of the lines less the discount.
+    subtotal: Money,
+    tax: Money,
+    total: Money,
+}
+
+impl OrderAmounts {
+    /// Computes the amounts of an order with the lines and discount, taxed at the rate.
+    fn new(lines: &[OrderLine], discount: Option<&AppliedDiscount>, tax_rate: i32) -> Self {
+        let discount = discount.map_or(Money::ZERO, |discount| discount.amount);
+        let subtotal = lines.iter().map(|line| line.amount).sum::<Money>() - discount;
+        let tax = subtotal.rate(tax_rate);
+        OrderAmounts {
+            discount,
+            subtotal,
+            tax,
+            total: subtotal + tax,
+        }
+    }
+}
+
+/// Creates an order with its lines, taxed by where the company is billed.
+/// The subtotal is the sum of the lines less the discount, the total adds the tax to it.
+/// An order that costs nothing is paid and applied right away, any other waits for payment.
+/// An order crediting the company is applied right away, and waits for its credit
+/// to be paid back. It is refused if more is credited than its license has left to refund.
+async fn create_order(
+    transaction: &mut Transaction<'_, Postgres>,
+    order_type: OrderType,
+    company_id: i32,
+    user_id: Option<i32>,
+    currency: Currency,
+    lines: Vec<OrderLine>,
+    discount: Option<&AppliedDiscount>,
+) -> Result<Order, OrderError> {
+    if let Some(discount) = discount {
+        if !discount::redeem(transaction, &discount.discount_code_id).await? {
+            return Err(OrderError::DiscountUsedUp);
+        }
+    }
+    let invoice_number = next_invoice_number(transaction).await?;
+    let billing = company::get_billing_address(&mut *transaction, &company_id).await?;
+    let billing_country = billing.as_ref().map(|billing| billing.country.clone());
+    let vat_number = billing.and_then(|billing| billing.vat_number);
+    let tax_rule = tax::tax_rule(billing_country.as_deref(), vat_number.as_deref());
+    let amounts = OrderAmounts::new(&lines, discount, tax_rule.rate);
+    if amounts.total.is_negative() {
+        let license_id = lines
+            .first()
+            .map(|line| line.license_id)
+            .unwrap_or_default();
+        if refund::get_refundable(transaction, &license_id).await? < -amounts.total {
+            return Err(OrderError::CreditNotRefundable);
+        }
+    }
+    let status = if amounts.total == Money::ZERO {
+        OrderStatus::Paid
+    } else {
+        OrderStatus::Pending
+    };
+    let order = query_as!(
+        OrderRow,
+        r#"INSERT INTO "order"
+        (invoice_number, order_type, status, company_id, user_id, currency, discount_code_id,
+        discount_code, discount, subtotal, tax_rate, tax_treatment, tax, total,
+        billing_country, vat_number)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+        RETURNING order_id, invoice_number, order_type as "order_type: _",
+        status as "status: _", company_id, user_id, created_at,
+        currency as "currency: _", discount_code, discount as "discount: _",
+        subtotal as "subtotal: _", tax_rate,
+        tax_treatment as "tax_treatment: _", tax as "tax: _", total as "total: _",
+        billing_country, vat_number"#,
+        invoice_number,
+        order_type as _,
+        status as _,
+        company_id,
+        user_id,
+        currency as _,
+        discount.map(|discount| discount.discount_code_id),
+        discount.map(|discount| discount.code.clone()),
+        amounts.discount as _,
+        amounts.subtotal as _,
+        tax_rule.rate,
+        tax_rule.treatment as _,
+        amounts.tax as _,
+        amounts.total as _,
+        billing_country,
+        vat_number
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    for line in lines.iter() {
+        query!(
+            r#"INSERT INTO order_line
+            (order_id, license_id, product_id, description, seats, unit_price,
+            period_start, period_end, amount, license_seats)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"#,
+            order.order_id,
+            line.license_id,
+            line.product_id,
+            line.description,
+            line.seats,
+            line.unit_price as _,
+            line.period_start,
+            line.period_end,
+            line.amount as _,
+            line.license_seats
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+    if status == OrderStatus::Paid || amounts.total.is_negative() {
+        apply_order(transaction, &order.order_id, order_type).await?;
+    }
+    Ok(order.with_lines(lines))
+}
+
+/// Applies a paid order to its licenses.
+///
+/// A bought license becomes valid, a renewal extends the license by the period paid for,
+/// from its end date or from now if it has expired, and a seat change sets the seats of the
+/// license, unless more seats are in use than that.
+/// A subscription renewal sets the end of the license and of its subscription period
+/// to the end of the period paid for, ending any grace period.
+/// An early renewal ends the license the length of the new term after now.
+/// A trial conversion makes the trial license a valid paid license with the seats paid for,
+/// starting now and lasting the term paid for.
+pub async fn apply_order(
+    transaction: &mut Transaction<'_, Postgres>,
+    order_id: &i32,
+    order_type: OrderType,
+) -> Result<(), OrderError> {
+    match order_type {
+        OrderType::Purchase => {
+            query!(
+                r#"UPDATE license
+                SET valid = true
+                FROM order_line
+                WHERE order_line.license_id = license.license_id AND order_line.order_id = $1"#,
+                order_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+        OrderType::Renewal => {
+            query!(
+                r#"UPDATE license
+                SET end_date = GREATEST(end_date, now()) + (period_end - period_start)
+                FROM order_line
+                WHERE order_line.license_id = license.license_id AND order_line.order_id = $1"#,
+                order_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+        OrderType::SeatChange => {
+            let lines = query!(
+                r#"SELECT license_id, license_seats as "license_seats!"
+                FROM order_line
+                WHERE order_id = $1 AND license_seats IS NOT NULL"#,
+                order_id
+            )
+            .fetch_all(&mut *transaction)
+            .await?;
+            for line in lines {
+                // checkouts of floating licenses are refused by the database once the seats
+                // are lowered, so only the users assigned to named licenses are checked again
+                let license = license::lock_license(transaction, &line.license_id).await?;
+                if license.license_type() == LicenseType::Named
+                    && license::get_assigned_seats(transaction, &line.license_id).await?
+                        > line.license_seats as i64
+                {
+                    return Err(OrderError::TooFewSeats);
+                }
+                query!(
+                    r#"UPDATE license SET amount = $2 WHERE license_id = $1"#,
+                    line.license_id,
+                    line.license_seats
+                )
+                .execute(&mut *transaction)
+                .await?;
+            }
+        }
+        OrderType::SubscriptionRenewal => {
+            query!(
+                r#"UPDATE license
+                SET end_date = order_line.period_end
+                FROM order_line
+                WHERE order_line.license_id = license.license_id AND order_line.order_id = $1"#,
+                order_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+            query!(
+                r#"UPDATE subscription
+                SET current_period_end = order_line.period_end, status = $2,
+                renewal_order_id = NULL, grace_until = NULL
+                FROM order_line
+                WHERE order_line.license_id = subscription.license_id
+                AND order_line.order_id = $1"#,
+                order_id,
+                SubscriptionStatus::Active as _
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+        OrderType::EarlyRenewal => {
+            query!(
+                r#"UPDATE license
+                SET end_date = now() + (period_end - period_start)
+                FROM order_line
+                WHERE order_line.license_id = license.license_id AND order_line.order_id = $1"#,
+                order_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+        OrderType::TrialConversion => {
+            query!(
+                r#"UPDATE license
+                SET trial = false, valid = true, start_date = now(),
+                end_date = now() + (period_end - period_start), amount = order_line.seats
+                FROM order_line
+                WHERE order_line.license_id = license.license_id AND order_line.order_id = $1"#,
+                order_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+            query!(
+                r#"UPDATE company_trial
+                SET converted_at = now()
+                FROM order_line
+                WHERE order_line.license_id = company_trial.license_id
+                AND order_line.order_id = $1"#,
+                order_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Creates licenses and the one order paying for all of them, with a line per license.
+async fn create_purchase(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: i32,
+    company_id: i32,
+    currency: Currency,
+    purchases: &[(&PartialLicense, &PriceQuote)],
+    discount: Option<&AppliedDiscount>,
+) -> Result<(Vec<License>, Order), OrderError> {
+    let mut license_ids = Vec::with_capacity(purchases.len());
+    let mut lines = Vec::with_capacity(purchases.len());
+    for (new_license, price) in purchases {
+        let license = license::create_license(&mut *transaction, new_license).await?;
+        license_ids.push(license.license_id());
+        lines.push(OrderLine {
+            license_id: license.license_id(),
+            product_id: price.product_id.clone(),
+            description: format!("{} seat(s) for {} year(s)", price.seats, price.term_years),
+            seats: price.seats,
+            unit_price: price.unit_price,
+            period_start: Utc::now(),
+            period_end: license.end_date(),
+            amount: price.subtotal,
+            license_seats: None,
+        });
+    }
+    let order = create_order(
+        transaction,
+        OrderType::Purchase,
+        company_id,
+        Some(user_id),
+        currency,
+        lines,
+        discount,
+    )
+    .await?;
+    let mut licenses = Vec::with_capacity(license_ids.len());
+    for license_id in license_ids.iter() {
+        licenses.push(license::lock_license(transaction, license_id).await?);
+    }
+    Ok((licenses, order))
+}
+
+/// Creates a license and the order paying for it.
+/// The license should not be valid, it becomes valid when the order is paid.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `user_id` - Id of the user buying the license
+/// * `company_id` - Id of the company buying the license
+/// * `new_license` - The license to create
+/// * `price` - Price of the license, as computed by the pricing engine
+pub async fn purchase_license(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    company_id: i32,
+    new_license: &PartialLicense,
+    price: &PriceQuote,
+) -> Result<(License, Order), OrderError> {
+    let mut transaction = pool.begin().await?;
+    let (mut licenses, order) = create_purchase(
+        &mut transaction,
+        user_id,
+        company_id,
+        price.currency,
+        &[(new_license, price)],
+        price.discount.as_ref(),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok((licenses.remove(0), order))
+}
+
+/// Buys all items of the cart of a user: creates a license per item and one order paying for
+/// all of them, and empties the cart. Nothing is saved if any of it fails.
+/// The licenses should not be valid, they become valid when the order is paid.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `user_id` - Id of the user buying the cart
+/// * `company_id` - Id of the company buying the licenses
+/// * `items` - Items of the cart the price was computed for
+/// * `new_licenses` - The license to create for each item
+/// * `price` - Price of the cart, as computed by the pricing engine
+pub async fn checkout_cart(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    company_id: i32,
+    items: &[CartItem],
+    new_licenses: &[PartialLicense],
+    price: &CartQuote,
+) -> Result<(Vec<License>, Order), OrderError> {
+    let mut transaction = pool.begin().await?;
+    if cart::take_cart(&mut transaction, &user_id).await? != items {
+        return Err(OrderError::CartChanged);
+    }
+    let purchases: Vec<(&PartialLicense, &PriceQuote)> =
+        new_licenses.iter().zip(price.lines.iter()).collect();
+    let (licenses, order) = create_purchase(
+        &mut transaction,
+        user_id,
+        company_id,
+        price.currency,
+        &purchases,
+        price.discount.as_ref(),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok((licenses, order))
+}
+
+/// Approves a pending purchase request by buying the license it asks for:
+/// creates the license and the order paying for it, and links the order to the request.
+/// The license should not be valid, it becomes valid when the order is paid.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `user_id` - Id of the IT head approving the request
+/// * `purchase_request_id` - Id of the request
+/// * `decision_note` - Note to the user who asked
+/// * `new_license` - The license to create
+/// * `price` - Price of the license, as computed by the pricing engine
+pub async fn approve_purchase_request(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    purchase_request_id: &i32,
+    decision_note: Option<&str>,
+    new_license: &PartialLicense,
+    price: &PriceQuote,
+) -> Result<(PurchaseRequest, License, Order), OrderError> {
+    let mut transaction = pool.begin().await?;
+    let request = match purchase_request::lock_purchase_request(
+        &mut transaction,
+        purchase_request_id,
+    )
+    .await
+    {
+        Ok(request) => request,
+        Err(sqlx::Error::RowNotFound) => return Err(OrderError::RequestNotPending),
+        Err(e) => return Err(e.into()),
+    };
+    if request.status != PurchaseRequestStatus::Pending {
+        return Err(OrderError::RequestNotPending);
+    }
+    let (mut licenses, order) = create_purchase(
+        &mut transaction,
+        user_id,
+        request.company_id,
+        price.currency,
+        &[(new_license, price)],
+        price.discount.as_ref(),
+    )
+    .await?;
+    let request = purchase_request::set_approved(
+        &mut transaction,
+        purchase_request_id,
+        &user_id,
+        decision_note,
+        &order.order_id,
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok((request, licenses.remove(0), order))
+}
+
+/// Returns whether an order of the type for a license is waiting for payment.
+async fn has_pending_order(
+    transaction: &mut Transaction<'_, Postgres>,
+    license_id: &i32,
+    order_type: OrderType,
+) -> Result<bool, sqlx::Error> {
+    Ok(query!(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM "order"
+            JOIN order_line USING (order_id)
+            WHERE order_line.license_id = $1 AND "order".order_type = $2
+            AND "order".status = $3
+        ) as "pending!""#,
+        license_id,
+        order_type as _,
+        OrderStatus::Pending as _
+    )
+    .fetch_one(&mut *transaction)
+    .await?
+    .pending)
+}
+
+/// Locks a license that can be renewed or changed, and checks its seats are still the ones
+/// the price was computed for.
+async fn lock_purchasable_license(
+    transaction: &mut Transaction<'_, Postgres>,
+    license_id: &i32,
+    expected_seats: i32,
+) -> Result<License, OrderError> {
+    let license = match license::lock_license(transaction, license_id).await {
+        Ok(license) => license,
+        Err(sqlx::Error::RowNotFound) => return Err(OrderError::LicenseNotFound),
+        Err(e) => return Err(e.into()),
+    };
+    if !license.valid() || license.trial() {
+        return Err(OrderError::LicenseNotPurchasable);
+    }
+    if license.amount() != expected_seats {
+        return Err(OrderError::LicenseChanged);
+    }
+    Ok(license)
+}
+
+/// Accepts a quote that is sent and still valid by buying the licenses it offers:
+/// creates a license per line and one order paying for all of them at the prices of the quote,
+/// and links the order to the quote.
+/// The licenses are not valid until the order is paid.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `user_id` - Id of the IT head accepting the quote
+/// * `quote_id` - Id of the quote
+pub async fn accept_quote(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    quote_id: &i32,
+) -> Result<(Quote, Vec<License>, Order), OrderError> {
+    let mut transaction = pool.begin().await?;
+    let quote = match quote::lock_quote(&mut transaction, quote_id).await {
+        Ok(quote) => quote,
+        Err(sqlx::Error::RowNotFound) => return Err(OrderError::QuoteNotAcceptable),
+        Err(e) => return Err(e.into()),
+    };
+    if !quote.is_acceptable() {
+        return Err(OrderError::QuoteNotAcceptable);
+    }
+    let mut new_licenses = Vec::with_capacity(quote.lines.len());
+    let mut prices = Vec::with_capacity(quote.lines.len());
+    for line in quote.lines.iter() {
+        let new_license = PartialLicense::purchase(
+            line.seats,
+            line.term_years,
+            quote.company_id,
+            &line.product_id,
+            line.license_type,
+        )
+        .ok_or(OrderError::QuoteNotAcceptable)?;
+        new_licenses.push(new_license);
+        prices.push(PriceQuote {
+            product_id: line.product_id.clone(),
+            seats: line.seats,
+            term_years: line.term_years,
+            currency: quote.currency,
+            unit_price: line.unit_price,
+            tier_min_seats: None,
+            subtotal: line.amount,
+            discount: None,
+            total: line.amount,
+        });
+    }
+    let purchases: Vec<(&PartialLicense, &PriceQuote)> =
+        new_licenses.iter().zip(prices.iter()).collect();
+    let (licenses, order) = create_purchase(
+        &mut transaction,
+        user_id,
+        quote.company_id,
+        quote.currency,
+        &purchases,
+        None,
+    )
+    .await?;
+    let quote = quote::set_accepted(&mut transaction, quote_id, &user_id, &order.order_id).await?;
+    transaction.commit().await?;
+    Ok((quote, licenses, order))
+}
+
+/// Creates the order extending a license by the term of the price.
+/// Once paid, the term is added to the end date, or to now if the license has expired.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `user_id` - Id of the user renewing the license
+/// * `license_id` - Id of the license
+/// * `price` - Price of the renewal for all seats of the license
+pub async fn renew_license(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    license_id: &i32,
+    price: &PriceQuote,
+) -> Result<(License, Order), OrderError> {
+    let mut transaction = pool.begin().await?;
+    let license = lock_purchasable_license(&mut transaction, license_id, price.seats).await?;
+
+    let period_start = license.end_date().max(Utc::now());
+    let period_end = match pricing::term_end(period_start, price.term_years) {
+        Some(period_end) => period_end,
+        None => return Err(OrderError::LicenseNotPurchasable),
+    };
+    let line = OrderLine {
+        license_id: *license_id,
+        product_id: price.product_id.clone(),
+        description: format!(
+            "Renewal of {} seat(s) for {} year(s)",
+            price.seats, price.term_years
+        ),
+        seats: price.seats,
+        unit_price: price.unit_price,
+        period_start,
+        period_end,
+        amount: price.subtotal,
+        license_seats: None,
+    };
+    let order = create_order(
+        &mut transaction,
+        OrderType::Renewal,
+        license.company_id,
+        Some(user_id),
+        price.currency,
+        vec![line],
+        price.discount.as_ref(),
+    )
+    .await?;
+    let license = license::lock_license(&mut transaction, license_id).await?;
+    transaction.commit().await?;
+    Ok((license, order))
+}
+
+/// Creates the order renewing a license before it ends, starting a new term.
+/// Once paid, the license ends the term after then, the days that were left are credited.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `user_id` - Id of the user renewing the license
+/// * `license_id` - Id of the license
+/// * `price` - Prorated price of the renewal for all seats of the license
+pub async fn renew_license_early(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    license_id: &i32,
+    price: &ProrationQuote,
+) -> Result<(License, Order), OrderError> {
+    let mut transaction = pool.begin().await?;
+    let license = lock_purchasable_license(&mut transaction, license_id, price.seats).await?;
+
+    let term_years = price.term_years.unwrap_or(1);
+    let line = OrderLine {
+        license_id: *license_id,
+        product_id: price.product_id.clone(),
+        description: format!(
+            "Renewal of {} seat(s) for {} year(s) from now, {} day(s) left credited",
+            price.seats, term_years, price.days_left
+        ),
+        seats: price.seats,
+        unit_price: price.unit_price,
+        period_start: price.period_start,
+        period_end: price.period_end,
+        amount: price.charge - price.credit,
+        license_seats: None,
+    };
+    let order = create_order(
+        &mut transaction,
+        OrderType::EarlyRenewal,
+        license.company_id,
+        Some(user_id),
+        price.currency,
+        vec![line],
+        price.discount.as_ref(),
+    )
+    .await?;
+    let license = license::lock_license(&mut transaction, license_id).await?;
+    transaction.commit().await?;
+    Ok((license, order))
+}
+
+/// Creates the order changing the number of seats of a license until its end date,
+/// at its prorated price. Added seats are added once paid,
+/// removed seats are removed right away and credited.
+/// A license cannot be changed while another seat change of it is waiting for payment.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `user_id` - Id of the user changing the license
+/// * `license_id` - Id of the license
+/// * `price` - Prorated price of the change, computed for the seats the license has
+/// * `checkout_timeout` - How long a checkout of a floating license is kept without a heartbeat
+pub async fn change_seats(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    license_id: &i32,
+    price: &ProrationQuote,
+    checkout_timeout: Duration,
+) -> Result<(License, Order), OrderError> {
+    let mut transaction = pool.begin().await?;
+    let license =
+        lock_purchasable_license(&mut transaction, license_id, price.current_seats).await?;
+    if has_pending_order(&mut transaction, license_id, OrderType::SeatChange).await? {
+        return Err(OrderError::SeatChangePending);
+    }
+    if license::get_used_seats(&mut transaction, &license, checkout_timeout).await?
+        > price.seats as i64
+    {
+        return Err(OrderError::TooFewSeats);
+    }
+
+    let seats = price.seats - price.current_seats;
+    let line = OrderLine {
+        license_id: *license_id,
+        product_id: license.product_id().to_string(),
+        description: if seats > 0 {
+            format!(
+                "{} seat(s) added, {} seat(s) in total, for {} day(s)",
+                seats, price.seats, price.days_left
+            )
+        } else {
+            format!(
+                "{} seat(s) removed, {} seat(s) in total, for {} day(s)",
+                -seats, price.seats, price.days_left
+            )
+        },
+        seats,
+        unit_price: price.unit_price,
+        period_start: price.period_start,
+        period_end: license.end_date(),
+        amount: price.total,
+        license_seats: Some(price.seats),
+    };
+    let order = create_order(
+        &mut transaction,
+        OrderType::SeatChange,
+        license.company_id,
+        Some(user_id),
+        price.currency,
+        vec![line],
+        None,
+    )
+    .await?;
+    let license = license::lock_license(&mut transaction, license_id).await?;
+    transaction.commit().await?;
+    Ok((license, order))
+}
+
+/// Creates the order converting a trial license into a paid license with the seats and term
+/// of the price. The license stays a trial until the order is paid, it keeps its id,
+/// so all seat assignments are kept.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `user_id` - Id of the user converting the trial
+/// * `license_id` - Id of the trial license
+/// * `price` - Price of the paid license, as computed by the pricing engine
+/// * `checkout_timeout` - How long a checkout of a floating license is kept without a heartbeat
+pub async fn convert_trial(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    license_id: &i32,
+    price: &PriceQuote,
+    checkout_timeout: Duration,
+) -> Result<(License, Order), OrderError> {
+    let mut transaction = pool.begin().await?;
+    let license = match license::lock_license(&mut transaction, license_id).await {
+        Ok(license) => license,
+        Err(sqlx::Error::RowNotFound) => return Err(OrderError::LicenseNotFound),
+        Err(e) => return Err(e.into()),
+    };
+    if !license.trial() || !license.valid() || license.product_id() != price.product_id {
+        return Err(OrderError::TrialNotConvertible);
+    }
+    if has_pending_order(&mut transaction, license_id, OrderType::TrialConversion).await? {
+        return Err(OrderError::TrialNotConvertible);
+    }
+    if license::get_used_seats(&mut transaction, &license, checkout_timeout).await?
+        > price.seats as i64
+    {
+        return Err(OrderError::TooFewSeats);
+    }
+
+    let period_start = Utc::now();
+    let period_end = match pricing::term_end(period_start, price.term_years) {
+        Some(period_end) => period_end,
+        None => return Err(OrderError::TrialNotConvertible),
+    };
+    let line = OrderLine {
+        license_id: *license_id,
+        product_id: price.product_id.clone(),
+        description: format!(
+            "Trial converted to {} seat(s) for {} year(s)",
+            price.seats, price.term_years
+        ),
+        seats: price.seats,
+        unit_price: price.unit_price,
+        period_start,
+        period_end,
+        amount: price.subtotal,
+        license_seats: None,
+    };
+    let order = create_order(
+        &mut transaction,
+        OrderType::TrialConversion,
+        license.company_id,
+        Some(user_id),
+        price.currency,
+        vec![line],
+        price.discount.as_ref(),
+    )
+    .await?;
+    let license = license::lock_license(&mut transaction, license_id).await?;
+    transaction.commit().await?;
+    Ok((license, order))
+}
+
+/// Creates the order renewing a subscription for its next billing period,
+/// from the end of the period paid for.
+/// A subscription can be renewed while it is not canceled and has no renewal waiting for payment,
+/// a failed renewal can be renewed again.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `subscription_id` - Id of the subscription
+/// * `user_id` - Id of the user renewing, the user who subscribed for automatic renewals
+/// * `price` - Price of the period for all seats of the license
+pub async fn renew_subscription(
+    pool: &Pool<Postgres>,
+    subscription_id: &i32,
+    user_id: Option<i32>,
+    price: &PeriodQuote,
+) -> Result<(Subscription, Order), OrderError> {
+    let mut transaction = pool.begin().await?;
+    let subscription = subscription::lock_subscription(&mut transaction, subscription_id).await?;
+    if subscription.status == SubscriptionStatus::Canceled {
+        return Err(OrderError::SubscriptionNotRenewable);
+    }
+    if let Some(order_id) = subscription.renewal_order_id {
+        let status = query!(
+            r#"SELECT status as "status: OrderStatus" FROM "order" WHERE order_id = $1"#,
+            order_id
+        )
+        .fetch_one(&mut transaction)
+        .await?
+        .status;
+        if status != OrderStatus::Failed {
+            return Err(OrderError::SubscriptionNotRenewable);
+        }
+    }
+    let license =
+        lock_purchasable_license(&mut transaction, &subscription.license_id, price.seats).await?;
+
+    let period_start = subscription.current_period_end;
+    let period_end =
+        match period_start.checked_add_months(Months::new(price.billing_interval.months())) {
+            Some(period_end) => period_end,
+            None => return Err(OrderError::LicenseNotPurchasable),
+        };
+    let line = OrderLine {
+        license_id: subscription.license_id,
+        product_id: price.product_id.clone(),
+        description: match price.billing_interval {
+            BillingInterval::Monthly => format!("Monthly subscription, {} seat(s)", price.seats),
+            BillingInterval::Yearly => format!("Yearly subscription, {} seat(s)", price.seats),
+        },
+        seats: price.seats,
+        unit_price: price.unit_price,
+        period_start,
+        period_end,
+        amount: price.total,
+        license_seats: None,
+    };
+    let order = create_order(
+        &mut transaction,
+        OrderType::SubscriptionRenewal,
+        license.company_id,
+        user_id,
+        price.currency,
+        vec![line],
+        None,
+    )
+    .await?;
+    if order.status == OrderStatus::Pending {
+        subscription::set_renewal_order(&mut transaction, subscription_id, &order.order_id).await?;
+    }
+    let subscription = subscription::lock_subscription(&mut transaction, subscription_id).await?;
+    transaction.commit().await?;
+    Ok((subscription, order))
+}
+
+/// Fetches the lines of the orders and attaches them, keeping the order of the orders.
+async fn attach_lines(
+    pool: &Pool<Postgres>,
+    orders: Vec<OrderRow>,
+) -> Result<Vec<Order>, sqlx::Error> {
+    let order_ids: Vec<i32> = orders.iter().map(|order| order.order_id).collect();
+    let rows = query_as!(
+        OrderLineRow,
+        r#"SELECT order_id, license_id, product_id, description, seats,
+        unit_price as "unit_price: _", period_start, period_end, amount as "amount: _",
+        license_seats
+        FROM order_line
+        WHERE order_id = ANY($1)
+        ORDER BY order_line_id"#,
+        &order_ids
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut lines: HashMap<i32, Vec<OrderLine>> = HashMap::new();
+    for row in rows {
+        lines.entry(row.order_id).or_default().push(row.into());
+    }
+    Ok(orders
+        .into_iter()
+        .map(|order| {
+            let order_lines = lines.remove(&order.order_id).unwrap_or_default();
+            order.with_lines(order_lines)
+        })
+        .collect())
+}
+
+/// Returns the lines of the paid orders of a license, and of the orders crediting it
+/// whose credit is still being paid back.
+pub async fn get_paid_lines(
+    pool: &Pool<Postgres>,
+    license_id: &i32,
+) -> Result<Vec<PaidLine>, sqlx::Error> {
+    query_as!(
+        PaidLine,
+        r#"SELECT order_line.amount as "amount: _", order_line.period_start,
+        order_line.period_end, "order".subtotal as "order_subtotal: _",
+        "order".discount as "order_discount: _",
+        COALESCE((
+            SELECT SUM(refund.amount - refund.tax) FROM refund
+            WHERE refund.order_id = "order".order_id AND refund.credit_order_id IS NULL
+        ), 0)::BIGINT as "order_refunded!: _"
+        FROM order_line
+        JOIN "order" ON "order".order_id = order_line.order_id
+        WHERE order_line.license_id = $1
+        AND ("order".status = $2 OR ("order".status = $3 AND "order".total < 0))"#,
+        license_id,
+        OrderStatus::Paid as _,
+        OrderStatus::Pending as _
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns all orders, newest first
+pub async fn get_orders(pool: &Pool<Postgres>) -> Result<Vec<Order>, sqlx::Error> {
+    let orders = query_as!(
+        OrderRow,
+        r#"SELECT order_id, invoice_number, order_type as "order_type: _",
+        status as "status: _", company_id, user_id, created_at,
+        currency as "currency: _", discount_code, discount as "discount: _",
+        subtotal as "subtotal: _", tax_rate, tax_treatment as "tax_treatment: _",
+        tax as "tax: _", total as "total: _", billing_country, vat_number
+        FROM "order"
+        ORDER BY order_id DESC"#
+    )
+    .fetch_all(pool)
+    .await?;
+    attach_lines(pool, orders).await
+}
+
+/// Returns all orders of a company, newest first
+pub async fn get_orders_by_company(
+    pool: &Pool<Postgres>,
+    company_id: &i32,
+) -> Result<Vec<Order>, sqlx::Error> {
+    let orders = query_as!(
+        OrderRow,
+        r#"SELECT order_id, invoice_number, order_type as "order_type: _",
+        status as "status: _", company_id, user_id, created_at,
+        currency as "currency: _", discount_code, discount as "discount: _",
+        subtotal as "subtotal: _", tax_rate, tax_treatment as "tax_treatment: _",
+        tax as "tax: _", total as "total: _", billing_country, vat_number
+        FROM "order"
+        WHERE company_id = $1
+        ORDER BY order_id DESC"#,
+        company_id
+    )
+    .fetch_all(pool)
+    .await?;
+    attach_lines(pool, orders).await
+}
+
+/// Returns an order
+pub async fn get_order_by_id(pool: &Pool<Postgres>, order_id: &i32) -> Result<Order, sqlx::Error> {
+    let order = query_as!(
+        OrderRow,
+        r#"SELECT order_id, invoice_number, order_type as "order_type: _",
+        status as "status: _", company_id, user_id, created_at,
+        currency as "currency: _", discount_code, discount as "discount: _",
+        subtotal as "subtotal: _", tax_rate, tax_treatment as "tax_treatment: _",
+        tax as "tax: _", total as "total: _", billing_country, vat_number
+        FROM "order"
+        WHERE order_id = $1"#,
+        order_id
+    )
+    .fetch_one(pool)
+    .await?;
+    let mut orders = attach_lines(pool, vec![order]).await?;
+    Ok(orders.remove(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn money(s: &str) -> Money {
+        s.parse().unwrap()
+    }
+
+    fn line(amount: &str) -> OrderLine {
+        OrderLine {
+            license_id: 1,
+            product_id: "proflex".to_string(),
+            description: "1 seat(s) for 1 year(s)".to_string(),
+            seats: 1,
+            unit_price: money(amount),
+            period_start: Utc::now(),
+            period_end: Utc::now(),
+            amount: money(amount),
+            license_seats: None,
+        }
+    }
+
+    fn discount(amount: &str) -> AppliedDiscount {
+        AppliedDiscount {
+            discount_code_id: 1,
+            code: "WELCOME10".to_string(),
+            amount: money(amount),
+        }
+    }
+
+    #[test]
+    fn invoice_numbers_are_padded_to_six_digits() {
+        assert_eq!(invoice_number(2023, 1), "2023-000001");
+        assert_eq!(invoice_number(2023, 42), "2023-000042");
+        assert_eq!(invoice_number(2024, 999_999), "2024-999999");
+        assert_eq!(invoice_number(2024, 1_000_000), "2024-1000000");
+    }
+
+    #[test]
+    fn amounts_add_the_tax_to_the_lines() {
+        let lines = [line("100.00"), line("37.50")];
+        assert_eq!(
+            OrderAmounts::new(&lines, None, 2500),
+            OrderAmounts {
+                discount: Money::ZERO,
+                subtotal: money("137.50"),
+                tax: money("34.38"),
+                total: money("171.88"),
+            }
+        );
+    }
+
+    #[test]
+    fn amounts_tax_the_price_after_the_discount() {
+        let lines = [line("100.00")];
+        let discount = discount("10.00");
+        assert_eq!(
+            OrderAmounts::new(&lines, Some(&discount), 2500),
+            OrderAmounts {
+                discount: money("10.00"),
+                subtotal: money("90.00"),
+                tax: money("22.50"),
+                total: money("112.50"),
+            }
+        );
+    }
+
+    #[test]
+    fn amounts_without_tax() {
+        let amounts = OrderAmounts::new(&[line("199.90")], None, 0);
+        assert_eq!(amounts.tax, Money::ZERO);
+        assert_eq!(amounts.total, money("199.90"));
+    }
+
+    #[test]
+    fn amounts_of_a_credit_are_negative() {
+        let amounts = OrderAmounts::new(&[line("-40.00")], None, 2500);
+        assert_eq!(amounts.subtotal, money("-40.00"));
+        assert_eq!(amounts.tax, money("-10.00"));
+        assert_eq!(amounts.total, money("-50.00"));
+    }
+}
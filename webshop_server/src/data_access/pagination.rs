@@ -0,0 +1,152 @@
+//! Pagination and sorting of lists.
+//!
+//! List endpoints take a [`PageQuery`] from the query string, and parse it into a
+//! [`PageRequest`] sorted by one of the fields of the list. Each list has its own enum of fields
+//! implementing [`SortField`]. The data access functions return a [`Page`] of the list,
+//! with the total number of items matching the filters of the list.
+//!
+//! The cursor of a page is opaque to clients, who get the cursors of the other pages
+//! from the `Link` header of the response.
+
+use std::fmt;
+
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+/// Number of items on a page if the query does not give a limit.
+pub const DEFAULT_LIMIT: i64 = 50;
+/// Largest number of items on a page.
+pub const MAX_LIMIT: i64 = 200;
+
+/// Direction to sort a list in.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// A field a list can be sorted by.
+pub trait SortField: Sized + Copy + Default {
+    /// Names of the fields, as given in the `sort` query parameter.
+    const NAMES: &'static [&'static str];
+
+    /// Returns the field with the name.
+    fn from_name(name: &str) -> Option<Self>;
+
+    /// Returns the name of the field, the same as the column it is sorted by.
+    fn name(&self) -> &'static str;
+}
+
+/// Page, sort field and sort direction to list, as given in the query string.
+#[derive(Deserialize, Debug, IntoParams)]
+pub struct PageQuery {
+    /// Number of items on the page, 50 if not given and at most 200
+    #[param(example = 50)]
+    pub limit: Option<i64>,
+    /// Cursor of the page, from the `Link` header of another page. The first page if not given
+    pub cursor: Option<String>,
+    /// Field to sort by, see the description of the endpoint for the fields
+    pub sort: Option<String>,
+    /// Direction to sort in, ascending if not given
+    pub order: Option<SortOrder>,
+}
+
+/// A page of a list, sorted by a field.
+#[derive(Debug, Clone, Copy)]
+pub struct PageRequest<S> {
+    pub limit: i64,
+    pub offset: i64,
+    pub sort: S,
+    pub order: SortOrder,
+}
+
+/// The items on a page of a list, and the total number of items in the list.
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PaginationError {
+    /// The limit is not between 1 and the largest limit.
+    InvalidLimit,
+    /// The cursor is not a cursor of the list.
+    InvalidCursor,
+    /// The list cannot be sorted by the field, has the names of the fields it can.
+    UnknownSortField(&'static [&'static str]),
+}
+
+impl fmt::Display for PaginationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaginationError::InvalidLimit => {
+                write!(f, "Limit must be between 1 and {}", MAX_LIMIT)
+            }
+            PaginationError::InvalidCursor => write!(f, "Invalid cursor"),
+            PaginationError::UnknownSortField(names) => {
+                write!(f, "Sort must be one of {}", names.join(", "))
+            }
+        }
+    }
+}
+
+impl PageQuery {
+    /// Parses the page and sort field, a list sorted by `S`.
+    pub fn page<S: SortField>(&self) -> Result<PageRequest<S>, PaginationError> {
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(PaginationError::InvalidLimit);
+        }
+        let offset = match self.cursor.as_deref() {
+            Some(cursor) => cursor
+                .parse::<i64>()
+                .ok()
+                .filter(|offset| *offset >= 0)
+                .ok_or(PaginationError::InvalidCursor)?,
+            None => 0,
+        };
+        let sort = match self.sort.as_deref() {
+            Some(name) => {
+                S::from_name(name.trim()).ok_or(PaginationError::UnknownSortField(S::NAMES))?
+            }
+            None => S::default(),
+        };
+        Ok(PageRequest {
+            limit,
+            offset,
+            sort,
+            order: self.order.unwrap_or_default(),
+        })
+    }
+}
+
+impl<S: SortField> PageRequest<S> {
+    /// Name of the field to sort by, for the `ORDER BY` of a query.
+    pub fn sort_name(&self) -> &'static str {
+        self.sort.name()
+    }
+
+    pub fn descending(&self) -> bool {
+        self.order == SortOrder::Desc
+    }
+
+    /// Cursor of the page after this one, if there are more items than on this page.
+    pub fn next_cursor(&self, total: i64) -> Option<String> {
+        let next = self.offset + self.limit;
+        (next < total).then(|| next.to_string())
+    }
+
+    /// Cursor of the page before this one, if this is not the first page.
+    pub fn prev_cursor(&self) -> Option<String> {
+        (self.offset > 0).then(|| (self.offset - self.limit).max(0).to_string())
+    }
+
+    /// Cursor of the last page of a list with `total` items.
+    pub fn last_cursor(&self, total: i64) -> String {
+        let last_page = (total - 1).max(0) / self.limit;
+        (last_page * self.limit).to_string()
+    }
+}
@@ -0,0 +1,231 @@
+//! Data access for payments of orders at a payment provider.
+
+use chrono::{DateTime, Utc};
+use sqlx::{query, Pool, Postgres};
+
+use crate::{
+    data_access::{
+        discount,
+        license::subscription,
+        order::{self, OrderError, OrderStatus, OrderType},
+    },
+    money::{Currency, Money},
+    payment::{PaymentIntent, PaymentStatus, WebhookEvent, WebhookEventType},
+};
+
+#[derive(Debug)]
+pub enum PaymentRecordError {
+    SqlxError(sqlx::Error),
+    /// No payment was created for the intent.
+    UnknownIntent,
+    /// The outcome of the payment has already been recorded.
+    AlreadyProcessed,
+    /// The amount or currency paid does not match the order.
+    AmountMismatch,
+    /// The paid order can no longer be applied to its licenses.
+    OrderNotApplicable(OrderError),
+    /// The payment succeeded, but its order, with the id, no longer waits for payment.
+    /// The outcome is recorded, the payment has to be refunded.
+    OrderNotPending(i32),
+}
+
+impl From<sqlx::Error> for PaymentRecordError {
+    fn from(e: sqlx::Error) -> Self {
+        PaymentRecordError::SqlxError(e)
+    }
+}
+
+impl std::fmt::Display for PaymentRecordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PaymentRecordError::SqlxError(e) => write!(f, "{}", e),
+            PaymentRecordError::UnknownIntent => write!(f, "Unknown payment intent"),
+            PaymentRecordError::AlreadyProcessed => write!(f, "Payment already processed"),
+            PaymentRecordError::AmountMismatch => {
+                write!(f, "Amount paid does not match the order")
+            }
+            PaymentRecordError::OrderNotApplicable(e) => write!(f, "{}", e),
+            PaymentRecordError::OrderNotPending(order_id) => {
+                write!(f, "Order {} no longer waits for payment", order_id)
+            }
+        }
+    }
+}
+
+/// Stores the payment intent created for an order, pending until the provider
+/// reports its outcome.
+pub async fn create_payment(
+    pool: &Pool<Postgres>,
+    order_id: &i32,
+    provider: &str,
+    intent: &PaymentIntent,
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"INSERT INTO payment (order_id, provider, intent_id, status, amount)
+        VALUES ($1, $2, $3, $4, $5)"#,
+        order_id,
+        provider,
+        intent.intent_id,
+        PaymentStatus::Pending as _,
+        intent.amount as _
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns the intents of the payments of an order at the provider that are still pending.
+pub async fn get_pending_intents(
+    pool: &Pool<Postgres>,
+    order_id: &i32,
+    provider: &str,
+) -> Result<Vec<String>, sqlx::Error> {
+    Ok(query!(
+        r#"SELECT intent_id FROM payment
+        WHERE order_id = $1 AND provider = $2 AND status = $3"#,
+        order_id,
+        provider,
+        PaymentStatus::Pending as _
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|payment| payment.intent_id)
+    .collect())
+}
+
+/// Marks an order waiting for payment as failed, when no payment could be created for it
+/// or it was not paid in time, giving back the use of its discount code.
+/// Its pending payments are marked failed too, they must have been canceled at the provider.
+pub async fn fail_order(pool: &Pool<Postgres>, order_id: &i32) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let failed = query!(
+        r#"UPDATE "order" SET status = $1 WHERE order_id = $2 AND status = $3"#,
+        OrderStatus::Failed as _,
+        order_id,
+        OrderStatus::Pending as _
+    )
+    .execute(&mut transaction)
+    .await?;
+    if failed.rows_affected() > 0 {
+        query!(
+            r#"UPDATE payment SET status = $1, updated_at = now()
+            WHERE order_id = $2 AND status = $3"#,
+            PaymentStatus::Failed as _,
+            order_id,
+            PaymentStatus::Pending as _
+        )
+        .execute(&mut transaction)
+        .await?;
+        discount::release(&mut transaction, order_id).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Returns the ids of the orders that have waited for payment since before `created_before`,
+/// oldest first. Orders crediting the company wait for their credit instead, and are left out.
+pub async fn get_unpaid_orders(
+    pool: &Pool<Postgres>,
+    created_before: DateTime<Utc>,
+) -> Result<Vec<i32>, sqlx::Error> {
+    Ok(query!(
+        r#"SELECT order_id FROM "order"
+        WHERE status = $1 AND total > 0 AND created_at < $2
+        ORDER BY order_id"#,
+        OrderStatus::Pending as _,
+        created_before
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|order| order.order_id)
+    .collect())
+}
+
+/// Records the outcome of a payment reported by the provider.
+/// A succeeded payment marks its order paid and applies it to the licenses,
+/// a failed one marks the order failed and gives back the use of its discount code.
+/// A payment method saved paying a subscription renewal is kept for the later renewals.
+/// An order that no longer waits for payment is left as it is.
+///
+/// # Returns
+/// The id of the order the payment was for.
+pub async fn record_payment_outcome(
+    pool: &Pool<Postgres>,
+    provider: &str,
+    event: &WebhookEvent,
+) -> Result<i32, PaymentRecordError> {
+    let mut transaction = pool.begin().await?;
+    let payment = query!(
+        r#"SELECT payment.payment_id, payment.order_id, payment.status as "status: PaymentStatus",
+        payment.amount as "amount: Money", "order".currency as "currency: Currency",
+        "order".order_type as "order_type: OrderType"
+        FROM payment
+        JOIN "order" ON "order".order_id = payment.order_id
+        WHERE payment.provider = $1 AND payment.intent_id = $2
+        FOR UPDATE"#,
+        provider,
+        event.intent_id
+    )
+    .fetch_optional(&mut transaction)
+    .await?
+    .ok_or(PaymentRecordError::UnknownIntent)?;
+
+    if payment.status != PaymentStatus::Pending {
+        return Err(PaymentRecordError::AlreadyProcessed);
+    }
+    if payment.amount != event.amount || payment.currency != event.currency {
+        return Err(PaymentRecordError::AmountMismatch);
+    }
+
+    let (payment_status, order_status) = match event.event_type {
+        WebhookEventType::PaymentSucceeded => (PaymentStatus::Succeeded, OrderStatus::Paid),
+        WebhookEventType::PaymentFailed => (PaymentStatus::Failed, OrderStatus::Failed),
+    };
+    query!(
+        r#"UPDATE payment SET status = $1, updated_at = now() WHERE payment_id = $2"#,
+        payment_status as _,
+        payment.payment_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    let updated = query!(
+        r#"UPDATE "order" SET status = $1 WHERE order_id = $2 AND status = $3"#,
+        order_status as _,
+        payment.order_id,
+        OrderStatus::Pending as _
+    )
+    .execute(&mut transaction)
+    .await?;
+    if updated.rows_affected() == 0 {
+        transaction.commit().await?;
+        return match order_status {
+            OrderStatus::Paid => Err(PaymentRecordError::OrderNotPending(payment.order_id)),
+            _ => Ok(payment.order_id),
+        };
+    }
+    match order_status {
+        OrderStatus::Paid => {
+            order::apply_order(&mut transaction, &payment.order_id, payment.order_type)
+                .await
+                .map_err(|e| match e {
+                    OrderError::SqlxError(e) => PaymentRecordError::SqlxError(e),
+                    e => PaymentRecordError::OrderNotApplicable(e),
+                })?;
+            if let (OrderType::SubscriptionRenewal, Some(payment_method_id)) =
+                (payment.order_type, &event.payment_method_id)
+            {
+                subscription::set_payment_method(
+                    &mut transaction,
+                    &payment.order_id,
+                    payment_method_id,
+                )
+                .await?;
+            }
+        }
+        _ => discount::release(&mut transaction, &payment.order_id).await?,
+    }
+    transaction.commit().await?;
+    Ok(payment.order_id)
+}
@@ -1,7 +1,12 @@
 //! This module is a data access implementation for products.  
 //! Allows for access and manipulation of products in the database.
 //!
-//! Its submodule `description` contains the data access implementation for product descriptions.
+//! Its submodule `description` contains the data access implementation for product descriptions,
+//! `price_list` the prices of products in each currency they are sold in,
+//! `price_tier` the volume pricing tiers of those price lists,
+//! `publishing` the drafting, scheduling and publishing of products,
+//! `release` the released versions of products,
+//! and `search` full-text search of products.
 
 use serde::{Deserialize, Serialize};
 use sqlx::{
@@ -9,15 +14,31 @@ use sqlx::{
 };
 use utoipa::ToSchema;
 
-use crate::data_access::testimonial;
+use crate::{
+    data_access::{
+        pagination::{Page, PageRequest, SortField},
+        testimonial,
+    },
+    money::{Currency, Money},
+};
+
+use self::publishing::PublishStatus;
 
 pub mod description;
+pub mod price_list;
+pub mod price_tier;
+pub mod publishing;
+pub mod release;
+pub mod search;
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct Product {
     product_id: String,
     display_name: String,
-    price_per_user: f32,
+    /// Price per user per year.
+    #[schema(value_type = String, example = "199.00")]
+    price_per_user: Money,
+    currency: Currency,
     short_description: String,
     main_image: String,
     available: bool,
@@ -26,7 +47,8 @@ impl Product {
     pub fn new(
         product_id: &str,
         display_name: &str,
-        price_per_user: f32,
+        price_per_user: Money,
+        currency: Currency,
         short_description: &str,
         main_image: &str,
         available: bool,
@@ -35,6 +57,7 @@ impl Product {
             product_id: product_id.to_string(),
             display_name: display_name.to_string(),
             price_per_user,
+            currency,
             short_description: short_description.to_string(),
             main_image: main_image.to_string(),
             available,
@@ -43,6 +66,15 @@ impl Product {
     pub fn product_id(&self) -> &str {
         &self.product_id
     }
+    pub fn display_name(&self) -> &str {
+        &self.display_name
+    }
+    pub fn price_per_user(&self) -> Money {
+        self.price_per_user
+    }
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
     pub fn main_image(&self) -> &str {
         &self.main_image
     }
@@ -64,38 +96,127 @@ pub fn generate_id(display_name: &str) -> String {
         .replace(' ', "_")
 }
 
-/// Returns all products, optionally only available products.
+/// Fields a list of products can be sorted by, by name if not given.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum ProductSort {
+    #[default]
+    DisplayName,
+    PricePerUser,
+    ProductId,
+}
+
+impl SortField for ProductSort {
+    const NAMES: &'static [&'static str] = &["display_name", "price_per_user", "product_id"];
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "display_name" => Some(ProductSort::DisplayName),
+            "price_per_user" => Some(ProductSort::PricePerUser),
+            "product_id" => Some(ProductSort::ProductId),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ProductSort::DisplayName => "display_name",
+            ProductSort::PricePerUser => "price_per_user",
+            ProductSort::ProductId => "product_id",
+        }
+    }
+}
+
+/// Filters for a list of products, all products if none are given.
+#[derive(Debug, Default)]
+pub struct ProductFilter {
+    /// Only products that are, or are not, available.
+    pub available: Option<bool>,
+    /// Only products in this currency.
+    pub currency: Option<Currency>,
+    /// Only products with this publish status.
+    pub status: Option<PublishStatus>,
+}
+
+/// Returns a page of the products matching the filter.
+///
+/// # Arguments
+///
+/// * `pool` - The database pool
+/// * `filter` - Which products to return, the public api should only return available products
+/// * `page` - The page and sort order of the products
+pub async fn get_products(
+    pool: &Pool<Postgres>,
+    filter: &ProductFilter,
+    page: &PageRequest<ProductSort>,
+) -> Result<Page<Product>, sqlx::Error> {
+    let items = query_as!(
+        Product,
+        r#"SELECT product_id, display_name, price_per_user as "price_per_user: _",
+        currency as "currency: _", short_description, main_image, available
+        FROM product
+        WHERE ($1::BOOLEAN IS NULL OR available = $1)
+        AND ($2::currency_enum IS NULL OR currency = $2)
+        AND ($7::publish_status_enum IS NULL OR publish_status = $7)
+        ORDER BY
+            CASE WHEN $3 = 'display_name' AND NOT $4 THEN display_name END ASC,
+            CASE WHEN $3 = 'display_name' AND $4 THEN display_name END DESC,
+            CASE WHEN $3 = 'price_per_user' AND NOT $4 THEN price_per_user END ASC,
+            CASE WHEN $3 = 'price_per_user' AND $4 THEN price_per_user END DESC,
+            CASE WHEN $3 = 'product_id' AND $4 THEN product_id END DESC,
+            product_id
+        LIMIT $5 OFFSET $6"#,
+        filter.available,
+        filter.currency as _,
+        page.sort_name(),
+        page.descending(),
+        page.limit,
+        page.offset,
+        filter.status as _
+    )
+    .fetch_all(pool)
+    .await?;
+    let total = query!(
+        r#"SELECT COUNT(*) as "total!" FROM product
+        WHERE ($1::BOOLEAN IS NULL OR available = $1)
+        AND ($2::currency_enum IS NULL OR currency = $2)
+        AND ($3::publish_status_enum IS NULL OR publish_status = $3)"#,
+        filter.available,
+        filter.currency as _,
+        filter.status as _
+    )
+    .fetch_one(pool)
+    .await?
+    .total;
+    Ok(Page { items, total })
+}
+
+/// Returns the products in a category, ordered by display name, optionally only available products.
 ///
 /// # Arguments
 ///
 /// * `pool` - The database pool
+/// * `category_id` - The id of the category
 /// * `only_available`
 ///     - true - only available products are returned, should be used for the public api
-///     - false - all products are returned
-///
-/// # Returns
-pub async fn get_products(
+///     - false - all products in the category are returned
+pub async fn get_products_by_category(
     pool: &Pool<Postgres>,
+    category_id: &i32,
     only_available: bool,
 ) -> Result<Vec<Product>, sqlx::Error> {
-    let products = if only_available {
-        query_as!(
-            Product,
-            r#"SELECT *
-            FROM product WHERE available = true"#
-        )
-        .fetch_all(pool)
-        .await?
-    } else {
-        query_as!(
-            Product,
-            r#"SELECT *
-            FROM product"#
-        )
-        .fetch_all(pool)
-        .await?
-    };
-    Ok(products)
+    query_as!(
+        Product,
+        r#"SELECT product.product_id, display_name, price_per_user as "price_per_user: _",
+        currency as "currency: _", short_description, main_image, available
+        FROM product
+        JOIN product_category ON product_category.product_id = product.product_id
+        WHERE product_category.category_id = $1 AND (available OR NOT $2)
+        ORDER BY display_name"#,
+        category_id,
+        only_available
+    )
+    .fetch_all(pool)
+    .await
 }
 
 /// Returns a product by its id.
@@ -110,7 +231,8 @@ pub async fn get_product_by_id(
 ) -> Result<Product, sqlx::Error> {
     let product = query_as!(
         Product,
-        r#"SELECT product_id, display_name, price_per_user, short_description, main_image, available
+        r#"SELECT product_id, display_name, price_per_user as "price_per_user: _",
+        currency as "currency: _", short_description, main_image, available
         FROM product WHERE product_id = $1"#,
         product_id
     )
@@ -119,7 +241,7 @@ pub async fn get_product_by_id(
     Ok(product)
 }
 
-/// Create a new product in the database.
+/// Create a new product in the database, as a draft.
 ///
 /// # Arguments
 ///
@@ -131,18 +253,21 @@ pub async fn create_product(
 ) -> Result<Product, sqlx::Error> {
     query!(
         r#"INSERT INTO product
-        (product_id, display_name, price_per_user, short_description, main_image, available)
+        (product_id, display_name, price_per_user, currency, short_description, main_image)
         VALUES ($1, $2, $3, $4, $5, $6)"#,
         product.product_id,
         product.display_name,
-        product.price_per_user,
+        product.price_per_user as _,
+        product.currency as _,
         product.short_description,
-        product.main_image,
-        product.available
+        product.main_image
     )
     .execute(pool)
     .await?;
-    Ok(product)
+    Ok(Product {
+        available: false,
+        ..product
+    })
 }
 
 /// Delete a product.
@@ -171,7 +296,7 @@ pub async fn delete_product(
     Ok(row.main_image)
 }
 
-/// Update a product.
+/// Update a product, its availability follows its publish status.
 ///
 /// # Arguments
 ///
@@ -184,16 +309,17 @@ pub async fn update_product(
     pool: &Pool<Postgres>,
     new_product: &Product,
 ) -> Result<Product, sqlx::Error> {
-    query_as!(Product,
+    query_as!(
+        Product,
         r#"UPDATE product
-        SET display_name = $1, price_per_user = $2, short_description = $3, main_image = $4, available = $5
-        WHERE product_id = $6
-        RETURNING *"#,
+        SET display_name = $1, price_per_user = $2, short_description = $3, main_image = $4
+        WHERE product_id = $5
+        RETURNING product_id, display_name, price_per_user as "price_per_user: _",
+        currency as "currency: _", short_description, main_image, available"#,
         new_product.display_name,
-        new_product.price_per_user,
+        new_product.price_per_user as _,
         new_product.short_description,
         new_product.main_image,
-        new_product.available,
         new_product.product_id
     )
     .fetch_one(pool)
@@ -203,6 +329,9 @@ pub async fn update_product(
 /// Update the availability of a product.
 /// Returns the updated product.
 ///
+/// Makes a product available by publishing it. Makes a published product unavailable
+/// by archiving it, and a scheduled one by making it a draft again.
+///
 /// # Arguments
 ///
 /// * `pool` - The database pool
@@ -219,9 +348,15 @@ pub async fn update_product_available(
     query_as!(
         Product,
         r#"UPDATE product
-        SET available = $1
+        SET publish_status = CASE
+            WHEN $1 THEN 'published'
+            WHEN publish_status = 'published' THEN 'archived'
+            WHEN publish_status = 'scheduled' THEN 'draft'
+            ELSE publish_status END,
+        publish_at = NULL
         WHERE product_id = $2
-        RETURNING *"#,
+        RETURNING product_id, display_name, price_per_user as "price_per_user: _",
+        currency as "currency: _", short_description, main_image, available"#,
         available,
         product_id
     )
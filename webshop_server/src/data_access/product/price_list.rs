@@ -0,0 +1,159 @@
+//! Data access for the price lists of products.
+//!
+//! The price list of a product in its own currency is its `price_per_user` and the tiers
+//! in that currency. It can have price lists in other currencies too,
+//! and is only sold in the currencies it has a price list in.
+//! Prices are never converted between currencies.
+
+use serde::{Deserialize, Serialize};
+use sqlx::{query, Pool, Postgres};
+use utoipa::ToSchema;
+
+use super::{
+    price_tier::{self, PriceTier},
+    Product,
+};
+use crate::money::{Currency, Money};
+
+/// Prices of a product in one currency.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PriceList {
+    pub currency: Currency,
+    /// Price per user per year, for purchases no tier applies to.
+    #[schema(value_type = String, example = "199.00")]
+    pub price_per_user: Money,
+    /// Volume pricing tiers, ordered by smallest number of seats.
+    pub tiers: Vec<PriceTier>,
+}
+
+/// Returns the price lists of a product, the one in its own currency first.
+pub async fn get_price_lists(
+    pool: &Pool<Postgres>,
+    product: &Product,
+) -> Result<Vec<PriceList>, sqlx::Error> {
+    let prices = query!(
+        r#"SELECT currency as "currency: Currency", price_per_user as "price_per_user: Money"
+        FROM product_price
+        WHERE product_id = $1 AND currency <> $2
+        ORDER BY currency"#,
+        product.product_id(),
+        product.currency() as _
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut price_lists = Vec::with_capacity(prices.len() + 1);
+    let own = (product.currency(), product.price_per_user());
+    for (currency, price_per_user) in std::iter::once(own).chain(
+        prices
+            .into_iter()
+            .map(|row| (row.currency, row.price_per_user)),
+    ) {
+        price_lists.push(PriceList {
+            currency,
+            price_per_user,
+            tiers: price_tier::get_price_tiers(pool, product.product_id(), currency).await?,
+        });
+    }
+    Ok(price_lists)
+}
+
+/// Returns the price list of a product in a currency, none if it is not sold in the currency.
+pub async fn get_price_list(
+    pool: &Pool<Postgres>,
+    product: &Product,
+    currency: Currency,
+) -> Result<Option<PriceList>, sqlx::Error> {
+    let price_per_user = if currency == product.currency() {
+        product.price_per_user()
+    } else {
+        let price = query!(
+            r#"SELECT price_per_user as "price_per_user: Money"
+            FROM product_price
+            WHERE product_id = $1 AND currency = $2"#,
+            product.product_id(),
+            currency as _
+        )
+        .fetch_optional(pool)
+        .await?;
+        match price {
+            Some(price) => price.price_per_user,
+            None => return Ok(None),
+        }
+    };
+    Ok(Some(PriceList {
+        currency,
+        price_per_user,
+        tiers: price_tier::get_price_tiers(pool, product.product_id(), currency).await?,
+    }))
+}
+
+/// Sets the price list of a product in a currency, replacing the one it had.
+/// The price list in the currency of the product sets its price per user.
+pub async fn set_price_list(
+    pool: &Pool<Postgres>,
+    product: &Product,
+    price_list: &PriceList,
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    if price_list.currency == product.currency() {
+        query!(
+            r#"UPDATE product SET price_per_user = $2 WHERE product_id = $1"#,
+            product.product_id(),
+            price_list.price_per_user as _
+        )
+        .execute(&mut transaction)
+        .await?;
+    } else {
+        query!(
+            r#"INSERT INTO product_price (product_id, currency, price_per_user)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (product_id, currency) DO UPDATE
+            SET price_per_user = $3"#,
+            product.product_id(),
+            price_list.currency as _,
+            price_list.price_per_user as _
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+    price_tier::replace_price_tiers(
+        &mut transaction,
+        product.product_id(),
+        price_list.currency,
+        &price_list.tiers,
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Removes the price list of a product in a currency other than its own,
+/// the product is no longer sold in that currency.
+/// Returns false if the product had no price list in the currency.
+pub async fn delete_price_list(
+    pool: &Pool<Postgres>,
+    product_id: &str,
+    currency: Currency,
+) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let deleted = query!(
+        r#"DELETE FROM product_price WHERE product_id = $1 AND currency = $2"#,
+        product_id,
+        currency as _
+    )
+    .execute(&mut transaction)
+    .await?;
+    if deleted.rows_affected() == 0 {
+        return Ok(false);
+    }
+    query!(
+        r#"DELETE FROM product_price_tier WHERE product_id = $1 AND currency = $2"#,
+        product_id,
+        currency as _
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(true)
+}
@@ -0,0 +1,83 @@
+//! Data access for volume pricing tiers of products.
+//!
+//! A tier gives a lower price per user for purchases of at least `min_seats` seats.
+//! Tiers belong to the price list of one currency, a price list without tiers sells
+//! the product at its price per user for any number of seats.
+
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Pool, Postgres, Transaction};
+use utoipa::ToSchema;
+
+use crate::money::{Currency, Money};
+
+/// Volume pricing tier of a product.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PriceTier {
+    /// Smallest number of seats the tier applies to.
+    pub min_seats: i32,
+    /// Price per user per year for every seat of a purchase in this tier,
+    /// in the currency of the price list.
+    #[schema(value_type = String, example = "179.00")]
+    pub price_per_user: Money,
+}
+
+/// Returns the price tiers of a product in a currency, ordered by `min_seats`.
+pub async fn get_price_tiers(
+    pool: &Pool<Postgres>,
+    product_id: &str,
+    currency: Currency,
+) -> Result<Vec<PriceTier>, sqlx::Error> {
+    query_as!(
+        PriceTier,
+        r#"SELECT min_seats, price_per_user as "price_per_user: _"
+        FROM product_price_tier
+        WHERE product_id = $1 AND currency = $2
+        ORDER BY min_seats"#,
+        product_id,
+        currency as _
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Replaces all price tiers of a product in a currency.
+pub async fn set_price_tiers(
+    pool: &Pool<Postgres>,
+    product_id: &str,
+    currency: Currency,
+    tiers: &[PriceTier],
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    replace_price_tiers(&mut transaction, product_id, currency, tiers).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Replaces all price tiers of a product in a currency, as part of a transaction.
+pub(super) async fn replace_price_tiers(
+    transaction: &mut Transaction<'_, Postgres>,
+    product_id: &str,
+    currency: Currency,
+    tiers: &[PriceTier],
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"DELETE FROM product_price_tier WHERE product_id = $1 AND currency = $2"#,
+        product_id,
+        currency as _
+    )
+    .execute(&mut *transaction)
+    .await?;
+    for tier in tiers {
+        query!(
+            r#"INSERT INTO product_price_tier (product_id, currency, min_seats, price_per_user)
+            VALUES ($1, $2, $3, $4)"#,
+            product_id,
+            currency as _,
+            tier.min_seats,
+            tier.price_per_user as _
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+//! Data access for the publishing of products.
+//!
+//! A product is created as a draft, and is published right away or scheduled to be published
+//! at a time, when the scheduled publishing job publishes it. A product is available for purchase
+//! while it is published, the `available` column follows the publish status.
+//! An archived product is no longer sold, but its page stays up for the companies licensing it.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Pool, Postgres};
+use utoipa::ToSchema;
+
+/// Where a product is in its publishing.
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[sqlx(type_name = "publish_status_enum", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PublishStatus {
+    /// Being written, only admins can see it.
+    Draft,
+    /// To be published at its publish time.
+    Scheduled,
+    /// Public and available for purchase.
+    Published,
+    /// Public, but no longer available for purchase.
+    Archived,
+}
+
+impl PublishStatus {
+    /// Returns true if the page of a product with the status is public.
+    pub fn is_public(&self) -> bool {
+        matches!(self, PublishStatus::Published | PublishStatus::Archived)
+    }
+}
+
+/// The publish status of a product.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Publishing {
+    pub product_id: String,
+    pub status: PublishStatus,
+    /// When the product is to be published, only set while it is scheduled.
+    pub publish_at: Option<DateTime<Utc>>,
+}
+
+/// Returns the publish status of a product.
+///
+/// Returns `RowNotFound` if the product does not exist.
+pub async fn get_publishing(
+    pool: &Pool<Postgres>,
+    product_id: &str,
+) -> Result<Publishing, sqlx::Error> {
+    query_as!(
+        Publishing,
+        r#"SELECT product_id, publish_status as "status: _", publish_at
+        FROM product WHERE product_id = $1"#,
+        product_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Sets the publish status of a product, `publish_at` must be given if and only if
+/// the product is scheduled.
+///
+/// Returns `RowNotFound` if the product does not exist.
+pub async fn set_publishing(
+    pool: &Pool<Postgres>,
+    product_id: &str,
+    status: PublishStatus,
+    publish_at: Option<DateTime<Utc>>,
+) -> Result<Publishing, sqlx::Error> {
+    query_as!(
+        Publishing,
+        r#"UPDATE product
+        SET publish_status = $2, publish_at = $3
+        WHERE product_id = $1
+        RETURNING product_id, publish_status as "status: _", publish_at"#,
+        product_id,
+        status as _,
+        publish_at
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Returns true if the page of a product is public, false if it is not or the product does not exist.
+pub async fn is_public(pool: &Pool<Postgres>, product_id: &str) -> Result<bool, sqlx::Error> {
+    match get_publishing(pool, product_id).await {
+        Ok(publishing) => Ok(publishing.status.is_public()),
+        Err(sqlx::Error::RowNotFound) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Publishes the scheduled products whose publish time has come.
+/// Returns the ids of the products published.
+pub async fn publish_scheduled(pool: &Pool<Postgres>) -> Result<Vec<String>, sqlx::Error> {
+    let published = query!(
+        r#"UPDATE product
+        SET publish_status = 'published', publish_at = NULL
+        WHERE publish_status = 'scheduled' AND publish_at <= now()
+        RETURNING product_id"#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(published.into_iter().map(|row| row.product_id).collect())
+}
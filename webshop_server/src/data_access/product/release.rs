@@ -0,0 +1,293 @@
+//! Data access for the released versions of products.
+//!
+//! A release has a version, a changelog and optionally a download. A license covers the releases
+//! of its product released before its end date, so customers keep the versions they paid for.
+//!
+//! The file of a download can be uploaded to file storage, it is then served to users holding
+//! a seat on a license covering the release, and each download is recorded.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Pool, Postgres};
+use utoipa::ToSchema;
+
+use crate::data_access::license::License;
+
+/// A released version of a product.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Release {
+    pub release_id: i32,
+    pub product_id: String,
+    #[schema(example = "3.0.0")]
+    pub version: String,
+    pub release_date: DateTime<Utc>,
+    /// What changed since the previous release.
+    pub changelog: String,
+    /// The download of the release, none if it has none.
+    pub download: Option<ReleaseDownload>,
+    /// Key of the uploaded file of the download in file storage, none if no file is uploaded.
+    #[serde(skip)]
+    pub storage_key: Option<String>,
+}
+
+/// The file to download for a release.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReleaseDownload {
+    #[schema(example = "proflex_tax_solutions-3.0.0.zip")]
+    pub file_name: String,
+    /// Size of the file in bytes.
+    pub file_size: i64,
+    /// SHA-256 checksum of the file, in lowercase hex.
+    #[schema(example = "c5f3e4d6a7b8293a415c6d7e8f9a0b12c3d4e5f60718293a4b5c6d7e8f9a0b12")]
+    pub sha256: String,
+}
+
+/// A release to create, or the new details of a release.
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct PartialRelease {
+    #[schema(example = "3.0.0")]
+    pub version: String,
+    /// When the version was released, now if not given.
+    pub release_date: Option<DateTime<Utc>>,
+    pub changelog: String,
+    pub download: Option<ReleaseDownload>,
+}
+
+/// A release of the product of a license, and whether the license covers it.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LicenseRelease {
+    pub release: Release,
+    /// True if the release was released before the license ended.
+    pub covered: bool,
+}
+
+/// A download of the file of a release.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DownloadEvent {
+    pub event_id: i32,
+    pub release_id: i32,
+    /// The license the file was downloaded through.
+    pub license_id: i32,
+    /// The user who downloaded it, none if the user is deleted.
+    pub user_id: Option<i32>,
+    pub downloaded_at: DateTime<Utc>,
+}
+
+struct ReleaseRow {
+    release_id: i32,
+    product_id: String,
+    version: String,
+    release_date: DateTime<Utc>,
+    changelog: String,
+    file_name: Option<String>,
+    file_size: Option<i64>,
+    sha256: Option<String>,
+    storage_key: Option<String>,
+}
+
+impl From<ReleaseRow> for Release {
+    fn from(row: ReleaseRow) -> Self {
+        let download = match (row.file_name, row.file_size, row.sha256) {
+            (Some(file_name), Some(file_size), Some(sha256)) => Some(ReleaseDownload {
+                file_name,
+                file_size,
+                sha256,
+            }),
+            _ => None,
+        };
+        Release {
+            release_id: row.release_id,
+            product_id: row.product_id,
+            version: row.version,
+            release_date: row.release_date,
+            changelog: row.changelog,
+            download,
+            storage_key: row.storage_key,
+        }
+    }
+}
+
+/// Returns the releases of a product, newest first.
+pub async fn get_releases(
+    pool: &Pool<Postgres>,
+    product_id: &str,
+) -> Result<Vec<Release>, sqlx::Error> {
+    let rows = query_as!(
+        ReleaseRow,
+        r#"SELECT release_id, product_id, version, release_date, changelog,
+        file_name, file_size, sha256, storage_key
+        FROM product_release
+        WHERE product_id = $1
+        ORDER BY release_date DESC, release_id DESC"#,
+        product_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(Release::from).collect())
+}
+
+/// Returns a release by its id.
+pub async fn get_release(pool: &Pool<Postgres>, release_id: &i32) -> Result<Release, sqlx::Error> {
+    query_as!(
+        ReleaseRow,
+        r#"SELECT release_id, product_id, version, release_date, changelog,
+        file_name, file_size, sha256, storage_key
+        FROM product_release
+        WHERE release_id = $1"#,
+        release_id
+    )
+    .fetch_one(pool)
+    .await
+    .map(Release::from)
+}
+
+/// Creates a release of a product.
+///
+/// Fails with a unique violation if the product has a release with the version already.
+pub async fn create_release(
+    pool: &Pool<Postgres>,
+    product_id: &str,
+    release: &PartialRelease,
+) -> Result<Release, sqlx::Error> {
+    let download = release.download.as_ref();
+    query_as!(
+        ReleaseRow,
+        r#"INSERT INTO product_release
+        (product_id, version, release_date, changelog, file_name, file_size, sha256)
+        VALUES ($1, $2, COALESCE($3, now()), $4, $5, $6, $7)
+        RETURNING release_id, product_id, version, release_date, changelog,
+        file_name, file_size, sha256, storage_key"#,
+        product_id,
+        release.version,
+        release.release_date,
+        release.changelog,
+        download.map(|download| download.file_name.as_str()),
+        download.map(|download| download.file_size),
+        download.map(|download| download.sha256.as_str()),
+    )
+    .fetch_one(pool)
+    .await
+    .map(Release::from)
+}
+
+/// Updates the details of a release, keeping its release date if no new one is given.
+/// The uploaded file is only kept if the checksum of the download is unchanged.
+///
+/// Returns `RowNotFound` if the release does not exist.
+pub async fn update_release(
+    pool: &Pool<Postgres>,
+    release_id: &i32,
+    release: &PartialRelease,
+) -> Result<Release, sqlx::Error> {
+    let download = release.download.as_ref();
+    query_as!(
+        ReleaseRow,
+        r#"UPDATE product_release
+        SET version = $2, release_date = COALESCE($3, release_date), changelog = $4,
+        file_name = $5, file_size = $6, sha256 = $7,
+        storage_key = CASE WHEN sha256 IS NOT DISTINCT FROM $7 THEN storage_key END
+        WHERE release_id = $1
+        RETURNING release_id, product_id, version, release_date, changelog,
+        file_name, file_size, sha256, storage_key"#,
+        release_id,
+        release.version,
+        release.release_date,
+        release.changelog,
+        download.map(|download| download.file_name.as_str()),
+        download.map(|download| download.file_size),
+        download.map(|download| download.sha256.as_str()),
+    )
+    .fetch_one(pool)
+    .await
+    .map(Release::from)
+}
+
+/// Sets the download of a release to a file uploaded to file storage.
+///
+/// Returns `RowNotFound` if the release does not exist.
+pub async fn set_release_file(
+    pool: &Pool<Postgres>,
+    release_id: &i32,
+    download: &ReleaseDownload,
+    storage_key: &str,
+) -> Result<Release, sqlx::Error> {
+    query_as!(
+        ReleaseRow,
+        r#"UPDATE product_release
+        SET file_name = $2, file_size = $3, sha256 = $4, storage_key = $5
+        WHERE release_id = $1
+        RETURNING release_id, product_id, version, release_date, changelog,
+        file_name, file_size, sha256, storage_key"#,
+        release_id,
+        download.file_name,
+        download.file_size,
+        download.sha256,
+        storage_key
+    )
+    .fetch_one(pool)
+    .await
+    .map(Release::from)
+}
+
+/// Deletes a release, returns false if it does not exist.
+pub async fn delete_release(pool: &Pool<Postgres>, release_id: &i32) -> Result<bool, sqlx::Error> {
+    let deleted = query!(
+        "DELETE FROM product_release WHERE release_id = $1",
+        release_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(deleted.rows_affected() > 0)
+}
+
+/// Returns the releases of the product of a license, newest first,
+/// with whether the license covers them.
+pub async fn get_license_releases(
+    pool: &Pool<Postgres>,
+    license: &License,
+) -> Result<Vec<LicenseRelease>, sqlx::Error> {
+    let releases = get_releases(pool, license.product_id()).await?;
+    Ok(releases
+        .into_iter()
+        .map(|release| LicenseRelease {
+            covered: release.release_date <= license.end_date(),
+            release,
+        })
+        .collect())
+}
+
+/// Records a download of the file of a release through a license.
+pub async fn record_download(
+    pool: &Pool<Postgres>,
+    release_id: &i32,
+    license_id: &i32,
+    user_id: &i32,
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"INSERT INTO download_event (release_id, license_id, user_id)
+        VALUES ($1, $2, $3)"#,
+        release_id,
+        license_id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns the downloads of the file of a release, newest first.
+pub async fn get_downloads(
+    pool: &Pool<Postgres>,
+    release_id: &i32,
+) -> Result<Vec<DownloadEvent>, sqlx::Error> {
+    query_as!(
+        DownloadEvent,
+        r#"SELECT event_id, release_id, license_id, user_id, downloaded_at
+        FROM download_event
+        WHERE release_id = $1
+        ORDER BY downloaded_at DESC, event_id DESC"#,
+        release_id
+    )
+    .fetch_all(pool)
+    .await
+}
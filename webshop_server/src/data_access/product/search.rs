@@ -0,0 +1,110 @@
+//! Full-text search of products.
+//!
+//! Searches the indexed `search_document` of each product, with its name and short description,
+//! and of each text of its description, with its title and paragraph. A product matches when
+//! either its own document or one of its texts matches the search, and ranks by all of them.
+//! Matches in the name rank above matches in the description.
+
+use serde::Serialize;
+use sqlx::{query, Pool, Postgres};
+use utoipa::ToSchema;
+
+use super::Product;
+use crate::money::{Currency, Money};
+
+/// Options for the ts_headline snippets, matched words are wrapped in `<mark>` tags.
+const SNIPPET_OPTIONS: &str =
+    "StartSel=<mark>, StopSel=</mark>, MaxFragments=2, MaxWords=20, MinWords=5";
+
+/// A product matching a search.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchHit {
+    pub product: Product,
+    /// How well the product matches, higher is better.
+    #[schema(example = 0.56)]
+    pub rank: f32,
+    /// Parts of the description matching the search, matched words wrapped in `<mark>` tags.
+    #[schema(example = "Streamline your <mark>tax</mark> preparation and filing process")]
+    pub snippet: String,
+}
+
+/// What to search for.
+pub struct SearchFilter<'a> {
+    /// Search terms, in web search syntax, e.g. `tax -legal` or `"business process"`.
+    pub text: &'a str,
+    /// Only products in this category.
+    pub category_id: Option<i32>,
+    /// Only products that are, or are not, available.
+    pub available: Option<bool>,
+}
+
+/// Returns the products matching a search, best match first.
+///
+/// # Arguments
+///
+/// * `pool` - The database pool
+/// * `filter` - The search terms and filters
+pub async fn search_products(
+    pool: &Pool<Postgres>,
+    filter: &SearchFilter<'_>,
+) -> Result<Vec<SearchHit>, sqlx::Error> {
+    let rows = query!(
+        r#"WITH search_query AS (
+            SELECT websearch_to_tsquery('english', $1) AS query
+        ),
+        matched AS (
+            SELECT product.product_id, ts_rank(product.search_document, search_query.query) AS rank
+            FROM product, search_query
+            WHERE product.search_document @@ search_query.query
+            UNION ALL
+            SELECT description_component.product_id,
+            ts_rank(product_text.search_document, search_query.query)
+            FROM product_text
+            JOIN description_component ON description_component.text_id = product_text.text_id,
+            search_query
+            WHERE product_text.search_document @@ search_query.query
+        )
+        SELECT product.product_id, display_name, price_per_user as "price_per_user: Money",
+        currency as "currency: Currency", short_description, main_image, available,
+        SUM(matched.rank)::REAL as "rank!",
+        ts_headline('english', short_description || ' ' || COALESCE((
+            SELECT string_agg(product_text.paragraph, ' ' ORDER BY description_component.priority)
+            FROM description_component
+            JOIN product_text ON product_text.text_id = description_component.text_id
+            WHERE description_component.product_id = product.product_id
+        ), ''), search_query.query, $4) as "snippet!"
+        FROM matched
+        JOIN product ON product.product_id = matched.product_id,
+        search_query
+        WHERE ($2::INT IS NULL OR EXISTS (
+            SELECT 1 FROM product_category
+            WHERE product_category.product_id = product.product_id
+            AND product_category.category_id = $2))
+        AND ($3::BOOLEAN IS NULL OR available = $3)
+        GROUP BY product.product_id, search_query.query
+        ORDER BY 8 DESC, display_name"#,
+        filter.text,
+        filter.category_id,
+        filter.available,
+        SNIPPET_OPTIONS
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| SearchHit {
+            product: Product::new(
+                &row.product_id,
+                &row.display_name,
+                row.price_per_user,
+                row.currency,
+                &row.short_description,
+                &row.main_image,
+                row.available,
+            ),
+            rank: row.rank,
+            snippet: row.snippet,
+        })
+        .collect())
+}
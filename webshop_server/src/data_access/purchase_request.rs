@@ -0,0 +1,230 @@
+//! Data access for purchase requests.
+//!
+//! Any company user can ask the IT heads of their company to buy a license.
+//! An IT head approves the request, which buys the license, or rejects it.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query_as, Pool, Postgres, Transaction};
+use utoipa::ToSchema;
+
+use crate::data_access::license::LicenseType;
+
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[sqlx(type_name = "purchase_request_status_enum", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PurchaseRequestStatus {
+    /// Waiting for an IT head to decide.
+    Pending,
+    /// Bought by an IT head.
+    Approved,
+    /// Turned down by an IT head.
+    Rejected,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PurchaseRequest {
+    pub purchase_request_id: i32,
+    pub company_id: i32,
+    /// The user who asked, none if the user has been deleted.
+    pub requested_by: Option<i32>,
+    pub product_id: String,
+    /// Number of seats.
+    pub seats: i32,
+    /// Length of the license in years.
+    pub term_years: i32,
+    pub license_type: LicenseType,
+    /// Why the license is needed.
+    pub comment: Option<String>,
+    pub status: PurchaseRequestStatus,
+    pub created_at: DateTime<Utc>,
+    /// The IT head who approved or rejected the request.
+    pub decided_by: Option<i32>,
+    pub decided_at: Option<DateTime<Utc>>,
+    /// Why the request was approved or rejected.
+    pub decision_note: Option<String>,
+    /// The order buying the license, once approved.
+    pub order_id: Option<i32>,
+}
+
+/// Purchase request to create, everything else is set by the server.
+pub struct PartialPurchaseRequest {
+    pub company_id: i32,
+    pub requested_by: i32,
+    pub product_id: String,
+    pub seats: i32,
+    pub term_years: i32,
+    pub license_type: LicenseType,
+    pub comment: Option<String>,
+}
+
+/// Creates a pending purchase request.
+pub async fn create_purchase_request(
+    pool: &Pool<Postgres>,
+    request: &PartialPurchaseRequest,
+) -> Result<PurchaseRequest, sqlx::Error> {
+    query_as!(
+        PurchaseRequest,
+        r#"INSERT INTO purchase_request
+        (company_id, requested_by, product_id, seats, term_years, license_type, comment)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING purchase_request_id, company_id, requested_by, product_id, seats, term_years,
+        license_type as "license_type: _", comment, status as "status: _", created_at,
+        decided_by, decided_at, decision_note, order_id"#,
+        request.company_id,
+        request.requested_by,
+        request.product_id,
+        request.seats,
+        request.term_years,
+        request.license_type as _,
+        request.comment
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Returns all purchase requests, newest first.
+pub async fn get_purchase_requests(
+    pool: &Pool<Postgres>,
+) -> Result<Vec<PurchaseRequest>, sqlx::Error> {
+    query_as!(
+        PurchaseRequest,
+        r#"SELECT purchase_request_id, company_id, requested_by, product_id, seats, term_years,
+        license_type as "license_type: _", comment, status as "status: _", created_at,
+        decided_by, decided_at, decision_note, order_id
+        FROM purchase_request
+        ORDER BY purchase_request_id DESC"#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns the purchase requests of a company, newest first.
+pub async fn get_purchase_requests_by_company(
+    pool: &Pool<Postgres>,
+    company_id: &i32,
+) -> Result<Vec<PurchaseRequest>, sqlx::Error> {
+    query_as!(
+        PurchaseRequest,
+        r#"SELECT purchase_request_id, company_id, requested_by, product_id, seats, term_years,
+        license_type as "license_type: _", comment, status as "status: _", created_at,
+        decided_by, decided_at, decision_note, order_id
+        FROM purchase_request
+        WHERE company_id = $1
+        ORDER BY purchase_request_id DESC"#,
+        company_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns the purchase requests a user made, newest first.
+pub async fn get_purchase_requests_by_user(
+    pool: &Pool<Postgres>,
+    user_id: &i32,
+) -> Result<Vec<PurchaseRequest>, sqlx::Error> {
+    query_as!(
+        PurchaseRequest,
+        r#"SELECT purchase_request_id, company_id, requested_by, product_id, seats, term_years,
+        license_type as "license_type: _", comment, status as "status: _", created_at,
+        decided_by, decided_at, decision_note, order_id
+        FROM purchase_request
+        WHERE requested_by = $1
+        ORDER BY purchase_request_id DESC"#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns a purchase request.
+pub async fn get_purchase_request_by_id(
+    pool: &Pool<Postgres>,
+    purchase_request_id: &i32,
+) -> Result<PurchaseRequest, sqlx::Error> {
+    query_as!(
+        PurchaseRequest,
+        r#"SELECT purchase_request_id, company_id, requested_by, product_id, seats, term_years,
+        license_type as "license_type: _", comment, status as "status: _", created_at,
+        decided_by, decided_at, decision_note, order_id
+        FROM purchase_request
+        WHERE purchase_request_id = $1"#,
+        purchase_request_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Returns a purchase request and locks it until the end of the transaction,
+/// so it cannot be decided twice.
+pub async fn lock_purchase_request(
+    transaction: &mut Transaction<'_, Postgres>,
+    purchase_request_id: &i32,
+) -> Result<PurchaseRequest, sqlx::Error> {
+    query_as!(
+        PurchaseRequest,
+        r#"SELECT purchase_request_id, company_id, requested_by, product_id, seats, term_years,
+        license_type as "license_type: _", comment, status as "status: _", created_at,
+        decided_by, decided_at, decision_note, order_id
+        FROM purchase_request
+        WHERE purchase_request_id = $1
+        FOR UPDATE"#,
+        purchase_request_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+}
+
+/// Marks a purchase request as approved, and bought with an order.
+pub async fn set_approved(
+    transaction: &mut Transaction<'_, Postgres>,
+    purchase_request_id: &i32,
+    decided_by: &i32,
+    decision_note: Option<&str>,
+    order_id: &i32,
+) -> Result<PurchaseRequest, sqlx::Error> {
+    query_as!(
+        PurchaseRequest,
+        r#"UPDATE purchase_request
+        SET status = $2, decided_by = $3, decided_at = now(), decision_note = $4, order_id = $5
+        WHERE purchase_request_id = $1
+        RETURNING purchase_request_id, company_id, requested_by, product_id, seats, term_years,
+        license_type as "license_type: _", comment, status as "status: _", created_at,
+        decided_by, decided_at, decision_note, order_id"#,
+        purchase_request_id,
+        PurchaseRequestStatus::Approved as _,
+        decided_by,
+        decision_note,
+        order_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+}
+
+/// Rejects a pending purchase request.
+///
+/// # Returns
+/// The rejected request, none if the request is not pending.
+pub async fn reject(
+    pool: &Pool<Postgres>,
+    purchase_request_id: &i32,
+    decided_by: &i32,
+    decision_note: Option<&str>,
+) -> Result<Option<PurchaseRequest>, sqlx::Error> {
+    query_as!(
+        PurchaseRequest,
+        r#"UPDATE purchase_request
+        SET status = $2, decided_by = $3, decided_at = now(), decision_note = $4
+        WHERE purchase_request_id = $1 AND status = $5
+        RETURNING purchase_request_id, company_id, requested_by, product_id, seats, term_years,
+        license_type as "license_type: _", comment, status as "status: _", created_at,
+        decided_by, decided_at, decision_note, order_id"#,
+        purchase_request_id,
+        PurchaseRequestStatus::Rejected as _,
+        decided_by,
+        decision_note,
+        PurchaseRequestStatus::Pending as _
+    )
+    .fetch_optional(pool)
+    .await
+}
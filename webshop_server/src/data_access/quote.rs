@@ -0,0 +1,421 @@
+//! Data access for quotes, formal offers made to a company before it buys.
+//!
+//! An admin prepares a quote with the licenses offered and their prices, which can differ
+//! from the list prices, and sends it to the company. An IT head of the company accepts it
+//! while it is valid, which buys all of its licenses in one order.
+//! Each quote gets a quote number of the form `Q-YYYY-NNNNNN`,
+//! numbered sequentially within the year it was made.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Datelike, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Pool, Postgres, Transaction};
+use utoipa::ToSchema;
+
+use crate::{
+    data_access::license::LicenseType,
+    money::{Currency, Money},
+};
+
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[sqlx(type_name = "quote_status_enum", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum QuoteStatus {
+    /// Being prepared, only admins can see it.
+    Draft,
+    /// Sent to the company, which can accept it while it is valid.
+    Sent,
+    /// Accepted by the company, and bought.
+    Accepted,
+    /// Taken back by an admin.
+    Withdrawn,
+}
+
+/// A license offered in a quote, and its price.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct QuoteLine {
+    pub line_number: i32,
+    pub product_id: String,
+    /// Number of seats.
+    pub seats: i32,
+    /// Length of the license in years.
+    pub term_years: i32,
+    pub license_type: LicenseType,
+    /// Price per user per year offered.
+    #[schema(value_type = String, example = "150.00")]
+    pub unit_price: Money,
+    /// Price per user per year of the product when the quote was made.
+    #[schema(value_type = String, example = "179.00")]
+    pub list_price: Money,
+    /// Price of all seats for the whole term.
+    #[schema(value_type = String, example = "45000.00")]
+    pub amount: Money,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Quote {
+    pub quote_id: i32,
+    #[schema(example = "Q-2023-000001")]
+    pub quote_number: String,
+    pub company_id: i32,
+    /// Currency of all prices.
+    pub currency: Currency,
+    pub status: QuoteStatus,
+    /// Last moment the quote can be accepted.
+    pub valid_until: DateTime<Utc>,
+    /// Terms of the offer, for the company.
+    pub note: Option<String>,
+    /// The admin who made the quote, none if the user has been deleted.
+    pub created_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    /// When the quote was last sent to the company.
+    pub sent_at: Option<DateTime<Utc>>,
+    /// The IT head who accepted the quote.
+    pub accepted_by: Option<i32>,
+    pub accepted_at: Option<DateTime<Utc>>,
+    /// The order buying the licenses, once accepted.
+    pub order_id: Option<i32>,
+    /// Sum of the lines, before tax.
+    #[schema(value_type = String, example = "45000.00")]
+    pub total: Money,
+    pub lines: Vec<QuoteLine>,
+}
+
+/// Quote without its lines, as stored in the `quote` table.
+struct QuoteRow {
+    quote_id: i32,
+    quote_number: String,
+    company_id: i32,
+    currency: Currency,
+    status: QuoteStatus,
+    valid_until: DateTime<Utc>,
+    note: Option<String>,
+    created_by: Option<i32>,
+    created_at: DateTime<Utc>,
+    sent_at: Option<DateTime<Utc>>,
+    accepted_by: Option<i32>,
+    accepted_at: Option<DateTime<Utc>>,
+    order_id: Option<i32>,
+}
+
+/// Quote line with the quote it belongs to.
+struct QuoteLineRow {
+    quote_id: i32,
+    line_number: i32,
+    product_id: String,
+    seats: i32,
+    term_years: i32,
+    license_type: LicenseType,
+    unit_price: Money,
+    list_price: Money,
+    amount: Money,
+}
+
+/// License to offer in a new quote.
+pub struct PartialQuoteLine {
+    pub product_id: String,
+    pub seats: i32,
+    pub term_years: i32,
+    pub license_type: LicenseType,
+    pub unit_price: Money,
+    pub list_price: Money,
+}
+
+/// Quote to create, everything else is set by the server.
+pub struct PartialQuote {
+    pub company_id: i32,
+    pub currency: Currency,
+    pub valid_until: DateTime<Utc>,
+    pub note: Option<String>,
+    pub created_by: i32,
+    pub lines: Vec<PartialQuoteLine>,
+}
+
+impl QuoteRow {
+    fn with_lines(self, lines: Vec<QuoteLine>) -> Quote {
+        Quote {
+            quote_id: self.quote_id,
+            quote_number: self.quote_number,
+            company_id: self.company_id,
+            currency: self.currency,
+            status: self.status,
+            valid_until: self.valid_until,
+            note: self.note,
+            created_by: self.created_by,
+            created_at: self.created_at,
+            sent_at: self.sent_at,
+            accepted_by: self.accepted_by,
+            accepted_at: self.accepted_at,
+            order_id: self.order_id,
+            total: lines.iter().map(|line| line.amount).sum(),
+            lines,
+        }
+    }
+}
+
+impl From<QuoteLineRow> for QuoteLine {
+    fn from(row: QuoteLineRow) -> Self {
+        QuoteLine {
+            line_number: row.line_number,
+            product_id: row.product_id,
+            seats: row.seats,
+            term_years: row.term_years,
+            license_type: row.license_type,
+            unit_price: row.unit_price,
+            list_price: row.list_price,
+            amount: row.amount,
+        }
+    }
+}
+
+impl Quote {
+    /// Whether the company can still accept the quote.
+    pub fn is_acceptable(&self) -> bool {
+        self.status == QuoteStatus::Sent && self.valid_until > Utc::now()
+    }
+}
+
+async fn next_quote_number(
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<String, sqlx::Error> {
+    let year = Utc::now().year();
+    let number = query!(
+        r#"INSERT INTO quote_counter (year, last_number)
+        VALUES ($1, 1)
+        ON CONFLICT (year) DO UPDATE
+        SET last_number = quote_counter.last_number + 1
+        RETURNING last_number"#,
+        year
+    )
+    .fetch_one(&mut *transaction)
+    .await?
+    .last_number;
+    Ok(format!("Q-{}-{:06}", year, number))
+}
+
+/// Adds the lines of each quote to it.
+async fn attach_lines<'e, E>(executor: E, quotes: Vec<QuoteRow>) -> Result<Vec<Quote>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = Postgres>,
+{
+    let quote_ids: Vec<i32> = quotes.iter().map(|quote| quote.quote_id).collect();
+    let rows = query_as!(
+        QuoteLineRow,
+        r#"SELECT quote_id, line_number, product_id, seats, term_years,
+        license_type as "license_type: _", unit_price as "unit_price: _",
+        list_price as "list_price: _", amount as "amount: _"
+        FROM quote_line
+        WHERE quote_id = ANY($1)
+        ORDER BY quote_id, line_number"#,
+        &quote_ids
+    )
+    .fetch_all(executor)
+    .await?;
+
+    let mut lines: HashMap<i32, Vec<QuoteLine>> = HashMap::new();
+    for row in rows {
+        lines.entry(row.quote_id).or_default().push(row.into());
+    }
+    Ok(quotes
+        .into_iter()
+        .map(|quote| {
+            let quote_lines = lines.remove(&quote.quote_id).unwrap_or_default();
+            quote.with_lines(quote_lines)
+        })
+        .collect())
+}
+
+/// Creates a draft quote with its lines, priced at the unit price of each line.
+pub async fn create_quote(
+    pool: &Pool<Postgres>,
+    quote: &PartialQuote,
+) -> Result<Quote, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let quote_number = next_quote_number(&mut transaction).await?;
+    let row = query_as!(
+        QuoteRow,
+        r#"INSERT INTO quote (quote_number, company_id, currency, valid_until, note, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING quote_id, quote_number, company_id, currency as "currency: _",
+        status as "status: _", valid_until, note, created_by, created_at, sent_at,
+        accepted_by, accepted_at, order_id"#,
+        quote_number,
+        quote.company_id,
+        quote.currency as _,
+        quote.valid_until,
+        quote.note,
+        quote.created_by
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+    for (line_number, line) in (1..).zip(quote.lines.iter()) {
+        let amount = line.unit_price * line.seats as i64 * line.term_years as i64;
+        query!(
+            r#"INSERT INTO quote_line (quote_id, line_number, product_id, seats, term_years,
+            license_type, unit_price, list_price, amount)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+            row.quote_id,
+            line_number,
+            line.product_id,
+            line.seats,
+            line.term_years,
+            line.license_type as _,
+            line.unit_price as _,
+            line.list_price as _,
+            amount as _
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+    let mut quotes = attach_lines(&mut transaction, vec![row]).await?;
+    transaction.commit().await?;
+    Ok(quotes.remove(0))
+}
+
+/// Returns all quotes, newest first.
+pub async fn get_quotes(pool: &Pool<Postgres>) -> Result<Vec<Quote>, sqlx::Error> {
+    let quotes = query_as!(
+        QuoteRow,
+        r#"SELECT quote_id, quote_number, company_id, currency as "currency: _",
+        status as "status: _", valid_until, note, created_by, created_at, sent_at,
+        accepted_by, accepted_at, order_id
+        FROM quote
+        ORDER BY quote_id DESC"#
+    )
+    .fetch_all(pool)
+    .await?;
+    attach_lines(pool, quotes).await
+}
+
+/// Returns the quotes made to a company, newest first.
+pub async fn get_quotes_by_company(
+    pool: &Pool<Postgres>,
+    company_id: &i32,
+) -> Result<Vec<Quote>, sqlx::Error> {
+    let quotes = query_as!(
+        QuoteRow,
+        r#"SELECT quote_id, quote_number, company_id, currency as "currency: _",
+        status as "status: _", valid_until, note, created_by, created_at, sent_at,
+        accepted_by, accepted_at, order_id
+        FROM quote
+        WHERE company_id = $1
+        ORDER BY quote_id DESC"#,
+        company_id
+    )
+    .fetch_all(pool)
+    .await?;
+    attach_lines(pool, quotes).await
+}
+
+pub async fn get_quote_by_id(pool: &Pool<Postgres>, quote_id: &i32) -> Result<Quote, sqlx::Error> {
+    let quote = query_as!(
+        QuoteRow,
+        r#"SELECT quote_id, quote_number, company_id, currency as "currency: _",
+        status as "status: _", valid_until, note, created_by, created_at, sent_at,
+        accepted_by, accepted_at, order_id
+        FROM quote
+        WHERE quote_id = $1"#,
+        quote_id
+    )
+    .fetch_one(pool)
+    .await?;
+    let mut quotes = attach_lines(pool, vec![quote]).await?;
+    Ok(quotes.remove(0))
+}
+
+/// Returns a quote and locks it until the end of the transaction,
+/// so it cannot be accepted twice.
+pub async fn lock_quote(
+    transaction: &mut Transaction<'_, Postgres>,
+    quote_id: &i32,
+) -> Result<Quote, sqlx::Error> {
+    let quote = query_as!(
+        QuoteRow,
+        r#"SELECT quote_id, quote_number, company_id, currency as "currency: _",
+        status as "status: _", valid_until, note, created_by, created_at, sent_at,
+        accepted_by, accepted_at, order_id
+        FROM quote
+        WHERE quote_id = $1
+        FOR UPDATE"#,
+        quote_id
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    let mut quotes = attach_lines(&mut *transaction, vec![quote]).await?;
+    Ok(quotes.remove(0))
+}
+
+/// Marks a draft or sent quote that is still valid as sent now.
+///
+/// # Returns
+/// The sent quote, none if it cannot be sent.
+pub async fn set_sent(pool: &Pool<Postgres>, quote_id: &i32) -> Result<Option<Quote>, sqlx::Error> {
+    let quote = query_as!(
+        QuoteRow,
+        r#"UPDATE quote SET status = $2, sent_at = now()
+        WHERE quote_id = $1 AND status IN ($2, $3) AND valid_until > now()
+        RETURNING quote_id, quote_number, company_id, currency as "currency: _",
+        status as "status: _", valid_until, note, created_by, created_at, sent_at,
+        accepted_by, accepted_at, order_id"#,
+        quote_id,
+        QuoteStatus::Sent as _,
+        QuoteStatus::Draft as _
+    )
+    .fetch_optional(pool)
+    .await?;
+    match quote {
+        Some(quote) => Ok(attach_lines(pool, vec![quote]).await?.pop()),
+        None => Ok(None),
+    }
+}
+
+/// Withdraws a quote that has not been accepted.
+///
+/// # Returns
+/// The withdrawn quote, none if it has been accepted or withdrawn already.
+pub async fn withdraw(pool: &Pool<Postgres>, quote_id: &i32) -> Result<Option<Quote>, sqlx::Error> {
+    let quote = query_as!(
+        QuoteRow,
+        r#"UPDATE quote SET status = $2
+        WHERE quote_id = $1 AND status IN ($3, $4)
+        RETURNING quote_id, quote_number, company_id, currency as "currency: _",
+        status as "status: _", valid_until, note, created_by, created_at, sent_at,
+        accepted_by, accepted_at, order_id"#,
+        quote_id,
+        QuoteStatus::Withdrawn as _,
+        QuoteStatus::Draft as _,
+        QuoteStatus::Sent as _
+    )
+    .fetch_optional(pool)
+    .await?;
+    match quote {
+        Some(quote) => Ok(attach_lines(pool, vec![quote]).await?.pop()),
+        None => Ok(None),
+    }
+}
+
+/// Marks a quote as accepted, and bought with an order.
+pub async fn set_accepted(
+    transaction: &mut Transaction<'_, Postgres>,
+    quote_id: &i32,
+    accepted_by: &i32,
+    order_id: &i32,
+) -> Result<Quote, sqlx::Error> {
+    let quote = query_as!(
+        QuoteRow,
+        r#"UPDATE quote SET status = $2, accepted_by = $3, accepted_at = now(), order_id = $4
+        WHERE quote_id = $1
+        RETURNING quote_id, quote_number, company_id, currency as "currency: _",
+        status as "status: _", valid_until, note, created_by, created_at, sent_at,
+        accepted_by, accepted_at, order_id"#,
+        quote_id,
+        QuoteStatus::Accepted as _,
+        accepted_by,
+        order_id
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    let mut quotes = attach_lines(&mut *transaction, vec![quote]).await?;
+    Ok(quotes.remove(0))
+}
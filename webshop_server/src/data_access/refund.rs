@@ -0,0 +1,442 @@
+//! Data access for refunds of orders.
+//!
+//! An admin can pay back part or all of a paid order, through the payment provider it was paid with.
+//! An order crediting a company, for removed seats or an early renewal, is paid back the same way,
+//! by refunding the earlier orders of the license.
+//! Each refund gets a credit note number of the form `CN-YYYY-NNNNNN`,
+//! numbered sequentially within the year it was made.
+//! A refund can leave the licenses of the order as they are, end them earlier,
+//! or revoke them, which also takes the users off them and cancels their subscriptions.
+
+use chrono::{DateTime, Datelike, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Pool, Postgres, Transaction};
+use utoipa::ToSchema;
+
+use crate::{
+    data_access::{license::subscription::SubscriptionStatus, order::OrderStatus},
+    money::Money,
+    payment::PaymentStatus,
+};
+
+/// What a refund does to the licenses of the order.
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[sqlx(type_name = "license_action_enum", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum LicenseAction {
+    /// The licenses are left as they are.
+    Keep,
+    /// The licenses end earlier.
+    Shorten,
+    /// The licenses are deactivated and their users removed.
+    Revoke,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Refund {
+    pub refund_id: i32,
+    pub order_id: i32,
+    #[schema(example = "CN-2023-000001")]
+    pub credit_note_number: String,
+    /// Payment provider the money was paid back through.
+    pub provider: String,
+    /// Id of the refund at the provider.
+    pub provider_refund_id: String,
+    /// Amount paid back, tax included, in the currency of the order.
+    #[schema(value_type = String, example = "1342.50")]
+    pub amount: Money,
+    /// Part of the amount that is tax.
+    #[schema(value_type = String, example = "268.50")]
+    pub tax: Money,
+    pub reason: Option<String>,
+    pub license_action: LicenseAction,
+    /// End date the licenses were shortened to.
+    pub license_end_date: Option<DateTime<Utc>>,
+    /// The admin who made the refund, or the user whose credit it pays back,
+    /// none if the user has been deleted.
+    pub created_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    /// Order crediting the company that the refund pays back, none for refunds made by an admin.
+    pub credit_order_id: Option<i32>,
+}
+
+/// Refund to record, once the provider has paid it back.
+pub struct PartialRefund {
+    pub order_id: i32,
+    pub provider: String,
+    pub provider_refund_id: String,
+    pub amount: Money,
+    pub tax: Money,
+    pub reason: Option<String>,
+    pub license_action: LicenseAction,
+    pub license_end_date: Option<DateTime<Utc>>,
+    pub created_by: Option<i32>,
+    pub credit_order_id: Option<i32>,
+}
+
+/// The payment an order was paid with.
+pub struct OrderPayment {
+    pub provider: String,
+    pub intent_id: String,
+}
+
+/// Returns the refunds of an order, oldest first.
+pub async fn get_refunds_by_order(
+    pool: &Pool<Postgres>,
+    order_id: &i32,
+) -> Result<Vec<Refund>, sqlx::Error> {
+    query_as!(
+        Refund,
+        r#"SELECT refund_id, order_id, credit_note_number, provider, provider_refund_id,
+        amount as "amount: _", tax as "tax: _", reason, license_action as "license_action: _",
+        license_end_date, created_by, created_at, credit_order_id
+        FROM refund
+        WHERE order_id = $1
+        ORDER BY refund_id"#,
+        order_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_refund_by_id(
+    pool: &Pool<Postgres>,
+    refund_id: &i32,
+) -> Result<Refund, sqlx::Error> {
+    query_as!(
+        Refund,
+        r#"SELECT refund_id, order_id, credit_note_number, provider, provider_refund_id,
+        amount as "amount: _", tax as "tax: _", reason, license_action as "license_action: _",
+        license_end_date, created_by, created_at, credit_order_id
+        FROM refund
+        WHERE refund_id = $1"#,
+        refund_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Returns the succeeded payment of an order, none if the order was not paid through a provider.
+pub async fn get_order_payment(
+    pool: &Pool<Postgres>,
+    order_id: &i32,
+) -> Result<Option<OrderPayment>, sqlx::Error> {
+    query_as!(
+        OrderPayment,
+        r#"SELECT provider, intent_id
+        FROM payment
+        WHERE order_id = $1 AND status = $2"#,
+        order_id,
+        PaymentStatus::Succeeded as _
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// A paid order of a license, whose payment a credit on the license can be paid back from.
+pub struct CreditSource {
+    pub order_id: i32,
+    pub total: Money,
+    pub provider: String,
+    pub intent_id: String,
+}
+
+/// Returns the paid orders of a license that were paid with a payment, newest first.
+pub async fn get_credit_sources(
+    pool: &Pool<Postgres>,
+    license_id: &i32,
+) -> Result<Vec<CreditSource>, sqlx::Error> {
+    query_as!(
+        CreditSource,
+        r#"SELECT "order".order_id, "order".total as "total: _", payment.provider,
+        payment.intent_id
+        FROM "order"
+        JOIN payment ON payment.order_id = "order".order_id AND payment.status = $2
+        WHERE "order".status = $3 AND "order".total > 0
+        AND EXISTS (
+            SELECT 1 FROM order_line
+            WHERE order_line.order_id = "order".order_id AND order_line.license_id = $1
+        )
+        ORDER BY "order".created_at DESC, "order".order_id DESC"#,
+        license_id,
+        PaymentStatus::Succeeded as _,
+        OrderStatus::Paid as _
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns what can still be paid back to the company of a license: what is left to refund
+/// of its paid orders, less the credits of its orders still being paid back.
+pub async fn get_refundable(
+    transaction: &mut Transaction<'_, Postgres>,
+    license_id: &i32,
+) -> Result<Money, sqlx::Error> {
+    let refundable = query!(
+        r#"WITH license_order AS (
+            SELECT "order".order_id, "order".status, "order".total
+            FROM "order"
+            WHERE EXISTS (
+                SELECT 1 FROM order_line
+                WHERE order_line.order_id = "order".order_id AND order_line.license_id = $1
+            )
+        )
+        SELECT ((
+            SELECT COALESCE(SUM(license_order.total - COALESCE((
+                SELECT SUM(refund.amount) FROM refund
+                WHERE refund.order_id = license_order.order_id
+            ), 0)), 0)
+            FROM license_order
+            JOIN payment ON payment.order_id = license_order.order_id AND payment.status = $3
+            WHERE license_order.status = $2 AND license_order.total > 0
+        ) - (
+            SELECT COALESCE(SUM(-license_order.total - COALESCE((
+                SELECT SUM(refund.amount) FROM refund
+                WHERE refund.credit_order_id = license_order.order_id
+            ), 0)), 0)
+            FROM license_order
+            WHERE license_order.status = $4 AND license_order.total < 0
+        ))::BIGINT as "refundable!: Money""#,
+        license_id,
+        OrderStatus::Paid as _,
+        PaymentStatus::Succeeded as _,
+        OrderStatus::Pending as _
+    )
+    .fetch_one(&mut *transaction)
+    .await?
+    .refundable;
+    Ok(refundable)
+}
+
+/// An order crediting a company, whose credit is being paid back.
+pub struct CreditOrder {
+    pub order_id: i32,
+    pub invoice_number: String,
+    pub user_id: Option<i32>,
+    /// License credited, whose paid orders the credit is paid back from.
+    pub license_id: i32,
+    /// Credit to pay back, tax included.
+    pub credit: Money,
+    /// Part of the credit that is tax.
+    pub credit_tax: Money,
+    /// Credit already paid back by earlier refunds, tax included.
+    pub paid_back: Money,
+    /// Part of what was paid back that is tax.
+    pub paid_back_tax: Money,
+}
+
+/// Locks an order crediting a company until the transaction ends, and returns it with what
+/// has been paid back of its credit. None if the order is not waiting for its credit to be paid back.
+///
+/// The credit of an order is paid back by one transaction at a time, so it is never paid back twice.
+pub async fn lock_credit_order(
+    transaction: &mut Transaction<'_, Postgres>,
+    order_id: &i32,
+) -> Result<Option<CreditOrder>, sqlx::Error> {
+    query_as!(
+        CreditOrder,
+        r#"SELECT "order".order_id, "order".invoice_number, "order".user_id,
+        (
+            SELECT order_line.license_id FROM order_line
+            WHERE order_line.order_id = "order".order_id
+            ORDER BY order_line.order_line_id LIMIT 1
+        ) as "license_id!",
+        -"order".total as "credit!: Money", -"order".tax as "credit_tax!: Money",
+        COALESCE((
+            SELECT SUM(refund.amount) FROM refund WHERE refund.credit_order_id = "order".order_id
+        ), 0)::BIGINT as "paid_back!: Money",
+        COALESCE((
+            SELECT SUM(refund.tax) FROM refund WHERE refund.credit_order_id = "order".order_id
+        ), 0)::BIGINT as "paid_back_tax!: Money"
+        FROM "order"
+        WHERE "order".order_id = $1 AND "order".status = $2 AND "order".total < 0
+        FOR NO KEY UPDATE"#,
+        order_id,
+        OrderStatus::Pending as _
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+}
+
+/// Returns the ids of the orders crediting a company whose credit is still being paid back,
+/// oldest first.
+pub async fn get_pending_credit_order_ids(pool: &Pool<Postgres>) -> Result<Vec<i32>, sqlx::Error> {
+    Ok(query!(
+        r#"SELECT order_id FROM "order"
+        WHERE status = $1 AND total < 0
+        ORDER BY order_id"#,
+        OrderStatus::Pending as _
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|order| order.order_id)
+    .collect())
+}
+
+/// Marks an order crediting a company paid, once its credit has been paid back.
+/// The order was applied to its licenses when it was created.
+///
+/// The order must be locked with [`lock_credit_order`] in the transaction,
+/// which the caller commits.
+pub async fn settle_credit_order(
+    transaction: &mut Transaction<'_, Postgres>,
+    order_id: &i32,
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"UPDATE "order" SET status = $2
+        WHERE order_id = $1 AND status = $3 AND total < 0"#,
+        order_id,
+        OrderStatus::Paid as _,
+        OrderStatus::Pending as _
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+async fn next_credit_note_number(
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<String, sqlx::Error> {
+    let year = Utc::now().year();
+    let number = query!(
+        r#"INSERT INTO credit_note_counter (year, last_number)
+        VALUES ($1, 1)
+        ON CONFLICT (year) DO UPDATE
+        SET last_number = credit_note_counter.last_number + 1
+        RETURNING last_number"#,
+        year
+    )
+    .fetch_one(&mut *transaction)
+    .await?
+    .last_number;
+    Ok(format!("CN-{}-{:06}", year, number))
+}
+
+/// Locks an order for refunding until the transaction ends, and returns its refunds, oldest first.
+///
+/// Refunds of an order are made one at a time, so what is left of it is never paid back twice.
+/// Returns `RowNotFound` if the order does not exist.
+pub async fn lock_order_refunds(
+    transaction: &mut Transaction<'_, Postgres>,
+    order_id: &i32,
+) -> Result<Vec<Refund>, sqlx::Error> {
+    query!(
+        r#"SELECT order_id FROM "order" WHERE order_id = $1 FOR UPDATE"#,
+        order_id
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    query_as!(
+        Refund,
+        r#"SELECT refund_id, order_id, credit_note_number, provider, provider_refund_id,
+        amount as "amount: _", tax as "tax: _", reason, license_action as "license_action: _",
+        license_end_date, created_by, created_at, credit_order_id
+        FROM refund
+        WHERE order_id = $1
+        ORDER BY refund_id"#,
+        order_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+}
+
+/// Records a refund paid back by the provider, with its credit note number,
+/// and applies its license action to the given licenses of the order.
+///
+/// The order must be locked with [`lock_order_refunds`] in the transaction,
+/// which the caller commits.
+pub async fn create_refund(
+    transaction: &mut Transaction<'_, Postgres>,
+    refund: &PartialRefund,
+    license_ids: &[i32],
+) -> Result<Refund, sqlx::Error> {
+    let credit_note_number = next_credit_note_number(transaction).await?;
+    let created = query_as!(
+        Refund,
+        r#"INSERT INTO refund (order_id, credit_note_number, provider, provider_refund_id,
+        amount, tax, reason, license_action, license_end_date, created_by, credit_order_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        RETURNING refund_id, order_id, credit_note_number, provider, provider_refund_id,
+        amount as "amount: _", tax as "tax: _", reason, license_action as "license_action: _",
+        license_end_date, created_by, created_at, credit_order_id"#,
+        refund.order_id,
+        credit_note_number,
+        refund.provider,
+        refund.provider_refund_id,
+        refund.amount as _,
+        refund.tax as _,
+        refund.reason,
+        refund.license_action as _,
+        refund.license_end_date,
+        refund.created_by,
+        refund.credit_order_id
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    match refund.license_action {
+        LicenseAction::Keep => (),
+        LicenseAction::Shorten => {
+            query!(
+                r#"UPDATE license SET end_date = LEAST(end_date, $2)
+                WHERE license_id = ANY($1)"#,
+                license_ids,
+                refund.license_end_date
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+        LicenseAction::Revoke => revoke_licenses(transaction, license_ids).await?,
+    }
+    Ok(created)
+}
+
+/// Deactivates licenses, takes their users and checkouts off them,
+/// and cancels their subscriptions along with any unpaid renewal.
+async fn revoke_licenses(
+    transaction: &mut Transaction<'_, Postgres>,
+    license_ids: &[i32],
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"UPDATE license SET valid = false WHERE license_id = ANY($1)"#,
+        license_ids
+    )
+    .execute(&mut *transaction)
+    .await?;
+    query!(
+        r#"DELETE FROM user_license WHERE license_id = ANY($1)"#,
+        license_ids
+    )
+    .execute(&mut *transaction)
+    .await?;
+    query!(
+        r#"DELETE FROM license_checkout WHERE license_id = ANY($1)"#,
+        license_ids
+    )
+    .execute(&mut *transaction)
+    .await?;
+    query!(
+        r#"UPDATE "order" SET status = $2
+        WHERE status = $3 AND order_id IN (
+            SELECT renewal_order_id FROM subscription
+            WHERE license_id = ANY($1) AND status <> $4
+        )"#,
+        license_ids,
+        OrderStatus::Failed as _,
+        OrderStatus::Pending as _,
+        SubscriptionStatus::Canceled as _
+    )
+    .execute(&mut *transaction)
+    .await?;
+    query!(
+        r#"UPDATE subscription
+        SET status = $2, canceled_at = now(), grace_until = NULL, renewal_order_id = NULL
+        WHERE license_id = ANY($1) AND status <> $2"#,
+        license_ids,
+        SubscriptionStatus::Canceled as _
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
@@ -6,9 +6,11 @@ use sqlx::{
     query, query_as, Executor, {Pool, Postgres},
 };
 use std::fmt::Display;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
+use crate::data_access::pagination::{Page, PageRequest, SortField};
+
 /// User struct with their password hash
 /// Use this only if you **need** the password hash.
 #[derive(Debug)]
@@ -58,14 +60,89 @@ pub struct RoleStruct {
     role: Role,
 }
 
-pub async fn get_all_users(pool: &Pool<Postgres>) -> Result<Vec<User>, sqlx::Error> {
-    let users = query_as!(
+/// Fields a list of users can be sorted by, by id if not given.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum UserSort {
+    #[default]
+    UserId,
+    Email,
+    CompanyId,
+    Role,
+}
+
+impl SortField for UserSort {
+    const NAMES: &'static [&'static str] = &["user_id", "email", "company_id", "role"];
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "user_id" => Some(UserSort::UserId),
+            "email" => Some(UserSort::Email),
+            "company_id" => Some(UserSort::CompanyId),
+            "role" => Some(UserSort::Role),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            UserSort::UserId => "user_id",
+            UserSort::Email => "email",
+            UserSort::CompanyId => "company_id",
+            UserSort::Role => "role",
+        }
+    }
+}
+
+/// Filters for a list of users, all users if none are given.
+#[derive(Deserialize, Debug, Default, IntoParams)]
+pub struct UserFilter {
+    /// Only users with this role
+    pub role: Option<Role>,
+    /// Only users in this company
+    pub company_id: Option<i32>,
+}
+
+/// Returns a page of the users matching the filter.
+pub async fn get_users(
+    pool: &Pool<Postgres>,
+    filter: &UserFilter,
+    page: &PageRequest<UserSort>,
+) -> Result<Page<User>, sqlx::Error> {
+    let items = query_as!(
         User,
-        r#"SELECT user_id, email, company_id, role as "role: _" FROM app_user"#
+        r#"SELECT user_id, email, company_id, role as "role: _" FROM app_user
+        WHERE ($1::role_enum IS NULL OR role = $1)
+        AND ($2::INT IS NULL OR company_id = $2)
+        ORDER BY
+            CASE WHEN $3 = 'email' AND NOT $4 THEN email END ASC,
+            CASE WHEN $3 = 'email' AND $4 THEN email END DESC,
+            CASE WHEN $3 = 'company_id' AND NOT $4 THEN company_id END ASC,
+            CASE WHEN $3 = 'company_id' AND $4 THEN company_id END DESC,
+            CASE WHEN $3 = 'role' AND NOT $4 THEN role END ASC,
+            CASE WHEN $3 = 'role' AND $4 THEN role END DESC,
+            CASE WHEN $3 = 'user_id' AND $4 THEN user_id END DESC,
+            user_id
+        LIMIT $5 OFFSET $6"#,
+        &filter.role as _,
+        filter.company_id,
+        page.sort_name(),
+        page.descending(),
+        page.limit,
+        page.offset
     )
     .fetch_all(pool)
     .await?;
-    Ok(users)
+    let total = query!(
+        r#"SELECT COUNT(*) as "total!" FROM app_user
+        WHERE ($1::role_enum IS NULL OR role = $1)
+        AND ($2::INT IS NULL OR company_id = $2)"#,
+        &filter.role as _,
+        filter.company_id
+    )
+    .fetch_one(pool)
+    .await?
+    .total;
+    Ok(Page { items, total })
 }
 
 pub async fn get_user_by_id(pool: &Pool<Postgres>, user_id: &i32) -> Result<User, sqlx::Error> {
@@ -0,0 +1,705 @@
+//! PDF invoices for orders, credit notes for their refunds, and quotes.
+//!
+//! Documents are rendered on request from the order or quote and the billing details of the company,
+//! so they always show what is stored for the order or quote. Text uses the built-in Helvetica font,
+//! which needs no font files on the server.
+
+use lettre::SmtpTransport;
+use log::{error, info};
+use printpdf::{
+    BuiltinFont, IndirectFontRef, Line, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference,
+    Point,
+};
+use sqlx::{Pool, Postgres};
+
+use crate::{
+    data_access::{
+        company::{self, BillingAddress, Company},
+        order::{self, Order, OrderStatus, OrderType},
+        product,
+        quote::Quote,
+        refund::{LicenseAction, Refund},
+        user,
+    },
+    money::{Currency, Money},
+    utils::email::{self, Email, EmailType, OrderConfirmationEmail},
+};
+
+const SELLER_NAME: &str = "ProFlex";
+const SELLER_ADDRESS: [&str; 3] = ["Sandbergvegen 98", "6009 Ålesund", "Norway"];
+const SELLER_EMAIL: &str = "group04webshop@gmail.com";
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 20.0;
+const LINE_HEIGHT: f32 = 6.0;
+const FONT_SIZE: f32 = 10.0;
+
+/// Left edge of each column of the order lines table, the last three are right aligned
+/// to the start of the next column.
+const COLUMNS: [f32; 5] = [MARGIN, 102.0, 122.0, 150.0, PAGE_WIDTH - MARGIN];
+
+/// File name of the invoice of an order.
+pub fn file_name(order: &Order) -> String {
+    format!("invoice-{}.pdf", order.invoice_number)
+}
+
+/// File name of the credit note of a refund.
+pub fn credit_note_file_name(refund: &Refund) -> String {
+    format!("credit-note-{}.pdf", refund.credit_note_number)
+}
+
+/// File name of the PDF of a quote.
+pub fn quote_file_name(quote: &Quote) -> String {
+    format!("quote-{}.pdf", quote.quote_number)
+}
+
+#[derive(Debug)]
+pub enum InvoiceError {
+    SqlxError(sqlx::Error),
+    PdfError(printpdf::Error),
+}
+
+impl From<sqlx::Error> for InvoiceError {
+    fn from(e: sqlx::Error) -> Self {
+        InvoiceError::SqlxError(e)
+    }
+}
+
+impl From<printpdf::Error> for InvoiceError {
+    fn from(e: printpdf::Error) -> Self {
+        InvoiceError::PdfError(e)
+    }
+}
+
+impl std::fmt::Display for InvoiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InvoiceError::SqlxError(e) => write!(f, "{}", e),
+            InvoiceError::PdfError(e) => write!(f, "Could not render invoice: {}", e),
+        }
+    }
+}
+
+/// Renders the invoice of an order with the current billing details of its company.
+pub async fn create_invoice(
+    pool: &Pool<Postgres>,
+    order: &Order,
+    seller_vat_number: &str,
+) -> Result<Vec<u8>, InvoiceError> {
+    let company = company::get_company_by_id(pool, &order.company_id).await?;
+    let billing = company::get_billing_address(pool, &order.company_id).await?;
+    Ok(render_invoice(
+        order,
+        &company,
+        billing.as_ref(),
+        seller_vat_number,
+    )?)
+}
+
+/// Renders the invoice of an order as a PDF.
+/// The company is billed at its billing address, or at its address if it has none.
+fn render_invoice(
+    order: &Order,
+    company: &Company,
+    billing: Option<&BillingAddress>,
+    seller_vat_number: &str,
+) -> Result<Vec<u8>, printpdf::Error> {
+    let title = format!("Invoice {}", order.invoice_number);
+    let (doc, page, layer) = PdfDocument::new(&title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Invoice");
+    let regular = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+    let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+    let mut writer = InvoiceWriter {
+        doc: &doc,
+        layer: doc.get_page(page).get_layer(layer),
+        regular,
+        bold,
+        y: PAGE_HEIGHT - MARGIN,
+        seller_vat_number,
+    };
+
+    let status = match order.status {
+        OrderStatus::Paid => "Paid",
+        OrderStatus::Pending => "Payment due",
+        OrderStatus::Failed => "Payment failed",
+    };
+    writer.parties(
+        "INVOICE",
+        &[
+            ("Invoice number", order.invoice_number.clone()),
+            (
+                "Invoice date",
+                order.created_at.format("%d.%m.%Y").to_string(),
+            ),
+            ("Order", order_type_name(order.order_type).to_string()),
+            ("Status", status.to_string()),
+        ],
+        company,
+        billing,
+        order.vat_number.as_deref(),
+    );
+
+    writer.y -= LINE_HEIGHT * 2.0;
+    writer.table_header(order.currency);
+    for line in &order.lines {
+        writer.ensure_space(LINE_HEIGHT * 3.0, &title, order.currency);
+        let description = fit_width(&line.description, COLUMNS[1] - COLUMNS[0] + 4.0);
+        writer.text(&description, FONT_SIZE, COLUMNS[0], false);
+        writer.text_right(&line.seats.to_string(), COLUMNS[2] - 4.0, false);
+        writer.text_right(&line.unit_price.to_string(), COLUMNS[3] - 4.0, false);
+        writer.text_right(&line.amount.to_string(), COLUMNS[4], false);
+        writer.y -= LINE_HEIGHT * 0.8;
+        let period = format!(
+            "{} - {}",
+            line.period_start.format("%d.%m.%Y"),
+            line.period_end.format("%d.%m.%Y")
+        );
+        writer.text(&period, 8.0, COLUMNS[0], false);
+        writer.y -= LINE_HEIGHT * 1.2;
+    }
+
+    writer.ensure_space(LINE_HEIGHT * 8.0, &title, order.currency);
+    writer.rule();
+    writer.y -= LINE_HEIGHT;
+    if let Some(code) = &order.discount_code {
+        writer.text(&format!("Discount ({})", code), FONT_SIZE, 122.0, false);
+        writer.text_right(&(-order.discount).to_string(), COLUMNS[4], false);
+        writer.y -= LINE_HEIGHT;
+    }
+    writer.text("Subtotal", FONT_SIZE, 122.0, false);
+    writer.text_right(&order.subtotal.to_string(), COLUMNS[4], false);
+    writer.y -= LINE_HEIGHT;
+    writer.text(
+        &format!("VAT {}", format_rate(order.tax_rate)),
+        FONT_SIZE,
+        122.0,
+        false,
+    );
+    writer.text_right(&order.tax.to_string(), COLUMNS[4], false);
+    writer.y -= LINE_HEIGHT;
+    writer.text(
+        &format!("Total ({})", order.currency),
+        FONT_SIZE,
+        122.0,
+        true,
+    );
+    writer.text_right(&order.total.to_string(), COLUMNS[4], true);
+    writer.y -= LINE_HEIGHT * 2.0;
+    if let Some(note) = order.tax_treatment.invoice_note() {
+        writer.text(note, FONT_SIZE, MARGIN, false);
+        writer.y -= LINE_HEIGHT;
+    }
+    if order.total.is_negative() {
+        writer.text(
+            "The credit is paid back to you by refunding earlier payments, see their credit notes.",
+            FONT_SIZE,
+            MARGIN,
+            false,
+        );
+    } else if order.status == OrderStatus::Paid {
+        writer.text("Thank you for your purchase.", FONT_SIZE, MARGIN, false);
+    } else {
+        writer.text(
+            "Please pay the total with the payment created for this order.",
+            FONT_SIZE,
+            MARGIN,
+            false,
+        );
+    }
+
+    doc.save_to_bytes()
+}
+
+/// Renders the credit note of a refund of an order with the current billing details of its company.
+pub async fn create_credit_note(
+    pool: &Pool<Postgres>,
+    order: &Order,
+    refund: &Refund,
+    seller_vat_number: &str,
+) -> Result<Vec<u8>, InvoiceError> {
+    let company = company::get_company_by_id(pool, &order.company_id).await?;
+    let billing = company::get_billing_address(pool, &order.company_id).await?;
+    Ok(render_credit_note(
+        order,
+        refund,
+        &company,
+        billing.as_ref(),
+        seller_vat_number,
+    )?)
+}
+
+/// Renders the credit note of a refund as a PDF, crediting the invoice of its order.
+fn render_credit_note(
+    order: &Order,
+    refund: &Refund,
+    company: &Company,
+    billing: Option<&BillingAddress>,
+    seller_vat_number: &str,
+) -> Result<Vec<u8>, printpdf::Error> {
+    let title = format!("Credit note {}", refund.credit_note_number);
+    let (doc, page, layer) =
+        PdfDocument::new(&title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Credit note");
+    let regular = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+    let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+    let mut writer = InvoiceWriter {
+        doc: &doc,
+        layer: doc.get_page(page).get_layer(layer),
+        regular,
+        bold,
+        y: PAGE_HEIGHT - MARGIN,
+        seller_vat_number,
+    };
+
+    writer.parties(
+        "CREDIT NOTE",
+        &[
+            ("Credit note", refund.credit_note_number.clone()),
+            ("Date", refund.created_at.format("%d.%m.%Y").to_string()),
+            ("Invoice", order.invoice_number.clone()),
+            (
+                "Invoice date",
+                order.created_at.format("%d.%m.%Y").to_string(),
+            ),
+        ],
+        company,
+        billing,
+        order.vat_number.as_deref(),
+    );
+
+    writer.y -= LINE_HEIGHT * 2.0;
+    writer.text(
+        &format!(
+            "Credit for invoice {}, {}",
+            order.invoice_number,
+            order_type_name(order.order_type).to_lowercase()
+        ),
+        FONT_SIZE,
+        MARGIN,
+        true,
+    );
+    writer.y -= LINE_HEIGHT;
+    for line in &order.lines {
+        let description = fit_width(&line.description, PAGE_WIDTH - MARGIN * 2.0);
+        writer.text(&description, FONT_SIZE, MARGIN, false);
+        writer.y -= LINE_HEIGHT;
+    }
+    if let Some(reason) = &refund.reason {
+        let reason = fit_width(&format!("Reason: {}", reason), PAGE_WIDTH - MARGIN * 2.0);
+        writer.text(&reason, FONT_SIZE, MARGIN, false);
+        writer.y -= LINE_HEIGHT;
+    }
+    let licenses = match (refund.license_action, refund.license_end_date) {
+        (LicenseAction::Keep, _) => "The licenses remain active.".to_string(),
+        (LicenseAction::Shorten, Some(end_date)) => format!(
+            "The licenses end no later than {}.",
+            end_date.format("%d.%m.%Y")
+        ),
+        (LicenseAction::Shorten, None) => "The licenses have been shortened.".to_string(),
+        (LicenseAction::Revoke, _) => "The licenses have been revoked.".to_string(),
+    };
+    writer.text(&licenses, FONT_SIZE, MARGIN, false);
+    writer.y -= LINE_HEIGHT * 2.0;
+
+    writer.rule();
+    writer.y -= LINE_HEIGHT;
+    writer.text("Subtotal", FONT_SIZE, 122.0, false);
+    writer.text_right(
+        &(-(refund.amount - refund.tax)).to_string(),
+        COLUMNS[4],
+        false,
+    );
+    writer.y -= LINE_HEIGHT;
+    writer.text(
+        &format!("VAT {}", format_rate(order.tax_rate)),
+        FONT_SIZE,
+        122.0,
+        false,
+    );
+    writer.text_right(&(-refund.tax).to_string(), COLUMNS[4], false);
+    writer.y -= LINE_HEIGHT;
+    writer.text(
+        &format!("Total ({})", order.currency),
+        FONT_SIZE,
+        122.0,
+        true,
+    );
+    writer.text_right(&(-refund.amount).to_string(), COLUMNS[4], true);
+    writer.y -= LINE_HEIGHT * 2.0;
+    if let Some(note) = order.tax_treatment.invoice_note() {
+        writer.text(note, FONT_SIZE, MARGIN, false);
+        writer.y -= LINE_HEIGHT;
+    }
+    writer.text(
+        "The total has been paid back with the payment of the invoice.",
+        FONT_SIZE,
+        MARGIN,
+        false,
+    );
+
+    doc.save_to_bytes()
+}
+
+/// Renders a quote with the current billing details of its company and the current product names.
+pub async fn create_quote_document(
+    pool: &Pool<Postgres>,
+    quote: &Quote,
+    seller_vat_number: &str,
+) -> Result<Vec<u8>, InvoiceError> {
+    let company = company::get_company_by_id(pool, &quote.company_id).await?;
+    let billing = company::get_billing_address(pool, &quote.company_id).await?;
+    let mut products = Vec::with_capacity(quote.lines.len());
+    for line in quote.lines.iter() {
+        let product = product::get_product_by_id(pool, &line.product_id).await?;
+        products.push(product.display_name().to_string());
+    }
+    Ok(render_quote(
+        quote,
+        &products,
+        &company,
+        billing.as_ref(),
+        seller_vat_number,
+    )?)
+}
+
+/// Renders a quote as a PDF, with the name of the product of each line.
+fn render_quote(
+    quote: &Quote,
+    products: &[String],
+    company: &Company,
+    billing: Option<&BillingAddress>,
+    seller_vat_number: &str,
+) -> Result<Vec<u8>, printpdf::Error> {
+    let title = format!("Quote {}", quote.quote_number);
+    let (doc, page, layer) = PdfDocument::new(&title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Quote");
+    let regular = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+    let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+    let mut writer = InvoiceWriter {
+        doc: &doc,
+        layer: doc.get_page(page).get_layer(layer),
+        regular,
+        bold,
+        y: PAGE_HEIGHT - MARGIN,
+        seller_vat_number,
+    };
+
+    let vat_number = billing.and_then(|billing| billing.vat_number.as_deref());
+    writer.parties(
+        "QUOTE",
+        &[
+            ("Quote number", quote.quote_number.clone()),
+            (
+                "Quote date",
+                quote
+                    .sent_at
+                    .unwrap_or(quote.created_at)
+                    .format("%d.%m.%Y")
+                    .to_string(),
+            ),
+            (
+                "Valid until",
+                quote.valid_until.format("%d.%m.%Y").to_string(),
+            ),
+        ],
+        company,
+        billing,
+        vat_number,
+    );
+
+    writer.y -= LINE_HEIGHT * 2.0;
+    writer.table_header(quote.currency);
+    for (line, product) in quote.lines.iter().zip(products.iter()) {
+        writer.ensure_space(LINE_HEIGHT * 3.0, &title, quote.currency);
+        let description = fit_width(product, COLUMNS[1] - COLUMNS[0] + 4.0);
+        writer.text(&description, FONT_SIZE, COLUMNS[0], false);
+        writer.text_right(&line.seats.to_string(), COLUMNS[2] - 4.0, false);
+        writer.text_right(&line.unit_price.to_string(), COLUMNS[3] - 4.0, false);
+        writer.text_right(&line.amount.to_string(), COLUMNS[4], false);
+        writer.y -= LINE_HEIGHT * 0.8;
+        let mut details = format!("{} year(s)", line.term_years);
+        if line.unit_price != line.list_price {
+            details.push_str(&format!(", list price {}", line.list_price));
+        }
+        writer.text(&details, 8.0, COLUMNS[0], false);
+        writer.y -= LINE_HEIGHT * 1.2;
+    }
+
+    writer.ensure_space(LINE_HEIGHT * 6.0, &title, quote.currency);
+    writer.rule();
+    writer.y -= LINE_HEIGHT;
+    writer.text(
+        &format!("Total ({})", quote.currency),
+        FONT_SIZE,
+        122.0,
+        true,
+    );
+    writer.text_right(&quote.total.to_string(), COLUMNS[4], true);
+    writer.y -= LINE_HEIGHT * 2.0;
+    writer.text(
+        "Prices are per user per year, VAT is added by the billing address when accepted.",
+        FONT_SIZE,
+        MARGIN,
+        false,
+    );
+    writer.y -= LINE_HEIGHT;
+    writer.text(
+        &format!(
+            "This quote can be accepted from your ProFlex profile until {}.",
+            quote.valid_until.format("%d.%m.%Y")
+        ),
+        FONT_SIZE,
+        MARGIN,
+        false,
+    );
+    if let Some(note) = &quote.note {
+        writer.y -= LINE_HEIGHT * 2.0;
+        writer.text("Terms", FONT_SIZE, MARGIN, true);
+        writer.y -= LINE_HEIGHT;
+        for line in note.lines() {
+            writer.ensure_space(LINE_HEIGHT, &title, quote.currency);
+            writer.text(
+                &fit_width(line, PAGE_WIDTH - MARGIN * 2.0),
+                FONT_SIZE,
+                MARGIN,
+                false,
+            );
+            writer.y -= LINE_HEIGHT;
+        }
+    }
+
+    doc.save_to_bytes()
+}
+
+/// Formats a rate in basis points as a percentage, e.g. `25%` or `25.5%`.
+fn format_rate(basis_points: i32) -> String {
+    let percent = format!("{}.{:02}", basis_points / 100, basis_points % 100);
+    format!("{}%", percent.trim_end_matches('0').trim_end_matches('.'))
+}
+
+fn order_type_name(order_type: OrderType) -> &'static str {
+    match order_type {
+        OrderType::Purchase => "License purchase",
+        OrderType::Renewal => "License renewal",
+        OrderType::SeatChange => "Seat change",
+        OrderType::SubscriptionRenewal => "Subscription renewal",
+        OrderType::EarlyRenewal => "Early renewal",
+        OrderType::TrialConversion => "Trial conversion",
+    }
+}
+
+/// Writes text top to bottom, starting new pages when a page is full.
+struct InvoiceWriter<'a> {
+    doc: &'a PdfDocumentReference,
+    layer: PdfLayerReference,
+    regular: IndirectFontRef,
+    bold: IndirectFontRef,
+    /// Baseline of the next line, from the bottom of the page.
+    y: f32,
+    /// VAT number of the seller, printed under its address.
+    seller_vat_number: &'a str,
+}
+
+impl InvoiceWriter<'_> {
+    fn text(&self, text: &str, size: f32, x: f32, bold: bool) {
+        let font = if bold { &self.bold } else { &self.regular };
+        self.layer.use_text(text, size, Mm(x), Mm(self.y), font);
+    }
+
+    /// Writes text ending at `right`.
+    fn text_right(&self, text: &str, right: f32, bold: bool) {
+        self.text(text, FONT_SIZE, right - text_width(text, FONT_SIZE), bold);
+    }
+
+    /// Draws a horizontal line across the page, just above the current line.
+    fn rule(&self) {
+        let y = Mm(self.y + LINE_HEIGHT * 0.6);
+        self.layer.add_line(Line {
+            points: vec![
+                (Point::new(Mm(MARGIN), y), false),
+                (Point::new(Mm(PAGE_WIDTH - MARGIN), y), false),
+            ],
+            is_closed: false,
+        });
+    }
+
+    /// Writes the seller and the document details side by side, then who is billed.
+    /// The company is billed at its billing address, or at its address if it has none,
+    /// with the VAT number the order was billed with, it may have changed since.
+    fn parties(
+        &mut self,
+        heading: &str,
+        details: &[(&str, String)],
+        company: &Company,
+        billing: Option<&BillingAddress>,
+        vat_number: Option<&str>,
+    ) {
+        self.text(SELLER_NAME, 18.0, MARGIN, true);
+        self.text(heading, 18.0, 150.0, true);
+        self.y -= LINE_HEIGHT * 2.0;
+        let details_top = self.y;
+        let seller_vat = format!("VAT {}", self.seller_vat_number);
+        for line in SELLER_ADDRESS
+            .iter()
+            .chain([SELLER_EMAIL, seller_vat.as_str()].iter())
+        {
+            self.text(line, FONT_SIZE, MARGIN, false);
+            self.y -= LINE_HEIGHT;
+        }
+        self.y = details_top;
+        for (label, value) in details {
+            self.text(label, FONT_SIZE, 122.0, true);
+            self.text(value, FONT_SIZE, 155.0, false);
+            self.y -= LINE_HEIGHT;
+        }
+
+        self.y -= LINE_HEIGHT * 2.0;
+        self.text("Bill to", FONT_SIZE, MARGIN, true);
+        self.y -= LINE_HEIGHT;
+        self.text(&company.company_name, FONT_SIZE, MARGIN, false);
+        self.y -= LINE_HEIGHT;
+        let address: Vec<String> = match billing {
+            Some(billing) => vec![
+                billing.street.clone(),
+                format!("{} {}", billing.postal_code, billing.city),
+                billing.country.clone(),
+            ],
+            None => company
+                .company_address
+                .split([',', '\n'])
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect(),
+        };
+        for line in address {
+            self.text(&line, FONT_SIZE, MARGIN, false);
+            self.y -= LINE_HEIGHT;
+        }
+        if let Some(vat_number) = vat_number {
+            self.text(&format!("VAT {}", vat_number), FONT_SIZE, MARGIN, false);
+            self.y -= LINE_HEIGHT;
+        }
+    }
+
+    fn table_header(&mut self, currency: Currency) {
+        self.text("Description", FONT_SIZE, COLUMNS[0], true);
+        self.text_right("Seats", COLUMNS[2] - 4.0, true);
+        self.text_right("Unit price", COLUMNS[3] - 4.0, true);
+        self.text_right(&format!("Amount ({})", currency), COLUMNS[4], true);
+        self.y -= LINE_HEIGHT * 0.5;
+        self.rule();
+        self.y -= LINE_HEIGHT;
+    }
+
+    /// Starts a new page, continuing the table, if less than `height` is left on this one.
+    /// The new page is headed with the title of the document.
+    fn ensure_space(&mut self, height: f32, title: &str, currency: Currency) {
+        if self.y - height >= MARGIN {
+            return;
+        }
+        let (page, layer) = self.doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), title);
+        self.layer = self.doc.get_page(page).get_layer(layer);
+        self.y = PAGE_HEIGHT - MARGIN;
+        self.text(&format!("{}, continued", title), FONT_SIZE, MARGIN, false);
+        self.y -= LINE_HEIGHT * 2.0;
+        self.table_header(currency);
+    }
+}
+
+/// Approximate width in mm of text in Helvetica, exact for digits and punctuation.
+fn text_width(text: &str, size: f32) -> f32 {
+    let units: u32 = text
+        .chars()
+        .map(|c| match c {
+            '.' | ',' | ' ' | ':' => 278,
+            '-' | '(' | ')' => 333,
+            'i' | 'l' | 'I' => 222,
+            _ => 556,
+        })
+        .sum();
+    // widths are in thousandths of the font size, which is in points
+    units as f32 / 1000.0 * size * 25.4 / 72.0
+}
+
+/// Shortens text to fit in `width` mm, ending it with "..." if it is cut.
+fn fit_width(text: &str, width: f32) -> String {
+    if text_width(text, FONT_SIZE) <= width {
+        return text.to_string();
+    }
+    let mut fitted = String::new();
+    for c in text.chars() {
+        if text_width(&format!("{}{}...", fitted, c), FONT_SIZE) > width {
+            break;
+        }
+        fitted.push(c);
+    }
+    format!("{}...", fitted.trim_end())
+}
+
+/// Sends the invoice of a paid order to the user who placed it.
+/// Orders that cost nothing or credit the company, and orders of deleted users, get no email.
+pub async fn send_order_confirmation(
+    pool: &Pool<Postgres>,
+    mailer: &SmtpTransport,
+    seller_vat_number: &str,
+    order_id: &i32,
+) {
+    let order = match order::get_order_by_id(pool, order_id).await {
+        Ok(order) => order,
+        Err(e) => {
+            error!(
+                "Could not get order {} for its confirmation: {}",
+                order_id, e
+            );
+            return;
+        }
+    };
+    if order.total <= Money::ZERO {
+        return;
+    }
+    let user_id = match order.user_id {
+        Some(user_id) => user_id,
+        None => {
+            info!(
+                "Order {} has no user to send its invoice to",
+                order.invoice_number
+            );
+            return;
+        }
+    };
+    let user = match user::get_user_by_id(pool, &user_id).await {
+        Ok(user) => user,
+        Err(e) => {
+            error!(
+                "Could not get the user of order {}: {}",
+                order.invoice_number, e
+            );
+            return;
+        }
+    };
+    let invoice = match create_invoice(pool, &order, seller_vat_number).await {
+        Ok(invoice) => invoice,
+        Err(e) => {
+            error!("Could not create invoice {}: {}", order.invoice_number, e);
+            return;
+        }
+    };
+
+    let mail = Email::new(
+        user.email,
+        EmailType::OrderConfirmation(OrderConfirmationEmail {
+            invoice_number: order.invoice_number.clone(),
+            total: format!("{} {}", order.total, order.currency),
+            products: order
+                .lines
+                .iter()
+                .map(|line| line.description.clone())
+                .collect(),
+            invoice_file_name: file_name(&order),
+            invoice,
+        }),
+        None,
+    );
+    if let Err(e) = email::send_email(mail, mailer).await {
+        error!("Could not send invoice {}: {}", order.invoice_number, e);
+    }
+}
@@ -0,0 +1,29 @@
+//! Background jobs that run periodically alongside the web server.
+//!
+//! Each job lives in its own submodule and is spawned on the actix runtime by [`start_jobs`].
+
+use actix_web::web::Data;
+
+use crate::SharedData;
+
+pub mod checkout_reaper;
+pub mod credit_payback;
+pub mod license_reminder;
+pub mod order_reaper;
+pub mod scheduled_publishing;
+pub mod subscription_renewal;
+pub mod trial_reminder;
+pub mod usage_snapshot;
+
+/// Spawns all background jobs on the current actix runtime.
+/// Must be called from within the runtime, before the server is started.
+pub fn start_jobs(shared_data: Data<SharedData>) {
+    actix_web::rt::spawn(checkout_reaper::run(shared_data.clone()));
+    actix_web::rt::spawn(credit_payback::run(shared_data.clone()));
+    actix_web::rt::spawn(order_reaper::run(shared_data.clone()));
+    actix_web::rt::spawn(trial_reminder::run(shared_data.clone()));
+    actix_web::rt::spawn(license_reminder::run(shared_data.clone()));
+    actix_web::rt::spawn(subscription_renewal::run(shared_data.clone()));
+    actix_web::rt::spawn(scheduled_publishing::run(shared_data.clone()));
+    actix_web::rt::spawn(usage_snapshot::run(shared_data));
+}
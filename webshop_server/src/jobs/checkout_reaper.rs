@@ -0,0 +1,26 @@
+//! Releases seats on floating licenses that stopped receiving heartbeats.
+
+use std::time::Duration;
+
+use actix_web::web::Data;
+use log::{error, info};
+
+use crate::{data_access::license::checkout, SharedData};
+
+/// How often stale checkouts are looked for.
+const REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Removes stale checkouts every [`REAP_INTERVAL`], forever.
+pub async fn run(shared_data: Data<SharedData>) {
+    let mut interval = actix_web::rt::time::interval(REAP_INTERVAL);
+    loop {
+        interval.tick().await;
+        match checkout::remove_stale_checkouts(&shared_data.db_pool, shared_data.checkout_timeout)
+            .await
+        {
+            Ok(0) => (),
+            Ok(removed) => info!("Released {} stale license checkout(s)", removed),
+            Err(e) => error!("Could not remove stale license checkouts: {}", e),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! Pays back the credits that could not be paid back when they were ordered.
+
+use std::time::Duration;
+
+use actix_web::web::Data;
+use log::{error, info};
+
+use crate::{data_access::refund, payment::credit, SharedData};
+
+/// How often credits still to be paid back are looked for.
+const PAYBACK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Resumes paying back the credits of pending orders every [`PAYBACK_INTERVAL`], forever.
+pub async fn run(shared_data: Data<SharedData>) {
+    let mut interval = actix_web::rt::time::interval(PAYBACK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let pool = &shared_data.db_pool;
+        let order_ids = match refund::get_pending_credit_order_ids(pool).await {
+            Ok(order_ids) => order_ids,
+            Err(e) => {
+                error!("Could not get credits to pay back: {}", e);
+                continue;
+            }
+        };
+        for order_id in order_ids {
+            match credit::pay_back_credit(pool, shared_data.payment_provider.as_ref(), &order_id)
+                .await
+            {
+                Ok(()) => info!("Paid back credit of order {}", order_id),
+                Err(e) => error!("Could not pay back credit of order {}: {}", order_id, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Reminds companies that their licenses are about to expire.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use actix_web::web::Data;
+use log::{error, info};
+
+use crate::{
+    data_access::{
+        license::reminder::{self, ExpiringLicense},
+        user::{self, Role},
+    },
+    utils::email::{self, Email, EmailType, ExpiringLicenseInfo, LicenseExpiryEmail},
+    SharedData,
+};
+
+/// How often expiring licenses are looked for.
+const REMINDER_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Sends reminders for expiring licenses every [`REMINDER_INTERVAL`], forever.
+///
+/// Offsets are handled from the smallest to the largest,
+/// see [`reminder::get_licenses_due_reminder`] for why.
+pub async fn run(shared_data: Data<SharedData>) {
+    let mut interval = actix_web::rt::time::interval(REMINDER_INTERVAL);
+    loop {
+        interval.tick().await;
+        for days_before in shared_data.license_reminder_days.iter() {
+            let licenses =
+                match reminder::get_licenses_due_reminder(&shared_data.db_pool, *days_before).await
+                {
+                    Ok(licenses) => licenses,
+                    Err(e) => {
+                        error!("Could not get expiring licenses: {}", e);
+                        continue;
+                    }
+                };
+
+            let mut by_company: BTreeMap<i32, Vec<ExpiringLicense>> = BTreeMap::new();
+            for license in licenses {
+                by_company
+                    .entry(license.company_id)
+                    .or_default()
+                    .push(license);
+            }
+            for (company_id, licenses) in by_company {
+                remind(&shared_data, company_id, licenses, *days_before).await;
+            }
+        }
+    }
+}
+
+/// Emails the IT heads of a company one reminder listing all its expiring licenses,
+/// and records the reminder as sent.
+/// The reminder is only recorded if at least one IT head was emailed,
+/// so it is retried on the next run otherwise.
+async fn remind(
+    shared_data: &SharedData,
+    company_id: i32,
+    licenses: Vec<ExpiringLicense>,
+    days_before: i32,
+) {
+    let pool = &shared_data.db_pool;
+    let it_heads = match user::get_users_by_company(pool, &company_id).await {
+        Ok(users) => users
+            .into_iter()
+            .filter(|user| user.role == Role::CompanyItHead),
+        Err(e) => {
+            error!("Could not get users of company {}: {}", company_id, e);
+            return;
+        }
+    };
+
+    let mut sent = false;
+    for it_head in it_heads {
+        let mail = Email::new(
+            it_head.email,
+            EmailType::LicenseExpiry(LicenseExpiryEmail {
+                days_before,
+                licenses: licenses
+                    .iter()
+                    .map(|license| ExpiringLicenseInfo {
+                        product: license.display_name.clone(),
+                        end_date: license.end_date,
+                        amount: license.amount,
+                        used_seats: license.used_seats,
+                    })
+                    .collect(),
+            }),
+            None,
+        );
+        if email::send_email(mail, &shared_data.mailer).await.is_ok() {
+            sent = true;
+        }
+    }
+
+    if sent {
+        match reminder::set_reminders_sent(pool, &licenses, days_before).await {
+            Ok(()) => info!(
+                "Sent {} day expiry reminder for {} license(s) of company {}",
+                days_before,
+                licenses.len(),
+                company_id
+            ),
+            Err(e) => error!(
+                "Could not record expiry reminder of company {} as sent: {}",
+                company_id, e
+            ),
+        }
+    }
+}
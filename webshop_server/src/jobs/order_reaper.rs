@@ -0,0 +1,69 @@
+//! Fails orders that were not paid in time, giving back the use of their discount codes.
+//! Their payments are canceled at the provider first, so they cannot be paid once failed.
+
+use std::time::Duration;
+
+use actix_web::web::Data;
+use log::{error, info};
+
+use crate::{data_access::payment, payment::PaymentError, SharedData};
+
+/// How often unpaid orders are looked for.
+const REAP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Fails the orders that waited for payment longer than the order timeout
+/// every [`REAP_INTERVAL`], forever.
+pub async fn run(shared_data: Data<SharedData>) {
+    let mut interval = actix_web::rt::time::interval(REAP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let pool = &shared_data.db_pool;
+        let created_before = chrono::Utc::now() - shared_data.order_timeout;
+        let order_ids = match payment::get_unpaid_orders(pool, created_before).await {
+            Ok(order_ids) => order_ids,
+            Err(e) => {
+                error!("Could not get unpaid orders: {}", e);
+                continue;
+            }
+        };
+        for order_id in order_ids {
+            if !cancel_payments(&shared_data, &order_id).await {
+                continue;
+            }
+            match payment::fail_order(pool, &order_id).await {
+                Ok(()) => info!("Failed order {}, it was not paid in time", order_id),
+                Err(e) => error!("Could not fail unpaid order {}: {}", order_id, e),
+            }
+        }
+    }
+}
+
+/// Cancels the pending payments of an order at the provider.
+/// Returns whether they were all canceled, the order is left as it is otherwise,
+/// it may have been paid meanwhile.
+async fn cancel_payments(shared_data: &SharedData, order_id: &i32) -> bool {
+    let provider = &shared_data.payment_provider;
+    let intent_ids =
+        match payment::get_pending_intents(&shared_data.db_pool, order_id, provider.name()).await {
+            Ok(intent_ids) => intent_ids,
+            Err(e) => {
+                error!("Could not get payments of unpaid order {}: {}", order_id, e);
+                return false;
+            }
+        };
+    for intent_id in intent_ids {
+        // an intent the provider does not know can never be paid
+        let canceled = match provider.cancel_intent(&intent_id).await {
+            Err(PaymentError::UnknownIntent) => Ok(()),
+            canceled => canceled,
+        };
+        if let Err(e) = canceled {
+            error!(
+                "Could not cancel payment {} of unpaid order {}: {}",
+                intent_id, order_id, e
+            );
+            return false;
+        }
+    }
+    true
+}
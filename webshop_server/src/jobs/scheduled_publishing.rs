@@ -0,0 +1,27 @@
+//! Publishes products scheduled to be published once their publish time has come.
+
+use std::time::Duration;
+
+use actix_web::web::Data;
+use log::{error, info};
+
+use crate::{data_access::product::publishing, SharedData};
+
+/// How often scheduled products are looked for.
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Publishes the scheduled products that are due every [`PUBLISH_INTERVAL`], forever.
+pub async fn run(shared_data: Data<SharedData>) {
+    let mut interval = actix_web::rt::time::interval(PUBLISH_INTERVAL);
+    loop {
+        interval.tick().await;
+        match publishing::publish_scheduled(&shared_data.db_pool).await {
+            Ok(published) => {
+                for product_id in published {
+                    info!("Published scheduled product {}", product_id);
+                }
+            }
+            Err(e) => error!("Could not publish scheduled products: {}", e),
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Renews subscriptions at the end of their billing period.
+//!
+//! Each run ends the subscriptions canceled at period end, cancels the ones whose grace period
+//! is over, starts the grace period of the ones whose renewal was not paid in time,
+//! and charges the renewal of the ones about to end to their saved payment method,
+//! see [`subscription`].
+
+use std::time::Duration;
+
+use actix_web::web::Data;
+use log::{error, info};
+
+use crate::{
+    data_access::{
+        company,
+        license::{
+            self,
+            subscription::{self, Subscription},
+        },
+        order::{self, Order, OrderStatus},
+        payment, product,
+        user::{self, Role},
+    },
+    invoice,
+    payment::WebhookEventType,
+    pricing,
+    utils::email::{self, Email, EmailType, RenewalFailedEmail},
+    SharedData,
+};
+
+/// How often subscriptions are looked at.
+const RENEWAL_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How long before the end of a period its renewal is charged,
+/// so a failed charge is known before the license would end.
+const RENEW_DAYS_BEFORE: i64 = 3;
+
+/// Looks after subscriptions every [`RENEWAL_INTERVAL`], forever.
+pub async fn run(shared_data: Data<SharedData>) {
+    let mut interval = actix_web::rt::time::interval(RENEWAL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let pool = &shared_data.db_pool;
+
+        match subscription::end_canceled(pool).await {
+            Ok(ended) if !ended.is_empty() => {
+                info!("Ended {} canceled subscription(s)", ended.len())
+            }
+            Ok(_) => (),
+            Err(e) => error!("Could not end canceled subscriptions: {}", e),
+        }
+        match subscription::end_grace_periods(pool).await {
+            Ok(ended) => {
+                for subscription in ended {
+                    info!(
+                        "Subscription {} was not paid within its grace period, license {} deactivated",
+                        subscription.subscription_id, subscription.license_id
+                    );
+                }
+            }
+            Err(e) => error!("Could not end grace periods of subscriptions: {}", e),
+        }
+        match subscription::start_grace_periods(pool, shared_data.subscription_grace_days).await {
+            Ok(past_due) => {
+                for subscription in past_due {
+                    notify_past_due(&shared_data, &subscription).await;
+                }
+            }
+            Err(e) => error!("Could not start grace periods of subscriptions: {}", e),
+        }
+
+        let before = chrono::Utc::now() + chrono::Duration::days(RENEW_DAYS_BEFORE);
+        let due = match subscription::get_subscriptions_due_renewal(pool, before).await {
+            Ok(due) => due,
+            Err(e) => {
+                error!("Could not get subscriptions due renewal: {}", e);
+                continue;
+            }
+        };
+        for subscription in due {
+            renew(&shared_data, &subscription).await;
+        }
+    }
+}
+
+/// Creates the renewal order of a subscription at the current price, and charges it
+/// to the saved payment method of the subscription.
+/// A renewal that cannot be created or charged is left unpaid,
+/// and the subscription becomes past due at the end of its period.
+async fn renew(shared_data: &SharedData, subscription: &Subscription) {
+    let pool = &shared_data.db_pool;
+    let payment_method_id = match &subscription.payment_method_id {
+        Some(payment_method_id) => payment_method_id,
+        None => return,
+    };
+    let license = match license::get_license_by_id(pool, &subscription.license_id).await {
+        Ok(license) => license,
+        Err(e) => {
+            error!(
+                "Could not get license {} of subscription {}: {}",
+                subscription.license_id, subscription.subscription_id, e
+            );
+            return;
+        }
+    };
+    let currency = match company::get_billing_currency(pool, &subscription.company_id).await {
+        Ok(currency) => currency,
+        Err(e) => {
+            error!(
+                "Could not get the billing currency of company {}: {}",
+                subscription.company_id, e
+            );
+            return;
+        }
+    };
+    let price = match pricing::quote_period(
+        pool,
+        license.product_id(),
+        license.amount(),
+        subscription.billing_interval,
+        currency,
+    )
+    .await
+    {
+        Ok(price) => price,
+        Err(e) => {
+            error!(
+                "Could not price the renewal of subscription {}: {}",
+                subscription.subscription_id, e
+            );
+            return;
+        }
+    };
+    let order = match order::renew_subscription(
+        pool,
+        &subscription.subscription_id,
+        subscription.created_by,
+        &price,
+    )
+    .await
+    {
+        Ok((_, order)) => order,
+        Err(e) => {
+            error!(
+                "Could not renew subscription {}: {}",
+                subscription.subscription_id, e
+            );
+            return;
+        }
+    };
+    info!(
+        "Renewing subscription {} with order {}",
+        subscription.subscription_id, order.invoice_number
+    );
+    if order.status == OrderStatus::Pending {
+        charge(shared_data, &order, payment_method_id).await;
+    }
+}
+
+/// Charges an order to the payment method the company saved.
+/// The outcome is reported through the webhook callback of the provider,
+/// the mock provider has none, so its callback is processed here.
+async fn charge(shared_data: &SharedData, order: &Order, payment_method_id: &str) {
+    let pool = &shared_data.db_pool;
+    let provider = &shared_data.payment_provider;
+    let intent = match provider
+        .charge_saved_method(
+            order.order_id,
+            order.total,
+            order.currency,
+            payment_method_id,
+        )
+        .await
+    {
+        Ok(intent) => intent,
+        Err(e) => {
+            error!("Could not charge order {}: {}", order.order_id, e);
+            if let Err(e) = payment::fail_order(pool, &order.order_id).await {
+                error!("{}", e);
+            }
+            return;
+        }
+    };
+    if let Err(e) = payment::create_payment(pool, &order.order_id, provider.name(), &intent).await {
+        error!("{}", e);
+        return;
+    }
+
+    let mock = match &shared_data.mock_payments {
+        Some(mock) => mock,
+        None => return,
+    };
+    let event = match mock
+        .signed_webhook(&intent.intent_id)
+        .and_then(|(payload, signature)| provider.verify_webhook(&payload, &signature))
+    {
+        Ok(event) => event,
+        Err(e) => {
+            error!(
+                "Could not build the mock webhook of order {}: {}",
+                order.order_id, e
+            );
+            return;
+        }
+    };
+    match payment::record_payment_outcome(pool, provider.name(), &event).await {
+        Ok(order_id) => {
+            if event.event_type == WebhookEventType::PaymentSucceeded {
+                invoice::send_order_confirmation(
+                    pool,
+                    &shared_data.mailer,
+                    &shared_data.seller_vat_number,
+                    &order_id,
+                )
+                .await;
+            }
+        }
+        Err(e) => error!(
+            "Could not record payment of order {}: {}",
+            order.order_id, e
+        ),
+    }
+}
+
+/// Emails the IT heads of the company that the renewal of a subscription was not paid,
+/// and until when they can still pay it.
+async fn notify_past_due(shared_data: &SharedData, subscription: &Subscription) {
+    info!(
+        "Subscription {} is past due until {:?}",
+        subscription.subscription_id, subscription.grace_until
+    );
+    let grace_until = match subscription.grace_until {
+        Some(grace_until) => grace_until,
+        None => return,
+    };
+    let pool = &shared_data.db_pool;
+    let license = match license::get_license_by_id(pool, &subscription.license_id).await {
+        Ok(license) => license,
+        Err(e) => {
+            error!("Could not get license {}: {}", subscription.license_id, e);
+            return;
+        }
+    };
+    let product = match product::get_product_by_id(pool, license.product_id()).await {
+        Ok(product) => product.display_name().to_string(),
+        Err(e) => {
+            error!("Could not get product {}: {}", license.product_id(), e);
+            return;
+        }
+    };
+    let it_heads = match user::get_users_by_company(pool, &subscription.company_id).await {
+        Ok(users) => users
+            .into_iter()
+            .filter(|user| user.role == Role::CompanyItHead),
+        Err(e) => {
+            error!(
+                "Could not get users of company {}: {}",
+                subscription.company_id, e
+            );
+            return;
+        }
+    };
+    for it_head in it_heads {
+        let mail = Email::new(
+            it_head.email,
+            EmailType::RenewalFailed(RenewalFailedEmail {
+                product: product.clone(),
+                grace_until,
+            }),
+            None,
+        );
+        if let Err(e) = email::send_email(mail, &shared_data.mailer).await {
+            error!("Could not email a failed renewal: {}", e);
+        }
+    }
+}
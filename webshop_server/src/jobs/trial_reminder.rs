@@ -0,0 +1,83 @@
+//! Reminds companies that their trial licenses are about to expire.
+
+use std::time::Duration;
+
+use actix_web::web::Data;
+use log::{error, info};
+
+use crate::{
+    data_access::{
+        license::trial::{self, ExpiringTrial},
+        user::{self, Role},
+    },
+    utils::email::{self, Email, EmailType, TrialReminderEmail},
+    SharedData,
+};
+
+/// How often expiring trials are looked for.
+const REMINDER_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How long before the end of a trial the reminder is sent.
+const REMIND_DAYS_BEFORE: i64 = 3;
+
+/// Sends reminders for expiring trials every [`REMINDER_INTERVAL`], forever.
+pub async fn run(shared_data: Data<SharedData>) {
+    let mut interval = actix_web::rt::time::interval(REMINDER_INTERVAL);
+    loop {
+        interval.tick().await;
+        let before = chrono::Utc::now() + chrono::Duration::days(REMIND_DAYS_BEFORE);
+        let trials = match trial::get_trials_expiring_before(&shared_data.db_pool, before).await {
+            Ok(trials) => trials,
+            Err(e) => {
+                error!("Could not get expiring trials: {}", e);
+                continue;
+            }
+        };
+        for expiring in trials {
+            remind(&shared_data, expiring).await;
+        }
+    }
+}
+
+/// Emails the IT heads of the company owning the trial, and marks the reminder as sent.
+/// The reminder is only marked as sent if at least one IT head was emailed,
+/// so it is retried on the next run otherwise.
+async fn remind(shared_data: &SharedData, expiring: ExpiringTrial) {
+    let pool = &shared_data.db_pool;
+    let it_heads = match user::get_users_by_company(pool, &expiring.company_id).await {
+        Ok(users) => users
+            .into_iter()
+            .filter(|user| user.role == Role::CompanyItHead),
+        Err(e) => {
+            error!(
+                "Could not get users of company {}: {}",
+                expiring.company_id, e
+            );
+            return;
+        }
+    };
+
+    let mut sent = false;
+    for it_head in it_heads {
+        let mail = Email::new(
+            it_head.email,
+            EmailType::TrialReminder(TrialReminderEmail {
+                product: expiring.display_name.clone(),
+                end_date: expiring.end_date,
+            }),
+            None,
+        );
+        if email::send_email(mail, &shared_data.mailer).await.is_ok() {
+            sent = true;
+        }
+    }
+
+    if sent {
+        match trial::set_reminder_sent(pool, &expiring.license_id).await {
+            Ok(()) => info!("Sent trial reminder for license {}", expiring.license_id),
+            Err(e) => error!(
+                "Could not mark trial reminder of license {} as sent: {}",
+                expiring.license_id, e
+            ),
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//! Records the daily seat usage of active licenses for utilization reports.
+
+use std::time::Duration;
+
+use actix_web::web::Data;
+use log::{error, info};
+
+use crate::{data_access::license::usage, SharedData};
+
+/// How often seat usage is recorded. Each day keeps the peak of its snapshots.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Records seat usage every [`SNAPSHOT_INTERVAL`], forever.
+pub async fn run(shared_data: Data<SharedData>) {
+    let mut interval = actix_web::rt::time::interval(SNAPSHOT_INTERVAL);
+    loop {
+        interval.tick().await;
+        match usage::take_snapshot(&shared_data.db_pool).await {
+            Ok(licenses) => info!("Recorded seat usage of {} license(s)", licenses),
+            Err(e) => error!("Could not record seat usage: {}", e),
+        }
+    }
+}
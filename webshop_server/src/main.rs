@@ -1,6 +1,7 @@
 use core::panic;
 use std::fs::File;
 use std::io::BufReader;
+use std::sync::Arc;
 
 use actix_cors::Cors;
 use actix_web::web::Data;
@@ -9,7 +10,7 @@ use actix_web::{http, middleware::Logger, web, App, HttpServer};
 use actix_web_static_files::ResourceFiles;
 use dotenvy::dotenv;
 use flexi_logger::{Duplicate, FileSpec};
-use log::info;
+use log::{info, warn};
 use rustls::{self, Certificate, PrivateKey, ServerConfig};
 use rustls_pemfile::{certs, pkcs8_private_keys};
 
@@ -17,18 +18,37 @@ use lettre::transport::smtp::authentication::Credentials;
 use lettre::SmtpTransport;
 
 mod data_access;
+mod invoice;
+mod jobs;
+mod money;
+mod payment;
+mod pricing;
 mod routes;
+mod storage;
+mod tax;
 mod utils;
 
 use routes::private::private;
-use routes::public::public;
+use routes::public::{payments, public};
 
 use crate::data_access::create_pool;
+use crate::payment::{mock::MockPaymentProvider, PaymentProvider};
 use crate::routes::{openapi_doc, serving_images};
+use crate::storage::{FileStorage, LocalStorage};
 
 include!(concat!(env!("OUT_DIR"), "/generated.rs"));
 
 const DEFALUT_LOG_LEVEL: &str = "info,sqlx=warn";
+/// Default time in seconds a floating license checkout is kept without a heartbeat.
+const DEFAULT_CHECKOUT_TIMEOUT_SECS: i64 = 300;
+/// Default hours an order waits for payment before it is failed.
+const DEFAULT_ORDER_TIMEOUT_HOURS: i64 = 24;
+/// Default days before the end of a license at which expiry reminders are sent.
+const DEFAULT_LICENSE_REMINDER_DAYS: &str = "30,7,1";
+/// Default days a subscribed license stays usable after its renewal was not paid.
+const DEFAULT_SUBSCRIPTION_GRACE_DAYS: i32 = 7;
+/// Default directory the local file storage keeps uploaded files in.
+const DEFAULT_STORAGE_DIR: &str = "resources/files";
 
 /// Data shared between actix-web threads.
 #[derive(Clone)]
@@ -37,6 +57,24 @@ struct SharedData {
     db_pool: sqlx::PgPool,
     /// The mailer for sending emails.
     mailer: SmtpTransport,
+    /// How long a seat on a floating license is kept without a heartbeat.
+    checkout_timeout: chrono::Duration,
+    /// How long an order waits for payment before it is failed.
+    order_timeout: chrono::Duration,
+    /// Days before the end of a license at which expiry reminders are sent, smallest first.
+    license_reminder_days: Vec<i32>,
+    /// Days a subscribed license stays usable after its renewal was not paid.
+    subscription_grace_days: i32,
+    /// The provider orders are paid through.
+    payment_provider: Arc<dyn PaymentProvider>,
+    /// The mock provider, when mock payments are enabled, for simulating customer payments.
+    mock_payments: Option<Arc<MockPaymentProvider>>,
+    /// Where uploaded release files are stored.
+    file_storage: Arc<dyn FileStorage>,
+    /// Secret download links are signed with.
+    download_secret: Vec<u8>,
+    /// VAT number of the seller, printed on invoices, credit notes and quotes.
+    seller_vat_number: String,
 }
 
 #[actix_web::main]
@@ -76,10 +114,88 @@ async fn main() -> std::io::Result<()> {
         .credentials(gmail_creds)
         .build();
 
+    let checkout_timeout = match std::env::var("CHECKOUT_TIMEOUT_SECS") {
+        Ok(secs) => secs
+            .parse::<i64>()
+            .expect("CHECKOUT_TIMEOUT_SECS must be a whole number of seconds"),
+        Err(_) => DEFAULT_CHECKOUT_TIMEOUT_SECS,
+    };
+    let order_timeout = match std::env::var("ORDER_TIMEOUT_HOURS") {
+        Ok(hours) => match hours.trim().parse::<i64>() {
+            Ok(hours) if hours > 0 => hours,
+            _ => panic!("ORDER_TIMEOUT_HOURS must be a whole number of hours"),
+        },
+        Err(_) => DEFAULT_ORDER_TIMEOUT_HOURS,
+    };
+
+    let mut license_reminder_days = std::env::var("LICENSE_REMINDER_DAYS")
+        .unwrap_or_else(|_| DEFAULT_LICENSE_REMINDER_DAYS.to_string())
+        .split(',')
+        .filter(|days| !days.trim().is_empty())
+        .map(|days| match days.trim().parse::<i32>() {
+            Ok(days) if days > 0 => days,
+            _ => panic!("LICENSE_REMINDER_DAYS must be a comma separated list of positive days"),
+        })
+        .collect::<Vec<i32>>();
+    license_reminder_days.sort_unstable();
+    license_reminder_days.dedup();
+
+    let subscription_grace_days = match std::env::var("SUBSCRIPTION_GRACE_DAYS") {
+        Ok(days) => match days.trim().parse::<i32>() {
+            Ok(days) if days >= 0 => days,
+            _ => panic!("SUBSCRIPTION_GRACE_DAYS must be a whole number of days"),
+        },
+        Err(_) => DEFAULT_SUBSCRIPTION_GRACE_DAYS,
+    };
+
+    let webhook_secret = std::env::var("PAYMENT_WEBHOOK_SECRET")
+        .expect("PAYMENT_WEBHOOK_SECRET environment variable not set");
+    let provider_name =
+        std::env::var("PAYMENT_PROVIDER").expect("PAYMENT_PROVIDER environment variable not set");
+    // the mock provider charges no one, and lets anyone pay, so it has to be enabled explicitly
+    let mock_payments_enabled = std::env::var("MOCK_PAYMENTS").as_deref() == Ok("true");
+    let (payment_provider, mock_payments): (Arc<dyn PaymentProvider>, _) =
+        match provider_name.as_str() {
+            "mock" if mock_payments_enabled => {
+                warn!("MOCK PAYMENTS ENABLED - orders are not charged, anyone can pay them");
+                warn!("Never set MOCK_PAYMENTS=true on a server taking real orders");
+                let mock = Arc::new(MockPaymentProvider::new(&webhook_secret));
+                (mock.clone(), Some(mock))
+            }
+            "mock" => panic!(
+                "PAYMENT_PROVIDER 'mock' is for development only, set MOCK_PAYMENTS=true to use it"
+            ),
+            other => panic!("Unknown PAYMENT_PROVIDER: {}", other),
+        };
+    let mount_mock_payments = mock_payments.is_some();
+
+    let storage_name = std::env::var("STORAGE_PROVIDER").unwrap_or_else(|_| "local".to_string());
+    let file_storage: Arc<dyn FileStorage> = match storage_name.as_str() {
+        "local" => Arc::new(LocalStorage::new(
+            std::env::var("STORAGE_DIR").unwrap_or_else(|_| DEFAULT_STORAGE_DIR.to_string()),
+        )),
+        other => panic!("Unknown STORAGE_PROVIDER: {}", other),
+    };
+    let download_secret = std::env::var("DOWNLOAD_SECRET")
+        .expect("DOWNLOAD_SECRET environment variable not set")
+        .into_bytes();
+    let seller_vat_number =
+        std::env::var("SELLER_VAT_NUMBER").expect("SELLER_VAT_NUMBER environment variable not set");
+
     let shared_data = Data::new(SharedData {
         db_pool: pool.get_ref().clone(),
         mailer: mailer.clone(),
+        checkout_timeout: chrono::Duration::seconds(checkout_timeout),
+        order_timeout: chrono::Duration::hours(order_timeout),
+        license_reminder_days,
+        subscription_grace_days,
+        payment_provider,
+        mock_payments,
+        file_storage,
+        download_secret,
+        seller_vat_number,
     });
+    jobs::start_jobs(shared_data.clone());
 
     let tls_config = load_rustls_config();
     let allowed_origins = std::env::var("ALLOWED_ORIGINS")
@@ -97,6 +213,10 @@ async fn main() -> std::io::Result<()> {
                 http::header::ACCEPT,
                 http::header::CONTENT_TYPE,
             ])
+            .expose_headers(vec![
+                http::header::LINK,
+                http::header::HeaderName::from_static("x-total-count"),
+            ])
             .max_age(3600)
             .supports_credentials()
             .allowed_origin_fn(move |origin, _req_head| {
@@ -104,6 +224,11 @@ async fn main() -> std::io::Result<()> {
             });
         let api_endpoints = web::scope("/api")
             .configure(public)
+            .configure(|cfg| {
+                if mount_mock_payments {
+                    payments::configure_mock(cfg);
+                }
+            })
             .service(web::scope("/priv").configure(private));
 
         let image_service = web::scope("/resources/images").configure(serving_images::config);
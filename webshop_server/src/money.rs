@@ -0,0 +1,389 @@
+//! Exact money amounts for prices, quotes and orders.
+//!
+//! Amounts are kept as a whole number of minor units (cents), so adding and multiplying them
+//! never rounds. The database stores them as `BIGINT` next to the currency they are in.
+//! In JSON an amount is a decimal string with two decimals, e.g. `"199.90"`.
+//! Numbers are accepted as input too, but never more than two decimals.
+
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{Add, Mul, Neg, Sub},
+    str::FromStr,
+};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sqlx::{
+    encode::IsNull,
+    error::BoxDynError,
+    postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef},
+    Decode, Encode, Postgres, Type,
+};
+use utoipa::ToSchema;
+
+/// Number of minor units in a major unit, all supported currencies have two decimals.
+const MINOR_PER_MAJOR: i64 = 100;
+
+/// Currency an amount is in.
+#[derive(
+    sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, ToSchema,
+)]
+#[sqlx(type_name = "currency_enum", rename_all = "UPPERCASE")]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Currency {
+    #[default]
+    Usd,
+    Eur,
+    Nok,
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = match self {
+            Currency::Usd => "USD",
+            Currency::Eur => "EUR",
+            Currency::Nok => "NOK",
+        };
+        write!(f, "{}", code)
+    }
+}
+
+impl FromStr for Currency {
+    type Err = MoneyError;
+
+    /// Parses an ISO 4217 currency code, in any case, e.g. `EUR` or `eur`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_uppercase().as_str() {
+            "USD" => Ok(Currency::Usd),
+            "EUR" => Ok(Currency::Eur),
+            "NOK" => Ok(Currency::Nok),
+            _ => Err(MoneyError::UnknownCurrency),
+        }
+    }
+}
+
+/// An exact amount of money, in minor units of the currency it belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Money(i64);
+
+#[derive(Debug, PartialEq)]
+pub enum MoneyError {
+    /// The amount is not a decimal number.
+    InvalidFormat,
+    /// The amount has more than two decimals.
+    TooPrecise,
+    /// The amount does not fit.
+    TooLarge,
+    /// The currency is not one of the supported currencies.
+    UnknownCurrency,
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MoneyError::InvalidFormat => write!(f, "Amount must be a decimal number"),
+            MoneyError::TooPrecise => write!(f, "Amount must not have more than two decimals"),
+            MoneyError::TooLarge => write!(f, "Amount is too large"),
+            MoneyError::UnknownCurrency => write!(f, "Currency must be one of USD, EUR or NOK"),
+        }
+    }
+}
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    /// Returns a whole number of major units, e.g. `Money::from_major(5)` is `5.00`.
+    pub const fn from_major(major: i64) -> Money {
+        Money(major * MINOR_PER_MAJOR)
+    }
+
+    /// Returns the amount in minor units.
+    pub fn minor_units(&self) -> i64 {
+        self.0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    /// Returns the given rate of the amount, in basis points (2500 is 25%),
+    /// rounded to the nearest minor unit, halves away from zero.
+    pub fn rate(&self, basis_points: i32) -> Money {
+        let scaled = self.0 as i128 * basis_points as i128;
+        let half = if scaled < 0 { -5_000 } else { 5_000 };
+        Money(((scaled + half) / 10_000) as i64)
+    }
+
+    /// Returns the share `part / whole` of the amount, `whole` being positive,
+    /// rounded to the nearest minor unit, halves away from zero.
+    pub fn prorate(&self, part: i64, whole: i64) -> Money {
+        let scaled = self.0 as i128 * part as i128;
+        let half = whole as i128 / 2;
+        let half = if scaled < 0 { -half } else { half };
+        Money(((scaled + half) / whole as i128) as i64)
+    }
+
+    /// Returns a twelfth of the amount, the price of a month at a yearly price,
+    /// rounded to the nearest minor unit, halves away from zero.
+    pub fn monthly(&self) -> Money {
+        let half = if self.0 < 0 { -6 } else { 6 };
+        Money((self.0 + half) / 12)
+    }
+}
+
+impl FromStr for Money {
+    type Err = MoneyError;
+
+    /// Parses a decimal amount with at most two decimals, e.g. `199`, `199.9` or `-5.25`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        let (major, minor) = match digits.split_once('.') {
+            Some((major, minor)) => (major, minor),
+            None => (digits, ""),
+        };
+        let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if major.is_empty() || !is_digits(major) || !is_digits(minor) {
+            return Err(MoneyError::InvalidFormat);
+        }
+        if digits.contains('.') && minor.is_empty() {
+            return Err(MoneyError::InvalidFormat);
+        }
+        if minor.len() > 2 {
+            return Err(MoneyError::TooPrecise);
+        }
+
+        let major = major.parse::<i64>().map_err(|_| MoneyError::TooLarge)?;
+        let minor = format!("{:0<2}", minor).parse::<i64>().unwrap_or(0);
+        let minor_units = major
+            .checked_mul(MINOR_PER_MAJOR)
+            .and_then(|units| units.checked_add(minor))
+            .ok_or(MoneyError::TooLarge)?;
+        Ok(Money(if negative { -minor_units } else { minor_units }))
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let units = self.0.unsigned_abs();
+        let per_major = MINOR_PER_MAJOR as u64;
+        write!(f, "{}{}.{:02}", sign, units / per_major, units % per_major)
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        Money(self.0 + other.0)
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, other: Money) -> Money {
+        Money(self.0 - other.0)
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money(-self.0)
+    }
+}
+
+/// Multiplies an amount by a quantity, e.g. a price per user by a number of seats.
+impl Mul<i64> for Money {
+    type Output = Money;
+
+    fn mul(self, quantity: i64) -> Money {
+        Money(self.0 * quantity)
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::ZERO, |total, amount| total + amount)
+    }
+}
+
+impl Type<Postgres> for Money {
+    fn type_info() -> PgTypeInfo {
+        <i64 as Type<Postgres>>::type_info()
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for Money {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        Ok(Money(<i64 as Decode<Postgres>>::decode(value)?))
+    }
+}
+
+impl Encode<'_, Postgres> for Money {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+        <i64 as Encode<Postgres>>::encode_by_ref(&self.0, buf)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MoneyVisitor;
+
+        impl<'de> de::Visitor<'de> for MoneyVisitor {
+            type Value = Money;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a decimal amount with at most two decimals")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Money, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Money, E> {
+                self.visit_str(&v.to_string())
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Money, E> {
+                self.visit_str(&v.to_string())
+            }
+
+            // the shortest representation of the number, so 199.9 is read as written
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Money, E> {
+                if !v.is_finite() {
+                    return Err(E::custom(MoneyError::InvalidFormat));
+                }
+                self.visit_str(&v.to_string())
+            }
+        }
+
+        deserializer.deserialize_any(MoneyVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn money(s: &str) -> Money {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_decimal_amounts() {
+        assert_eq!(money("199"), Money(19_900));
+        assert_eq!(money("199.9"), Money(19_990));
+        assert_eq!(money("199.95"), Money(19_995));
+        assert_eq!(money(" 0.05 "), Money(5));
+        assert_eq!(money("-5.25"), Money(-525));
+        assert_eq!(money("0"), Money::ZERO);
+    }
+
+    #[test]
+    fn rejects_invalid_amounts() {
+        for invalid in ["", "-", "abc", "1.", ".5", "1,50", "1.5.0", "+1", "1e3"] {
+            assert_eq!(
+                invalid.parse::<Money>(),
+                Err(MoneyError::InvalidFormat),
+                "{:?}",
+                invalid
+            );
+        }
+        assert_eq!("1.999".parse::<Money>(), Err(MoneyError::TooPrecise));
+        assert_eq!(
+            "92233720368547758.08".parse::<Money>(),
+            Err(MoneyError::TooLarge)
+        );
+        assert_eq!(
+            "99999999999999999999".parse::<Money>(),
+            Err(MoneyError::TooLarge)
+        );
+    }
+
+    #[test]
+    fn deserializes_strings_and_numbers() {
+        let parse = |json: &str| serde_json::from_str::<Money>(json);
+        assert_eq!(parse("\"199.90\"").unwrap(), Money(19_990));
+        assert_eq!(parse("199.9").unwrap(), Money(19_990));
+        assert_eq!(parse("42").unwrap(), Money(4_200));
+        assert!(parse("0.125").is_err());
+        assert!(parse("true").is_err());
+    }
+
+    #[test]
+    fn formats_with_two_decimals() {
+        assert_eq!(Money(19_990).to_string(), "199.90");
+        assert_eq!(Money(5).to_string(), "0.05");
+        assert_eq!(Money(-525).to_string(), "-5.25");
+        assert_eq!(Money::ZERO.to_string(), "0.00");
+        assert_eq!(Money::from_major(1_000_000).to_string(), "1000000.00");
+        assert_eq!(serde_json::to_string(&Money(100)).unwrap(), "\"1.00\"");
+    }
+
+    #[test]
+    fn rate_rounds_halves_away_from_zero() {
+        assert_eq!(Money(10_000).rate(2_500), Money(2_500));
+        // 0.5 minor units
+        assert_eq!(Money(2).rate(2_500), Money(1));
+        assert_eq!(Money(-2).rate(2_500), Money(-1));
+        // 0.4 and 0.6 minor units
+        assert_eq!(Money(4).rate(1_000), Money(0));
+        assert_eq!(Money(6).rate(1_000), Money(1));
+        assert_eq!(Money(12_345).rate(0), Money::ZERO);
+        assert_eq!(Money(12_345).rate(10_000), Money(12_345));
+    }
+
+    #[test]
+    fn monthly_rounds_halves_away_from_zero() {
+        assert_eq!(Money(120_000).monthly(), Money(10_000));
+        // 0.5 minor units
+        assert_eq!(Money(6).monthly(), Money(1));
+        assert_eq!(Money(-6).monthly(), Money(-1));
+        assert_eq!(Money(5).monthly(), Money(0));
+        assert_eq!(Money(100).monthly(), Money(8));
+    }
+
+    #[test]
+    fn prorate_rounds_halves_away_from_zero() {
+        assert_eq!(Money(36_500).prorate(100, 365), Money(10_000));
+        assert_eq!(Money(10_000).prorate(1, 3), Money(3_333));
+        assert_eq!(Money(20_000).prorate(1, 3), Money(6_667));
+        assert_eq!(Money(-20_000).prorate(1, 3), Money(-6_667));
+        // 0.5 minor units
+        assert_eq!(Money(1).prorate(1, 2), Money(1));
+        assert_eq!(Money(-1).prorate(1, 2), Money(-1));
+    }
+
+    #[test]
+    fn prorate_boundaries() {
+        let year = Money(123_456);
+        assert_eq!(year.prorate(0, 365), Money::ZERO);
+        assert_eq!(year.prorate(365, 365), year);
+        // does not overflow while scaling
+        let large = Money(i64::MAX / 2);
+        assert_eq!(large.prorate(365, 365), large);
+    }
+
+    #[test]
+    fn arithmetic_is_exact() {
+        assert_eq!(money("0.10") + money("0.20"), money("0.30"));
+        assert_eq!(money("1.00") - money("1.01"), money("-0.01"));
+        assert_eq!(-money("5.25"), money("-5.25"));
+        assert_eq!(money("199.90") * 3, money("599.70"));
+        let amounts = [money("1.10"), money("2.20"), money("3.30")];
+        assert_eq!(amounts.into_iter().sum::<Money>(), money("6.60"));
+    }
+}
@@ -0,0 +1,187 @@
+//! Payment subsystem.
+//!
+//! Orders are paid through a [`PaymentProvider`]. When an order that costs something is placed,
+//! a payment intent is created at the provider, and the customer pays it there.
+//! The provider reports the outcome with a signed webhook callback, and only then is the order
+//! marked paid and applied to its licenses.
+//! A customer paying a subscription renewal saves the payment method at the provider, later
+//! renewals are charged to it without the customer, and reported the same way.
+//! The credit of an order crediting a company is paid back from its earlier payments, see [`credit`].
+//!
+//! [`mock::MockPaymentProvider`] stands in for a real provider in development and tests,
+//! it is only used when mock payments are enabled.
+
+use chrono::{DateTime, Duration, Utc};
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use utoipa::ToSchema;
+
+use crate::money::{Currency, Money};
+
+pub mod credit;
+pub mod mock;
+
+/// Header carrying the signature of a webhook callback.
+pub const SIGNATURE_HEADER: &str = "Payment-Signature";
+/// How old a webhook event may be, older events are rejected so they cannot be replayed.
+const WEBHOOK_TOLERANCE_MINUTES: i64 = 5;
+
+/// Status of a payment, as stored in the database.
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[sqlx(type_name = "payment_status_enum", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentStatus {
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+/// Payment of an order at the provider, for the customer to pay.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PaymentIntent {
+    pub intent_id: String,
+    /// Secret the customer uses to pay the intent at the provider.
+    pub client_secret: String,
+    pub status: PaymentStatus,
+    #[schema(value_type = String, example = "5370.00")]
+    pub amount: Money,
+    pub currency: Currency,
+}
+
+/// Money paid back to the customer from a payment.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Refund {
+    pub refund_id: String,
+    pub intent_id: String,
+    #[schema(value_type = String, example = "100.00")]
+    pub amount: Money,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventType {
+    PaymentSucceeded,
+    PaymentFailed,
+}
+
+/// Outcome of a payment, as reported by the provider in a webhook callback.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookEvent {
+    pub event_id: String,
+    pub event_type: WebhookEventType,
+    pub intent_id: String,
+    pub amount: Money,
+    pub currency: Currency,
+    pub created_at: DateTime<Utc>,
+    /// Payment method the customer saved for later payments when paying, if asked to.
+    #[serde(default)]
+    pub payment_method_id: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum PaymentError {
+    /// The provider does not know the payment intent.
+    UnknownIntent,
+    /// The payment intent is not in a state that allows the operation.
+    InvalidState,
+    /// The refund is larger than what is left of the payment.
+    RefundTooLarge,
+    /// The provider does not know the saved payment method, or it can no longer be charged.
+    UnknownPaymentMethod,
+    /// The webhook callback is not signed with our secret, or too old.
+    InvalidSignature,
+    /// The webhook callback is not a valid event.
+    InvalidPayload,
+}
+
+impl std::fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PaymentError::UnknownIntent => write!(f, "Unknown payment intent"),
+            PaymentError::InvalidState => {
+                write!(f, "Payment intent does not allow this operation")
+            }
+            PaymentError::RefundTooLarge => write!(f, "Refund is larger than the payment"),
+            PaymentError::UnknownPaymentMethod => write!(f, "Unknown payment method"),
+            PaymentError::InvalidSignature => write!(f, "Invalid webhook signature"),
+            PaymentError::InvalidPayload => write!(f, "Invalid webhook payload"),
+        }
+    }
+}
+
+/// A payment provider (gateway) orders are paid through.
+pub trait PaymentProvider: Send + Sync {
+    /// Name of the provider, stored with its payments.
+    fn name(&self) -> &'static str;
+
+    /// Creates a payment intent for an order, to be paid by the customer.
+    /// With `save_payment_method`, the customer agrees to the payment method being saved
+    /// for later payments, it is reported with the outcome of the payment.
+    fn create_intent(
+        &self,
+        order_id: i32,
+        amount: Money,
+        currency: Currency,
+        save_payment_method: bool,
+    ) -> BoxFuture<'_, Result<PaymentIntent, PaymentError>>;
+
+    /// Charges an order to a payment method the customer saved, without the customer.
+    /// The outcome is reported through a webhook callback.
+    fn charge_saved_method<'a>(
+        &'a self,
+        order_id: i32,
+        amount: Money,
+        currency: Currency,
+        payment_method_id: &'a str,
+    ) -> BoxFuture<'a, Result<PaymentIntent, PaymentError>>;
+
+    /// Cancels a payment intent the customer has not paid, so it can no longer be paid.
+    /// Canceling an intent that failed or was canceled does nothing,
+    /// an intent that was paid cannot be canceled.
+    fn cancel_intent<'a>(&'a self, intent_id: &'a str) -> BoxFuture<'a, Result<(), PaymentError>>;
+
+    /// Pays back part or all of a confirmed payment.
+    ///
+    /// A refund made again with the same idempotency key is only paid back once,
+    /// the provider returns the refund it made the first time.
+    fn refund<'a>(
+        &'a self,
+        intent_id: &'a str,
+        amount: Money,
+        idempotency_key: &'a str,
+    ) -> BoxFuture<'a, Result<Refund, PaymentError>>;
+
+    /// Checks that a webhook callback comes from the provider, and returns its event.
+    fn verify_webhook(&self, payload: &[u8], signature: &str)
+        -> Result<WebhookEvent, PaymentError>;
+}
+
+/// Signs a webhook payload with HMAC-SHA256, returning the signature as hex.
+pub fn sign_payload(secret: &[u8], payload: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(payload);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Checks the HMAC-SHA256 signature of a webhook payload, and parses the event in it.
+/// The signature is compared in constant time, and events older than a few minutes are rejected.
+pub fn verify_signed_event(
+    secret: &[u8],
+    payload: &[u8],
+    signature: &str,
+) -> Result<WebhookEvent, PaymentError> {
+    let signature = hex::decode(signature.trim()).map_err(|_| PaymentError::InvalidSignature)?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(payload);
+    mac.verify_slice(&signature)
+        .map_err(|_| PaymentError::InvalidSignature)?;
+
+    let event: WebhookEvent =
+        serde_json::from_slice(payload).map_err(|_| PaymentError::InvalidPayload)?;
+    if Utc::now() - event.created_at > Duration::minutes(WEBHOOK_TOLERANCE_MINUTES) {
+        return Err(PaymentError::InvalidSignature);
+    }
+    Ok(event)
+}
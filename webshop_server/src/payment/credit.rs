@@ -0,0 +1,297 @@
+//! Paying back the credit of orders crediting a company.
+//!
+//! The credit is paid back by refunding the earlier paid orders of the credited license,
+//! newest first, with a credit note for each refund. The refund from each order is made with
+//! an idempotency key of the credit order and that order, so a pay back that failed partway
+//! can be retried: what was recorded is not paid back again, and a refund the provider made
+//! but that was not recorded is returned again by the provider instead of being paid twice.
+//! The credit order stays pending until its credit is paid back in full.
+
+use log::error;
+use sqlx::{Pool, Postgres};
+
+use super::{PaymentError, PaymentProvider};
+use crate::{
+    data_access::refund::{self, CreditOrder, LicenseAction, PartialRefund},
+    money::Money,
+};
+
+#[derive(Debug)]
+pub enum CreditError {
+    SqlxError(sqlx::Error),
+    /// The provider could not make a refund.
+    PaymentError(PaymentError),
+    /// The license has too little left to refund, the amount is what is left of the credit.
+    NotPaidBack(Money),
+}
+
+impl From<sqlx::Error> for CreditError {
+    fn from(e: sqlx::Error) -> Self {
+        CreditError::SqlxError(e)
+    }
+}
+
+impl From<PaymentError> for CreditError {
+    fn from(e: PaymentError) -> Self {
+        CreditError::PaymentError(e)
+    }
+}
+
+impl std::fmt::Display for CreditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CreditError::SqlxError(e) => write!(f, "{}", e),
+            CreditError::PaymentError(e) => write!(f, "{}", e),
+            CreditError::NotPaidBack(left) => {
+                write!(f, "Credit not paid back in full, {} is left", left)
+            }
+        }
+    }
+}
+
+/// Part of a credit paid back from one earlier order.
+#[derive(Debug, PartialEq)]
+pub struct CreditShare {
+    /// Amount paid back, tax included.
+    pub amount: Money,
+    /// Part of the amount that is tax.
+    pub tax: Money,
+}
+
+/// Returns the part of a credit paid back from an earlier order with `refundable` left to refund,
+/// none if nothing is.
+///
+/// `left` and `tax_left` are what is still to be paid back of the credit and its tax.
+/// The tax is prorated, and the last share pays back what is left of it,
+/// so rounding never adds up to more.
+pub fn credit_share(
+    credit: &CreditOrder,
+    left: Money,
+    tax_left: Money,
+    refundable: Money,
+) -> Option<CreditShare> {
+    let amount = refundable.min(left);
+    if amount <= Money::ZERO {
+        return None;
+    }
+    let tax = if amount == left {
+        tax_left
+    } else {
+        credit
+            .credit_tax
+            .prorate(amount.minor_units(), credit.credit.minor_units())
+    };
+    Some(CreditShare { amount, tax })
+}
+
+/// Pays back what is left of the credit of an order crediting a company, and marks it paid
+/// once it is paid back in full. Does nothing if the order is not waiting for its credit.
+///
+/// Only the orders paid through the provider are refunded.
+/// On error the order stays pending, and calling this again resumes where it stopped.
+pub async fn pay_back_credit(
+    pool: &Pool<Postgres>,
+    provider: &dyn PaymentProvider,
+    order_id: &i32,
+) -> Result<(), CreditError> {
+    // the credit order stays locked until it is paid back, so it is paid back once at a time
+    let mut credit_transaction = pool.begin().await?;
+    let credit = match refund::lock_credit_order(&mut credit_transaction, order_id).await? {
+        Some(credit) => credit,
+        None => return Ok(()),
+    };
+    let sources = refund::get_credit_sources(pool, &credit.license_id).await?;
+
+    let mut left = credit.credit - credit.paid_back;
+    let mut tax_left = credit.credit_tax - credit.paid_back_tax;
+    for source in sources
+        .iter()
+        .filter(|source| source.provider == provider.name())
+    {
+        if left <= Money::ZERO {
+            break;
+        }
+        // the source order stays locked until the refund is recorded, see `refund_order`
+        let mut transaction = pool.begin().await?;
+        let refunds = refund::lock_order_refunds(&mut transaction, &source.order_id).await?;
+        let refunded: Money = refunds.iter().map(|refund| refund.amount).sum();
+        let share = match credit_share(&credit, left, tax_left, source.total - refunded) {
+            Some(share) => share,
+            None => continue,
+        };
+        let idempotency_key = format!("order_{}_credit_{}", credit.order_id, source.order_id);
+        let provider_refund = provider
+            .refund(&source.intent_id, share.amount, &idempotency_key)
+            .await?;
+        let new_refund = PartialRefund {
+            order_id: source.order_id,
+            provider: source.provider.clone(),
+            provider_refund_id: provider_refund.refund_id,
+            amount: share.amount,
+            tax: share.tax,
+            reason: Some(format!("Credit of order {}", credit.invoice_number)),
+            license_action: LicenseAction::Keep,
+            license_end_date: None,
+            created_by: credit.user_id,
+            credit_order_id: Some(credit.order_id),
+        };
+        let created = match refund::create_refund(&mut transaction, &new_refund, &[]).await {
+            Ok(_) => transaction.commit().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = created {
+            // the money has been paid back, refunding again with the same key records it
+            error!(
+                "Could not record refund {} paying back credit of order {}",
+                new_refund.provider_refund_id, credit.invoice_number
+            );
+            return Err(e.into());
+        }
+        left = left - share.amount;
+        tax_left = tax_left - share.tax;
+    }
+    if left > Money::ZERO {
+        return Err(CreditError::NotPaidBack(left));
+    }
+    refund::settle_credit_order(&mut credit_transaction, &credit.order_id).await?;
+    credit_transaction.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        money::Currency,
+        payment::{mock::MockPaymentProvider, PaymentStatus},
+    };
+
+    fn money(s: &str) -> Money {
+        s.parse().unwrap()
+    }
+
+    fn credit(credit: &str, credit_tax: &str) -> CreditOrder {
+        CreditOrder {
+            order_id: 2,
+            invoice_number: "INV-2026-000002".to_string(),
+            user_id: None,
+            license_id: 1,
+            credit: money(credit),
+            credit_tax: money(credit_tax),
+            paid_back: Money::ZERO,
+            paid_back_tax: Money::ZERO,
+        }
+    }
+
+    #[test]
+    fn credit_is_paid_back_from_orders_in_turn_with_all_of_its_tax() {
+        let credit = credit("100.00", "20.00");
+        let first = credit_share(&credit, credit.credit, credit.credit_tax, money("33.33"));
+        assert_eq!(
+            first,
+            Some(CreditShare {
+                amount: money("33.33"),
+                tax: money("6.67"),
+            })
+        );
+        let first = first.unwrap();
+        let last = credit_share(
+            &credit,
+            credit.credit - first.amount,
+            credit.credit_tax - first.tax,
+            money("500.00"),
+        );
+        assert_eq!(
+            last,
+            Some(CreditShare {
+                amount: money("66.67"),
+                tax: money("13.33"),
+            })
+        );
+    }
+
+    #[test]
+    fn nothing_is_paid_back_from_refunded_orders_or_once_paid_back() {
+        let credit = credit("100.00", "20.00");
+        assert_eq!(
+            credit_share(&credit, credit.credit, credit.credit_tax, Money::ZERO),
+            None
+        );
+        assert_eq!(
+            credit_share(&credit, Money::ZERO, Money::ZERO, money("500.00")),
+            None
+        );
+    }
+
+    #[actix_web::test]
+    async fn credit_paid_back_partway_is_resumed_without_paying_twice() {
+        let provider = MockPaymentProvider::new("secret");
+        let newest = provider
+            .create_intent(3, money("40.00"), Currency::Eur, false)
+            .await
+            .unwrap();
+        provider.pay(&newest.intent_id).unwrap();
+        let oldest = provider
+            .create_intent(1, money("200.00"), Currency::Eur, false)
+            .await
+            .unwrap();
+        let mut credit = credit("100.00", "20.00");
+
+        // the first refund is made, but the second fails, the payment not having gone through yet
+        let first =
+            credit_share(&credit, credit.credit, credit.credit_tax, money("40.00")).unwrap();
+        let first_refund = provider
+            .refund(&newest.intent_id, first.amount, "order_2_credit_3")
+            .await
+            .unwrap();
+        let second = credit_share(
+            &credit,
+            credit.credit - first.amount,
+            credit.credit_tax - first.tax,
+            money("200.00"),
+        )
+        .unwrap();
+        assert!(matches!(
+            provider
+                .refund(&oldest.intent_id, second.amount, "order_2_credit_1")
+                .await,
+            Err(PaymentError::InvalidState)
+        ));
+        assert_eq!(
+            provider.pay(&oldest.intent_id).unwrap().status,
+            PaymentStatus::Succeeded
+        );
+
+        // the retry refunds the first order again with the same key, as if the refund was not recorded,
+        // which returns the refund made the first time instead of paying it back twice
+        let again = provider
+            .refund(&newest.intent_id, first.amount, "order_2_credit_3")
+            .await
+            .unwrap();
+        assert_eq!(again.refund_id, first_refund.refund_id);
+        assert!(matches!(
+            provider
+                .refund(&newest.intent_id, money("0.01"), "order_2_credit_3_again")
+                .await,
+            Err(PaymentError::RefundTooLarge)
+        ));
+
+        // once the first refund is recorded, the retry only pays back what is left
+        credit.paid_back = first.amount;
+        credit.paid_back_tax = first.tax;
+        let resumed = credit_share(
+            &credit,
+            credit.credit - credit.paid_back,
+            credit.credit_tax - credit.paid_back_tax,
+            money("200.00"),
+        )
+        .unwrap();
+        assert_eq!(resumed, second);
+        assert_eq!(resumed.amount, money("60.00"));
+        assert_eq!(resumed.tax, money("12.00"));
+        provider
+            .refund(&oldest.intent_id, resumed.amount, "order_2_credit_1")
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,328 @@
+//! Local mock payment gateway, for development and tests.
+//!
+//! Intents are kept in memory and never charge anyone. The customer side of the gateway is
+//! simulated by [`MockPaymentProvider::authorize`], [`MockPaymentProvider::pay`] and
+//! [`MockPaymentProvider::decline`], and [`MockPaymentProvider::signed_webhook`] builds
+//! the callback a real provider would send.
+//! Charges to a saved payment method go through right away.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::Utc;
+use futures::future::BoxFuture;
+use uuid::Uuid;
+
+use super::{
+    sign_payload, verify_signed_event, PaymentError, PaymentIntent, PaymentProvider, PaymentStatus,
+    Refund, WebhookEvent, WebhookEventType,
+};
+use crate::money::{Currency, Money};
+
+struct MockIntent {
+    intent: PaymentIntent,
+    /// Whether the customer agreed to save the payment method when paying.
+    save_payment_method: bool,
+    /// Payment method the intent was paid with, once paid.
+    payment_method_id: Option<String>,
+    refunded: Money,
+    /// Refunds made, by their idempotency key.
+    refunds: HashMap<String, Refund>,
+}
+
+pub struct MockPaymentProvider {
+    webhook_secret: Vec<u8>,
+    intents: Mutex<HashMap<String, MockIntent>>,
+}
+
+impl MockPaymentProvider {
+    pub fn new(webhook_secret: &str) -> Self {
+        MockPaymentProvider {
+            webhook_secret: webhook_secret.as_bytes().to_vec(),
+            intents: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks that the customer holds the client secret of a pending intent,
+    /// as the payment page of the gateway would.
+    pub fn authorize(&self, intent_id: &str, client_secret: &str) -> Result<(), PaymentError> {
+        let intents = self.intents.lock().expect("mock payment state poisoned");
+        let mock = intents.get(intent_id).ok_or(PaymentError::UnknownIntent)?;
+        if mock.intent.client_secret != client_secret {
+            return Err(PaymentError::UnknownIntent);
+        }
+        if mock.intent.status != PaymentStatus::Pending {
+            return Err(PaymentError::InvalidState);
+        }
+        Ok(())
+    }
+
+    /// Simulates the customer paying an intent with a new payment method.
+    pub fn pay(&self, intent_id: &str) -> Result<PaymentIntent, PaymentError> {
+        let payment_method_id = format!("mock_pm_{}", Uuid::new_v4().simple());
+        self.finish(intent_id, PaymentStatus::Succeeded, Some(payment_method_id))
+    }
+
+    /// Simulates the customer's payment being declined.
+    pub fn decline(&self, intent_id: &str) -> Result<PaymentIntent, PaymentError> {
+        self.finish(intent_id, PaymentStatus::Failed, None)
+    }
+
+    /// Builds the signed webhook callback reporting the outcome of an intent.
+    ///
+    /// # Returns
+    /// The payload and the signature to send in the [`super::SIGNATURE_HEADER`] header.
+    pub fn signed_webhook(&self, intent_id: &str) -> Result<(Vec<u8>, String), PaymentError> {
+        let intents = self.intents.lock().expect("mock payment state poisoned");
+        let mock = intents.get(intent_id).ok_or(PaymentError::UnknownIntent)?;
+        let event_type = match mock.intent.status {
+            PaymentStatus::Succeeded => WebhookEventType::PaymentSucceeded,
+            PaymentStatus::Failed => WebhookEventType::PaymentFailed,
+            PaymentStatus::Pending => return Err(PaymentError::InvalidState),
+        };
+        let event = WebhookEvent {
+            event_id: format!("mock_evt_{}", Uuid::new_v4().simple()),
+            event_type,
+            intent_id: intent_id.to_string(),
+            amount: mock.intent.amount,
+            currency: mock.intent.currency,
+            created_at: Utc::now(),
+            payment_method_id: mock
+                .payment_method_id
+                .clone()
+                .filter(|_| mock.save_payment_method),
+        };
+        let payload = serde_json::to_vec(&event).map_err(|_| PaymentError::InvalidPayload)?;
+        let signature = sign_payload(&self.webhook_secret, &payload);
+        Ok((payload, signature))
+    }
+
+    /// Moves a pending intent to its final status, paid with the payment method, if any.
+    fn finish(
+        &self,
+        intent_id: &str,
+        status: PaymentStatus,
+        payment_method_id: Option<String>,
+    ) -> Result<PaymentIntent, PaymentError> {
+        let mut intents = self.intents.lock().expect("mock payment state poisoned");
+        let mock = intents
+            .get_mut(intent_id)
+            .ok_or(PaymentError::UnknownIntent)?;
+        if mock.intent.status != PaymentStatus::Pending {
+            return Err(PaymentError::InvalidState);
+        }
+        mock.intent.status = status;
+        mock.payment_method_id = payment_method_id;
+        Ok(mock.intent.clone())
+    }
+
+    /// Creates an intent, and keeps it with the payment method it is paid with, if any.
+    fn new_intent(
+        &self,
+        amount: Money,
+        currency: Currency,
+        save_payment_method: bool,
+        payment_method_id: Option<String>,
+    ) -> PaymentIntent {
+        let intent = PaymentIntent {
+            intent_id: format!("mock_pi_{}", Uuid::new_v4().simple()),
+            client_secret: Uuid::new_v4().simple().to_string(),
+            status: match payment_method_id {
+                Some(_) => PaymentStatus::Succeeded,
+                None => PaymentStatus::Pending,
+            },
+            amount,
+            currency,
+        };
+        self.intents
+            .lock()
+            .expect("mock payment state poisoned")
+            .insert(
+                intent.intent_id.clone(),
+                MockIntent {
+                    intent: intent.clone(),
+                    save_payment_method,
+                    payment_method_id,
+                    refunded: Money::ZERO,
+                    refunds: HashMap::new(),
+                },
+            );
+        intent
+    }
+}
+
+impl PaymentProvider for MockPaymentProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn create_intent(
+        &self,
+        _order_id: i32,
+        amount: Money,
+        currency: Currency,
+        save_payment_method: bool,
+    ) -> BoxFuture<'_, Result<PaymentIntent, PaymentError>> {
+        Box::pin(async move { Ok(self.new_intent(amount, currency, save_payment_method, None)) })
+    }
+
+    fn charge_saved_method<'a>(
+        &'a self,
+        _order_id: i32,
+        amount: Money,
+        currency: Currency,
+        payment_method_id: &'a str,
+    ) -> BoxFuture<'a, Result<PaymentIntent, PaymentError>> {
+        Box::pin(async move {
+            let saved = self
+                .intents
+                .lock()
+                .expect("mock payment state poisoned")
+                .values()
+                .any(|mock| {
+                    mock.save_payment_method
+                        && mock.payment_method_id.as_deref() == Some(payment_method_id)
+                });
+            if !saved {
+                return Err(PaymentError::UnknownPaymentMethod);
+            }
+            Ok(self.new_intent(amount, currency, true, Some(payment_method_id.to_string())))
+        })
+    }
+
+    fn cancel_intent<'a>(&'a self, intent_id: &'a str) -> BoxFuture<'a, Result<(), PaymentError>> {
+        Box::pin(async move {
+            let mut intents = self.intents.lock().expect("mock payment state poisoned");
+            let mock = intents
+                .get_mut(intent_id)
+                .ok_or(PaymentError::UnknownIntent)?;
+            match mock.intent.status {
+                PaymentStatus::Pending | PaymentStatus::Failed => {
+                    mock.intent.status = PaymentStatus::Failed;
+                    Ok(())
+                }
+                PaymentStatus::Succeeded => Err(PaymentError::InvalidState),
+            }
+        })
+    }
+
+    fn refund<'a>(
+        &'a self,
+        intent_id: &'a str,
+        amount: Money,
+        idempotency_key: &'a str,
+    ) -> BoxFuture<'a, Result<Refund, PaymentError>> {
+        Box::pin(async move {
+            let mut intents = self.intents.lock().expect("mock payment state poisoned");
+            let mock = intents
+                .get_mut(intent_id)
+                .ok_or(PaymentError::UnknownIntent)?;
+            if let Some(refund) = mock.refunds.get(idempotency_key) {
+                return Ok(refund.clone());
+            }
+            if mock.intent.status != PaymentStatus::Succeeded {
+                return Err(PaymentError::InvalidState);
+            }
+            if amount <= Money::ZERO || mock.refunded + amount > mock.intent.amount {
+                return Err(PaymentError::RefundTooLarge);
+            }
+            mock.refunded = mock.refunded + amount;
+            let refund = Refund {
+                refund_id: format!("mock_re_{}", Uuid::new_v4().simple()),
+                intent_id: intent_id.to_string(),
+                amount,
+            };
+            mock.refunds
+                .insert(idempotency_key.to_string(), refund.clone());
+            Ok(refund)
+        })
+    }
+
+    fn verify_webhook(
+        &self,
+        payload: &[u8],
+        signature: &str,
+    ) -> Result<WebhookEvent, PaymentError> {
+        verify_signed_event(&self.webhook_secret, payload, signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(provider: &MockPaymentProvider, intent_id: &str) -> WebhookEvent {
+        let (payload, signature) = provider.signed_webhook(intent_id).unwrap();
+        provider.verify_webhook(&payload, &signature).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn saved_payment_method_is_charged() {
+        let provider = MockPaymentProvider::new("secret");
+        let amount = Money::from_major(100);
+        let intent = provider
+            .create_intent(1, amount, Currency::Eur, true)
+            .await
+            .unwrap();
+        provider.pay(&intent.intent_id).unwrap();
+        let payment_method_id = event(&provider, &intent.intent_id)
+            .payment_method_id
+            .unwrap();
+
+        let charge = provider
+            .charge_saved_method(2, amount, Currency::Eur, &payment_method_id)
+            .await
+            .unwrap();
+        let charged = event(&provider, &charge.intent_id);
+        assert_eq!(charged.event_type, WebhookEventType::PaymentSucceeded);
+        assert_eq!(charged.amount, amount);
+    }
+
+    #[actix_web::test]
+    async fn canceled_intent_cannot_be_paid() {
+        let provider = MockPaymentProvider::new("secret");
+        let amount = Money::from_major(100);
+        let canceled = provider
+            .create_intent(1, amount, Currency::Eur, false)
+            .await
+            .unwrap();
+        provider.cancel_intent(&canceled.intent_id).await.unwrap();
+        assert!(matches!(
+            provider.pay(&canceled.intent_id),
+            Err(PaymentError::InvalidState)
+        ));
+        assert!(provider.cancel_intent(&canceled.intent_id).await.is_ok());
+
+        let paid = provider
+            .create_intent(2, amount, Currency::Eur, false)
+            .await
+            .unwrap();
+        provider.pay(&paid.intent_id).unwrap();
+        assert!(matches!(
+            provider.cancel_intent(&paid.intent_id).await,
+            Err(PaymentError::InvalidState)
+        ));
+    }
+
+    #[actix_web::test]
+    async fn payment_method_is_only_saved_when_asked() {
+        let provider = MockPaymentProvider::new("secret");
+        let amount = Money::from_major(100);
+        let intent = provider
+            .create_intent(1, amount, Currency::Eur, false)
+            .await
+            .unwrap();
+        provider.pay(&intent.intent_id).unwrap();
+        assert_eq!(event(&provider, &intent.intent_id).payment_method_id, None);
+
+        let paid_with = provider.intents.lock().unwrap()[&intent.intent_id]
+            .payment_method_id
+            .clone()
+            .unwrap();
+        assert!(matches!(
+            provider
+                .charge_saved_method(2, amount, Currency::Eur, &paid_with)
+                .await,
+            Err(PaymentError::UnknownPaymentMethod)
+        ));
+    }
+}
@@ -0,0 +1,1047 @@
+//! Pricing engine, the only place license prices are computed.
+//!
+//! Prices are per user per year, from the price list of the product in the currency priced in.
+//! A product is sold at the price of the highest volume tier the number of seats reaches,
+//! or at the price per user of the list if it reaches none,
+//! and that price applies to every seat of the purchase.
+//! Companies are priced in their billing currency, prices are never converted between
+//! currencies, and totals are exact.
+//! A discount code can take a percentage or a fixed amount off the price of the seats.
+//! Only available products are sold, but licenses of archived products are still renewed
+//! and changed at the prices of their product.
+//! A cart is priced as the sum of the prices of its items.
+//! A subscription is renewed at the price of a year, or a twelfth of it for a month.
+//! Changing the seats of a license, or renewing it before it ends, is prorated by the day:
+//! the days left on the license are credited at the current price of its seats,
+//! but never more than what is left of what was paid for the license,
+//! and the new seats or term are charged from now.
+
+use chrono::{DateTime, Months, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use utoipa::ToSchema;
+
+use crate::{
+    data_access::{
+        cart::CartItem,
+        company,
+        discount::{self, DiscountCode, DiscountKind},
+        license::{subscription::BillingInterval, License},
+        order::{self, PaidLine},
+        product::{
+            self,
+            price_list::{self, PriceList},
+            price_tier::PriceTier,
+            Product,
+        },
+    },
+    money::{Currency, Money},
+};
+
+/// Most seats that can be bought in one purchase.
+pub const MAX_SEATS: i32 = 10_000;
+/// Longest term, in years, a license can be bought for.
+pub const MAX_TERM_YEARS: i32 = 3;
+/// Highest price per user per year a product can be sold at.
+/// With at most [`MAX_SEATS`] seats for [`MAX_TERM_YEARS`] years, no total comes near
+/// overflowing an amount.
+pub const MAX_PRICE: Money = Money::from_major(1_000_000);
+/// Days a yearly price is prorated over.
+const DAYS_PER_YEAR: i64 = 365;
+
+/// Price of a number of seats of a product for a term, as computed by the server.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PriceQuote {
+    pub product_id: String,
+    pub seats: i32,
+    pub term_years: i32,
+    pub currency: Currency,
+    /// Price per user per year the seats are sold at.
+    #[schema(value_type = String, example = "179.00")]
+    pub unit_price: Money,
+    /// Smallest number of seats of the volume tier applied, none if the base price applies.
+    pub tier_min_seats: Option<i32>,
+    /// Price of all seats for the whole term, before any discount.
+    #[schema(value_type = String, example = "5370.00")]
+    pub subtotal: Money,
+    /// Discount code applied to the price, if any.
+    pub discount: Option<AppliedDiscount>,
+    /// Price of all seats for the whole term, less the discount.
+    #[schema(value_type = String, example = "4833.00")]
+    pub total: Money,
+}
+
+/// Price of all items of a cart, as computed by the server.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CartQuote {
+    pub currency: Currency,
+    /// Price of each item, in the order of the items.
+    pub lines: Vec<PriceQuote>,
+    /// Price of all items, before any discount.
+    #[schema(value_type = String, example = "5370.00")]
+    pub subtotal: Money,
+    /// Discount code applied to the price, if any.
+    pub discount: Option<AppliedDiscount>,
+    /// Price of all items, less the discount.
+    #[schema(value_type = String, example = "4833.00")]
+    pub total: Money,
+}
+
+/// Price of one billing period of a subscription, as computed by the server.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PeriodQuote {
+    pub product_id: String,
+    pub seats: i32,
+    pub billing_interval: BillingInterval,
+    pub currency: Currency,
+    /// Price per user per year the seats are sold at.
+    #[schema(value_type = String, example = "179.00")]
+    pub unit_price: Money,
+    /// Smallest number of seats of the volume tier applied, none if the base price applies.
+    pub tier_min_seats: Option<i32>,
+    /// Price of all seats for the period.
+    #[schema(value_type = String, example = "447.50")]
+    pub total: Money,
+}
+
+/// Prorated price of changing the seats of a license, or of renewing it before it ends,
+/// as computed by the server.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ProrationQuote {
+    pub product_id: String,
+    pub currency: Currency,
+    /// Number of seats of the license now.
+    pub current_seats: i32,
+    /// Price per user per year the current seats are credited at, their current price.
+    #[schema(value_type = String, example = "179.00")]
+    pub current_unit_price: Money,
+    /// Number of seats after the change.
+    pub seats: i32,
+    /// Price per user per year the seats are charged at after the change.
+    #[schema(value_type = String, example = "179.00")]
+    pub unit_price: Money,
+    /// Smallest number of seats of the volume tier applied after the change,
+    /// none if the base price applies.
+    pub tier_min_seats: Option<i32>,
+    /// Years of the new term of an early renewal, none for a seat change.
+    pub term_years: Option<i32>,
+    /// Start of the period charged, now.
+    pub period_start: DateTime<Utc>,
+    /// End of the period charged, the end date of the license for a seat change,
+    /// the end of the new term for an early renewal.
+    pub period_end: DateTime<Utc>,
+    /// Started days left on the license, credited.
+    pub days_left: i64,
+    /// Value of the current seats for the days left on the license.
+    #[schema(value_type = String, example = "2685.00")]
+    pub credit: Money,
+    /// Price of the seats after the change for the period charged, before any discount.
+    #[schema(value_type = String, example = "3580.00")]
+    pub charge: Money,
+    /// Discount code applied to the charge of an early renewal, if any.
+    pub discount: Option<AppliedDiscount>,
+    /// Charge less discount and credit, negative if the change credits the company.
+    #[schema(value_type = String, example = "895.00")]
+    pub total: Money,
+}
+
+/// Discount code applied to a price.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AppliedDiscount {
+    pub discount_code_id: i32,
+    #[schema(example = "WELCOME10")]
+    pub code: String,
+    /// Amount taken off the price.
+    #[schema(value_type = String, example = "537.00")]
+    pub amount: Money,
+}
+
+#[derive(Debug)]
+pub enum PricingError {
+    SqlxError(sqlx::Error),
+    /// The product does not exist, or is not available for purchase.
+    ProductNotFound,
+    /// The number of seats is not between 1 and [`MAX_SEATS`].
+    InvalidSeats,
+    /// The term is not between 1 and [`MAX_TERM_YEARS`] years.
+    InvalidTerm,
+    /// No discount code matches the code given.
+    DiscountNotFound,
+    /// The discount code is inactive, not valid yet or no longer valid.
+    DiscountNotValidNow,
+    /// The discount code has been used as many times as it can be.
+    DiscountUsedUp,
+    /// The discount code is for another product, company or currency.
+    DiscountNotApplicable,
+    /// The cart has no items.
+    EmptyCart,
+    /// The product has no price list in the currency.
+    NotSoldInCurrency,
+}
+
+impl From<sqlx::Error> for PricingError {
+    fn from(e: sqlx::Error) -> Self {
+        PricingError::SqlxError(e)
+    }
+}
+
+impl std::fmt::Display for PricingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PricingError::SqlxError(e) => write!(f, "{}", e),
+            PricingError::ProductNotFound => write!(f, "Product not found"),
+            PricingError::InvalidSeats => {
+                write!(f, "Seats must be between 1 and {}", MAX_SEATS)
+            }
+            PricingError::InvalidTerm => {
+                write!(f, "Term must be between 1 and {} years", MAX_TERM_YEARS)
+            }
+            PricingError::DiscountNotFound => write!(f, "Discount code not found"),
+            PricingError::DiscountNotValidNow => write!(f, "Discount code is not valid now"),
+            PricingError::DiscountUsedUp => write!(f, "Discount code has been used up"),
+            PricingError::DiscountNotApplicable => {
+                write!(f, "Discount code does not apply to this purchase")
+            }
+            PricingError::EmptyCart => write!(f, "Cart is empty"),
+            PricingError::NotSoldInCurrency => {
+                write!(f, "Product is not sold in this currency")
+            }
+        }
+    }
+}
+
+/// Returns the tier that applies to a number of seats, if any.
+fn applicable_tier(tiers: &[PriceTier], seats: i32) -> Option<&PriceTier> {
+    tiers
+        .iter()
+        .filter(|tier| tier.min_seats <= seats)
+        .max_by_key(|tier| tier.min_seats)
+}
+
+/// Computes the price of a number of seats of an available product for a term,
+/// to sell a new license of it.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `product_id` - Id of the product
+/// * `seats` - Number of seats
+/// * `term_years` - Length of the license, in years
+/// * `currency` - Currency to price in, the billing currency of the company buying,
+///   none for the currency of the product
+pub async fn quote(
+    pool: &Pool<Postgres>,
+    product_id: &str,
+    seats: i32,
+    term_years: i32,
+    currency: Option<Currency>,
+) -> Result<PriceQuote, PricingError> {
+    let product = match product::get_product_by_id(pool, product_id).await {
+        Ok(product) if product.available() => product,
+        Ok(_) | Err(sqlx::Error::RowNotFound) => return Err(PricingError::ProductNotFound),
+        Err(e) => return Err(e.into()),
+    };
+    price_seats(pool, &product, seats, term_years, currency).await
+}
+
+/// Computes the price of a number of seats of the product of an existing license for a term,
+/// to renew the license or change its seats.
+///
+/// The product does not have to be available: a license of an archived product
+/// is still renewed and changed at the prices of the product.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `product_id` - Id of the product of the license
+/// * `seats` - Number of seats
+/// * `term_years` - Length of the term, in years
+/// * `currency` - Billing currency of the company of the license
+pub async fn quote_renewal(
+    pool: &Pool<Postgres>,
+    product_id: &str,
+    seats: i32,
+    term_years: i32,
+    currency: Currency,
+) -> Result<PriceQuote, PricingError> {
+    let product = match product::get_product_by_id(pool, product_id).await {
+        Ok(product) => product,
+        Err(sqlx::Error::RowNotFound) => return Err(PricingError::ProductNotFound),
+        Err(e) => return Err(e.into()),
+    };
+    price_seats(pool, &product, seats, term_years, Some(currency)).await
+}
+
+/// Computes the price of a number of seats of a product for a term from its price list.
+async fn price_seats(
+    pool: &Pool<Postgres>,
+    product: &Product,
+    seats: i32,
+    term_years: i32,
+    currency: Option<Currency>,
+) -> Result<PriceQuote, PricingError> {
+    check_seats_and_term(seats, term_years)?;
+    let currency = currency.unwrap_or(product.currency());
+    let price_list = price_list::get_price_list(pool, product, currency)
+        .await?
+        .ok_or(PricingError::NotSoldInCurrency)?;
+    Ok(price_from_list(
+        product.product_id(),
+        &price_list,
+        seats,
+        term_years,
+    ))
+}
+
+/// Checks the number of seats and the term can be bought.
+fn check_seats_and_term(seats: i32, term_years: i32) -> Result<(), PricingError> {
+    if !(1..=MAX_SEATS).contains(&seats) {
+        return Err(PricingError::InvalidSeats);
+    }
+    if !(1..=MAX_TERM_YEARS).contains(&term_years) {
+        return Err(PricingError::InvalidTerm);
+    }
+    Ok(())
+}
+
+/// Computes the price of a number of seats for a term from a price list,
+/// at the price of the tier the seats reach.
+fn price_from_list(
+    product_id: &str,
+    price_list: &PriceList,
+    seats: i32,
+    term_years: i32,
+) -> PriceQuote {
+    let tier = applicable_tier(&price_list.tiers, seats);
+    let unit_price = match tier {
+        Some(tier) => tier.price_per_user,
+        None => price_list.price_per_user,
+    };
+
+    let total = unit_price * seats as i64 * term_years as i64;
+    PriceQuote {
+        product_id: product_id.to_string(),
+        seats,
+        term_years,
+        currency: price_list.currency,
+        unit_price,
+        tier_min_seats: tier.map(|tier| tier.min_seats),
+        subtotal: total,
+        discount: None,
+        total,
+    }
+}
+
+/// Computes the price of a number of seats of an available product for a term for a company,
+/// in its billing currency. See [`quote`].
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `product_id` - Id of the product
+/// * `seats` - Number of seats
+/// * `term_years` - Length of the license, in years
+/// * `company_id` - Id of the company buying
+pub async fn quote_for_company(
+    pool: &Pool<Postgres>,
+    product_id: &str,
+    seats: i32,
+    term_years: i32,
+    company_id: i32,
+) -> Result<PriceQuote, PricingError> {
+    let currency = company::get_billing_currency(pool, &company_id).await?;
+    quote(pool, product_id, seats, term_years, Some(currency)).await
+}
+
+/// Returns when a term of a number of years starting at `start` ends,
+/// none if it is out of range.
+pub fn term_end(start: DateTime<Utc>, term_years: i32) -> Option<DateTime<Utc>> {
+    start.checked_add_months(Months::new(12 * term_years as u32))
+}
+
+/// Returns the started days left until a license ends, none if it has ended.
+fn days_left(now: DateTime<Utc>, end_date: DateTime<Utc>) -> i64 {
+    let hours = (end_date - now).num_hours().max(0);
+    (hours + 23) / 24
+}
+
+/// Returns what is left of what was paid for a license at `now`: the share of each paid line
+/// for the days left of its period, after the discount of its order and what an admin paid back.
+/// Nothing was paid for the lines of an order that cost nothing, while the lines of an order
+/// crediting the company take what they credit off what is left.
+pub fn paid_left(lines: &[PaidLine], now: DateTime<Utc>) -> Money {
+    lines
+        .iter()
+        .map(|line| {
+            let paid = if line.order_subtotal > Money::ZERO {
+                line.amount.prorate(
+                    (line.order_subtotal - line.order_refunded).minor_units(),
+                    (line.order_subtotal + line.order_discount).minor_units(),
+                )
+            } else {
+                line.amount.min(Money::ZERO)
+            };
+            let period_days = days_left(line.period_start, line.period_end);
+            if period_days == 0 {
+                return Money::ZERO;
+            }
+            let days_left = days_left(now.max(line.period_start), line.period_end);
+            paid.prorate(days_left, period_days)
+        })
+        .sum()
+}
+
+/// Credits the days left of a license at the current price of its seats,
+/// at most what is left of what was paid for it.
+async fn credit(
+    pool: &Pool<Postgres>,
+    license: &License,
+    current: &PriceQuote,
+    now: DateTime<Utc>,
+) -> Result<Money, PricingError> {
+    let lines = order::get_paid_lines(pool, &license.license_id()).await?;
+    let credit = current
+        .total
+        .prorate(days_left(now, license.end_date()), DAYS_PER_YEAR);
+    Ok(credit.min(paid_left(&lines, now).max(Money::ZERO)))
+}
+
+/// Computes the prorated price of changing the number of seats of a license until its end date.
+///
+/// The current seats are credited for the days left at their current price, see [`paid_left`]
+/// for the most that is credited, and the new number of seats is charged for the days left
+/// at the price of the tier it reaches. A decrease credits the company, and is never charged
+/// even when less was paid for the license than its new seats cost.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `license` - The license
+/// * `new_seats` - Number of seats of the license after the change
+/// * `currency` - Billing currency of the company of the license
+pub async fn quote_seat_change(
+    pool: &Pool<Postgres>,
+    license: &License,
+    new_seats: i32,
+    currency: Currency,
+) -> Result<ProrationQuote, PricingError> {
+    let current_seats = license.amount();
+    if new_seats == current_seats {
+        return Err(PricingError::InvalidSeats);
+    }
+    let product_id = license.product_id();
+    let current = quote_renewal(pool, product_id, current_seats, 1, currency).await?;
+    let new = quote_renewal(pool, product_id, new_seats, 1, currency).await?;
+    let now = Utc::now();
+    let end_date = license.end_date();
+    let days_left = days_left(now, end_date);
+    let credit = credit(pool, license, &current, now).await?;
+    let mut charge = new.total.prorate(days_left, DAYS_PER_YEAR);
+    if new_seats < current_seats {
+        charge = charge.min(credit);
+    }
+    Ok(ProrationQuote {
+        product_id: new.product_id,
+        currency: new.currency,
+        current_seats,
+        current_unit_price: current.unit_price,
+        seats: new_seats,
+        unit_price: new.unit_price,
+        tier_min_seats: new.tier_min_seats,
+        term_years: None,
+        period_start: now,
+        period_end: end_date,
+        days_left,
+        credit,
+        charge,
+        discount: None,
+        total: charge - credit,
+    })
+}
+
+/// Computes the prorated price of renewing a license before it ends, starting a new term now.
+///
+/// The seats are credited for the days left at their current price, see [`paid_left`]
+/// for the most that is credited, and charged for the whole new term, as if bought now,
+/// less the discount of the price.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `license` - The license
+/// * `price` - Price of the seats for the new term, as computed by [`quote_renewal`],
+///   with any discount applied
+pub async fn quote_early_renewal(
+    pool: &Pool<Postgres>,
+    license: &License,
+    price: &PriceQuote,
+) -> Result<ProrationQuote, PricingError> {
+    let current = quote_renewal(pool, &price.product_id, price.seats, 1, price.currency).await?;
+    let now = Utc::now();
+    let period_end = term_end(now, price.term_years).ok_or(PricingError::InvalidTerm)?;
+    let days_left = days_left(now, license.end_date());
+    let credit = credit(pool, license, &current, now).await?;
+    Ok(ProrationQuote {
+        product_id: price.product_id.clone(),
+        currency: price.currency,
+        current_seats: price.seats,
+        current_unit_price: current.unit_price,
+        seats: price.seats,
+        unit_price: price.unit_price,
+        tier_min_seats: price.tier_min_seats,
+        term_years: Some(price.term_years),
+        period_start: now,
+        period_end,
+        days_left,
+        credit,
+        charge: price.subtotal,
+        discount: price.discount.clone(),
+        total: price.total - credit,
+    })
+}
+
+/// Computes the price of renewing a subscription for one billing period.
+///
+/// A yearly period costs the same as buying the seats for a year,
+/// a monthly period a twelfth of that.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `product_id` - Id of the product of the license
+/// * `seats` - Number of seats of the license
+/// * `billing_interval` - Length of the period
+/// * `currency` - Billing currency of the company of the license
+pub async fn quote_period(
+    pool: &Pool<Postgres>,
+    product_id: &str,
+    seats: i32,
+    billing_interval: BillingInterval,
+    currency: Currency,
+) -> Result<PeriodQuote, PricingError> {
+    let year = quote_renewal(pool, product_id, seats, 1, currency).await?;
+    let total = match billing_interval {
+        BillingInterval::Monthly => year.total.monthly(),
+        BillingInterval::Yearly => year.total,
+    };
+    Ok(PeriodQuote {
+        product_id: year.product_id,
+        seats,
+        billing_interval,
+        currency: year.currency,
+        unit_price: year.unit_price,
+        tier_min_seats: year.tier_min_seats,
+        total,
+    })
+}
+
+/// Looks up a discount code and checks it can be used now by a company,
+/// see [`check_discount`].
+async fn discount_for(
+    pool: &Pool<Postgres>,
+    code: &str,
+    company_id: Option<i32>,
+    prices: &[PriceQuote],
+) -> Result<AppliedDiscount, PricingError> {
+    let discount_code = discount::get_discount_code(pool, code)
+        .await?
+        .ok_or(PricingError::DiscountNotFound)?;
+    check_discount(discount_code, company_id, prices, Utc::now())
+}
+
+/// Checks a discount code can be used at `now` by a company, and computes what it takes off
+/// the prices it applies to. The prices must all be in the same currency.
+///
+/// The code must be active, within its validity period and have uses left.
+/// It must be for the company, or for all companies, and apply to at least one of the prices:
+/// the ones of its product, or all of them if it is for all products.
+/// A fixed discount only applies to prices in its currency,
+/// and never takes more off than the prices it applies to.
+fn check_discount(
+    discount_code: DiscountCode,
+    company_id: Option<i32>,
+    prices: &[PriceQuote],
+    now: DateTime<Utc>,
+) -> Result<AppliedDiscount, PricingError> {
+    if !discount_code.active
+        || discount_code.valid_from.is_some_and(|from| now < from)
+        || discount_code.valid_until.is_some_and(|until| now >= until)
+    {
+        return Err(PricingError::DiscountNotValidNow);
+    }
+    if discount_code
+        .max_uses
+        .is_some_and(|max_uses| discount_code.times_used >= max_uses)
+    {
+        return Err(PricingError::DiscountUsedUp);
+    }
+    let for_company = match company_id {
+        Some(company_id) => {
+            discount_code.company_ids.is_empty() || discount_code.company_ids.contains(&company_id)
+        }
+        None => true,
+    };
+    let applicable: Vec<&PriceQuote> = prices
+        .iter()
+        .filter(|price| {
+            discount_code
+                .product_id
+                .as_ref()
+                .is_none_or(|product_id| *product_id == price.product_id)
+        })
+        .collect();
+    if !for_company || applicable.is_empty() {
+        return Err(PricingError::DiscountNotApplicable);
+    }
+    let subtotal: Money = applicable.iter().map(|price| price.subtotal).sum();
+
+    let amount = match (
+        discount_code.kind,
+        discount_code.percent_off,
+        discount_code.amount_off,
+    ) {
+        (DiscountKind::Percentage, Some(percent_off), _) => subtotal.rate(percent_off),
+        (DiscountKind::Fixed, _, Some(amount_off)) => {
+            if discount_code.currency != Some(applicable[0].currency) {
+                return Err(PricingError::DiscountNotApplicable);
+            }
+            amount_off.min(subtotal)
+        }
+        _ => return Err(PricingError::DiscountNotApplicable),
+    };
+    Ok(AppliedDiscount {
+        discount_code_id: discount_code.discount_code_id,
+        code: discount_code.code,
+        amount,
+    })
+}
+
+/// Applies a discount code to a price, see [`check_discount`] for when a code can be used.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `price` - Price without a discount
+/// * `code` - Discount code entered by the customer
+/// * `company_id` - Id of the company buying, none if not known yet
+pub async fn apply_discount(
+    pool: &Pool<Postgres>,
+    price: PriceQuote,
+    code: &str,
+    company_id: Option<i32>,
+) -> Result<PriceQuote, PricingError> {
+    let discount = discount_for(pool, code, company_id, std::slice::from_ref(&price)).await?;
+    Ok(PriceQuote {
+        total: price.subtotal - discount.amount,
+        discount: Some(discount),
+        ..price
+    })
+}
+
+/// Computes the price of all items of a cart.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `items` - Items of the cart
+/// * `currency` - Billing currency of the company of the cart
+pub async fn quote_cart(
+    pool: &Pool<Postgres>,
+    items: &[CartItem],
+    currency: Currency,
+) -> Result<CartQuote, PricingError> {
+    if items.is_empty() {
+        return Err(PricingError::EmptyCart);
+    }
+    let mut lines = Vec::with_capacity(items.len());
+    for item in items {
+        lines.push(
+            quote(
+                pool,
+                &item.product_id,
+                item.seats,
+                item.term_years,
+                Some(currency),
+            )
+            .await?,
+        );
+    }
+    let subtotal = lines.iter().map(|line| line.subtotal).sum();
+    Ok(CartQuote {
+        currency,
+        lines,
+        subtotal,
+        discount: None,
+        total: subtotal,
+    })
+}
+
+/// Applies a discount code to the price of a cart, see [`check_discount`] for when a code
+/// can be used.
+///
+/// # Arguments
+/// * `pool` - Database connection pool
+/// * `cart` - Price of the cart without a discount
+/// * `code` - Discount code entered by the customer
+/// * `company_id` - Id of the company buying
+pub async fn apply_cart_discount(
+    pool: &Pool<Postgres>,
+    cart: CartQuote,
+    code: &str,
+    company_id: Option<i32>,
+) -> Result<CartQuote, PricingError> {
+    let discount = discount_for(pool, code, company_id, &cart.lines).await?;
+    Ok(CartQuote {
+        total: cart.subtotal - discount.amount,
+        discount: Some(discount),
+        ..cart
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+
+    fn money(s: &str) -> Money {
+        s.parse().unwrap()
+    }
+
+    fn tier(min_seats: i32, price_per_user: &str) -> PriceTier {
+        PriceTier {
+            min_seats,
+            price_per_user: money(price_per_user),
+        }
+    }
+
+    fn price_list() -> PriceList {
+        PriceList {
+            currency: Currency::Eur,
+            price_per_user: money("100.00"),
+            tiers: vec![tier(10, "90.00"), tier(50, "80.00"), tier(100, "70.00")],
+        }
+    }
+
+    fn price(product_id: &str, subtotal: &str) -> PriceQuote {
+        PriceQuote {
+            product_id: product_id.to_string(),
+            seats: 1,
+            term_years: 1,
+            currency: Currency::Eur,
+            unit_price: money(subtotal),
+            tier_min_seats: None,
+            subtotal: money(subtotal),
+            discount: None,
+            total: money(subtotal),
+        }
+    }
+
+    fn percentage_code(percent_off: i32) -> DiscountCode {
+        DiscountCode {
+            discount_code_id: 1,
+            code: "WELCOME10".to_string(),
+            kind: DiscountKind::Percentage,
+            percent_off: Some(percent_off),
+            amount_off: None,
+            currency: None,
+            product_id: None,
+            company_ids: vec![],
+            max_uses: None,
+            times_used: 0,
+            valid_from: None,
+            valid_until: None,
+            active: true,
+        }
+    }
+
+    fn fixed_code(amount_off: &str, currency: Currency) -> DiscountCode {
+        DiscountCode {
+            kind: DiscountKind::Fixed,
+            percent_off: None,
+            amount_off: Some(money(amount_off)),
+            currency: Some(currency),
+            ..percentage_code(0)
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 6, 15, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn tier_is_the_highest_the_seats_reach() {
+        let tiers = price_list().tiers;
+        assert!(applicable_tier(&tiers, 1).is_none());
+        assert!(applicable_tier(&tiers, 9).is_none());
+        assert_eq!(applicable_tier(&tiers, 10).unwrap().min_seats, 10);
+        assert_eq!(applicable_tier(&tiers, 49).unwrap().min_seats, 10);
+        assert_eq!(applicable_tier(&tiers, 50).unwrap().min_seats, 50);
+        assert_eq!(applicable_tier(&tiers, 10_000).unwrap().min_seats, 100);
+        assert!(applicable_tier(&[], 100).is_none());
+    }
+
+    #[test]
+    fn tier_does_not_depend_on_order() {
+        let tiers = vec![tier(100, "70.00"), tier(10, "90.00"), tier(50, "80.00")];
+        assert_eq!(applicable_tier(&tiers, 60).unwrap().min_seats, 50);
+    }
+
+    #[test]
+    fn prices_every_seat_at_the_tier_reached() {
+        let below = price_from_list("proflex", &price_list(), 9, 1);
+        assert_eq!(below.unit_price, money("100.00"));
+        assert_eq!(below.tier_min_seats, None);
+        assert_eq!(below.total, money("900.00"));
+
+        let tiered = price_from_list("proflex", &price_list(), 10, 1);
+        assert_eq!(tiered.unit_price, money("90.00"));
+        assert_eq!(tiered.tier_min_seats, Some(10));
+        assert_eq!(tiered.subtotal, money("900.00"));
+        assert_eq!(tiered.total, money("900.00"));
+        assert_eq!(tiered.currency, Currency::Eur);
+        assert!(tiered.discount.is_none());
+    }
+
+    #[test]
+    fn price_is_charged_for_every_year_of_the_term() {
+        let one = price_from_list("proflex", &price_list(), 50, 1);
+        let three = price_from_list("proflex", &price_list(), 50, 3);
+        assert_eq!(one.total, money("4000.00"));
+        assert_eq!(three.total, money("12000.00"));
+        assert_eq!(three.unit_price, one.unit_price);
+    }
+
+    #[test]
+    fn largest_purchase_does_not_overflow() {
+        let list = PriceList {
+            currency: Currency::Nok,
+            price_per_user: MAX_PRICE,
+            tiers: vec![],
+        };
+        let price = price_from_list("proflex", &list, MAX_SEATS, MAX_TERM_YEARS);
+        assert_eq!(
+            price.total.minor_units(),
+            MAX_PRICE.minor_units() * MAX_SEATS as i64 * MAX_TERM_YEARS as i64
+        );
+    }
+
+    #[test]
+    fn seats_and_term_must_be_in_range() {
+        assert!(check_seats_and_term(1, 1).is_ok());
+        assert!(check_seats_and_term(MAX_SEATS, MAX_TERM_YEARS).is_ok());
+        assert!(matches!(
+            check_seats_and_term(0, 1),
+            Err(PricingError::InvalidSeats)
+        ));
+        assert!(matches!(
+            check_seats_and_term(MAX_SEATS + 1, 1),
+            Err(PricingError::InvalidSeats)
+        ));
+        assert!(matches!(
+            check_seats_and_term(1, 0),
+            Err(PricingError::InvalidTerm)
+        ));
+        assert!(matches!(
+            check_seats_and_term(1, MAX_TERM_YEARS + 1),
+            Err(PricingError::InvalidTerm)
+        ));
+    }
+
+    #[test]
+    fn term_ends_whole_years_later() {
+        let start = Utc.with_ymd_and_hms(2023, 3, 1, 8, 30, 0).unwrap();
+        assert_eq!(
+            term_end(start, 1),
+            Some(Utc.with_ymd_and_hms(2024, 3, 1, 8, 30, 0).unwrap())
+        );
+        assert_eq!(
+            term_end(start, 3),
+            Some(Utc.with_ymd_and_hms(2026, 3, 1, 8, 30, 0).unwrap())
+        );
+        // a term from a leap day ends on the last day of February
+        let leap_day = Utc.with_ymd_and_hms(2024, 2, 29, 0, 0, 0).unwrap();
+        assert_eq!(
+            term_end(leap_day, 1),
+            Some(Utc.with_ymd_and_hms(2025, 2, 28, 0, 0, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn days_left_counts_started_days() {
+        let now = now();
+        assert_eq!(days_left(now, now - Duration::days(3)), 0);
+        assert_eq!(days_left(now, now), 0);
+        assert_eq!(days_left(now, now + Duration::minutes(59)), 0);
+        assert_eq!(days_left(now, now + Duration::hours(1)), 1);
+        assert_eq!(days_left(now, now + Duration::hours(24)), 1);
+        assert_eq!(days_left(now, now + Duration::hours(25)), 2);
+        assert_eq!(days_left(now, now + Duration::days(365)), 365);
+    }
+
+    #[test]
+    fn proration_credits_the_days_left() {
+        let year = money("3650.00");
+        let now = now();
+        let credit = |end_date| year.prorate(days_left(now, end_date), DAYS_PER_YEAR);
+        assert_eq!(credit(now), Money::ZERO);
+        assert_eq!(credit(now - Duration::days(10)), Money::ZERO);
+        assert_eq!(credit(now + Duration::hours(1)), money("10.00"));
+        assert_eq!(credit(now + Duration::days(100)), money("1000.00"));
+        assert_eq!(credit(now + Duration::days(365)), year);
+    }
+
+    #[test]
+    fn percentage_discount_takes_its_rate_off() {
+        let prices = [price("proflex", "199.90")];
+        let discount = check_discount(percentage_code(1_000), None, &prices, now()).unwrap();
+        assert_eq!(discount.amount, money("19.99"));
+        assert_eq!(discount.code, "WELCOME10");
+
+        let prices = [price("proflex", "0.05")];
+        let discount = check_discount(percentage_code(1_000), None, &prices, now()).unwrap();
+        assert_eq!(discount.amount, money("0.01"));
+    }
+
+    #[test]
+    fn discount_only_applies_to_its_product() {
+        let code = DiscountCode {
+            product_id: Some("proflex".to_string()),
+            ..percentage_code(5_000)
+        };
+        let prices = [price("proflex", "100.00"), price("other", "300.00")];
+        let discount = check_discount(code.clone(), None, &prices, now()).unwrap();
+        assert_eq!(discount.amount, money("50.00"));
+
+        let prices = [price("other", "300.00")];
+        assert!(matches!(
+            check_discount(code, None, &prices, now()),
+            Err(PricingError::DiscountNotApplicable)
+        ));
+    }
+
+    #[test]
+    fn fixed_discount_never_takes_more_than_the_price() {
+        let prices = [price("proflex", "30.00"), price("other", "20.00")];
+        let discount =
+            check_discount(fixed_code("10.00", Currency::Eur), None, &prices, now()).unwrap();
+        assert_eq!(discount.amount, money("10.00"));
+        let discount =
+            check_discount(fixed_code("80.00", Currency::Eur), None, &prices, now()).unwrap();
+        assert_eq!(discount.amount, money("50.00"));
+        assert!(matches!(
+            check_discount(fixed_code("10.00", Currency::Usd), None, &prices, now()),
+            Err(PricingError::DiscountNotApplicable)
+        ));
+    }
+
+    #[test]
+    fn discount_must_be_valid_now() {
+        let prices = [price("proflex", "100.00")];
+        let inactive = DiscountCode {
+            active: false,
+            ..percentage_code(1_000)
+        };
+        let not_yet = DiscountCode {
+            valid_from: Some(now() + Duration::seconds(1)),
+            ..percentage_code(1_000)
+        };
+        let expired = DiscountCode {
+            valid_until: Some(now()),
+            ..percentage_code(1_000)
+        };
+        for code in [inactive, not_yet, expired] {
+            assert!(matches!(
+                check_discount(code, None, &prices, now()),
+                Err(PricingError::DiscountNotValidNow)
+            ));
+        }
+        let started = DiscountCode {
+            valid_from: Some(now()),
+            valid_until: Some(now() + Duration::seconds(1)),
+            ..percentage_code(1_000)
+        };
+        assert!(check_discount(started, None, &prices, now()).is_ok());
+    }
+
+    #[test]
+    fn discount_must_have_uses_left() {
+        let prices = [price("proflex", "100.00")];
+        let used_up = DiscountCode {
+            max_uses: Some(3),
+            times_used: 3,
+            ..percentage_code(1_000)
+        };
+        assert!(matches!(
+            check_discount(used_up, None, &prices, now()),
+            Err(PricingError::DiscountUsedUp)
+        ));
+        let last_use = DiscountCode {
+            max_uses: Some(3),
+            times_used: 2,
+            ..percentage_code(1_000)
+        };
+        assert!(check_discount(last_use, None, &prices, now()).is_ok());
+    }
+
+    #[test]
+    fn discount_must_be_for_the_company() {
+        let prices = [price("proflex", "100.00")];
+        let code = DiscountCode {
+            company_ids: vec![2, 3],
+            ..percentage_code(1_000)
+        };
+        assert!(check_discount(code.clone(), Some(3), &prices, now()).is_ok());
+        // the company is only checked once it is known
+        assert!(check_discount(code.clone(), None, &prices, now()).is_ok());
+        assert!(matches!(
+            check_discount(code, Some(4), &prices, now()),
+            Err(PricingError::DiscountNotApplicable)
+        ));
+    }
+
+    #[test]
+    fn paid_left_counts_days_left_of_what_was_paid() {
+        let line = |amount: &str, start: DateTime<Utc>| PaidLine {
+            amount: money(amount),
+            period_start: start,
+            period_end: start + Duration::days(200),
+            order_subtotal: money("90.00"),
+            order_discount: money("10.00"),
+            order_refunded: Money::ZERO,
+        };
+        let start = now() - Duration::days(100);
+        let bought = line("100.00", start);
+        // half of the period is left of the 90.00 paid after the discount
+        assert_eq!(paid_left(&[bought], now()), money("45.00"));
+
+        let refunded = PaidLine {
+            order_refunded: money("40.00"),
+            ..line("100.00", start)
+        };
+        assert_eq!(paid_left(&[refunded], now()), money("25.00"));
+
+        // periods that have not started yet are left in full, ended ones not at all
+        let renewal = line("100.00", now() + Duration::days(30));
+        let ended = line("100.00", now() - Duration::days(300));
+        assert_eq!(paid_left(&[renewal, ended], now()), money("90.00"));
+
+        let free = PaidLine {
+            order_subtotal: Money::ZERO,
+            order_discount: Money::ZERO,
+            ..line("0.00", start)
+        };
+        assert_eq!(paid_left(&[free], now()), Money::ZERO);
+
+        // a fully discounted order paid nothing, whatever its lines are worth
+        let discounted = PaidLine {
+            order_subtotal: Money::ZERO,
+            order_discount: money("100.00"),
+            ..line("100.00", start)
+        };
+        assert_eq!(paid_left(&[discounted], now()), Money::ZERO);
+
+        // what a credit order credited is taken off what is left
+        let credited = PaidLine {
+            order_subtotal: money("-20.00"),
+            order_discount: Money::ZERO,
+            ..line("-20.00", start)
+        };
+        assert_eq!(
+            paid_left(&[line("100.00", start), credited], now()),
+            money("35.00")
+        );
+    }
+}
@@ -1,6 +1,9 @@
-use actix_web::{HttpResponse, Responder};
+use actix_web::{http::header, HttpRequest, HttpResponse, Responder};
+use serde::Serialize;
 use serde_json::json;
 
+use crate::data_access::pagination::{Page, PageRequest, SortField};
+
 pub mod openapi_doc;
 pub mod private;
 pub mod public;
@@ -21,3 +24,48 @@ pub async fn resource_not_found() -> impl Responder {
         "message": "The resource could not be found."
     }))
 }
+
+/// Response with a page of a list.
+///
+/// The body is the items on the page. The total number of items in the list is in the
+/// `X-Total-Count` header, and links to the first, previous, next and last pages
+/// are in the `Link` header, the request with only the cursor changed.
+pub fn page_response<T: Serialize, S: SortField>(
+    req: &HttpRequest,
+    page: Page<T>,
+    request: &PageRequest<S>,
+) -> HttpResponse {
+    let mut links = vec![page_link(req, "0", "first")];
+    if let Some(cursor) = request.prev_cursor() {
+        links.push(page_link(req, &cursor, "prev"));
+    }
+    if let Some(cursor) = request.next_cursor(page.total) {
+        links.push(page_link(req, &cursor, "next"));
+    }
+    links.push(page_link(req, &request.last_cursor(page.total), "last"));
+
+    HttpResponse::Ok()
+        .insert_header(("X-Total-Count", page.total.to_string()))
+        .insert_header((header::LINK, links.join(", ")))
+        .json(page.items)
+}
+
+/// Link to the request with another cursor, e.g. `</api/products?limit=10&cursor=10>; rel="next"`.
+fn page_link(req: &HttpRequest, cursor: &str, rel: &str) -> String {
+    let mut query = req
+        .query_string()
+        .split('&')
+        .filter(|param| !param.is_empty() && !param.starts_with("cursor="))
+        .collect::<Vec<_>>()
+        .join("&");
+    if !query.is_empty() {
+        query.push('&');
+    }
+    format!(
+        "<{}?{}cursor={}>; rel=\"{}\"",
+        req.path(),
+        query,
+        cursor,
+        rel
+    )
+}
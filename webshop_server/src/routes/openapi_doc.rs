@@ -13,6 +13,8 @@ fn public_routes_docs() -> Vec<openapi::OpenApi> {
         public::testimonials::TestimonialsOpenApi::openapi(),
         public::categories::CategoryOpenApi::openapi(),
         public::auth::AuthOpenApi::openapi(),
+        public::payments::PaymentsApiDoc::openapi(),
+        public::downloads::DownloadsApiDoc::openapi(),
     ]
 }
 
@@ -21,7 +23,18 @@ fn protected_routes_docs() -> Vec<openapi::OpenApi> {
         private::users_protected::UserApiDoc::openapi(),
         private::me::UserApiDoc::openapi(),
         private::licenses_protected::ProtectedLicensesOpenApi::openapi(),
+        private::licenses_protected::cart_protected::CartOpenApi::openapi(),
+        private::licenses_protected::purchase_requests_protected::PurchaseRequestsOpenApi::openapi(
+        ),
+        private::licenses_protected::quotes_protected::QuotesOpenApi::openapi(),
+        private::licenses_protected::subscriptions_protected::SubscriptionsOpenApi::openapi(),
+        private::licenses_protected::checkouts_protected::CheckoutsOpenApi::openapi(),
+        private::licenses_protected::downloads_protected::DownloadsOpenApi::openapi(),
+        private::licenses_protected::assignments_protected::AssignmentsOpenApi::openapi(),
+        private::trials_protected::TrialsOpenApi::openapi(),
         private::companies_protected::CompanyApiDoc::openapi(),
+        private::reports_protected::ReportsOpenApi::openapi(),
+        private::orders_protected::OrdersOpenApi::openapi(),
         private::auth::AuthApiDoc::openapi(),
     ]
 }
@@ -30,7 +43,12 @@ fn admin_routes_docs() -> Vec<openapi::OpenApi> {
     vec![
         private::products_protected::ProductsApiDoc::openapi(),
         private::products_protected::descriptions_protected::DescriptionApiDoc::openapi(),
+        private::products_protected::publishing_protected::PublishingApiDoc::openapi(),
+        private::products_protected::releases_protected::ReleasesApiDoc::openapi(),
         private::testimonials_protected::TestimonialsProtectedOpenApi::openapi(),
+        private::pricing_protected::PricingApiDoc::openapi(),
+        private::categories_protected::CategoriesApiDoc::openapi(),
+        private::discounts_protected::DiscountsApiDoc::openapi(),
     ]
 }
 
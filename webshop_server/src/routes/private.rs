@@ -1,11 +1,17 @@
 use actix_web::{get, web, Responder};
 
 pub mod auth;
+pub mod categories_protected;
 pub mod companies_protected;
+pub mod discounts_protected;
 pub mod licenses_protected;
 pub mod me;
+pub mod orders_protected;
+pub mod pricing_protected;
 pub mod products_protected;
+pub mod reports_protected;
 pub mod testimonials_protected;
+pub mod trials_protected;
 pub mod users_protected;
 
 #[get("")]
@@ -15,10 +21,17 @@ async fn index() -> impl Responder {
 
 pub fn private(cfg: &mut web::ServiceConfig) {
     cfg.configure(auth::configure);
+    // before products_protected, whose /products scope would shadow these routes
+    cfg.configure(trials_protected::configure);
+    cfg.configure(pricing_protected::configure);
     cfg.configure(products_protected::configure);
     cfg.configure(licenses_protected::configure);
     cfg.configure(me::configure);
     cfg.configure(users_protected::configure);
     cfg.configure(testimonials_protected::configure);
     cfg.configure(companies_protected::configure);
+    cfg.configure(reports_protected::configure);
+    cfg.configure(orders_protected::configure);
+    cfg.configure(discounts_protected::configure);
+    cfg.configure(categories_protected::configure);
 }
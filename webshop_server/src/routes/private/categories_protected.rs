@@ -0,0 +1,366 @@
+//! Endpoints for managing categories and the products in them.
+
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Responder};
+use log::error;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    data_access::{
+        category::{self, Category},
+        product,
+        user::Role,
+    },
+    utils::auth,
+    SharedData,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(create_category);
+    cfg.service(update_category);
+    cfg.service(delete_category);
+    cfg.service(category_products);
+    cfg.service(add_product);
+    cfg.service(remove_product);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        create_category,
+        update_category,
+        delete_category,
+        category_products,
+        add_product,
+        remove_product,
+    ),
+    components(
+        schemas(Category, CategoryForm)
+    ),
+    tags(
+        (name = "Categories", description = "API endpoints for managing categories")
+    ),
+)]
+pub struct CategoriesApiDoc;
+
+/// Category to create or update.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct CategoryForm {
+    #[schema(example = "Finance")]
+    name: String,
+    #[schema(example = "Software for accounting, tax and payroll")]
+    description: String,
+}
+
+impl CategoryForm {
+    /// Trims the name and description, returning them or why they are invalid.
+    fn validate(&self) -> Result<(&str, &str), &'static str> {
+        let name = self.name.trim();
+        if name.is_empty() {
+            return Err("Name is required");
+        }
+        Ok((name, self.description.trim()))
+    }
+}
+
+/// Checks the user is an admin, returning the response to send if not.
+async fn validate_admin(shared_data: &SharedData, req: HttpRequest) -> Result<(), HttpResponse> {
+    match auth::validate_user(req, &shared_data.db_pool).await {
+        Ok(user) => {
+            if user.role != Role::Admin {
+                return Err(HttpResponse::Forbidden().finish());
+            }
+            Ok(())
+        }
+        Err(e) => match e {
+            auth::AuthError::Unauthorized => Err(HttpResponse::Unauthorized().finish()),
+            auth::AuthError::SqlxError(e) => {
+                error!("{}", e);
+                Err(HttpResponse::InternalServerError().finish())
+            }
+        },
+    }
+}
+
+/// Parses the id of a category and checks it exists, returning the response to send if not.
+async fn existing_category(
+    shared_data: &SharedData,
+    category_id: &str,
+) -> Result<i32, HttpResponse> {
+    let category_id = match category_id.parse::<i32>() {
+        Ok(category_id) => category_id,
+        Err(_) => return Err(HttpResponse::BadRequest().json("Bad Request")),
+    };
+    match category::get_category_by_id(&shared_data.db_pool, &category_id).await {
+        Ok(_) => Ok(category_id),
+        Err(sqlx::Error::RowNotFound) => Err(HttpResponse::NotFound().json("Category not found")),
+        Err(e) => {
+            error!("{}", e);
+            Err(HttpResponse::InternalServerError().json("Internal Server Error"))
+        }
+    }
+}
+
+/// Create a category.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Categories",
+    responses(
+        (status = 201, description = "Category created", body = Category),
+        (status = 400, description = "Bad Request - no name"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    request_body(
+        description = "The category to create",
+        content = CategoryForm,
+    ),
+)]
+#[post("/categories")]
+async fn create_category(
+    shared_data: web::Data<SharedData>,
+    form: web::Json<CategoryForm>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let (name, description) = match form.validate() {
+        Ok(fields) => fields,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+    match category::create_category(&shared_data.db_pool, name, description).await {
+        Ok(category) => HttpResponse::Created().json(category),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Update the name and description of a category.
+#[utoipa::path(
+    context_path = "/api/priv",
+    put,
+    tag = "Categories",
+    responses(
+        (status = 200, description = "Category updated", body = Category),
+        (status = 400, description = "Bad Request - no name"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Category not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("category_id", description = "The id of the category"),
+    ),
+    request_body(
+        description = "The updated category",
+        content = CategoryForm,
+    ),
+)]
+#[put("/categories/{category_id}")]
+async fn update_category(
+    shared_data: web::Data<SharedData>,
+    category_id: web::Path<String>,
+    form: web::Json<CategoryForm>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let category_id = match category_id.parse::<i32>() {
+        Ok(category_id) => category_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    let (name, description) = match form.validate() {
+        Ok(fields) => fields,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+    match category::update_category(&shared_data.db_pool, &category_id, name, description).await {
+        Ok(category) => HttpResponse::Ok().json(category),
+        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json("Category not found"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Delete a category.
+///
+/// Its products are not deleted, they are just no longer in the category.
+#[utoipa::path(
+    context_path = "/api/priv",
+    delete,
+    tag = "Categories",
+    responses(
+        (status = 204, description = "Category deleted"),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Category not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("category_id", description = "The id of the category"),
+    ),
+)]
+#[delete("/categories/{category_id}")]
+async fn delete_category(
+    shared_data: web::Data<SharedData>,
+    category_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let category_id = match category_id.parse::<i32>() {
+        Ok(category_id) => category_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    match category::delete_category(&shared_data.db_pool, &category_id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json("Category not found"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Get all products in a category, available or not, ordered by name.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Categories",
+    responses(
+        (status = 200, description = "Products in the category", body = Vec<product::Product>),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Category not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("category_id", description = "The id of the category"),
+    ),
+)]
+#[get("/categories/{category_id}/products")]
+async fn category_products(
+    shared_data: web::Data<SharedData>,
+    category_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let category_id = match existing_category(&shared_data, &category_id).await {
+        Ok(category_id) => category_id,
+        Err(response) => return response,
+    };
+    match product::get_products_by_category(&shared_data.db_pool, &category_id, false).await {
+        Ok(products) => HttpResponse::Ok().json(products),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Put a product in a category.
+///
+/// A product can be in several categories. Putting it in a category it is in already does nothing.
+#[utoipa::path(
+    context_path = "/api/priv",
+    put,
+    tag = "Categories",
+    responses(
+        (status = 204, description = "Product is in the category"),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Category or product not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("category_id", description = "The id of the category"),
+        ("product_id", description = "The id of the product"),
+    ),
+)]
+#[put("/categories/{category_id}/products/{product_id}")]
+async fn add_product(
+    shared_data: web::Data<SharedData>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let (category_id, product_id) = path.into_inner();
+    let category_id = match existing_category(&shared_data, &category_id).await {
+        Ok(category_id) => category_id,
+        Err(response) => return response,
+    };
+    let pool = &shared_data.db_pool;
+    match product::product_exists(pool, &product_id).await {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::NotFound().json("Product not found"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    }
+
+    match category::add_product(pool, &category_id, &product_id).await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Take a product out of a category.
+#[utoipa::path(
+    context_path = "/api/priv",
+    delete,
+    tag = "Categories",
+    responses(
+        (status = 204, description = "Product taken out of the category"),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Product is not in the category"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("category_id", description = "The id of the category"),
+        ("product_id", description = "The id of the product"),
+    ),
+)]
+#[delete("/categories/{category_id}/products/{product_id}")]
+async fn remove_product(
+    shared_data: web::Data<SharedData>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let (category_id, product_id) = path.into_inner();
+    let category_id = match category_id.parse::<i32>() {
+        Ok(category_id) => category_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    match category::remove_product(&shared_data.db_pool, &category_id, &product_id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json("Product is not in the category"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
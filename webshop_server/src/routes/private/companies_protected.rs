@@ -1,18 +1,40 @@
-use actix_web::{get, web, HttpResponse, Responder};
-use utoipa::OpenApi;
+use actix_web::{get, put, web, HttpRequest, HttpResponse, Responder};
+use log::error;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
 
 use crate::{
-    data_access::{company, error_handling},
+    data_access::{
+        company::{self, BillingAddress, CompanyFilter, CompanySort},
+        error_handling,
+        pagination::{PageQuery, SortOrder},
+        user::{Role, User},
+    },
+    money::Currency,
+    routes::page_response,
+    tax,
+    utils::auth,
     SharedData,
 };
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(companies);
+    cfg.service(billing_address);
+    cfg.service(update_billing_address);
+    cfg.service(company_billing_currency);
+    cfg.service(update_billing_currency);
 }
 #[derive(OpenApi)]
 #[openapi(
     paths(
-        companies
+        companies,
+        billing_address,
+        update_billing_address,
+        company_billing_currency,
+        update_billing_currency,
+    ),
+    components(
+        schemas(BillingAddress, BillingAddressForm, BillingCurrency, Currency, SortOrder)
     ),
 
     tags(
@@ -21,37 +43,347 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 )]
 pub struct CompanyApiDoc;
 
-/// Get all companies from the database.
-/// returns a json array of all companies.
+/// Get a page of the companies.
+///
+/// Sorted by `company_id` if not given, or by `company_name`.
 #[utoipa::path(
     context_path = "/api/priv",
     tag = "Company",
     responses(
-        (status = 200, description = "JSON containing the companies"),
-        (status = 404, description = "No companies found"),
-        (status = 409, description = "Company already exists"),
+        (status = 200, description = "JSON containing the page of companies",
+            headers(
+                ("X-Total-Count" = i64, description = "Number of companies matching the filters"),
+                ("Link" = String, description = "Links to the first, previous, next and last pages"),
+            ),
+        ),
+        (status = 400, description = "Invalid limit, cursor or sort field"),
         (status = 500, description = "Internal Server Error")
-    )
+    ),
+    params(PageQuery, CompanyFilter),
 )]
 #[get("/companies")]
-async fn companies(shared_data: web::Data<SharedData>) -> impl Responder {
+async fn companies(
+    shared_data: web::Data<SharedData>,
+    page_query: web::Query<PageQuery>,
+    filter: web::Query<CompanyFilter>,
+    req: HttpRequest,
+) -> impl Responder {
     let pool = &shared_data.db_pool;
-    let companies = company::get_all_companies(pool).await;
-
-    match companies {
-        Ok(companies) => HttpResponse::Ok().json(companies),
-        Err(e) => match e {
-            sqlx::Error::Database(e) => match error_handling::PostgresDBError::from_str(e) {
-                error_handling::PostgresDBError::NoDataFound => {
-                    HttpResponse::NotFound().json("No companies found")
+    let page = match page_query.page::<CompanySort>() {
+        Ok(page) => page,
+        Err(e) => return HttpResponse::BadRequest().json(e.to_string()),
+    };
+    match company::get_companies(pool, &filter, &page).await {
+        Ok(all_companies) => page_response(&req, all_companies, &page),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Checks that a user may see the billing address of a company.
+/// Admins may see all, company IT staff only their own company's.
+fn may_see_billing(user: &User, company_id: i32) -> bool {
+    match user.role {
+        Role::Admin => true,
+        Role::CompanyItHead | Role::CompanyIt => user.company_id == company_id,
+        Role::Default => false,
+    }
+}
+
+/// Get the billing address of a company.
+///
+/// Available to admins, and to the IT staff of the company.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Company",
+    responses(
+        (status = 200, description = "The billing address", body = BillingAddress),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "The company has no billing address"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("company_id", description = "The ID of the company"),
+    ),
+)]
+#[get("/companies/{company_id}/billing")]
+async fn billing_address(
+    shared_data: web::Data<SharedData>,
+    company_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
                 }
-                // if the data already exists, return a Conflict
-                error_handling::PostgresDBError::UniqueViolation => {
-                    HttpResponse::Conflict().json("Company already exists")
+            }
+        }
+    };
+    let company_id = match company_id.parse::<i32>() {
+        Ok(company_id) => company_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    if !may_see_billing(&user, company_id) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    match company::get_billing_address(pool, &company_id).await {
+        Ok(Some(address)) => HttpResponse::Ok().json(address),
+        Ok(None) => HttpResponse::NotFound().json("The company has no billing address"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct BillingAddressForm {
+    street: String,
+    postal_code: String,
+    city: String,
+    /// ISO 3166-1 alpha-2 country code.
+    #[schema(example = "NO")]
+    country: String,
+    /// VAT number with its country prefix. EU companies with a VAT number are not charged VAT.
+    #[schema(example = "NO999999999MVA")]
+    vat_number: Option<String>,
+}
+
+/// Set the billing address of a company.
+///
+/// The country and VAT number decide the tax on new orders of the company.
+/// Available to admins, and to the IT heads of the company.
+#[utoipa::path(
+    context_path = "/api/priv",
+    put,
+    tag = "Company",
+    request_body = BillingAddressForm,
+    responses(
+        (status = 200, description = "The billing address", body = BillingAddress),
+        (status = 400, description = "Bad Request - missing fields, invalid country or VAT number"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Company not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("company_id", description = "The ID of the company"),
+    ),
+)]
+#[put("/companies/{company_id}/billing")]
+async fn update_billing_address(
+    shared_data: web::Data<SharedData>,
+    company_id: web::Path<String>,
+    form: web::Json<BillingAddressForm>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
                 }
-                _ => HttpResponse::InternalServerError().json("Internal Server Error"),
-            },
+            }
+        }
+    };
+    let company_id = match company_id.parse::<i32>() {
+        Ok(company_id) => company_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    let allowed = match user.role {
+        Role::Admin => true,
+        Role::CompanyItHead => user.company_id == company_id,
+        Role::CompanyIt | Role::Default => false,
+    };
+    if !allowed {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    let form = form.into_inner();
+    let (street, postal_code, city) = (
+        form.street.trim().to_string(),
+        form.postal_code.trim().to_string(),
+        form.city.trim().to_string(),
+    );
+    if street.is_empty() || postal_code.is_empty() || city.is_empty() {
+        return HttpResponse::BadRequest().json("Street, postal code and city are required");
+    }
+    let country = match tax::normalize_country(&form.country) {
+        Ok(country) => country,
+        Err(e) => return HttpResponse::BadRequest().json(e.to_string()),
+    };
+    let vat_number = match form.vat_number.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(vat_number) => match tax::normalize_vat_number(&country, vat_number) {
+            Ok(vat_number) => Some(vat_number),
+            Err(e) => return HttpResponse::BadRequest().json(e.to_string()),
+        },
+    };
+
+    let address = BillingAddress {
+        company_id,
+        street,
+        postal_code,
+        city,
+        country,
+        vat_number,
+    };
+    match company::set_billing_address(pool, &address).await {
+        Ok(address) => HttpResponse::Ok().json(address),
+        Err(sqlx::Error::Database(e)) => match error_handling::PostgresDBError::from_str(e) {
+            error_handling::PostgresDBError::ForeignKeyViolation => {
+                HttpResponse::NotFound().json("Company not found")
+            }
             _ => HttpResponse::InternalServerError().json("Internal Server Error"),
         },
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct BillingCurrency {
+    /// Currency the company is priced, billed and invoiced in.
+    billing_currency: Currency,
+}
+
+/// Get the billing currency of a company.
+///
+/// Available to admins, and to the IT staff of the company.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Company",
+    responses(
+        (status = 200, description = "The billing currency", body = BillingCurrency),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Company not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("company_id", description = "The ID of the company"),
+    ),
+)]
+#[get("/companies/{company_id}/billing_currency")]
+async fn company_billing_currency(
+    shared_data: web::Data<SharedData>,
+    company_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let company_id = match company_id.parse::<i32>() {
+        Ok(company_id) => company_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    if !may_see_billing(&user, company_id) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    match company::get_billing_currency(pool, &company_id).await {
+        Ok(billing_currency) => HttpResponse::Ok().json(BillingCurrency { billing_currency }),
+        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json("Company not found"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Set the billing currency of a company.
+///
+/// New prices, carts, orders and subscription renewals of the company are in the currency,
+/// from the price lists of the products in it. Products without a price list in the currency
+/// can no longer be bought by the company. Orders and quotes made before keep their currency.
+/// Available to admins, and to the IT heads of the company.
+#[utoipa::path(
+    context_path = "/api/priv",
+    put,
+    tag = "Company",
+    request_body = BillingCurrency,
+    responses(
+        (status = 200, description = "The billing currency", body = BillingCurrency),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Company not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("company_id", description = "The ID of the company"),
+    ),
+)]
+#[put("/companies/{company_id}/billing_currency")]
+async fn update_billing_currency(
+    shared_data: web::Data<SharedData>,
+    company_id: web::Path<String>,
+    form: web::Json<BillingCurrency>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let company_id = match company_id.parse::<i32>() {
+        Ok(company_id) => company_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    let allowed = match user.role {
+        Role::Admin => true,
+        Role::CompanyItHead => user.company_id == company_id,
+        Role::CompanyIt | Role::Default => false,
+    };
+    if !allowed {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    let billing_currency = form.billing_currency;
+    match company::set_billing_currency(pool, &company_id, billing_currency).await {
+        Ok(true) => HttpResponse::Ok().json(BillingCurrency { billing_currency }),
+        Ok(false) => HttpResponse::NotFound().json("Company not found"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
     }
 }
@@ -0,0 +1,363 @@
+//! Endpoints for managing discount codes.
+
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    data_access::{
+        discount::{self, DiscountCode, DiscountKind, PartialDiscountCode},
+        error_handling,
+        user::Role,
+    },
+    money::{Currency, Money},
+    utils::auth,
+    SharedData,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(discount_codes);
+    cfg.service(discount_code_by_id);
+    cfg.service(create_discount_code);
+    cfg.service(update_discount_code);
+    cfg.service(delete_discount_code);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        discount_codes,
+        discount_code_by_id,
+        create_discount_code,
+        update_discount_code,
+        delete_discount_code,
+    ),
+    components(
+        schemas(DiscountCode, DiscountKind, DiscountCodeForm, Currency)
+    ),
+    tags(
+        (name = "Discounts", description = "API endpoints for managing discount codes")
+    ),
+)]
+pub struct DiscountsApiDoc;
+
+/// Discount code to create or update.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct DiscountCodeForm {
+    /// 3 to 32 letters, digits, `-` or `_`, stored in upper case.
+    #[schema(example = "SPRING25")]
+    code: String,
+    kind: DiscountKind,
+    /// Percentage off in basis points, 2500 is 25%. Required for percentage discounts.
+    #[schema(example = 2500)]
+    percent_off: Option<i32>,
+    /// Amount off. Required for fixed discounts.
+    #[schema(value_type = Option<String>, example = "100.00")]
+    amount_off: Option<Money>,
+    /// Currency of the amount off. Required for fixed discounts.
+    currency: Option<Currency>,
+    /// Product the code applies to, all products if not given.
+    product_id: Option<String>,
+    /// Companies that may use the code, all companies if not given.
+    company_ids: Option<Vec<i32>>,
+    /// How many times the code can be used, no limit if not given.
+    max_uses: Option<i32>,
+    valid_from: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
+    /// Defaults to true.
+    active: Option<bool>,
+}
+
+impl DiscountCodeForm {
+    /// Checks the form, returning the discount code to save or why it is invalid.
+    fn validate(self) -> Result<PartialDiscountCode, &'static str> {
+        let code = self.code.trim().to_uppercase();
+        if !(3..=32).contains(&code.len())
+            || !code
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err("Code must be 3 to 32 letters, digits, dashes or underscores");
+        }
+        match self.kind {
+            DiscountKind::Percentage => {
+                if !self.percent_off.is_some_and(|p| (1..=10_000).contains(&p)) {
+                    return Err("Percentage off must be between 1 and 10000 basis points");
+                }
+                if self.amount_off.is_some() || self.currency.is_some() {
+                    return Err("Percentage discounts have no amount off or currency");
+                }
+            }
+            DiscountKind::Fixed => {
+                if self.amount_off.is_none_or(|a| a <= Money::ZERO) || self.currency.is_none() {
+                    return Err("Fixed discounts need an amount off greater than 0 and a currency");
+                }
+                if self.percent_off.is_some() {
+                    return Err("Fixed discounts have no percentage off");
+                }
+            }
+        }
+        if self.max_uses.is_some_and(|max_uses| max_uses <= 0) {
+            return Err("Maximum uses must be greater than 0");
+        }
+        if let (Some(from), Some(until)) = (self.valid_from, self.valid_until) {
+            if from >= until {
+                return Err("Valid from must be before valid until");
+            }
+        }
+        let mut company_ids = self.company_ids.unwrap_or_default();
+        company_ids.sort_unstable();
+        company_ids.dedup();
+        Ok(PartialDiscountCode {
+            code,
+            kind: self.kind,
+            percent_off: self.percent_off,
+            amount_off: self.amount_off,
+            currency: self.currency,
+            product_id: self.product_id,
+            company_ids,
+            max_uses: self.max_uses,
+            valid_from: self.valid_from,
+            valid_until: self.valid_until,
+            active: self.active.unwrap_or(true),
+        })
+    }
+}
+
+/// Checks the user is an admin, returning the response to send if not.
+async fn validate_admin(shared_data: &SharedData, req: HttpRequest) -> Result<(), HttpResponse> {
+    match auth::validate_user(req, &shared_data.db_pool).await {
+        Ok(user) => {
+            if user.role != Role::Admin {
+                return Err(HttpResponse::Forbidden().finish());
+            }
+            Ok(())
+        }
+        Err(e) => match e {
+            auth::AuthError::Unauthorized => Err(HttpResponse::Unauthorized().finish()),
+            auth::AuthError::SqlxError(e) => {
+                error!("{}", e);
+                Err(HttpResponse::InternalServerError().finish())
+            }
+        },
+    }
+}
+
+/// Maps errors saving a discount code to responses.
+fn save_error_response(e: sqlx::Error) -> HttpResponse {
+    match e {
+        sqlx::Error::RowNotFound => HttpResponse::NotFound().json("Discount code not found"),
+        sqlx::Error::Database(e) => match error_handling::PostgresDBError::from_str(e) {
+            error_handling::PostgresDBError::UniqueViolation => {
+                HttpResponse::Conflict().json("Discount code already exists")
+            }
+            error_handling::PostgresDBError::ForeignKeyViolation => {
+                HttpResponse::BadRequest().json("Product or company not found")
+            }
+            _ => HttpResponse::InternalServerError().json("Internal Server Error"),
+        },
+        e => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Get all discount codes, newest first.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Discounts",
+    responses(
+        (status = 200, description = "List of all discount codes", body = Vec<DiscountCode>),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+)]
+#[get("/discounts")]
+async fn discount_codes(shared_data: web::Data<SharedData>, req: HttpRequest) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    match discount::get_discount_codes(&shared_data.db_pool).await {
+        Ok(codes) => HttpResponse::Ok().json(codes),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Get a discount code, with how many times it has been used.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Discounts",
+    responses(
+        (status = 200, description = "The discount code", body = DiscountCode),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Discount code not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("discount_code_id", description = "The id of the discount code"),
+    ),
+)]
+#[get("/discounts/{discount_code_id}")]
+async fn discount_code_by_id(
+    shared_data: web::Data<SharedData>,
+    discount_code_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let discount_code_id = match discount_code_id.parse::<i32>() {
+        Ok(discount_code_id) => discount_code_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    match discount::get_discount_code_by_id(&shared_data.db_pool, &discount_code_id).await {
+        Ok(code) => HttpResponse::Ok().json(code),
+        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json("Discount code not found"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Create a discount code.
+///
+/// A percentage discount needs `percent_off`, a fixed discount needs `amount_off`
+/// and the `currency` it applies to.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Discounts",
+    responses(
+        (status = 201, description = "Discount code created", body = DiscountCode),
+        (status = 400, description = "Bad Request - invalid discount code, or product or company not found"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 409, description = "Conflict - code already exists"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    request_body(
+        description = "The discount code to create",
+        content = DiscountCodeForm,
+    ),
+)]
+#[post("/discounts")]
+async fn create_discount_code(
+    shared_data: web::Data<SharedData>,
+    form: web::Json<DiscountCodeForm>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let code = match form.into_inner().validate() {
+        Ok(code) => code,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+    match discount::create_discount_code(&shared_data.db_pool, &code).await {
+        Ok(code) => HttpResponse::Created().json(code),
+        Err(e) => save_error_response(e),
+    }
+}
+
+/// Update a discount code.
+///
+/// Replaces everything but how many times the code has been used.
+/// Orders already placed with the code keep their discount.
+#[utoipa::path(
+    context_path = "/api/priv",
+    put,
+    tag = "Discounts",
+    responses(
+        (status = 200, description = "Discount code updated", body = DiscountCode),
+        (status = 400, description = "Bad Request - invalid discount code, or product or company not found"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Discount code not found"),
+        (status = 409, description = "Conflict - code already exists"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("discount_code_id", description = "The id of the discount code"),
+    ),
+    request_body(
+        description = "The updated discount code",
+        content = DiscountCodeForm,
+    ),
+)]
+#[put("/discounts/{discount_code_id}")]
+async fn update_discount_code(
+    shared_data: web::Data<SharedData>,
+    discount_code_id: web::Path<String>,
+    form: web::Json<DiscountCodeForm>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let discount_code_id = match discount_code_id.parse::<i32>() {
+        Ok(discount_code_id) => discount_code_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    let code = match form.into_inner().validate() {
+        Ok(code) => code,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+    match discount::update_discount_code(&shared_data.db_pool, &discount_code_id, &code).await {
+        Ok(code) => HttpResponse::Ok().json(code),
+        Err(e) => save_error_response(e),
+    }
+}
+
+/// Delete a discount code.
+///
+/// Orders placed with the code keep the code and their discount.
+#[utoipa::path(
+    context_path = "/api/priv",
+    delete,
+    tag = "Discounts",
+    responses(
+        (status = 204, description = "Discount code deleted"),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Discount code not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("discount_code_id", description = "The id of the discount code"),
+    ),
+)]
+#[delete("/discounts/{discount_code_id}")]
+async fn delete_discount_code(
+    shared_data: web::Data<SharedData>,
+    discount_code_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let discount_code_id = match discount_code_id.parse::<i32>() {
+        Ok(discount_code_id) => discount_code_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    match discount::delete_discount_code(&shared_data.db_pool, &discount_code_id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json("Discount code not found"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
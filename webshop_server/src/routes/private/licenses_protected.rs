@@ -1,16 +1,40 @@
 use crate::{
     data_access::{
-        license::{self, License, LicenseValidation, PartialLicense},
-        user::{self, Role},
+        company,
+        license::{
+            self, License, LicenseFilter, LicenseSort, LicenseType, LicenseValidation,
+            PartialLicense,
+        },
+        order::{self, Order, OrderError, OrderLine, OrderStatus, OrderType},
+        pagination::{PageQuery, SortOrder},
+        payment,
+        product::release::{self, LicenseRelease, Release, ReleaseDownload},
+        user::{self, Role, User},
     },
+    payment::{
+        credit::{self, CreditError},
+        PaymentIntent, PaymentStatus,
+    },
+    pricing::{self, AppliedDiscount, PriceQuote, PricingError, ProrationQuote},
+    routes::page_response,
+    tax::TaxTreatment,
     utils::auth,
     SharedData,
 };
 
-use actix_web::{get, patch, post, web, HttpRequest, HttpResponse, Responder};
+use actix_web::{get, patch, post, put, web, HttpRequest, HttpResponse, Responder};
 use log::error;
 use serde::{Deserialize, Serialize};
-use utoipa::OpenApi;
+use sqlx::{Pool, Postgres};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+pub mod assignments_protected;
+pub mod cart_protected;
+pub mod checkouts_protected;
+pub mod downloads_protected;
+pub mod purchase_requests_protected;
+pub mod quotes_protected;
+pub mod subscriptions_protected;
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(licenses);
@@ -19,8 +43,20 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(licenses_full_by_company);
     cfg.service(licenses_for_user);
     cfg.service(licenses_for_user_no_access);
+    cfg.service(license_releases);
     cfg.service(create_license);
+    cfg.service(purchase_license);
+    cfg.service(renew_license);
+    cfg.service(change_seats);
+    cfg.service(license_proration);
     cfg.service(update_license_validations);
+    cfg.configure(cart_protected::configure);
+    cfg.configure(checkouts_protected::configure);
+    cfg.configure(downloads_protected::configure);
+    cfg.configure(assignments_protected::configure);
+    cfg.configure(purchase_requests_protected::configure);
+    cfg.configure(quotes_protected::configure);
+    cfg.configure(subscriptions_protected::configure);
 }
 
 #[derive(OpenApi)]
@@ -32,11 +68,18 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
         licenses_full_by_company,
         licenses_for_user,
         licenses_for_user_no_access,
+        license_releases,
         create_license,
+        purchase_license,
+        renew_license,
+        change_seats,
+        license_proration,
         update_license_validations
     ),
     components(
-        schemas(License)
+        schemas(License, LicenseType, PartialLicense, PurchaseRequest, Purchase, PriceQuote,
+            AppliedDiscount, RenewalRequest, SeatChangeRequest, ProrationQuote, LicenseOrder, Order, OrderLine, OrderType,
+            OrderStatus, PaymentIntent, PaymentStatus, TaxTreatment, SortOrder, LicenseRelease, Release, ReleaseDownload)
     ),
     tags(
         (name = "Licenses", description = "API endpoints for licenses")
@@ -72,32 +115,45 @@ async fn licenses(shared_data: web::Data<SharedData>) -> impl Responder {
     HttpResponse::InternalServerError().json("Internal Server Error")
 }
 
-/// Get the most vital information about a license.
+/// Get a page of the licenses with the names of their company and product.
+///
+/// Sorted by `license_id` if not given, or by `start_date`, `end_date`, `company_name`
+/// or `display_name`.
 #[utoipa::path(
     context_path = "/api",
     get,
     tag = "Licenses",
     responses(
-    (status = 200, description = "List of all licenses with only their vital information", body = Vec<License>),
+    (status = 200, description = "Page of the licenses with the names of their company and product", body = Vec<License>,
+        headers(
+            ("X-Total-Count" = i64, description = "Number of licenses matching the filters"),
+            ("Link" = String, description = "Links to the first, previous, next and last pages"),
+        ),
+    ),
+    (status = 400, description = "Invalid limit, cursor or sort field"),
     (status = 500, description = "Internal Server Error"),
-)
+    ),
+    params(PageQuery, LicenseFilter),
 )]
 #[get("/licenses_full")]
-async fn licenses_full(shared_data: web::Data<SharedData>) -> impl Responder {
+async fn licenses_full(
+    shared_data: web::Data<SharedData>,
+    page_query: web::Query<PageQuery>,
+    filter: web::Query<LicenseFilter>,
+    req: HttpRequest,
+) -> impl Responder {
     let pool = &shared_data.db_pool;
-    let other_licenses = license::get_licenses_full(pool).await;
-
-    // Error check
-    if other_licenses.is_err() {
-        return HttpResponse::InternalServerError().json("Internal Server Error");
-    }
-
-    // Parse to JSON
-    if let Ok(other_licenses) = other_licenses {
-        return HttpResponse::Ok().json(other_licenses);
+    let page = match page_query.page::<LicenseSort>() {
+        Ok(page) => page,
+        Err(e) => return HttpResponse::BadRequest().json(e.to_string()),
+    };
+    match license::get_licenses_full(pool, &filter, &page).await {
+        Ok(other_licenses) => page_response(&req, other_licenses, &page),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
     }
-
-    HttpResponse::InternalServerError().json("Internal Server Error")
 }
 
 /// Get a specific license by ID.
@@ -267,11 +323,90 @@ async fn licenses_for_user_no_access(
     HttpResponse::InternalServerError().finish()
 }
 
+/// Get the releases of the product of a license, and whether the license covers them.
+///
+/// A license covers the releases released before it ends.
+/// Admins, the IT staff of the company owning the license, and users with access to it
+/// may see its releases. All users of the company have access to a floating license.
+#[utoipa::path (
+    context_path = "/api/priv",
+    get,
+    tag = "Licenses",
+    responses(
+        (status = 200, description = "Releases of the product of the license, newest first", body = Vec<LicenseRelease>),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden - user has no access to the license"),
+        (status = 404, description = "License not found"),
+        (status = 500, description = "Internal Server Error"),
+        ),
+    params(
+        ("license_id", description = "The ID of the license"),
+        )
+    )
+]
+#[get("/licenses/{license_id}/releases")]
+async fn license_releases(
+    shared_data: web::Data<SharedData>,
+    license_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let license_id = match license_id.parse::<i32>() {
+        Ok(license_id) => license_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    let license = match license::get_license_by_id(pool, &license_id).await {
+        Ok(license) => license,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json("License not found"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    };
+
+    let has_access = match user.role {
+        Role::Admin => true,
+        _ if user.company_id != license.company_id => false,
+        Role::CompanyItHead | Role::CompanyIt => true,
+        Role::Default if license.license_type() == LicenseType::Floating => true,
+        Role::Default => match license::user_has_seat(pool, &license_id, &user.user_id).await {
+            Ok(has_seat) => has_seat,
+            Err(e) => {
+                error!("{}", e);
+                return HttpResponse::InternalServerError().json("Internal Server Error");
+            }
+        },
+    };
+    if !has_access {
+        return HttpResponse::Forbidden().json("No access to the license");
+    }
+
+    match release::get_license_releases(pool, &license).await {
+        Ok(releases) => HttpResponse::Ok().json(releases),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
 /// Create a license.
 ///
-/// An admin can create a license for any company.
-/// Default user cannot create a license.
-/// CompanyIt or CompanyItHead can create a license for their company.
+/// Only admins can create licenses directly, with any amount, dates and validity.
+/// Companies buy licenses through `POST /licenses/purchase`, where the server sets them.
 #[utoipa::path (
     context_path = "/api/priv",
     post,
@@ -310,28 +445,666 @@ async fn create_license(
             }
         }
     };
+    if user.role != Role::Admin {
+        return HttpResponse::Forbidden()
+            .body("Licenses must be bought through /licenses/purchase");
+    }
+
+    match license::create_license(pool, &license).await {
+        Ok(new_license) => HttpResponse::Created().json(new_license),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// License to buy, everything else is set by the server.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct PurchaseRequest {
+    product_id: String,
+    /// Number of seats.
+    seats: i32,
+    /// Length of the license in years, defaults to 1.
+    term_years: Option<i32>,
+    /// Defaults to named.
+    license_type: Option<LicenseType>,
+    /// Discount code to apply to the price.
+    discount_code: Option<String>,
+}
+
+/// The bought license, the price it was bought at and the order paying for it.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct Purchase {
+    license: License,
+    price: PriceQuote,
+    order: Order,
+    /// Payment to pay the order with, none if the order is already paid.
+    payment: Option<PaymentIntent>,
+}
+
+/// Buy a license for your company.
+///
+/// The server computes the price with the pricing engine, in the billing currency of your company,
+/// and creates a license starting now and lasting for the term.
+/// The license becomes valid once the order is paid with the returned payment. A discount code given is checked by the pricing engine
+/// and recorded on the order. Only company IT staff can buy licenses.
+#[utoipa::path (
+    context_path = "/api/priv",
+    post,
+    tag = "Licenses",
+    responses (
+        (status = 201, description = "License bought", body = Purchase),
+        (status = 400, description = "Bad Request - invalid number of seats or term, or discount code not valid for the purchase"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Product or discount code not found"),
+        (status = 409, description = "Conflict - discount code used up, or product not sold in the billing currency"),
+        (status = 500, description = "Internal Server Error"),
+        (status = 502, description = "Payment provider error"),
+      ),
+
+    request_body(
+        description = "The license to buy",
+        content = PurchaseRequest,
+    ),
+  )
+]
+#[post("/licenses/purchase")]
+async fn purchase_license(
+    shared_data: web::Data<SharedData>,
+    purchase: web::Json<PurchaseRequest>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
     match user.role {
-        Role::Default => {
-            return HttpResponse::Forbidden().body("Cannot create licenses as a default user")
+        Role::CompanyIt | Role::CompanyItHead => (),
+        Role::Admin | Role::Default => {
+            return HttpResponse::Forbidden().body("Only company IT staff can buy licenses")
         }
-        Role::CompanyIt | Role::CompanyItHead => {
-            if user.company_id != license.company_id() {
-                return HttpResponse::Forbidden()
-                    .body("Cannot create licenses for other companies");
+    }
+
+    let term_years = purchase.term_years.unwrap_or(1);
+    let price = match pricing::quote_for_company(
+        pool,
+        &purchase.product_id,
+        purchase.seats,
+        term_years,
+        user.company_id,
+    )
+    .await
+    {
+        Ok(price) => price,
+        Err(e) => return pricing_error_response(e),
+    };
+    let price = match discounted(pool, price, &purchase.discount_code, user.company_id).await {
+        Ok(price) => price,
+        Err(e) => return pricing_error_response(e),
+    };
+
+    let new_license = match PartialLicense::purchase(
+        price.seats,
+        price.term_years,
+        user.company_id,
+        &price.product_id,
+        purchase.license_type.unwrap_or_default(),
+    ) {
+        Some(new_license) => new_license,
+        None => return HttpResponse::BadRequest().json("Invalid term"),
+    };
+
+    match order::purchase_license(pool, user.user_id, user.company_id, &new_license, &price).await {
+        Ok((license, order)) => match start_payment(&shared_data, &order).await {
+            Ok(payment) => HttpResponse::Created().json(Purchase {
+                license,
+                price,
+                order,
+                payment,
+            }),
+            Err(response) => response,
+        },
+        Err(e) => order_error_response(e),
+    }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct RenewalRequest {
+    /// Years to extend the license by, defaults to 1.
+    term_years: Option<i32>,
+    /// Discount code to apply to the price.
+    discount_code: Option<String>,
+    /// Start the new term now instead of at the end date, crediting the days left.
+    /// Defaults to false.
+    start_now: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct SeatChangeRequest {
+    /// Number of seats the license should have.
+    seats: i32,
+}
+
+#[derive(Deserialize, IntoParams)]
+struct ProrationQuery {
+    /// Number of seats the license should have, to price a seat change
+    seats: Option<i32>,
+    /// Years of the new term, to price an early renewal
+    term_years: Option<i32>,
+    /// Discount code to apply to the price of an early renewal
+    discount_code: Option<String>,
+}
+
+/// The license and the order for the change, the change is applied once the order is paid.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct LicenseOrder {
+    license: License,
+    order: Order,
+    /// Payment to pay the order with, none if the order is already paid.
+    payment: Option<PaymentIntent>,
+}
+
+/// Creates the payment for an order waiting for payment at the payment provider.
+/// Paying a subscription renewal saves the payment method for the later renewals.
+/// If the provider fails, the order is marked failed and the response to send is returned.
+async fn start_payment(
+    shared_data: &SharedData,
+    order: &Order,
+) -> Result<Option<PaymentIntent>, HttpResponse> {
+    if order.status != OrderStatus::Pending {
+        return Ok(None);
+    }
+    let pool = &shared_data.db_pool;
+    let provider = &shared_data.payment_provider;
+    let save_payment_method = order.order_type == OrderType::SubscriptionRenewal;
+    let intent = match provider
+        .create_intent(
+            order.order_id,
+            order.total,
+            order.currency,
+            save_payment_method,
+        )
+        .await
+    {
+        Ok(intent) => intent,
+        Err(e) => {
+            error!("Payment provider error for order {}: {}", order.order_id, e);
+            if let Err(e) = payment::fail_order(pool, &order.order_id).await {
+                error!("{}", e);
             }
+            return Err(HttpResponse::BadGateway().json("Payment provider error"));
+        }
+    };
+    match payment::create_payment(pool, &order.order_id, provider.name(), &intent).await {
+        Ok(()) => Ok(Some(intent)),
+        Err(e) => {
+            error!("{}", e);
+            Err(HttpResponse::InternalServerError().json("Internal Server Error"))
         }
-        Role::Admin => (),
     }
+}
 
-    match license::create_license(pool, &license).await {
-        Ok(new_license) => HttpResponse::Created().json(new_license),
+/// Pays back the credit of an order crediting the company of a license, see [`credit`].
+/// If it cannot be paid back now, the order stays pending, its credit is paid back later
+/// by [`crate::jobs::credit_payback`], and the response to send is returned.
+async fn pay_back_credit(shared_data: &SharedData, order: &Order) -> Result<(), HttpResponse> {
+    let pool = &shared_data.db_pool;
+    let provider = shared_data.payment_provider.as_ref();
+    match credit::pay_back_credit(pool, provider, &order.order_id).await {
+        Ok(()) => Ok(()),
+        Err(CreditError::PaymentError(e)) => {
+            error!(
+                "Could not pay back credit of order {}: {}",
+                order.invoice_number, e
+            );
+            Err(HttpResponse::BadGateway()
+                .json("Payment provider error, the credit will be paid back later"))
+        }
+        Err(CreditError::NotPaidBack(left)) => {
+            error!(
+                "Credit of order {} was not paid back in full, {} {} is left",
+                order.invoice_number, left, order.currency
+            );
+            Err(HttpResponse::Conflict()
+                .json("The credit cannot be paid back, the license has too little left to refund"))
+        }
+        Err(CreditError::SqlxError(e)) => {
+            error!("{}", e);
+            Err(HttpResponse::InternalServerError().json("Internal Server Error"))
+        }
+    }
+}
+
+/// Responds with the license and its order, and the payment for the order if it is not paid.
+/// The credit of an order crediting the company is paid back before responding.
+pub(super) async fn license_order_response(
+    shared_data: &SharedData,
+    license: License,
+    order: Order,
+) -> HttpResponse {
+    if order.status == OrderStatus::Pending && order.total.is_negative() {
+        if let Err(response) = pay_back_credit(shared_data, &order).await {
+            return response;
+        }
+        let pool = &shared_data.db_pool;
+        return match (
+            license::get_license_by_id(pool, &license.license_id()).await,
+            order::get_order_by_id(pool, &order.order_id).await,
+        ) {
+            (Ok(license), Ok(order)) => HttpResponse::Ok().json(LicenseOrder {
+                license,
+                order,
+                payment: None,
+            }),
+            (Err(e), _) | (_, Err(e)) => {
+                error!("{}", e);
+                HttpResponse::InternalServerError().json("Internal Server Error")
+            }
+        };
+    }
+    match start_payment(shared_data, &order).await {
+        Ok(payment) => HttpResponse::Ok().json(LicenseOrder {
+            license,
+            order,
+            payment,
+        }),
+        Err(response) => response,
+    }
+}
+
+/// Fetches a license the user may renew or change the seats of.
+/// Returns the response to send if the user cannot buy for the license.
+pub(super) async fn get_purchasable_license(
+    shared_data: &SharedData,
+    license_id: &str,
+    user: &User,
+) -> Result<License, HttpResponse> {
+    let license_id = match license_id.parse::<i32>() {
+        Ok(license_id) => license_id,
+        Err(_) => return Err(HttpResponse::BadRequest().json("Bad Request")),
+    };
+    let license = match license::get_license_by_id(&shared_data.db_pool, &license_id).await {
+        Ok(license) => license,
         Err(e) => {
+            return match e {
+                sqlx::Error::RowNotFound => Err(HttpResponse::NotFound().json("License not found")),
+                _ => {
+                    error!("{}", e);
+                    Err(HttpResponse::InternalServerError().json("Internal Server Error"))
+                }
+            }
+        }
+    };
+    match user.role {
+        Role::CompanyIt | Role::CompanyItHead => {
+            if license.company_id != user.company_id {
+                return Err(HttpResponse::Forbidden().json("License is not owned by your company"));
+            }
+        }
+        Role::Admin | Role::Default => {
+            return Err(HttpResponse::Forbidden().body("Only company IT staff can buy licenses"))
+        }
+    }
+    Ok(license)
+}
+
+/// Returns the discount code given, if it is not blank.
+fn discount_code(code: &Option<String>) -> Option<&str> {
+    code.as_deref()
+        .map(str::trim)
+        .filter(|code| !code.is_empty())
+}
+
+/// Applies the discount code given, if any, to a price for a company.
+pub(super) async fn discounted(
+    pool: &Pool<Postgres>,
+    price: PriceQuote,
+    code: &Option<String>,
+    company_id: i32,
+) -> Result<PriceQuote, PricingError> {
+    match discount_code(code) {
+        Some(code) => pricing::apply_discount(pool, price, code, Some(company_id)).await,
+        None => Ok(price),
+    }
+}
+
+/// Computes the prorated price of changing the seats of a license.
+/// Returns the response to send if the seats cannot be changed.
+async fn prorate_seat_change(
+    pool: &Pool<Postgres>,
+    license: &License,
+    seats: i32,
+) -> Result<ProrationQuote, HttpResponse> {
+    if !license.is_active() {
+        return Err(HttpResponse::Conflict().json("Seats can only be changed on active licenses"));
+    }
+    if seats == license.amount() {
+        return Err(HttpResponse::BadRequest().json("License already has this number of seats"));
+    }
+    let currency = company::get_billing_currency(pool, &license.company_id)
+        .await
+        .map_err(|e| pricing_error_response(e.into()))?;
+    pricing::quote_seat_change(pool, license, seats, currency)
+        .await
+        .map_err(pricing_error_response)
+}
+
+/// Computes the prorated price of renewing a license now, with the discount code given, if any.
+/// Returns the response to send if the license cannot be renewed.
+async fn prorate_early_renewal(
+    pool: &Pool<Postgres>,
+    license: &License,
+    term_years: i32,
+    discount_code: &Option<String>,
+) -> Result<ProrationQuote, HttpResponse> {
+    let currency = company::get_billing_currency(pool, &license.company_id)
+        .await
+        .map_err(|e| pricing_error_response(e.into()))?;
+    let price = pricing::quote_renewal(
+        pool,
+        license.product_id(),
+        license.amount(),
+        term_years,
+        currency,
+    )
+    .await
+    .map_err(pricing_error_response)?;
+    let price = discounted(pool, price, discount_code, license.company_id)
+        .await
+        .map_err(pricing_error_response)?;
+    pricing::quote_early_renewal(pool, license, &price)
+        .await
+        .map_err(pricing_error_response)
+}
+
+/// Maps pricing errors to responses.
+pub(super) fn pricing_error_response(e: PricingError) -> HttpResponse {
+    match e {
+        PricingError::ProductNotFound | PricingError::DiscountNotFound => {
+            HttpResponse::NotFound().json(e.to_string())
+        }
+        PricingError::InvalidSeats
+        | PricingError::InvalidTerm
+        | PricingError::DiscountNotValidNow
+        | PricingError::DiscountNotApplicable
+        | PricingError::EmptyCart => HttpResponse::BadRequest().json(e.to_string()),
+        PricingError::DiscountUsedUp => HttpResponse::Conflict().json(e.to_string()),
+        PricingError::NotSoldInCurrency => HttpResponse::Conflict()
+            .json("Product is not sold in the billing currency of the company"),
+        PricingError::SqlxError(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Maps order errors to responses.
+pub(super) fn order_error_response(e: OrderError) -> HttpResponse {
+    match e {
+        OrderError::LicenseNotFound => HttpResponse::NotFound().json(e.to_string()),
+        OrderError::LicenseNotPurchasable
+        | OrderError::LicenseChanged
+        | OrderError::TooFewSeats
+        | OrderError::DiscountUsedUp
+        | OrderError::CartChanged
+        | OrderError::RequestNotPending
+        | OrderError::SubscriptionNotRenewable
+        | OrderError::QuoteNotAcceptable
+        | OrderError::TrialNotConvertible
+        | OrderError::SeatChangePending
+        | OrderError::CreditNotRefundable => HttpResponse::Conflict().json(e.to_string()),
+        OrderError::SqlxError(e) => {
             error!("{}", e);
             HttpResponse::InternalServerError().json("Internal Server Error")
         }
     }
 }
 
+/// Renew a license of your company.
+///
+/// Creates a renewal order for all seats of the license at the current price,
+/// less the discount code given, if any. Once the order is paid, the license is extended by the term,
+/// from its end date or from then if it has expired.
+/// A renewal started now instead ends the license the term after the order is paid,
+/// and credits the days left on the license at the current price.
+#[utoipa::path (
+    context_path = "/api/priv",
+    post,
+    tag = "Licenses",
+    responses (
+        (status = 200, description = "Renewal ordered", body = LicenseOrder),
+        (status = 400, description = "Bad Request - invalid term, or discount code not valid for the renewal"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "License, product or discount code not found"),
+        (status = 409, description = "Conflict - license is invalid or a trial, or changed meanwhile, discount code used up, or product not sold in the billing currency"),
+        (status = 500, description = "Internal Server Error"),
+        (status = 502, description = "Payment provider error"),
+    ),
+    params(
+        ("license_id", description = "The ID of the license"),
+    ),
+    request_body(
+        description = "The renewal",
+        content = RenewalRequest,
+    ),
+)]
+#[post("/licenses/{license_id}/renew")]
+async fn renew_license(
+    shared_data: web::Data<SharedData>,
+    license_id: web::Path<String>,
+    renewal: web::Json<RenewalRequest>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let license = match get_purchasable_license(&shared_data, &license_id, &user).await {
+        Ok(license) => license,
+        Err(response) => return response,
+    };
+
+    let term_years = renewal.term_years.unwrap_or(1);
+    if renewal.start_now.unwrap_or(false) {
+        let price =
+            match prorate_early_renewal(pool, &license, term_years, &renewal.discount_code).await {
+                Ok(price) => price,
+                Err(response) => return response,
+            };
+        return match order::renew_license_early(pool, user.user_id, &license.license_id(), &price)
+            .await
+        {
+            Ok((license, order)) => license_order_response(&shared_data, license, order).await,
+            Err(e) => order_error_response(e),
+        };
+    }
+    let currency = match company::get_billing_currency(pool, &license.company_id).await {
+        Ok(currency) => currency,
+        Err(e) => return pricing_error_response(e.into()),
+    };
+    let price = match pricing::quote_renewal(
+        pool,
+        license.product_id(),
+        license.amount(),
+        term_years,
+        currency,
+    )
+    .await
+    {
+        Ok(price) => price,
+        Err(e) => return pricing_error_response(e),
+    };
+    let price = match discounted(pool, price, &renewal.discount_code, license.company_id).await {
+        Ok(price) => price,
+        Err(e) => return pricing_error_response(e),
+    };
+
+    match order::renew_license(pool, user.user_id, &license.license_id(), &price).await {
+        Ok((license, order)) => license_order_response(&shared_data, license, order).await,
+        Err(e) => order_error_response(e),
+    }
+}
+
+/// Change the number of seats of a license of your company.
+///
+/// The change is prorated by the days left on the license: the seats it has are credited
+/// at their current price, and the new number of seats is charged at the price of the tier it reaches.
+/// Added seats are added once paid. Removed seats are removed right away,
+/// the order credits them, seats in use cannot be removed.
+/// The seats cannot be changed again while a seat change is waiting for payment.
+#[utoipa::path (
+    context_path = "/api/priv",
+    put,
+    tag = "Licenses",
+    responses (
+        (status = 200, description = "Seat change ordered", body = LicenseOrder),
+        (status = 400, description = "Bad Request - invalid number of seats"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "License or product not found"),
+        (status = 409, description = "Conflict - license is invalid, a trial or expired, changed meanwhile, has more seats in use or a seat change waiting for payment, too little left to refund the credit, or product not sold in the billing currency"),
+        (status = 500, description = "Internal Server Error"),
+        (status = 502, description = "Payment provider error"),
+    ),
+    params(
+        ("license_id", description = "The ID of the license"),
+    ),
+    request_body(
+        description = "The new number of seats",
+        content = SeatChangeRequest,
+    ),
+)]
+#[put("/licenses/{license_id}/seats")]
+async fn change_seats(
+    shared_data: web::Data<SharedData>,
+    license_id: web::Path<String>,
+    change: web::Json<SeatChangeRequest>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let license = match get_purchasable_license(&shared_data, &license_id, &user).await {
+        Ok(license) => license,
+        Err(response) => return response,
+    };
+    let price = match prorate_seat_change(pool, &license, change.seats).await {
+        Ok(price) => price,
+        Err(response) => return response,
+    };
+
+    match order::change_seats(
+        pool,
+        user.user_id,
+        &license.license_id(),
+        &price,
+        shared_data.checkout_timeout,
+    )
+    .await
+    {
+        Ok((license, order)) => license_order_response(&shared_data, license, order).await,
+        Err(e) => order_error_response(e),
+    }
+}
+
+/// Get the prorated price of changing a license of your company before committing to it.
+///
+/// Give `seats` to price a seat change, or `term_years` to price renewing the license now.
+/// The days left on the license are credited at the current price of its seats,
+/// the new seats or term are charged from now.
+/// A negative total is credited to the company.
+#[utoipa::path (
+    context_path = "/api/priv",
+    get,
+    tag = "Licenses",
+    responses (
+        (status = 200, description = "Price computed by the server", body = ProrationQuote),
+        (status = 400, description = "Bad Request - neither or both of seats and term given, invalid number of seats or term, or discount code not valid"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "License, product or discount code not found"),
+        (status = 409, description = "Conflict - license is not active, discount code used up, or product not sold in the billing currency"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("license_id", description = "The ID of the license"),
+        ProrationQuery,
+    ),
+)]
+#[get("/licenses/{license_id}/proration")]
+async fn license_proration(
+    shared_data: web::Data<SharedData>,
+    license_id: web::Path<String>,
+    query: web::Query<ProrationQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let license = match get_purchasable_license(&shared_data, &license_id, &user).await {
+        Ok(license) => license,
+        Err(response) => return response,
+    };
+    if !license.valid() || license.trial() {
+        return HttpResponse::Conflict().json(OrderError::LicenseNotPurchasable.to_string());
+    }
+
+    let price = match (query.seats, query.term_years) {
+        (Some(seats), None) => prorate_seat_change(pool, &license, seats).await,
+        (None, Some(term_years)) => {
+            prorate_early_renewal(pool, &license, term_years, &query.discount_code).await
+        }
+        _ => {
+            return HttpResponse::BadRequest().json("Give either seats or term_years");
+        }
+    };
+    match price {
+        Ok(price) => HttpResponse::Ok().json(price),
+        Err(response) => response,
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct LicenseValidations {
     licenses: Vec<LicenseValidation>,
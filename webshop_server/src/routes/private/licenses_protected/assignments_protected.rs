@@ -0,0 +1,257 @@
+//! Endpoints for assigning seats on named licenses by rule.
+//!
+//! IT heads can assign all company users, or all users with a role, in one request,
+//! and set a license to automatically assign company users when they register.
+
+use actix_web::{get, post, put, web, HttpRequest, HttpResponse, Responder};
+use log::error;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    data_access::{
+        license::{
+            self,
+            assignment::{self, Assignment, AssignmentResult, AssignmentRule, FailedAssignment},
+            License, LicenseType,
+        },
+        user::{Role, User},
+    },
+    utils::auth,
+    SharedData,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(assign_by_rule);
+    cfg.service(get_auto_assign);
+    cfg.service(set_auto_assign);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        assign_by_rule,
+        get_auto_assign,
+        set_auto_assign,
+    ),
+    components(
+        schemas(AssignmentRule, AssignmentResult, Assignment, FailedAssignment, AutoAssignPolicy)
+    ),
+    tags(
+        (name = "Seat assignment", description = "API endpoints for assigning seats on named licenses by rule")
+    ),
+)]
+pub struct AssignmentsOpenApi;
+
+/// Whether company users are assigned to a license automatically when they register.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct AutoAssignPolicy {
+    auto_assign: bool,
+}
+
+/// Fetches a named license whose seats the user is allowed to manage.
+/// Returns the response to send if the user cannot manage the license.
+async fn get_managed_license(
+    shared_data: &SharedData,
+    license_id: &str,
+    user: &User,
+) -> Result<License, HttpResponse> {
+    let license_id = match license_id.parse::<i32>() {
+        Ok(license_id) => license_id,
+        Err(_) => return Err(HttpResponse::BadRequest().json("Bad Request")),
+    };
+    let license = match license::get_license_by_id(&shared_data.db_pool, &license_id).await {
+        Ok(license) => license,
+        Err(e) => {
+            return match e {
+                sqlx::Error::RowNotFound => Err(HttpResponse::NotFound().json("License not found")),
+                _ => {
+                    error!("{}", e);
+                    Err(HttpResponse::InternalServerError().json("Internal Server Error"))
+                }
+            }
+        }
+    };
+    match user.role {
+        Role::Admin => (),
+        Role::CompanyItHead => {
+            if license.company_id != user.company_id {
+                return Err(HttpResponse::Forbidden().json("License is not owned by your company"));
+            }
+        }
+        Role::CompanyIt | Role::Default => return Err(HttpResponse::Forbidden().finish()),
+    }
+    if license.license_type() != LicenseType::Named {
+        return Err(HttpResponse::BadRequest()
+            .json("Seats of floating licenses are checked out, not assigned"));
+    }
+    Ok(license)
+}
+
+/// Assign seats on a license to all company users matching a rule.
+///
+/// Users are assigned one by one until the license runs out of seats.
+/// Users that could not be assigned are listed in the response with the reason.
+/// Available to admins, and to the IT head of the company owning the license.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Seat assignment",
+    responses(
+        (status = 200, description = "Outcome of the assignment", body = AssignmentResult),
+        (status = 400, description = "Bad Request - license is not a named license"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "License not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("license_id", description = "The ID of the license"),
+    ),
+    request_body(
+        content_type = "application/json",
+        description = "Which users of the company to assign",
+        content = AssignmentRule,
+    ),
+)]
+#[post("/licenses/{license_id}/assign")]
+async fn assign_by_rule(
+    shared_data: web::Data<SharedData>,
+    license_id: web::Path<String>,
+    rule: web::Json<AssignmentRule>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let license = match get_managed_license(&shared_data, &license_id, &user).await {
+        Ok(license) => license,
+        Err(response) => return response,
+    };
+
+    match assignment::assign_by_rule(pool, license.license_id(), license.company_id, &rule).await {
+        Ok(result) => HttpResponse::Ok().json(result),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Get whether company users are assigned to a license automatically when they register.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Seat assignment",
+    responses(
+        (status = 200, description = "Auto-assign policy of the license", body = AutoAssignPolicy),
+        (status = 400, description = "Bad Request - license is not a named license"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "License not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("license_id", description = "The ID of the license"),
+    ),
+)]
+#[get("/licenses/{license_id}/auto_assign")]
+async fn get_auto_assign(
+    shared_data: web::Data<SharedData>,
+    license_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let license = match get_managed_license(&shared_data, &license_id, &user).await {
+        Ok(license) => license,
+        Err(response) => return response,
+    };
+
+    match assignment::get_auto_assign(pool, &license.license_id()).await {
+        Ok(auto_assign) => HttpResponse::Ok().json(AutoAssignPolicy { auto_assign }),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Set whether company users are assigned to a license automatically when they register.
+///
+/// Only applies to users registering afterwards, use the assign endpoint for existing users.
+#[utoipa::path(
+    context_path = "/api/priv",
+    put,
+    tag = "Seat assignment",
+    responses(
+        (status = 200, description = "Auto-assign policy saved", body = AutoAssignPolicy),
+        (status = 400, description = "Bad Request - license is not a named license"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "License not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("license_id", description = "The ID of the license"),
+    ),
+    request_body(
+        content_type = "application/json",
+        description = "The auto-assign policy",
+        content = AutoAssignPolicy,
+    ),
+)]
+#[put("/licenses/{license_id}/auto_assign")]
+async fn set_auto_assign(
+    shared_data: web::Data<SharedData>,
+    license_id: web::Path<String>,
+    policy: web::Json<AutoAssignPolicy>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let license = match get_managed_license(&shared_data, &license_id, &user).await {
+        Ok(license) => license,
+        Err(response) => return response,
+    };
+
+    match assignment::set_auto_assign(pool, &license.license_id(), policy.auto_assign).await {
+        Ok(()) => HttpResponse::Ok().json(policy.into_inner()),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
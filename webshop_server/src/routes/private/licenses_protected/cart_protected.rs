@@ -0,0 +1,517 @@
+//! Endpoints for the cart of a company user.
+//!
+//! Company IT staff put the licenses they want to buy in their cart, and buy all of them
+//! at checkout with one order and one payment.
+
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Responder};
+use log::error;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use super::{discount_code, order_error_response, pricing_error_response, start_payment};
+use crate::{
+    data_access::{
+        cart::{self, CartItem},
+        company,
+        error_handling::PostgresDBError,
+        license::{License, LicenseType, PartialLicense},
+        order::{self, Order},
+        user::{Role, User},
+    },
+    payment::PaymentIntent,
+    pricing::{self, CartQuote},
+    utils::auth,
+    SharedData,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_cart);
+    cfg.service(cart_quote);
+    cfg.service(add_cart_item);
+    cfg.service(update_cart_item);
+    cfg.service(remove_cart_item);
+    cfg.service(clear_cart);
+    cfg.service(checkout);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        get_cart,
+        cart_quote,
+        add_cart_item,
+        update_cart_item,
+        remove_cart_item,
+        clear_cart,
+        checkout,
+    ),
+    components(
+        schemas(CartItem, CartItemRequest, CartItemUpdate, CartQuote, CheckoutRequest, CartPurchase)
+    ),
+    tags(
+        (name = "Cart", description = "API endpoints for buying several licenses at once")
+    ),
+)]
+pub struct CartOpenApi;
+
+/// Product to add to the cart.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct CartItemRequest {
+    product_id: String,
+    /// Number of seats.
+    seats: i32,
+    /// Length of the license in years, defaults to 1.
+    term_years: Option<i32>,
+    /// Defaults to named.
+    license_type: Option<LicenseType>,
+}
+
+/// New seats, term and license type of an item in the cart.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct CartItemUpdate {
+    /// Number of seats.
+    seats: i32,
+    /// Length of the license in years, defaults to 1.
+    term_years: Option<i32>,
+    /// Defaults to named.
+    license_type: Option<LicenseType>,
+}
+
+#[derive(Deserialize, IntoParams)]
+struct CartQuoteQuery {
+    /// Discount code to apply to the price
+    discount_code: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct CheckoutRequest {
+    /// Discount code to apply to the price.
+    discount_code: Option<String>,
+}
+
+/// The bought licenses, in the order of the items of the cart,
+/// the price they were bought at and the order paying for them.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct CartPurchase {
+    licenses: Vec<License>,
+    price: CartQuote,
+    order: Order,
+    /// Payment to pay the order with, none if the order is already paid.
+    payment: Option<PaymentIntent>,
+}
+
+/// Checks the user can buy licenses, returning the response to send if not.
+async fn validate_buyer(shared_data: &SharedData, req: HttpRequest) -> Result<User, HttpResponse> {
+    let user = match auth::validate_user(req, &shared_data.db_pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => Err(HttpResponse::Unauthorized().finish()),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    Err(HttpResponse::InternalServerError().finish())
+                }
+            }
+        }
+    };
+    match user.role {
+        Role::CompanyIt | Role::CompanyItHead => Ok(user),
+        Role::Admin | Role::Default => {
+            Err(HttpResponse::Forbidden().body("Only company IT staff can buy licenses"))
+        }
+    }
+}
+
+/// Computes the price of the cart of a user, with the discount code given, if any.
+async fn price_cart(
+    shared_data: &SharedData,
+    user: &User,
+    items: &[CartItem],
+    code: &Option<String>,
+) -> Result<CartQuote, HttpResponse> {
+    let pool = &shared_data.db_pool;
+    let price = match company::get_billing_currency(pool, &user.company_id).await {
+        Ok(currency) => pricing::quote_cart(pool, items, currency).await,
+        Err(e) => Err(e.into()),
+    };
+    let price = match price {
+        Ok(price) => price,
+        Err(e) => return Err(pricing_error_response(e)),
+    };
+    match discount_code(code) {
+        Some(code) => pricing::apply_cart_discount(pool, price, code, Some(user.company_id))
+            .await
+            .map_err(pricing_error_response),
+        None => Ok(price),
+    }
+}
+
+/// Get the items in your cart.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Cart",
+    responses(
+        (status = 200, description = "Items in the cart, in the order they were added", body = Vec<CartItem>),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+)]
+#[get("/cart")]
+async fn get_cart(shared_data: web::Data<SharedData>, req: HttpRequest) -> impl Responder {
+    let user = match validate_buyer(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    match cart::get_cart(&shared_data.db_pool, &user.user_id).await {
+        Ok(items) => HttpResponse::Ok().json(items),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Get the price of everything in your cart.
+///
+/// The cart is priced in the billing currency of your company.
+/// A discount code given is applied to the items it is valid for.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Cart",
+    responses(
+        (status = 200, description = "Price computed by the server", body = CartQuote),
+        (status = 400, description = "Bad Request - empty cart, or discount code not valid for the cart"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Product or discount code not found"),
+        (status = 409, description = "Conflict - discount code used up, or a product not sold in the billing currency"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(CartQuoteQuery),
+)]
+#[get("/cart/quote")]
+async fn cart_quote(
+    shared_data: web::Data<SharedData>,
+    query: web::Query<CartQuoteQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate_buyer(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let items = match cart::get_cart(&shared_data.db_pool, &user.user_id).await {
+        Ok(items) => items,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    };
+    match price_cart(&shared_data, &user, &items, &query.discount_code).await {
+        Ok(price) => HttpResponse::Ok().json(price),
+        Err(response) => response,
+    }
+}
+
+/// Add a product to your cart.
+///
+/// A product can only be in the cart once, change its item to buy more seats.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Cart",
+    responses(
+        (status = 201, description = "Product added to the cart", body = CartItem),
+        (status = 400, description = "Bad Request - invalid number of seats or term"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Product not found"),
+        (status = 409, description = "Conflict - product already in the cart, or not sold in the billing currency"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    request_body(
+        description = "The product to add",
+        content = CartItemRequest,
+    ),
+)]
+#[post("/cart/items")]
+async fn add_cart_item(
+    shared_data: web::Data<SharedData>,
+    item: web::Json<CartItemRequest>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate_buyer(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let pool = &shared_data.db_pool;
+    let term_years = item.term_years.unwrap_or(1);
+    // the item is priced again at checkout, this only checks it can be bought
+    if let Err(e) = pricing::quote_for_company(
+        pool,
+        &item.product_id,
+        item.seats,
+        term_years,
+        user.company_id,
+    )
+    .await
+    {
+        return pricing_error_response(e);
+    }
+    match cart::add_cart_item(
+        pool,
+        &user.user_id,
+        &item.product_id,
+        item.seats,
+        term_years,
+        item.license_type.unwrap_or_default(),
+    )
+    .await
+    {
+        Ok(item) => HttpResponse::Created().json(item),
+        Err(sqlx::Error::Database(e)) => match PostgresDBError::from_str(e) {
+            PostgresDBError::UniqueViolation => {
+                HttpResponse::Conflict().json("Product already in the cart")
+            }
+            _ => HttpResponse::InternalServerError().json("Internal Server Error"),
+        },
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Change the seats, term and license type of an item in your cart.
+#[utoipa::path(
+    context_path = "/api/priv",
+    put,
+    tag = "Cart",
+    responses(
+        (status = 200, description = "Item changed", body = CartItem),
+        (status = 400, description = "Bad Request - invalid number of seats or term"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Item or product not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("cart_item_id", description = "The id of the item"),
+    ),
+    request_body(
+        description = "The new seats, term and license type",
+        content = CartItemUpdate,
+    ),
+)]
+#[put("/cart/items/{cart_item_id}")]
+async fn update_cart_item(
+    shared_data: web::Data<SharedData>,
+    cart_item_id: web::Path<String>,
+    update: web::Json<CartItemUpdate>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate_buyer(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let cart_item_id = match cart_item_id.parse::<i32>() {
+        Ok(cart_item_id) => cart_item_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    let pool = &shared_data.db_pool;
+    let items = match cart::get_cart(pool, &user.user_id).await {
+        Ok(items) => items,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    };
+    let item = match items.iter().find(|item| item.cart_item_id == cart_item_id) {
+        Some(item) => item,
+        None => return HttpResponse::NotFound().json("Item not found"),
+    };
+    let term_years = update.term_years.unwrap_or(1);
+    if let Err(e) = pricing::quote_for_company(
+        pool,
+        &item.product_id,
+        update.seats,
+        term_years,
+        user.company_id,
+    )
+    .await
+    {
+        return pricing_error_response(e);
+    }
+    match cart::update_cart_item(
+        pool,
+        &user.user_id,
+        &cart_item_id,
+        update.seats,
+        term_years,
+        update.license_type.unwrap_or_default(),
+    )
+    .await
+    {
+        Ok(item) => HttpResponse::Ok().json(item),
+        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json("Item not found"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Remove an item from your cart.
+#[utoipa::path(
+    context_path = "/api/priv",
+    delete,
+    tag = "Cart",
+    responses(
+        (status = 204, description = "Item removed"),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Item not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("cart_item_id", description = "The id of the item"),
+    ),
+)]
+#[delete("/cart/items/{cart_item_id}")]
+async fn remove_cart_item(
+    shared_data: web::Data<SharedData>,
+    cart_item_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate_buyer(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let cart_item_id = match cart_item_id.parse::<i32>() {
+        Ok(cart_item_id) => cart_item_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    match cart::remove_cart_item(&shared_data.db_pool, &user.user_id, &cart_item_id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json("Item not found"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Remove everything from your cart.
+#[utoipa::path(
+    context_path = "/api/priv",
+    delete,
+    tag = "Cart",
+    responses(
+        (status = 204, description = "Cart emptied"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+)]
+#[delete("/cart")]
+async fn clear_cart(shared_data: web::Data<SharedData>, req: HttpRequest) -> impl Responder {
+    let user = match validate_buyer(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    match cart::clear_cart(&shared_data.db_pool, &user.user_id).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Buy everything in your cart.
+///
+/// The server prices the whole cart, and in one transaction creates a license for each item,
+/// starting now and lasting for its term, one order paying for all of them,
+/// and empties the cart. If anything fails nothing is bought and the cart is kept.
+/// The licenses become valid once the order is paid with the returned payment.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Cart",
+    responses(
+        (status = 201, description = "Cart bought", body = CartPurchase),
+        (status = 400, description = "Bad Request - empty cart, or discount code not valid for the cart"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Product or discount code not found"),
+        (status = 409, description = "Conflict - cart changed meanwhile, discount code used up, or a product not sold in the billing currency"),
+        (status = 500, description = "Internal Server Error"),
+        (status = 502, description = "Payment provider error"),
+    ),
+    request_body(
+        description = "The discount code to apply",
+        content = CheckoutRequest,
+    ),
+)]
+#[post("/cart/checkout")]
+async fn checkout(
+    shared_data: web::Data<SharedData>,
+    request: web::Json<CheckoutRequest>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate_buyer(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let pool = &shared_data.db_pool;
+    let items = match cart::get_cart(pool, &user.user_id).await {
+        Ok(items) => items,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    };
+    let price = match price_cart(&shared_data, &user, &items, &request.discount_code).await {
+        Ok(price) => price,
+        Err(response) => return response,
+    };
+
+    let mut new_licenses = Vec::with_capacity(items.len());
+    for (item, line) in items.iter().zip(price.lines.iter()) {
+        match PartialLicense::purchase(
+            line.seats,
+            line.term_years,
+            user.company_id,
+            &line.product_id,
+            item.license_type,
+        ) {
+            Some(new_license) => new_licenses.push(new_license),
+            None => return HttpResponse::BadRequest().json("Invalid term"),
+        }
+    }
+
+    match order::checkout_cart(
+        pool,
+        user.user_id,
+        user.company_id,
+        &items,
+        &new_licenses,
+        &price,
+    )
+    .await
+    {
+        Ok((licenses, order)) => match start_payment(&shared_data, &order).await {
+            Ok(payment) => HttpResponse::Created().json(CartPurchase {
+                licenses,
+                price,
+                order,
+                payment,
+            }),
+            Err(response) => response,
+        },
+        Err(e) => order_error_response(e),
+    }
+}
@@ -0,0 +1,344 @@
+//! Endpoints for floating (concurrent-use) licenses.
+//!
+//! A company user checks out a seat, keeps it alive by sending heartbeats and releases it when done.
+//! Seats that stop receiving heartbeats are released automatically by the checkout reaper job.
+
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse, Responder};
+use log::error;
+use utoipa::OpenApi;
+
+use crate::{
+    data_access::{
+        error_handling::PostgresDBError,
+        license::{
+            self,
+            checkout::{self, LicenseCheckout},
+            License, LicenseType,
+        },
+        user::{Role, User},
+    },
+    utils::auth,
+    SharedData,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(checkout_seat);
+    cfg.service(heartbeat);
+    cfg.service(release_seat);
+    cfg.service(active_checkouts);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        checkout_seat,
+        heartbeat,
+        release_seat,
+        active_checkouts,
+    ),
+    components(
+        schemas(LicenseCheckout, LicenseType)
+    ),
+    tags(
+        (name = "Floating licenses", description = "API endpoints for checking out seats on floating licenses")
+    ),
+)]
+pub struct CheckoutsOpenApi;
+
+/// Fetches a floating license that the user is allowed to check out.
+/// Returns the response to send if the license cannot be checked out by the user.
+async fn get_floating_license(
+    shared_data: &SharedData,
+    license_id: &str,
+    user: &User,
+) -> Result<License, HttpResponse> {
+    let license_id = match license_id.parse::<i32>() {
+        Ok(license_id) => license_id,
+        Err(_) => return Err(HttpResponse::BadRequest().json("Bad Request")),
+    };
+    let license = match license::get_license_by_id(&shared_data.db_pool, &license_id).await {
+        Ok(license) => license,
+        Err(e) => {
+            return match e {
+                sqlx::Error::RowNotFound => Err(HttpResponse::NotFound().json("License not found")),
+                _ => {
+                    error!("{}", e);
+                    Err(HttpResponse::InternalServerError().json("Internal Server Error"))
+                }
+            }
+        }
+    };
+    if license.license_type() != LicenseType::Floating {
+        return Err(HttpResponse::BadRequest().json("License is not a floating license"));
+    }
+    if license.company_id != user.company_id {
+        return Err(HttpResponse::Forbidden().json("License is not owned by your company"));
+    }
+    Ok(license)
+}
+
+/// Check out a seat on a floating license.
+///
+/// If the user already holds a seat, its heartbeat is refreshed.
+/// The seat is kept for as long as heartbeats are sent within the checkout timeout.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Floating licenses",
+    responses(
+        (status = 201, description = "Seat checked out", body = LicenseCheckout),
+        (status = 400, description = "Bad Request - license is not a floating license"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden - license is not valid or not owned by the user's company"),
+        (status = 404, description = "License not found"),
+        (status = 409, description = "Conflict - all seats are in use"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("license_id", description = "The ID of the license"),
+    )
+)]
+#[post("/licenses/{license_id}/checkout")]
+async fn checkout_seat(
+    shared_data: web::Data<SharedData>,
+    license_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let license = match get_floating_license(&shared_data, &license_id, &user).await {
+        Ok(license) => license,
+        Err(response) => return response,
+    };
+    if !license.is_active() {
+        return HttpResponse::Forbidden().json("License is not valid");
+    }
+
+    match checkout::checkout_seat(
+        pool,
+        &license.license_id(),
+        &user.user_id,
+        shared_data.checkout_timeout,
+    )
+    .await
+    {
+        Ok(checkout) => HttpResponse::Created().json(checkout),
+        Err(e) => match e {
+            sqlx::Error::Database(e) => match PostgresDBError::from_str(e) {
+                PostgresDBError::RaiseException => {
+                    HttpResponse::Conflict().json("All seats of the license are in use")
+                }
+                e => {
+                    error!("PostgresDBError while checking out a seat: {:?}", e);
+                    HttpResponse::InternalServerError().json("Internal Server Error")
+                }
+            },
+            _ => {
+                error!("{}", e);
+                HttpResponse::InternalServerError().json("Internal Server Error")
+            }
+        },
+    }
+}
+
+/// Send a heartbeat for a checked out seat.
+///
+/// Must be sent within the checkout timeout, otherwise the seat is released
+/// and has to be checked out again.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Floating licenses",
+    responses(
+        (status = 200, description = "Heartbeat registered", body = LicenseCheckout),
+        (status = 400, description = "Bad Request - license is not a floating license"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden - license is not owned by the user's company"),
+        (status = 404, description = "No active checkout - seat must be checked out again"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("license_id", description = "The ID of the license"),
+    )
+)]
+#[post("/licenses/{license_id}/heartbeat")]
+async fn heartbeat(
+    shared_data: web::Data<SharedData>,
+    license_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let license = match get_floating_license(&shared_data, &license_id, &user).await {
+        Ok(license) => license,
+        Err(response) => return response,
+    };
+
+    match checkout::heartbeat(
+        pool,
+        &license.license_id(),
+        &user.user_id,
+        shared_data.checkout_timeout,
+    )
+    .await
+    {
+        Ok(checkout) => HttpResponse::Ok().json(checkout),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => HttpResponse::NotFound()
+                .json("No active checkout, the seat must be checked out again"),
+            _ => {
+                error!("{}", e);
+                HttpResponse::InternalServerError().json("Internal Server Error")
+            }
+        },
+    }
+}
+
+/// Release a checked out seat.
+#[utoipa::path(
+    context_path = "/api/priv",
+    delete,
+    tag = "Floating licenses",
+    responses(
+        (status = 204, description = "Seat released"),
+        (status = 400, description = "Bad Request - license is not a floating license"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden - license is not owned by the user's company"),
+        (status = 404, description = "User holds no seat on the license"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("license_id", description = "The ID of the license"),
+    )
+)]
+#[delete("/licenses/{license_id}/checkout")]
+async fn release_seat(
+    shared_data: web::Data<SharedData>,
+    license_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let license = match get_floating_license(&shared_data, &license_id, &user).await {
+        Ok(license) => license,
+        Err(response) => return response,
+    };
+
+    match checkout::release_seat(pool, &license.license_id(), &user.user_id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json("No seat checked out on this license"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Get all active checkouts of a floating license.
+///
+/// Available to admins, and to IT staff of the company owning the license.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Floating licenses",
+    responses(
+        (status = 200, description = "List of active checkouts", body = Vec<LicenseCheckout>),
+        (status = 400, description = "Bad Request - license is not a floating license"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "License not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("license_id", description = "The ID of the license"),
+    )
+)]
+#[get("/licenses/{license_id}/checkouts")]
+async fn active_checkouts(
+    shared_data: web::Data<SharedData>,
+    license_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let license_id = match license_id.parse::<i32>() {
+        Ok(license_id) => license_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    let license = match license::get_license_by_id(pool, &license_id).await {
+        Ok(license) => license,
+        Err(e) => {
+            return match e {
+                sqlx::Error::RowNotFound => HttpResponse::NotFound().json("License not found"),
+                _ => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().json("Internal Server Error")
+                }
+            }
+        }
+    };
+    match user.role {
+        Role::Admin => (),
+        Role::CompanyItHead | Role::CompanyIt => {
+            if user.company_id != license.company_id {
+                return HttpResponse::Forbidden().finish();
+            }
+        }
+        Role::Default => return HttpResponse::Forbidden().finish(),
+    }
+    if license.license_type() != LicenseType::Floating {
+        return HttpResponse::BadRequest().json("License is not a floating license");
+    }
+
+    match checkout::get_active_checkouts(pool, &license_id, shared_data.checkout_timeout).await {
+        Ok(checkouts) => HttpResponse::Ok().json(checkouts),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
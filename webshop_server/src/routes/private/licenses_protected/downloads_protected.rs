@@ -0,0 +1,156 @@
+//! Endpoints for downloading the releases covered by a license.
+//!
+//! A user holding a seat on a license gets a short-lived signed link to download the file
+//! of a release, which is served by `GET /api/downloads/{release_id}`.
+
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    data_access::{
+        license::{self, checkout, LicenseType},
+        product::release,
+    },
+    storage::DownloadGrant,
+    utils::auth,
+    SharedData,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(download_link);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        download_link,
+    ),
+    components(
+        schemas(DownloadLink)
+    ),
+    tags(
+        (name = "Downloads", description = "API endpoints for downloading licensed releases")
+    ),
+)]
+pub struct DownloadsOpenApi;
+
+/// A link to download the file of a release.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DownloadLink {
+    /// Path and query of the link, relative to the server.
+    #[schema(
+        example = "/api/downloads/3?license_id=3&user_id=4&expires=1700000000&signature=9f86d08188"
+    )]
+    pub url: String,
+    /// When the link stops working.
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Get a link to download the file of a release covered by a license.
+///
+/// The user must hold a seat on the license: be assigned one on a named license,
+/// or have a seat checked out on a floating license. The license must be active,
+/// and the release must be released before the license ends.
+/// The link works for a few minutes.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Downloads",
+    responses(
+        (status = 200, description = "Link to download the file", body = DownloadLink),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden - no seat on the license, license not active or release not covered"),
+        (status = 404, description = "License, release or file not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("license_id", description = "The ID of the license"),
+        ("release_id", description = "The ID of the release"),
+    ),
+)]
+#[post("/licenses/{license_id}/releases/{release_id}/download")]
+async fn download_link(
+    shared_data: web::Data<SharedData>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let (license_id, release_id) = match (path.0.parse::<i32>(), path.1.parse::<i32>()) {
+        (Ok(license_id), Ok(release_id)) => (license_id, release_id),
+        _ => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    let license = match license::get_license_by_id(pool, &license_id).await {
+        Ok(license) => license,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json("License not found"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    };
+    if license.company_id != user.company_id {
+        return HttpResponse::Forbidden().json("License is not owned by your company");
+    }
+
+    let has_seat = match license.license_type() {
+        LicenseType::Named => license::user_has_seat(pool, &license_id, &user.user_id).await,
+        LicenseType::Floating => {
+            checkout::holds_checkout(
+                pool,
+                &license_id,
+                &user.user_id,
+                shared_data.checkout_timeout,
+            )
+            .await
+        }
+    };
+    match has_seat {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::Forbidden().json("You hold no seat on the license"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    }
+    if !license.is_active() {
+        return HttpResponse::Forbidden().json("License is not active");
+    }
+
+    let release = match release::get_release(pool, &release_id).await {
+        Ok(release) if release.product_id == license.product_id() => release,
+        Ok(_) | Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json("Release not found")
+        }
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    };
+    if release.release_date > license.end_date() {
+        return HttpResponse::Forbidden().json("Release is not covered by the license");
+    }
+    if release.storage_key.is_none() {
+        return HttpResponse::NotFound().json("Release has no file to download");
+    }
+
+    let grant = DownloadGrant::new(release_id, license_id, user.user_id);
+    HttpResponse::Ok().json(DownloadLink {
+        url: grant.link(&shared_data.download_secret),
+        expires_at: grant.expires_at,
+    })
+}
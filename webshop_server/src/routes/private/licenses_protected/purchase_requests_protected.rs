@@ -0,0 +1,602 @@
+//! Endpoints for purchase requests.
+//!
+//! Only IT heads can buy licenses, so any other company user asks them with a purchase request.
+//! The IT heads of the company approve the request, which buys the license, or reject it.
+//! The IT heads are emailed when a request is made, and the user who asked when it is decided.
+
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use lettre::SmtpTransport;
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use super::{discounted, order_error_response, pricing_error_response, start_payment};
+use crate::{
+    data_access::{
+        license::{License, LicenseType, PartialLicense},
+        order::{self, Order},
+        product,
+        purchase_request::{self, PartialPurchaseRequest, PurchaseRequest, PurchaseRequestStatus},
+        user::{self, Role, User},
+    },
+    payment::PaymentIntent,
+    pricing::{self, PriceQuote},
+    utils::{
+        auth,
+        email::{self, Email, EmailType, PurchaseRequestEmail, PurchaseRequestUpdate},
+    },
+    SharedData,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(create_purchase_request);
+    cfg.service(get_purchase_requests);
+    cfg.service(get_purchase_request);
+    cfg.service(approve_purchase_request);
+    cfg.service(reject_purchase_request);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        create_purchase_request,
+        get_purchase_requests,
+        get_purchase_request,
+        approve_purchase_request,
+        reject_purchase_request,
+    ),
+    components(
+        schemas(
+            PurchaseRequest,
+            PurchaseRequestStatus,
+            NewPurchaseRequest,
+            ApprovalRequest,
+            RejectionRequest,
+            ApprovedPurchase
+        )
+    ),
+    tags(
+        (name = "Purchase requests", description = "API endpoints for asking the IT heads of your company to buy a license")
+    ),
+)]
+pub struct PurchaseRequestsOpenApi;
+
+/// License to ask the IT heads to buy.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct NewPurchaseRequest {
+    product_id: String,
+    /// Number of seats.
+    seats: i32,
+    /// Length of the license in years, defaults to 1.
+    term_years: Option<i32>,
+    /// Defaults to named.
+    license_type: Option<LicenseType>,
+    /// Why the license is needed.
+    comment: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+struct PurchaseRequestsQuery {
+    /// Only return requests with this status
+    status: Option<PurchaseRequestStatus>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct ApprovalRequest {
+    /// Discount code to apply to the price.
+    discount_code: Option<String>,
+    /// Note for the user who asked.
+    note: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct RejectionRequest {
+    /// Why the request is rejected, for the user who asked.
+    note: Option<String>,
+}
+
+/// The approved request, the bought license, the price it was bought at and the order paying for it.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct ApprovedPurchase {
+    request: PurchaseRequest,
+    license: License,
+    price: PriceQuote,
+    order: Order,
+    /// Payment to pay the order with, none if the order is already paid.
+    payment: Option<PaymentIntent>,
+}
+
+/// Checks the user is signed in, returning the response to send if not.
+async fn validate(shared_data: &SharedData, req: HttpRequest) -> Result<User, HttpResponse> {
+    match auth::validate_user(req, &shared_data.db_pool).await {
+        Ok(user) => Ok(user),
+        Err(auth::AuthError::Unauthorized) => Err(HttpResponse::Unauthorized().finish()),
+        Err(auth::AuthError::SqlxError(e)) => {
+            error!("{}", e);
+            Err(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+/// Fetches a purchase request, returning the response to send if it cannot be found.
+async fn fetch_purchase_request(
+    shared_data: &SharedData,
+    purchase_request_id: &str,
+) -> Result<PurchaseRequest, HttpResponse> {
+    let purchase_request_id = match purchase_request_id.parse::<i32>() {
+        Ok(purchase_request_id) => purchase_request_id,
+        Err(_) => return Err(HttpResponse::BadRequest().json("Bad Request")),
+    };
+    match purchase_request::get_purchase_request_by_id(&shared_data.db_pool, &purchase_request_id)
+        .await
+    {
+        Ok(request) => Ok(request),
+        Err(sqlx::Error::RowNotFound) => {
+            Err(HttpResponse::NotFound().json("Purchase request not found"))
+        }
+        Err(e) => {
+            error!("{}", e);
+            Err(HttpResponse::InternalServerError().json("Internal Server Error"))
+        }
+    }
+}
+
+/// Checks the user is an IT head of the company of the request,
+/// returning the response to send if not.
+fn validate_decider(user: &User, request: &PurchaseRequest) -> Result<(), HttpResponse> {
+    match user.role {
+        Role::CompanyItHead if user.company_id == request.company_id => Ok(()),
+        Role::CompanyItHead => {
+            Err(HttpResponse::Forbidden().json("Purchase request was not made in your company"))
+        }
+        Role::Admin | Role::CompanyIt | Role::Default => Err(HttpResponse::Forbidden()
+            .json("Only the IT head of the company can decide on purchase requests")),
+    }
+}
+
+/// Emails everyone concerned by a purchase request, in the background.
+/// Failures are only logged, the request has been saved already.
+fn notify(shared_data: &SharedData, request: &PurchaseRequest, update: PurchaseRequestUpdate) {
+    let pool = shared_data.db_pool.clone();
+    let mailer = shared_data.mailer.clone();
+    let company_id = request.company_id;
+    let requested_by = request.requested_by;
+    let product_id = request.product_id.clone();
+    let seats = request.seats;
+    let term_years = request.term_years;
+    actix_web::rt::spawn(async move {
+        let recipients = match recipients(&pool, &update, company_id, requested_by).await {
+            Ok(recipients) => recipients,
+            Err(e) => {
+                error!("Could not get who to notify of a purchase request: {}", e);
+                return;
+            }
+        };
+        let product = match product::get_product_by_id(&pool, &product_id).await {
+            Ok(product) => product.display_name().to_string(),
+            Err(e) => {
+                error!("Could not get product {}: {}", product_id, e);
+                return;
+            }
+        };
+        let request_email = PurchaseRequestEmail {
+            product,
+            seats,
+            term_years,
+            update,
+        };
+        send_emails(&mailer, recipients, request_email).await;
+    });
+}
+
+/// Returns the email addresses to notify of a purchase request:
+/// the IT heads of the company for a new request, the user who asked otherwise.
+async fn recipients(
+    pool: &Pool<Postgres>,
+    update: &PurchaseRequestUpdate,
+    company_id: i32,
+    requested_by: Option<i32>,
+) -> Result<Vec<String>, sqlx::Error> {
+    match update {
+        PurchaseRequestUpdate::Submitted { .. } => {
+            Ok(user::get_users_by_company(pool, &company_id)
+                .await?
+                .into_iter()
+                .filter(|user| user.role == Role::CompanyItHead)
+                .map(|user| user.email)
+                .collect())
+        }
+        PurchaseRequestUpdate::Approved { .. } | PurchaseRequestUpdate::Rejected { .. } => {
+            match requested_by {
+                Some(user_id) => Ok(vec![user::get_user_by_id(pool, &user_id).await?.email]),
+                None => Ok(Vec::new()),
+            }
+        }
+    }
+}
+
+async fn send_emails(
+    mailer: &SmtpTransport,
+    recipients: Vec<String>,
+    request_email: PurchaseRequestEmail,
+) {
+    for recipient in recipients {
+        let mail = Email::new(
+            recipient.clone(),
+            EmailType::PurchaseRequest(request_email.clone()),
+            None,
+        );
+        if let Err(e) = email::send_email(mail, mailer).await {
+            error!(
+                "Could not email {} about a purchase request: {}",
+                recipient, e
+            );
+        }
+    }
+}
+
+/// Ask the IT heads of your company to buy a license.
+///
+/// Any company user can ask, the product, seats and term must be ones that can be bought.
+/// The IT heads of the company are emailed about the request.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Purchase requests",
+    responses(
+        (status = 201, description = "Purchase request made", body = PurchaseRequest),
+        (status = 400, description = "Bad Request - invalid number of seats or term"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Product not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    request_body(
+        description = "The license to ask for",
+        content = NewPurchaseRequest,
+    ),
+)]
+#[post("/purchase_requests")]
+async fn create_purchase_request(
+    shared_data: web::Data<SharedData>,
+    new_request: web::Json<NewPurchaseRequest>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if user.role == Role::Admin {
+        return HttpResponse::Forbidden().json("Only company users can make purchase requests");
+    }
+    let pool = &shared_data.db_pool;
+    let term_years = new_request.term_years.unwrap_or(1);
+    // the request is priced again when approved, this only checks it can be bought
+    if let Err(e) = pricing::quote_for_company(
+        pool,
+        &new_request.product_id,
+        new_request.seats,
+        term_years,
+        user.company_id,
+    )
+    .await
+    {
+        return pricing_error_response(e);
+    }
+    let comment = new_request
+        .comment
+        .as_deref()
+        .map(str::trim)
+        .filter(|comment| !comment.is_empty())
+        .map(str::to_string);
+    let partial = PartialPurchaseRequest {
+        company_id: user.company_id,
+        requested_by: user.user_id,
+        product_id: new_request.product_id.clone(),
+        seats: new_request.seats,
+        term_years,
+        license_type: new_request.license_type.unwrap_or_default(),
+        comment: comment.clone(),
+    };
+    match purchase_request::create_purchase_request(pool, &partial).await {
+        Ok(request) => {
+            notify(
+                &shared_data,
+                &request,
+                PurchaseRequestUpdate::Submitted {
+                    requested_by: user.email,
+                    comment,
+                },
+            );
+            HttpResponse::Created().json(request)
+        }
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Get purchase requests, newest first.
+///
+/// Admins get all requests, IT heads get the requests of their company,
+/// and other users get the requests they made.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Purchase requests",
+    responses(
+        (status = 200, description = "Purchase requests", body = Vec<PurchaseRequest>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(PurchaseRequestsQuery),
+)]
+#[get("/purchase_requests")]
+async fn get_purchase_requests(
+    shared_data: web::Data<SharedData>,
+    query: web::Query<PurchaseRequestsQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let pool = &shared_data.db_pool;
+    let requests = match user.role {
+        Role::Admin => purchase_request::get_purchase_requests(pool).await,
+        Role::CompanyItHead => {
+            purchase_request::get_purchase_requests_by_company(pool, &user.company_id).await
+        }
+        Role::CompanyIt | Role::Default => {
+            purchase_request::get_purchase_requests_by_user(pool, &user.user_id).await
+        }
+    };
+    match requests {
+        Ok(requests) => {
+            let requests: Vec<PurchaseRequest> = requests
+                .into_iter()
+                .filter(|request| query.status.is_none_or(|status| request.status == status))
+                .collect();
+            HttpResponse::Ok().json(requests)
+        }
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Get a purchase request.
+///
+/// Only the user who asked, the IT heads of the company and admins can see a request.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Purchase requests",
+    responses(
+        (status = 200, description = "Purchase request", body = PurchaseRequest),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Purchase request not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("purchase_request_id", description = "The id of the purchase request"),
+    ),
+)]
+#[get("/purchase_requests/{purchase_request_id}")]
+async fn get_purchase_request(
+    shared_data: web::Data<SharedData>,
+    purchase_request_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let request = match fetch_purchase_request(&shared_data, &purchase_request_id).await {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+    let allowed = match user.role {
+        Role::Admin => true,
+        Role::CompanyItHead => request.company_id == user.company_id,
+        Role::CompanyIt | Role::Default => request.requested_by == Some(user.user_id),
+    };
+    if !allowed {
+        return HttpResponse::Forbidden().json("You cannot see this purchase request");
+    }
+    HttpResponse::Ok().json(request)
+}
+
+/// Approve a purchase request of your company, buying the license.
+///
+/// The license is bought for the company at the current price, less the discount code given, if any,
+/// like buying it directly. It becomes valid once the order is paid with the returned payment.
+/// The user who asked is emailed.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Purchase requests",
+    responses(
+        (status = 201, description = "Request approved and license bought", body = ApprovedPurchase),
+        (status = 400, description = "Bad Request - invalid number of seats or term, or discount code not valid for the purchase"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Purchase request, product or discount code not found"),
+        (status = 409, description = "Conflict - request already decided, discount code used up, or product not sold in the billing currency"),
+        (status = 500, description = "Internal Server Error"),
+        (status = 502, description = "Payment provider error"),
+    ),
+    params(
+        ("purchase_request_id", description = "The id of the purchase request"),
+    ),
+    request_body(
+        description = "The discount code and note",
+        content = ApprovalRequest,
+    ),
+)]
+#[post("/purchase_requests/{purchase_request_id}/approve")]
+async fn approve_purchase_request(
+    shared_data: web::Data<SharedData>,
+    purchase_request_id: web::Path<String>,
+    approval: web::Json<ApprovalRequest>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let request = match fetch_purchase_request(&shared_data, &purchase_request_id).await {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+    if let Err(response) = validate_decider(&user, &request) {
+        return response;
+    }
+    if request.status != PurchaseRequestStatus::Pending {
+        return HttpResponse::Conflict().json("The purchase request has already been decided");
+    }
+
+    let pool = &shared_data.db_pool;
+    let price = match pricing::quote_for_company(
+        pool,
+        &request.product_id,
+        request.seats,
+        request.term_years,
+        request.company_id,
+    )
+    .await
+    {
+        Ok(price) => price,
+        Err(e) => return pricing_error_response(e),
+    };
+    let price = match discounted(pool, price, &approval.discount_code, request.company_id).await {
+        Ok(price) => price,
+        Err(e) => return pricing_error_response(e),
+    };
+    let new_license = match PartialLicense::purchase(
+        price.seats,
+        price.term_years,
+        request.company_id,
+        &price.product_id,
+        request.license_type,
+    ) {
+        Some(new_license) => new_license,
+        None => return HttpResponse::BadRequest().json("Invalid term"),
+    };
+    let note = approval
+        .note
+        .as_deref()
+        .map(str::trim)
+        .filter(|note| !note.is_empty());
+
+    match order::approve_purchase_request(
+        pool,
+        user.user_id,
+        &request.purchase_request_id,
+        note,
+        &new_license,
+        &price,
+    )
+    .await
+    {
+        Ok((request, license, order)) => {
+            notify(
+                &shared_data,
+                &request,
+                PurchaseRequestUpdate::Approved {
+                    note: request.decision_note.clone(),
+                },
+            );
+            match start_payment(&shared_data, &order).await {
+                Ok(payment) => HttpResponse::Created().json(ApprovedPurchase {
+                    request,
+                    license,
+                    price,
+                    order,
+                    payment,
+                }),
+                Err(response) => response,
+            }
+        }
+        Err(e) => order_error_response(e),
+    }
+}
+
+/// Reject a purchase request of your company.
+///
+/// The user who asked is emailed, with the note given.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Purchase requests",
+    responses(
+        (status = 200, description = "Request rejected", body = PurchaseRequest),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Purchase request not found"),
+        (status = 409, description = "Conflict - request already decided"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("purchase_request_id", description = "The id of the purchase request"),
+    ),
+    request_body(
+        description = "Why the request is rejected",
+        content = RejectionRequest,
+    ),
+)]
+#[post("/purchase_requests/{purchase_request_id}/reject")]
+async fn reject_purchase_request(
+    shared_data: web::Data<SharedData>,
+    purchase_request_id: web::Path<String>,
+    rejection: web::Json<RejectionRequest>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let request = match fetch_purchase_request(&shared_data, &purchase_request_id).await {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+    if let Err(response) = validate_decider(&user, &request) {
+        return response;
+    }
+    let note = rejection
+        .note
+        .as_deref()
+        .map(str::trim)
+        .filter(|note| !note.is_empty());
+
+    match purchase_request::reject(
+        &shared_data.db_pool,
+        &request.purchase_request_id,
+        &user.user_id,
+        note,
+    )
+    .await
+    {
+        Ok(Some(request)) => {
+            notify(
+                &shared_data,
+                &request,
+                PurchaseRequestUpdate::Rejected {
+                    note: request.decision_note.clone(),
+                },
+            );
+            HttpResponse::Ok().json(request)
+        }
+        Ok(None) => HttpResponse::Conflict().json("The purchase request has already been decided"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
@@ -0,0 +1,618 @@
+//! Endpoints for quotes.
+//!
+//! Admins make quotes for large customers, with the licenses offered at prices they choose,
+//! and send them to the IT heads of the company by email as PDF.
+//! An IT head of the company accepts a sent quote while it is valid,
+//! which buys all of its licenses in one order at the prices of the quote.
+
+use actix_web::{
+    get,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    post, web, HttpRequest, HttpResponse, Responder,
+};
+use chrono::{DateTime, Duration, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use super::{order_error_response, pricing_error_response, start_payment};
+use crate::{
+    data_access::{
+        company,
+        license::{License, LicenseType},
+        order::{self, Order},
+        quote::{self, PartialQuote, PartialQuoteLine, Quote, QuoteLine, QuoteStatus},
+        user::{self, Role, User},
+    },
+    invoice,
+    money::Money,
+    payment::PaymentIntent,
+    pricing,
+    utils::{
+        auth,
+        email::{self, Email, EmailType, QuoteEmail},
+    },
+    SharedData,
+};
+
+/// How long a quote is valid if no date is given.
+const DEFAULT_VALIDITY_DAYS: i64 = 30;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(create_quote);
+    cfg.service(get_quotes);
+    cfg.service(get_quote);
+    cfg.service(quote_pdf);
+    cfg.service(send_quote);
+    cfg.service(withdraw_quote);
+    cfg.service(accept_quote);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        create_quote,
+        get_quotes,
+        get_quote,
+        quote_pdf,
+        send_quote,
+        withdraw_quote,
+        accept_quote,
+    ),
+    components(
+        schemas(Quote, QuoteLine, QuoteStatus, NewQuote, NewQuoteLine, AcceptedQuote)
+    ),
+    tags(
+        (name = "Quotes", description = "API endpoints for quotes made to companies before they buy")
+    ),
+)]
+pub struct QuotesOpenApi;
+
+/// License to offer in a quote.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct NewQuoteLine {
+    product_id: String,
+    /// Number of seats.
+    seats: i32,
+    /// Length of the license in years, defaults to 1.
+    term_years: Option<i32>,
+    /// Defaults to named.
+    license_type: Option<LicenseType>,
+    /// Price per user per year offered, in the billing currency of the company,
+    /// defaults to the price the product is sold at.
+    #[schema(value_type = Option<String>, example = "150.00")]
+    unit_price: Option<Money>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct NewQuote {
+    company_id: i32,
+    /// Last moment the quote can be accepted, defaults to 30 days from now.
+    valid_until: Option<DateTime<Utc>>,
+    /// Terms of the offer, for the company.
+    note: Option<String>,
+    lines: Vec<NewQuoteLine>,
+}
+
+#[derive(Deserialize, IntoParams)]
+struct QuotesQuery {
+    /// Only return quotes with this status
+    status: Option<QuoteStatus>,
+}
+
+/// The accepted quote, the licenses bought and the order paying for them.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct AcceptedQuote {
+    quote: Quote,
+    licenses: Vec<License>,
+    order: Order,
+    /// Payment to pay the order with, none if the order is already paid.
+    payment: Option<PaymentIntent>,
+}
+
+/// Checks the user is signed in, returning the response to send if not.
+async fn validate(shared_data: &SharedData, req: HttpRequest) -> Result<User, HttpResponse> {
+    match auth::validate_user(req, &shared_data.db_pool).await {
+        Ok(user) => Ok(user),
+        Err(auth::AuthError::Unauthorized) => Err(HttpResponse::Unauthorized().finish()),
+        Err(auth::AuthError::SqlxError(e)) => {
+            error!("{}", e);
+            Err(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+/// Fetches a quote the user may see, returning the response to send if it cannot be found
+/// or the user may not see it.
+/// Admins see all quotes, IT heads the quotes sent to their company.
+async fn fetch_quote(
+    shared_data: &SharedData,
+    quote_id: &str,
+    user: &User,
+) -> Result<Quote, HttpResponse> {
+    let quote_id = match quote_id.parse::<i32>() {
+        Ok(quote_id) => quote_id,
+        Err(_) => return Err(HttpResponse::BadRequest().json("Bad Request")),
+    };
+    let quote = match quote::get_quote_by_id(&shared_data.db_pool, &quote_id).await {
+        Ok(quote) => quote,
+        Err(sqlx::Error::RowNotFound) => {
+            return Err(HttpResponse::NotFound().json("Quote not found"))
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(HttpResponse::InternalServerError().json("Internal Server Error"));
+        }
+    };
+    match user.role {
+        Role::Admin => Ok(quote),
+        Role::CompanyItHead if user.company_id == quote.company_id => {
+            if quote.status == QuoteStatus::Draft {
+                Err(HttpResponse::NotFound().json("Quote not found"))
+            } else {
+                Ok(quote)
+            }
+        }
+        Role::CompanyItHead | Role::CompanyIt | Role::Default => {
+            Err(HttpResponse::Forbidden().finish())
+        }
+    }
+}
+
+/// Emails a quote as PDF to the IT heads of its company, in the background.
+/// Failures are only logged, the quote has been marked sent already.
+fn send_to_company(shared_data: &SharedData, quote: Quote) {
+    let pool = shared_data.db_pool.clone();
+    let mailer = shared_data.mailer.clone();
+    let seller_vat_number = shared_data.seller_vat_number.clone();
+    actix_web::rt::spawn(async move {
+        let pdf = match invoice::create_quote_document(&pool, &quote, &seller_vat_number).await {
+            Ok(pdf) => pdf,
+            Err(e) => {
+                error!("Could not create quote {}: {}", quote.quote_number, e);
+                return;
+            }
+        };
+        let it_heads = match user::get_users_by_company(&pool, &quote.company_id).await {
+            Ok(users) => users
+                .into_iter()
+                .filter(|user| user.role == Role::CompanyItHead),
+            Err(e) => {
+                error!("Could not get users of company {}: {}", quote.company_id, e);
+                return;
+            }
+        };
+        let quote_email = QuoteEmail {
+            quote_number: quote.quote_number.clone(),
+            total: format!("{} {}", quote.total, quote.currency),
+            valid_until: quote.valid_until,
+            quote_file_name: invoice::quote_file_name(&quote),
+            quote: pdf,
+        };
+        for it_head in it_heads {
+            let mail = Email::new(
+                it_head.email.clone(),
+                EmailType::Quote(quote_email.clone()),
+                None,
+            );
+            if let Err(e) = email::send_email(mail, &mailer).await {
+                error!(
+                    "Could not email quote {} to {}: {}",
+                    quote.quote_number, it_head.email, e
+                );
+            }
+        }
+    });
+}
+
+/// Make a quote for a company.
+///
+/// Admin only. Each line is a license to offer, at the price given or the price the product is sold at.
+/// The quote is in the billing currency of the company, which all products must be sold in.
+/// The quote is a draft until it is sent.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Quotes",
+    responses(
+        (status = 201, description = "Quote made", body = Quote),
+        (status = 400, description = "Bad Request - no lines, invalid seats, term, price or date"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Company or product not found"),
+        (status = 409, description = "Conflict - a product not sold in the billing currency of the company"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    request_body(
+        description = "The quote to make",
+        content = NewQuote,
+    ),
+)]
+#[post("/quotes")]
+async fn create_quote(
+    shared_data: web::Data<SharedData>,
+    new_quote: web::Json<NewQuote>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if user.role != Role::Admin {
+        return HttpResponse::Forbidden().finish();
+    }
+    let new_quote = new_quote.into_inner();
+    if new_quote.lines.is_empty() {
+        return HttpResponse::BadRequest().json("A quote must offer at least one license");
+    }
+    let valid_until = new_quote
+        .valid_until
+        .unwrap_or_else(|| Utc::now() + Duration::days(DEFAULT_VALIDITY_DAYS));
+    if valid_until <= Utc::now() {
+        return HttpResponse::BadRequest().json("Valid until must be in the future");
+    }
+
+    let pool = &shared_data.db_pool;
+    let currency = match company::get_billing_currency(pool, &new_quote.company_id).await {
+        Ok(currency) => currency,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json("Company not found"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    };
+    let mut lines = Vec::with_capacity(new_quote.lines.len());
+    for line in new_quote.lines {
+        let term_years = line.term_years.unwrap_or(1);
+        let price = match pricing::quote(
+            pool,
+            &line.product_id,
+            line.seats,
+            term_years,
+            Some(currency),
+        )
+        .await
+        {
+            Ok(price) => price,
+            Err(e) => return pricing_error_response(e),
+        };
+        let unit_price = line.unit_price.unwrap_or(price.unit_price);
+        if unit_price.is_negative() {
+            return HttpResponse::BadRequest().json("Unit price must not be negative");
+        }
+        if unit_price > pricing::MAX_PRICE {
+            return HttpResponse::BadRequest().json(format!(
+                "Unit price must not be more than {}",
+                pricing::MAX_PRICE
+            ));
+        }
+        lines.push(PartialQuoteLine {
+            product_id: price.product_id,
+            seats: price.seats,
+            term_years,
+            license_type: line.license_type.unwrap_or_default(),
+            unit_price,
+            list_price: price.unit_price,
+        });
+    }
+    let partial = PartialQuote {
+        company_id: new_quote.company_id,
+        currency,
+        valid_until,
+        note: new_quote
+            .note
+            .as_deref()
+            .map(str::trim)
+            .filter(|note| !note.is_empty())
+            .map(str::to_string),
+        created_by: user.user_id,
+        lines,
+    };
+    match quote::create_quote(pool, &partial).await {
+        Ok(quote) => HttpResponse::Created().json(quote),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Get quotes, newest first.
+///
+/// Admins get all quotes, IT heads get the quotes sent to their company.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Quotes",
+    responses(
+        (status = 200, description = "Quotes", body = Vec<Quote>),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(QuotesQuery),
+)]
+#[get("/quotes")]
+async fn get_quotes(
+    shared_data: web::Data<SharedData>,
+    query: web::Query<QuotesQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let pool = &shared_data.db_pool;
+    let quotes = match user.role {
+        Role::Admin => quote::get_quotes(pool).await,
+        Role::CompanyItHead => quote::get_quotes_by_company(pool, &user.company_id)
+            .await
+            .map(|quotes| {
+                quotes
+                    .into_iter()
+                    .filter(|quote| quote.status != QuoteStatus::Draft)
+                    .collect()
+            }),
+        Role::CompanyIt | Role::Default => return HttpResponse::Forbidden().finish(),
+    };
+    match quotes {
+        Ok(mut quotes) => {
+            if let Some(status) = query.status {
+                quotes.retain(|quote| quote.status == status);
+            }
+            HttpResponse::Ok().json(quotes)
+        }
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Get a quote.
+///
+/// Available to admins, and to the IT heads of the company once the quote is sent.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Quotes",
+    responses(
+        (status = 200, description = "The quote", body = Quote),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Quote not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("quote_id", description = "The id of the quote"),
+    ),
+)]
+#[get("/quotes/{quote_id}")]
+async fn get_quote(
+    shared_data: web::Data<SharedData>,
+    quote_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    match fetch_quote(&shared_data, &quote_id, &user).await {
+        Ok(quote) => HttpResponse::Ok().json(quote),
+        Err(response) => response,
+    }
+}
+
+/// Download a quote as PDF.
+///
+/// Available to admins, and to the IT heads of the company once the quote is sent.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Quotes",
+    responses(
+        (status = 200, description = "The quote", content_type = "application/pdf", body = Vec<u8>),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Quote not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("quote_id", description = "The id of the quote"),
+    ),
+)]
+#[get("/quotes/{quote_id}/pdf")]
+async fn quote_pdf(
+    shared_data: web::Data<SharedData>,
+    quote_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let quote = match fetch_quote(&shared_data, &quote_id, &user).await {
+        Ok(quote) => quote,
+        Err(response) => return response,
+    };
+    match invoice::create_quote_document(
+        &shared_data.db_pool,
+        &quote,
+        &shared_data.seller_vat_number,
+    )
+    .await
+    {
+        Ok(pdf) => HttpResponse::Ok()
+            .content_type("application/pdf")
+            .insert_header(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(invoice::quote_file_name(&quote))],
+            })
+            .body(pdf),
+        Err(e) => {
+            error!("Could not create quote {}: {}", quote.quote_number, e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Send a quote to the company.
+///
+/// Admin only. The quote is emailed as PDF to the IT heads of the company,
+/// who can accept it from then on. A sent quote can be sent again while it is valid.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Quotes",
+    responses(
+        (status = 200, description = "Quote sent", body = Quote),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Quote not found"),
+        (status = 409, description = "Conflict - quote accepted, withdrawn or no longer valid"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("quote_id", description = "The id of the quote"),
+    ),
+)]
+#[post("/quotes/{quote_id}/send")]
+async fn send_quote(
+    shared_data: web::Data<SharedData>,
+    quote_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if user.role != Role::Admin {
+        return HttpResponse::Forbidden().finish();
+    }
+    let quote = match fetch_quote(&shared_data, &quote_id, &user).await {
+        Ok(quote) => quote,
+        Err(response) => return response,
+    };
+    match quote::set_sent(&shared_data.db_pool, &quote.quote_id).await {
+        Ok(Some(quote)) => {
+            send_to_company(&shared_data, quote.clone());
+            HttpResponse::Ok().json(quote)
+        }
+        Ok(None) => HttpResponse::Conflict()
+            .json("Only draft and sent quotes that are still valid can be sent"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Withdraw a quote.
+///
+/// Admin only. A withdrawn quote can no longer be accepted.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Quotes",
+    responses(
+        (status = 200, description = "Quote withdrawn", body = Quote),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Quote not found"),
+        (status = 409, description = "Conflict - quote accepted or withdrawn already"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("quote_id", description = "The id of the quote"),
+    ),
+)]
+#[post("/quotes/{quote_id}/withdraw")]
+async fn withdraw_quote(
+    shared_data: web::Data<SharedData>,
+    quote_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if user.role != Role::Admin {
+        return HttpResponse::Forbidden().finish();
+    }
+    let quote = match fetch_quote(&shared_data, &quote_id, &user).await {
+        Ok(quote) => quote,
+        Err(response) => return response,
+    };
+    match quote::withdraw(&shared_data.db_pool, &quote.quote_id).await {
+        Ok(Some(quote)) => HttpResponse::Ok().json(quote),
+        Ok(None) => {
+            HttpResponse::Conflict().json("The quote has been accepted or withdrawn already")
+        }
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Accept a quote sent to your company.
+///
+/// IT heads only, while the quote is valid. Creates a license for each line and one order
+/// paying for all of them at the prices of the quote, with tax by the billing address of the company.
+/// The licenses become valid once the order is paid.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Quotes",
+    responses(
+        (status = 201, description = "Quote accepted", body = AcceptedQuote),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Quote not found"),
+        (status = 409, description = "Conflict - quote accepted, withdrawn or no longer valid"),
+        (status = 500, description = "Internal Server Error"),
+        (status = 502, description = "Payment provider error"),
+    ),
+    params(
+        ("quote_id", description = "The id of the quote"),
+    ),
+)]
+#[post("/quotes/{quote_id}/accept")]
+async fn accept_quote(
+    shared_data: web::Data<SharedData>,
+    quote_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if user.role != Role::CompanyItHead {
+        return HttpResponse::Forbidden().json("Only the IT head of the company can accept quotes");
+    }
+    let quote = match fetch_quote(&shared_data, &quote_id, &user).await {
+        Ok(quote) => quote,
+        Err(response) => return response,
+    };
+    if !quote.is_acceptable() {
+        return HttpResponse::Conflict().json(order::OrderError::QuoteNotAcceptable.to_string());
+    }
+
+    match order::accept_quote(&shared_data.db_pool, user.user_id, &quote.quote_id).await {
+        Ok((quote, licenses, order)) => match start_payment(&shared_data, &order).await {
+            Ok(payment) => HttpResponse::Created().json(AcceptedQuote {
+                quote,
+                licenses,
+                order,
+                payment,
+            }),
+            Err(response) => response,
+        },
+        Err(e) => order_error_response(e),
+    }
+}
@@ -0,0 +1,496 @@
+//! Endpoints for subscriptions, licenses renewed automatically every billing period.
+//!
+//! Company IT staff subscribe a paid license of their company to a monthly or yearly plan,
+//! and can cancel it at the end of the period, or pay a renewal that could not be charged.
+
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use log::error;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+
+use super::{get_purchasable_license, order_error_response, pricing_error_response, start_payment};
+use crate::{
+    data_access::{
+        company,
+        error_handling::PostgresDBError,
+        license::{
+            self,
+            subscription::{self, BillingInterval, Subscription, SubscriptionStatus},
+        },
+        order::{self, Order},
+        user::{Role, User},
+    },
+    payment::PaymentIntent,
+    pricing::{self, PeriodQuote},
+    utils::auth,
+    SharedData,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_subscriptions);
+    cfg.service(create_subscription);
+    cfg.service(get_subscription);
+    cfg.service(subscription_quote);
+    cfg.service(cancel_subscription);
+    cfg.service(resume_subscription);
+    cfg.service(renew_subscription);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        get_subscriptions,
+        create_subscription,
+        get_subscription,
+        subscription_quote,
+        cancel_subscription,
+        resume_subscription,
+        renew_subscription,
+    ),
+    components(
+        schemas(
+            Subscription,
+            SubscriptionStatus,
+            BillingInterval,
+            SubscriptionRequest,
+            PeriodQuote,
+            SubscriptionOrder
+        )
+    ),
+    tags(
+        (name = "Subscriptions", description = "API endpoints for renewing licenses automatically")
+    ),
+)]
+pub struct SubscriptionsOpenApi;
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct SubscriptionRequest {
+    /// How often the license is renewed.
+    billing_interval: BillingInterval,
+}
+
+/// The subscription and the order renewing it, the renewal is applied once the order is paid.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct SubscriptionOrder {
+    subscription: Subscription,
+    order: Order,
+    /// Payment to pay the order with, none if the order is already paid.
+    payment: Option<PaymentIntent>,
+}
+
+/// Checks the user is signed in, returning the response to send if not.
+async fn validate(shared_data: &SharedData, req: HttpRequest) -> Result<User, HttpResponse> {
+    match auth::validate_user(req, &shared_data.db_pool).await {
+        Ok(user) => Ok(user),
+        Err(auth::AuthError::Unauthorized) => Err(HttpResponse::Unauthorized().finish()),
+        Err(auth::AuthError::SqlxError(e)) => {
+            error!("{}", e);
+            Err(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+/// Fetches a subscription the user may see, admins may see all of them and company IT staff
+/// the ones of their company. Returns the response to send otherwise.
+async fn fetch_subscription(
+    shared_data: &SharedData,
+    subscription_id: &str,
+    user: &User,
+) -> Result<Subscription, HttpResponse> {
+    let subscription_id = match subscription_id.parse::<i32>() {
+        Ok(subscription_id) => subscription_id,
+        Err(_) => return Err(HttpResponse::BadRequest().json("Bad Request")),
+    };
+    let subscription =
+        match subscription::get_subscription_by_id(&shared_data.db_pool, &subscription_id).await {
+            Ok(subscription) => subscription,
+            Err(sqlx::Error::RowNotFound) => {
+                return Err(HttpResponse::NotFound().json("Subscription not found"))
+            }
+            Err(e) => {
+                error!("{}", e);
+                return Err(HttpResponse::InternalServerError().json("Internal Server Error"));
+            }
+        };
+    match user.role {
+        Role::Admin => Ok(subscription),
+        Role::CompanyIt | Role::CompanyItHead if subscription.company_id == user.company_id => {
+            Ok(subscription)
+        }
+        Role::CompanyIt | Role::CompanyItHead => {
+            Err(HttpResponse::Forbidden().json("Subscription is not owned by your company"))
+        }
+        Role::Default => {
+            Err(HttpResponse::Forbidden().json("Only company IT staff can manage subscriptions"))
+        }
+    }
+}
+
+/// Fetches a subscription the user may change, only company IT staff of its company may.
+async fn fetch_own_subscription(
+    shared_data: &SharedData,
+    subscription_id: &str,
+    user: &User,
+) -> Result<Subscription, HttpResponse> {
+    if user.role == Role::Admin {
+        return Err(
+            HttpResponse::Forbidden().json("Only company IT staff can manage subscriptions")
+        );
+    }
+    fetch_subscription(shared_data, subscription_id, user).await
+}
+
+/// Computes the price of the next period of a subscription.
+async fn price_period(
+    shared_data: &SharedData,
+    subscription: &Subscription,
+) -> Result<PeriodQuote, HttpResponse> {
+    let pool = &shared_data.db_pool;
+    let license = match license::get_license_by_id(pool, &subscription.license_id).await {
+        Ok(license) => license,
+        Err(e) => {
+            error!("{}", e);
+            return Err(HttpResponse::InternalServerError().json("Internal Server Error"));
+        }
+    };
+    let currency = company::get_billing_currency(pool, &subscription.company_id)
+        .await
+        .map_err(|e| pricing_error_response(e.into()))?;
+    pricing::quote_period(
+        pool,
+        license.product_id(),
+        license.amount(),
+        subscription.billing_interval,
+        currency,
+    )
+    .await
+    .map_err(pricing_error_response)
+}
+
+/// Get subscriptions, newest first.
+///
+/// Admins get all subscriptions, company IT staff the ones of their company.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Subscriptions",
+    responses(
+        (status = 200, description = "Subscriptions", body = Vec<Subscription>),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+)]
+#[get("/subscriptions")]
+async fn get_subscriptions(shared_data: web::Data<SharedData>, req: HttpRequest) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let pool = &shared_data.db_pool;
+    let subscriptions = match user.role {
+        Role::Admin => subscription::get_subscriptions(pool).await,
+        Role::CompanyIt | Role::CompanyItHead => {
+            subscription::get_subscriptions_by_company(pool, &user.company_id).await
+        }
+        Role::Default => {
+            return HttpResponse::Forbidden().json("Only company IT staff can manage subscriptions")
+        }
+    };
+    match subscriptions {
+        Ok(subscriptions) => HttpResponse::Ok().json(subscriptions),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Subscribe a license of your company, renewing it automatically.
+///
+/// The license is renewed at its end date for the billing interval, and then at the end of
+/// every period, at the price of the pricing engine then. The renewal is charged a few days
+/// before the period ends, to the payment method saved when your company last paid a renewal
+/// itself, so pay the first renewal to have the later ones charged.
+/// If it is not paid by then, the license stays usable for a grace period
+/// during which the renewal can be paid, after which the license is deactivated.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Subscriptions",
+    responses(
+        (status = 201, description = "License subscribed", body = Subscription),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "License not found"),
+        (status = 409, description = "Conflict - license is invalid or a trial, already subscribed, or product not sold in the billing currency"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("license_id", description = "The id of the license"),
+    ),
+    request_body(
+        description = "The billing interval",
+        content = SubscriptionRequest,
+    ),
+)]
+#[post("/licenses/{license_id}/subscription")]
+async fn create_subscription(
+    shared_data: web::Data<SharedData>,
+    license_id: web::Path<String>,
+    request: web::Json<SubscriptionRequest>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let license = match get_purchasable_license(&shared_data, &license_id, &user).await {
+        Ok(license) => license,
+        Err(response) => return response,
+    };
+    if !license.valid() || license.trial() {
+        return HttpResponse::Conflict().json("Invalid and trial licenses cannot be subscribed");
+    }
+    match subscription::create_subscription(
+        &shared_data.db_pool,
+        &license.license_id(),
+        request.billing_interval,
+        &user.user_id,
+    )
+    .await
+    {
+        Ok(subscription) => HttpResponse::Created().json(subscription),
+        Err(sqlx::Error::Database(e)) => match PostgresDBError::from_str(e) {
+            PostgresDBError::UniqueViolation => {
+                HttpResponse::Conflict().json("License is already subscribed")
+            }
+            _ => HttpResponse::InternalServerError().json("Internal Server Error"),
+        },
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Get a subscription.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Subscriptions",
+    responses(
+        (status = 200, description = "Subscription", body = Subscription),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Subscription not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("subscription_id", description = "The id of the subscription"),
+    ),
+)]
+#[get("/subscriptions/{subscription_id}")]
+async fn get_subscription(
+    shared_data: web::Data<SharedData>,
+    subscription_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    match fetch_subscription(&shared_data, &subscription_id, &user).await {
+        Ok(subscription) => HttpResponse::Ok().json(subscription),
+        Err(response) => response,
+    }
+}
+
+/// Get the price of the next period of a subscription, at the current prices.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Subscriptions",
+    responses(
+        (status = 200, description = "Price computed by the server", body = PeriodQuote),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Subscription or product not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("subscription_id", description = "The id of the subscription"),
+    ),
+)]
+#[get("/subscriptions/{subscription_id}/quote")]
+async fn subscription_quote(
+    shared_data: web::Data<SharedData>,
+    subscription_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let subscription = match fetch_subscription(&shared_data, &subscription_id, &user).await {
+        Ok(subscription) => subscription,
+        Err(response) => return response,
+    };
+    match price_period(&shared_data, &subscription).await {
+        Ok(price) => HttpResponse::Ok().json(price),
+        Err(response) => response,
+    }
+}
+
+/// Cancel a subscription of your company.
+///
+/// An active subscription is not renewed anymore, and its license ends at the end of the
+/// period paid for. A past due subscription ends right away, its license ends at the end of the
+/// period paid for instead of the grace period.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Subscriptions",
+    responses(
+        (status = 200, description = "Subscription canceled", body = Subscription),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Subscription not found"),
+        (status = 409, description = "Conflict - subscription already canceled"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("subscription_id", description = "The id of the subscription"),
+    ),
+)]
+#[post("/subscriptions/{subscription_id}/cancel")]
+async fn cancel_subscription(
+    shared_data: web::Data<SharedData>,
+    subscription_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let subscription = match fetch_own_subscription(&shared_data, &subscription_id, &user).await {
+        Ok(subscription) => subscription,
+        Err(response) => return response,
+    };
+    match subscription::cancel(&shared_data.db_pool, &subscription.subscription_id).await {
+        Ok(Some(subscription)) => HttpResponse::Ok().json(subscription),
+        Ok(None) => HttpResponse::Conflict().json("Subscription is already canceled"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Keep renewing a subscription of your company that was canceled at the end of its period.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Subscriptions",
+    responses(
+        (status = 200, description = "Subscription resumed", body = Subscription),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Subscription not found"),
+        (status = 409, description = "Conflict - subscription is not canceled at period end"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("subscription_id", description = "The id of the subscription"),
+    ),
+)]
+#[post("/subscriptions/{subscription_id}/resume")]
+async fn resume_subscription(
+    shared_data: web::Data<SharedData>,
+    subscription_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let subscription = match fetch_own_subscription(&shared_data, &subscription_id, &user).await {
+        Ok(subscription) => subscription,
+        Err(response) => return response,
+    };
+    match subscription::resume(&shared_data.db_pool, &subscription.subscription_id).await {
+        Ok(Some(subscription)) => HttpResponse::Ok().json(subscription),
+        Ok(None) => HttpResponse::Conflict().json("Subscription is not canceled at period end"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Pay the renewal of a subscription of your company yourself.
+///
+/// Creates the order renewing the subscription for its next period at the current price,
+/// to pay with the returned payment. Used to pay a renewal that could not be charged,
+/// which also ends the grace period of a past due subscription once paid.
+/// The payment method you pay with is saved, later renewals are charged to it.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Subscriptions",
+    responses(
+        (status = 200, description = "Renewal ordered", body = SubscriptionOrder),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Subscription or product not found"),
+        (status = 409, description = "Conflict - subscription canceled, renewal already being paid, or license changed"),
+        (status = 500, description = "Internal Server Error"),
+        (status = 502, description = "Payment provider error"),
+    ),
+    params(
+        ("subscription_id", description = "The id of the subscription"),
+    ),
+)]
+#[post("/subscriptions/{subscription_id}/renew")]
+async fn renew_subscription(
+    shared_data: web::Data<SharedData>,
+    subscription_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match validate(&shared_data, req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let subscription = match fetch_own_subscription(&shared_data, &subscription_id, &user).await {
+        Ok(subscription) => subscription,
+        Err(response) => return response,
+    };
+    let price = match price_period(&shared_data, &subscription).await {
+        Ok(price) => price,
+        Err(response) => return response,
+    };
+    match order::renew_subscription(
+        &shared_data.db_pool,
+        &subscription.subscription_id,
+        Some(user.user_id),
+        &price,
+    )
+    .await
+    {
+        Ok((subscription, order)) => match start_payment(&shared_data, &order).await {
+            Ok(payment) => HttpResponse::Ok().json(SubscriptionOrder {
+                subscription,
+                order,
+                payment,
+            }),
+            Err(response) => response,
+        },
+        Err(e) => order_error_response(e),
+    }
+}
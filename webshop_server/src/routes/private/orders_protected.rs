@@ -0,0 +1,596 @@
+//! Endpoints for orders.
+//!
+//! Orders are created by license purchases, renewals and seat changes.
+//! IT heads can see the orders of their company, admins can see all orders.
+//! The invoice of an order can be downloaded as PDF.
+//! Admins can refund paid orders, each refund has a credit note that can be downloaded as PDF.
+
+use actix_web::{
+    get,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    post, web, HttpRequest, HttpResponse, Responder,
+};
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    data_access::{
+        order::{self, Order, OrderLine, OrderStatus, OrderType},
+        refund::{self, LicenseAction, PartialRefund, Refund},
+        user::Role,
+    },
+    invoice,
+    money::Money,
+    payment::PaymentError,
+    tax::TaxTreatment,
+    utils::auth,
+    SharedData,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(orders);
+    cfg.service(order_by_id);
+    cfg.service(order_invoice);
+    cfg.service(order_refunds);
+    cfg.service(refund_order);
+    cfg.service(credit_note);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        orders,
+        order_by_id,
+        order_invoice,
+        order_refunds,
+        refund_order,
+        credit_note,
+    ),
+    components(
+        schemas(Order, OrderLine, OrderType, OrderStatus, TaxTreatment, Refund, LicenseAction, RefundRequest)
+    ),
+    tags(
+        (name = "Orders", description = "API endpoints for orders")
+    ),
+)]
+pub struct OrdersOpenApi;
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct RefundRequest {
+    /// Amount to pay back, tax included, defaults to what is left of the order total.
+    #[schema(value_type = Option<String>, example = "1342.50")]
+    amount: Option<Money>,
+    reason: Option<String>,
+    license_action: LicenseAction,
+    /// End date to shorten the licenses to, required to shorten them.
+    license_end_date: Option<DateTime<Utc>>,
+}
+
+/// Get orders, newest first.
+///
+/// Admins get all orders, IT heads get the orders of their company.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Orders",
+    responses(
+        (status = 200, description = "List of orders", body = Vec<Order>),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+)]
+#[get("/orders")]
+async fn orders(shared_data: web::Data<SharedData>, req: HttpRequest) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+
+    let orders = match user.role {
+        Role::Admin => order::get_orders(pool).await,
+        Role::CompanyItHead => order::get_orders_by_company(pool, &user.company_id).await,
+        Role::CompanyIt | Role::Default => return HttpResponse::Forbidden().finish(),
+    };
+    match orders {
+        Ok(orders) => HttpResponse::Ok().json(orders),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Get an order.
+///
+/// Available to admins, and to the IT head of the company that placed the order.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Orders",
+    responses(
+        (status = 200, description = "The order", body = Order),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Order not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("order_id", description = "The ID of the order"),
+    ),
+)]
+#[get("/orders/{order_id}")]
+async fn order_by_id(
+    shared_data: web::Data<SharedData>,
+    order_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let order_id = match order_id.parse::<i32>() {
+        Ok(order_id) => order_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    if !matches!(user.role, Role::Admin | Role::CompanyItHead) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    match order::get_order_by_id(pool, &order_id).await {
+        Ok(order) => {
+            if user.role != Role::Admin && order.company_id != user.company_id {
+                return HttpResponse::Forbidden().finish();
+            }
+            HttpResponse::Ok().json(order)
+        }
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => HttpResponse::NotFound().json("Order not found"),
+            _ => {
+                error!("{}", e);
+                HttpResponse::InternalServerError().json("Internal Server Error")
+            }
+        },
+    }
+}
+
+/// Download the invoice of an order as PDF.
+///
+/// Available to admins, and to the IT head of the company that placed the order.
+/// Failed orders have no invoice.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Orders",
+    responses(
+        (status = 200, description = "The invoice", content_type = "application/pdf", body = Vec<u8>),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Order not found"),
+        (status = 409, description = "The payment of the order failed"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("order_id", description = "The ID of the order"),
+    ),
+)]
+#[get("/orders/{order_id}/invoice")]
+async fn order_invoice(
+    shared_data: web::Data<SharedData>,
+    order_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let order_id = match order_id.parse::<i32>() {
+        Ok(order_id) => order_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    if !matches!(user.role, Role::Admin | Role::CompanyItHead) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    let order = match order::get_order_by_id(pool, &order_id).await {
+        Ok(order) => order,
+        Err(e) => {
+            return match e {
+                sqlx::Error::RowNotFound => HttpResponse::NotFound().json("Order not found"),
+                _ => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().json("Internal Server Error")
+                }
+            }
+        }
+    };
+    if user.role != Role::Admin && order.company_id != user.company_id {
+        return HttpResponse::Forbidden().finish();
+    }
+    if order.status == OrderStatus::Failed {
+        return HttpResponse::Conflict().json("The payment of the order failed");
+    }
+    match invoice::create_invoice(pool, &order, &shared_data.seller_vat_number).await {
+        Ok(pdf) => HttpResponse::Ok()
+            .content_type("application/pdf")
+            .insert_header(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(invoice::file_name(&order))],
+            })
+            .body(pdf),
+        Err(e) => {
+            error!("Could not create invoice {}: {}", order.invoice_number, e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Get the refunds of an order, oldest first.
+///
+/// Available to admins, and to the IT head of the company that placed the order.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Orders",
+    responses(
+        (status = 200, description = "List of refunds", body = Vec<Refund>),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Order not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("order_id", description = "The ID of the order"),
+    ),
+)]
+#[get("/orders/{order_id}/refunds")]
+async fn order_refunds(
+    shared_data: web::Data<SharedData>,
+    order_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let order_id = match order_id.parse::<i32>() {
+        Ok(order_id) => order_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    if !matches!(user.role, Role::Admin | Role::CompanyItHead) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    let order = match order::get_order_by_id(pool, &order_id).await {
+        Ok(order) => order,
+        Err(e) => {
+            return match e {
+                sqlx::Error::RowNotFound => HttpResponse::NotFound().json("Order not found"),
+                _ => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().json("Internal Server Error")
+                }
+            }
+        }
+    };
+    if user.role != Role::Admin && order.company_id != user.company_id {
+        return HttpResponse::Forbidden().finish();
+    }
+    match refund::get_refunds_by_order(pool, &order_id).await {
+        Ok(refunds) => HttpResponse::Ok().json(refunds),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Refund a paid order, in full or in part.
+///
+/// Admin only. The amount is paid back through the payment provider the order was paid with,
+/// and a credit note is created for it. The tax of the order is credited in proportion.
+/// The licenses of the order can be kept, shortened to an end date, or revoked,
+/// which removes their users and cancels their subscriptions.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Orders",
+    request_body = RefundRequest,
+    responses(
+        (status = 201, description = "The refund", body = Refund),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Order not found"),
+        (status = 409, description = "The order is not paid, or not that much is left to refund"),
+        (status = 500, description = "Internal Server Error"),
+        (status = 502, description = "Payment provider error"),
+    ),
+    params(
+        ("order_id", description = "The ID of the order"),
+    ),
+)]
+#[post("/orders/{order_id}/refunds")]
+async fn refund_order(
+    shared_data: web::Data<SharedData>,
+    order_id: web::Path<String>,
+    body: web::Json<RefundRequest>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let order_id = match order_id.parse::<i32>() {
+        Ok(order_id) => order_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    if user.role != Role::Admin {
+        return HttpResponse::Forbidden().finish();
+    }
+    let body = body.into_inner();
+    let license_end_date = match (body.license_action, body.license_end_date) {
+        (LicenseAction::Shorten, None) => {
+            return HttpResponse::BadRequest().json("An end date is required to shorten licenses")
+        }
+        (LicenseAction::Shorten, end_date) => end_date,
+        _ => None,
+    };
+
+    let order = match order::get_order_by_id(pool, &order_id).await {
+        Ok(order) => order,
+        Err(e) => {
+            return match e {
+                sqlx::Error::RowNotFound => HttpResponse::NotFound().json("Order not found"),
+                _ => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().json("Internal Server Error")
+                }
+            }
+        }
+    };
+    if order.status != OrderStatus::Paid {
+        return HttpResponse::Conflict().json("Only paid orders can be refunded");
+    }
+    let payment = match refund::get_order_payment(pool, &order_id).await {
+        Ok(Some(payment)) => payment,
+        Ok(None) => return HttpResponse::Conflict().json("The order was not paid with a payment"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    };
+    // the order stays locked until the refund is recorded, so refunds made at the same time
+    // cannot both pay back what is left of it
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    };
+    let refunds = match refund::lock_order_refunds(&mut transaction, &order_id).await {
+        Ok(refunds) => refunds,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    };
+    let refunded: Money = refunds.iter().map(|refund| refund.amount).sum();
+    let refunded_tax: Money = refunds.iter().map(|refund| refund.tax).sum();
+    let remaining = order.total - refunded;
+    if remaining == Money::ZERO {
+        return HttpResponse::Conflict().json("The order has been refunded in full");
+    }
+    let amount = body.amount.unwrap_or(remaining);
+    if amount <= Money::ZERO {
+        return HttpResponse::BadRequest().json("Amount must be positive");
+    }
+    if amount > remaining {
+        return HttpResponse::Conflict().json(format!(
+            "Only {} {} is left to refund",
+            remaining, order.currency
+        ));
+    }
+    // the last refund credits what is left of the tax, so rounding never adds up to more
+    let tax = if amount == remaining {
+        order.tax - refunded_tax
+    } else {
+        order
+            .tax
+            .prorate(amount.minor_units(), order.total.minor_units())
+    };
+
+    let provider = &shared_data.payment_provider;
+    if payment.provider != provider.name() {
+        return HttpResponse::Conflict().json(format!(
+            "The order was paid through {}, which is not in use",
+            payment.provider
+        ));
+    }
+    // the same key until the refund is recorded, so a retried refund is only paid back once
+    let idempotency_key = format!("order_{}_refund_{}", order_id, refunds.len() + 1);
+    let provider_refund = match provider
+        .refund(&payment.intent_id, amount, &idempotency_key)
+        .await
+    {
+        Ok(provider_refund) => provider_refund,
+        Err(e) => {
+            return match e {
+                PaymentError::RefundTooLarge
+                | PaymentError::InvalidState
+                | PaymentError::UnknownIntent => HttpResponse::Conflict().json(e.to_string()),
+                _ => {
+                    error!("Could not refund order {}: {}", order.invoice_number, e);
+                    HttpResponse::BadGateway().json("Payment provider error")
+                }
+            }
+        }
+    };
+    let license_ids: Vec<i32> = order.lines.iter().map(|line| line.license_id).collect();
+    let new_refund = PartialRefund {
+        order_id,
+        provider: payment.provider,
+        provider_refund_id: provider_refund.refund_id,
+        amount: provider_refund.amount,
+        tax,
+        reason: body.reason,
+        license_action: body.license_action,
+        license_end_date,
+        created_by: Some(user.user_id),
+        credit_order_id: None,
+    };
+    let created = match refund::create_refund(&mut transaction, &new_refund, &license_ids).await {
+        Ok(refund) => transaction.commit().await.map(|_| refund),
+        Err(e) => Err(e),
+    };
+    match created {
+        Ok(refund) => HttpResponse::Created().json(refund),
+        Err(e) => {
+            // the money has been paid back, refunding again with the same amount records it
+            error!(
+                "Could not record refund {} of order {}: {}",
+                new_refund.provider_refund_id, order.invoice_number, e
+            );
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Download the credit note of a refund as PDF.
+///
+/// Available to admins, and to the IT head of the company that placed the refunded order.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Orders",
+    responses(
+        (status = 200, description = "The credit note", content_type = "application/pdf", body = Vec<u8>),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Refund not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("refund_id", description = "The ID of the refund"),
+    ),
+)]
+#[get("/refunds/{refund_id}/credit_note")]
+async fn credit_note(
+    shared_data: web::Data<SharedData>,
+    refund_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let refund_id = match refund_id.parse::<i32>() {
+        Ok(refund_id) => refund_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    if !matches!(user.role, Role::Admin | Role::CompanyItHead) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    let refund = match refund::get_refund_by_id(pool, &refund_id).await {
+        Ok(refund) => refund,
+        Err(e) => {
+            return match e {
+                sqlx::Error::RowNotFound => HttpResponse::NotFound().json("Refund not found"),
+                _ => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().json("Internal Server Error")
+                }
+            }
+        }
+    };
+    let order = match order::get_order_by_id(pool, &refund.order_id).await {
+        Ok(order) => order,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    };
+    if user.role != Role::Admin && order.company_id != user.company_id {
+        return HttpResponse::Forbidden().finish();
+    }
+    match invoice::create_credit_note(pool, &order, &refund, &shared_data.seller_vat_number).await {
+        Ok(pdf) => HttpResponse::Ok()
+            .content_type("application/pdf")
+            .insert_header(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(invoice::credit_note_file_name(
+                    &refund,
+                ))],
+            })
+            .body(pdf),
+        Err(e) => {
+            error!(
+                "Could not create credit note {}: {}",
+                refund.credit_note_number, e
+            );
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
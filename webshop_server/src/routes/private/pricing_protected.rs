@@ -0,0 +1,309 @@
+//! Endpoints for managing the price lists and volume pricing of products.
+
+use actix_web::{delete, put, web, HttpRequest, HttpResponse, Responder};
+use log::error;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    data_access::{
+        product::{
+            self,
+            price_list::{self, PriceList},
+            price_tier::{self, PriceTier},
+            Product,
+        },
+        user::Role,
+    },
+    money::{Currency, Money},
+    pricing::MAX_PRICE,
+    utils::auth,
+    SharedData,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(set_price_tiers);
+    cfg.service(set_price_list);
+    cfg.service(delete_price_list);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        set_price_tiers,
+        set_price_list,
+        delete_price_list,
+    ),
+    components(
+        schemas(PriceTier, PriceList, PriceListForm)
+    ),
+    tags(
+        (name = "Pricing", description = "API endpoints for managing the pricing of products")
+    ),
+)]
+pub struct PricingApiDoc;
+
+/// Checks the user is an admin, returning the response to send if not.
+async fn validate_admin(shared_data: &SharedData, req: HttpRequest) -> Result<(), HttpResponse> {
+    match auth::validate_user(req, &shared_data.db_pool).await {
+        Ok(user) if user.role == Role::Admin => Ok(()),
+        Ok(_) => Err(HttpResponse::Forbidden().finish()),
+        Err(auth::AuthError::Unauthorized) => Err(HttpResponse::Unauthorized().finish()),
+        Err(auth::AuthError::SqlxError(e)) => {
+            error!("{}", e);
+            Err(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+/// Fetches a product, returning the response to send if it cannot be found.
+async fn fetch_product(
+    shared_data: &SharedData,
+    product_id: &str,
+) -> Result<Product, HttpResponse> {
+    match product::get_product_by_id(&shared_data.db_pool, product_id).await {
+        Ok(product) => Ok(product),
+        Err(sqlx::Error::RowNotFound) => Err(HttpResponse::NotFound().json("Product not found")),
+        Err(e) => {
+            error!("{}", e);
+            Err(HttpResponse::InternalServerError().json("Internal Server Error"))
+        }
+    }
+}
+
+/// Sorts price tiers by number of seats, and checks each has a distinct number of seats
+/// greater than 0 and a price between 0 and [`MAX_PRICE`].
+fn validate_tiers(tiers: &mut [PriceTier]) -> Result<(), String> {
+    tiers.sort_by_key(|tier| tier.min_seats);
+    if tiers.iter().any(|tier| tier.min_seats <= 0) {
+        return Err("Number of seats must be greater than 0".to_string());
+    }
+    if tiers.iter().any(|tier| tier.price_per_user.is_negative()) {
+        return Err("Price must not be negative".to_string());
+    }
+    if tiers.iter().any(|tier| tier.price_per_user > MAX_PRICE) {
+        return Err(format!("Price must not be more than {}", MAX_PRICE));
+    }
+    if tiers
+        .windows(2)
+        .any(|pair| pair[0].min_seats == pair[1].min_seats)
+    {
+        return Err("Each tier must have a distinct number of seats".to_string());
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, IntoParams)]
+struct PriceTiersQuery {
+    /// Currency of the price list, defaults to the currency of the product
+    currency: Option<Currency>,
+}
+
+/// Replace the volume pricing tiers of a product.
+///
+/// Each tier needs a distinct number of seats greater than 0 and a price that is not negative,
+/// with at most two decimals.
+/// An empty list removes all tiers, the product is then sold at the price per user of the list.
+/// The tiers are set on the price list in the currency given, which the product must have.
+#[utoipa::path(
+    context_path = "/api/priv",
+    put,
+    tag = "Pricing",
+    responses(
+        (status = 200, description = "Price tiers saved", body = Vec<PriceTier>),
+        (status = 400, description = "Bad Request - invalid tiers"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Product not found, or it has no price list in the currency"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("product_id", description = "The id of the product"),
+        PriceTiersQuery,
+    ),
+    request_body(
+        content_type = "application/json",
+        description = "All price tiers of the price list",
+        content = Vec<PriceTier>,
+    ),
+)]
+#[put("/products/{product_id}/price_tiers")]
+async fn set_price_tiers(
+    shared_data: web::Data<SharedData>,
+    product_id: web::Path<String>,
+    query: web::Query<PriceTiersQuery>,
+    tiers: web::Json<Vec<PriceTier>>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let mut tiers = tiers.into_inner();
+    if let Err(message) = validate_tiers(&mut tiers) {
+        return HttpResponse::BadRequest().json(message);
+    }
+
+    let pool = &shared_data.db_pool;
+    let product = match fetch_product(&shared_data, &product_id).await {
+        Ok(product) => product,
+        Err(response) => return response,
+    };
+    let currency = query.currency.unwrap_or(product.currency());
+    match price_list::get_price_list(pool, &product, currency).await {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            return HttpResponse::NotFound().json("The product has no price list in this currency")
+        }
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    }
+
+    match price_tier::set_price_tiers(pool, &product_id, currency, &tiers).await {
+        Ok(()) => HttpResponse::Ok().json(tiers),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct PriceListForm {
+    /// Price per user per year, for purchases no tier applies to.
+    #[schema(value_type = String, example = "199.00")]
+    price_per_user: Money,
+    /// Volume pricing tiers, none if omitted.
+    tiers: Option<Vec<PriceTier>>,
+}
+
+/// Set the price list of a product in a currency.
+///
+/// The product is sold in the currency from then on, companies billed in it are priced
+/// from this list. The price list in the currency of the product sets its price per user.
+/// Replaces the price list the product had in the currency, tiers included.
+#[utoipa::path(
+    context_path = "/api/priv",
+    put,
+    tag = "Pricing",
+    responses(
+        (status = 200, description = "Price list saved", body = PriceList),
+        (status = 400, description = "Bad Request - unknown currency, invalid price or tiers"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Product not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("product_id", description = "The id of the product"),
+        ("currency", description = "The currency of the price list", example = "EUR"),
+    ),
+    request_body(
+        content_type = "application/json",
+        description = "The price list",
+        content = PriceListForm,
+    ),
+)]
+#[put("/products/{product_id}/prices/{currency}")]
+async fn set_price_list(
+    shared_data: web::Data<SharedData>,
+    path: web::Path<(String, String)>,
+    form: web::Json<PriceListForm>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let (product_id, currency) = path.into_inner();
+    let currency = match currency.parse::<Currency>() {
+        Ok(currency) => currency,
+        Err(e) => return HttpResponse::BadRequest().json(e.to_string()),
+    };
+    let form = form.into_inner();
+    if form.price_per_user.is_negative() {
+        return HttpResponse::BadRequest().json("Price must not be negative");
+    }
+    if form.price_per_user > MAX_PRICE {
+        return HttpResponse::BadRequest()
+            .json(format!("Price must not be more than {}", MAX_PRICE));
+    }
+    let mut tiers = form.tiers.unwrap_or_default();
+    if let Err(message) = validate_tiers(&mut tiers) {
+        return HttpResponse::BadRequest().json(message);
+    }
+
+    let product = match fetch_product(&shared_data, &product_id).await {
+        Ok(product) => product,
+        Err(response) => return response,
+    };
+    let price_list = PriceList {
+        currency,
+        price_per_user: form.price_per_user,
+        tiers,
+    };
+    match price_list::set_price_list(&shared_data.db_pool, &product, &price_list).await {
+        Ok(()) => HttpResponse::Ok().json(price_list),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Remove the price list of a product in a currency.
+///
+/// The product is no longer sold in the currency, companies billed in it cannot buy it.
+/// The price list in the currency of the product cannot be removed.
+#[utoipa::path(
+    context_path = "/api/priv",
+    delete,
+    tag = "Pricing",
+    responses(
+        (status = 204, description = "Price list removed"),
+        (status = 400, description = "Bad Request - unknown currency"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Product not found, or it has no price list in the currency"),
+        (status = 409, description = "Conflict - the currency is the currency of the product"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("product_id", description = "The id of the product"),
+        ("currency", description = "The currency of the price list", example = "EUR"),
+    ),
+)]
+#[delete("/products/{product_id}/prices/{currency}")]
+async fn delete_price_list(
+    shared_data: web::Data<SharedData>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let (product_id, currency) = path.into_inner();
+    let currency = match currency.parse::<Currency>() {
+        Ok(currency) => currency,
+        Err(e) => return HttpResponse::BadRequest().json(e.to_string()),
+    };
+    let product = match fetch_product(&shared_data, &product_id).await {
+        Ok(product) => product,
+        Err(response) => return response,
+    };
+    if currency == product.currency() {
+        return HttpResponse::Conflict()
+            .json("The price list in the currency of the product cannot be removed");
+    }
+
+    match price_list::delete_price_list(&shared_data.db_pool, &product_id, currency).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => {
+            HttpResponse::NotFound().json("The product has no price list in this currency")
+        }
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
@@ -5,15 +5,25 @@ use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse, R
 use image::ImageError;
 use log::error;
 use serde::{Deserialize, Serialize};
-use utoipa::{OpenApi, ToSchema};
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
 pub mod descriptions_protected;
+pub mod publishing_protected;
+pub mod releases_protected;
 
 use crate::{
-    data_access::{error_handling::PostgresDBError, user},
+    data_access::{error_handling::PostgresDBError, pagination::PageQuery, user},
+    money::{Currency, Money},
+    pricing::MAX_PRICE,
+    routes::page_response,
     SharedData,
     {
-        data_access::product::{self, Product},
+        data_access::product::{
+            self,
+            publishing::PublishStatus,
+            search::{self, SearchFilter, SearchHit},
+            Product, ProductFilter, ProductSort,
+        },
         utils::{
             auth,
             img_multipart::{
@@ -25,17 +35,24 @@ use crate::{
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(get_all_products);
+    cfg.service(search_products);
     cfg.service(create_product);
     cfg.service(delete_product);
     cfg.service(update_product);
     cfg.service(update_availability);
-    cfg.service(web::scope("/products").configure(descriptions_protected::configure));
+    cfg.service(
+        web::scope("/products")
+            .configure(descriptions_protected::configure)
+            .configure(publishing_protected::configure)
+            .configure(releases_protected::configure),
+    );
 }
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         get_all_products,
+        search_products,
         create_product,
         update_product,
         update_availability,
@@ -44,6 +61,7 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
     components(
         schemas(
             Product,
+            SearchHit,
         )
     ),
     tags(
@@ -55,20 +73,113 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 )]
 pub struct ProductsApiDoc;
 
-/// Get all products.
+#[derive(Deserialize, IntoParams)]
+struct ProductsQuery {
+    /// Only products that are, or are not, available. Both if not given
+    available: Option<bool>,
+    /// Only products in this currency
+    currency: Option<Currency>,
+    /// Only products with this publish status
+    status: Option<PublishStatus>,
+}
+
+/// Get a page of all products.
 ///
 /// Includes products that are not available.
+/// Sorted by `display_name` if not given, or by `price_per_user` or `product_id`.
 #[utoipa::path(
     context_path = "/api/priv",
     get,
     tag = "Products",
     responses(
-    (status = 200, description = "List of all products", body = Vec<Product>),
-    (status = 500, description = "Internal Server Error"),
-)
+        (status = 200, description = "Page of the products", body = Vec<Product>,
+            headers(
+                ("X-Total-Count" = i64, description = "Number of products matching the filters"),
+                ("Link" = String, description = "Links to the first, previous, next and last pages"),
+            ),
+        ),
+        (status = 400, description = "Invalid limit, cursor or sort field"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(PageQuery, ProductsQuery),
 )]
 #[get("/products")]
-async fn get_all_products(shared_data: web::Data<SharedData>, req: HttpRequest) -> impl Responder {
+async fn get_all_products(
+    shared_data: web::Data<SharedData>,
+    page_query: web::Query<PageQuery>,
+    query: web::Query<ProductsQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    match auth::validate_user(req.clone(), pool).await {
+        Ok(user) => {
+            if user.role != user::Role::Admin {
+                return HttpResponse::Forbidden().finish();
+            }
+        }
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+
+    let page = match page_query.page::<ProductSort>() {
+        Ok(page) => page,
+        Err(e) => return HttpResponse::BadRequest().json(e.to_string()),
+    };
+    let filter = ProductFilter {
+        available: query.available,
+        currency: query.currency,
+        status: query.status,
+    };
+    match product::get_products(pool, &filter, &page).await {
+        Ok(products) => page_response(&req, products, &page),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+struct SearchQuery {
+    /// Search terms, in web search syntax, e.g. `tax -legal` or `"business process"`
+    q: String,
+    /// Only products in this category
+    category_id: Option<i32>,
+    /// Only products that are, or are not, available. Both if not given
+    available: Option<bool>,
+}
+
+/// Search all products.
+///
+/// Like the public search, but includes products that are not available.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Products",
+    responses(
+        (status = 200, description = "Products matching the search, best match first", body = Vec<SearchHit>),
+        (status = 400, description = "No search terms"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(SearchQuery),
+)]
+#[get("/products/search")]
+async fn search_products(
+    shared_data: web::Data<SharedData>,
+    query: web::Query<SearchQuery>,
+    req: HttpRequest,
+) -> impl Responder {
     let pool = &shared_data.db_pool;
     match auth::validate_user(req, pool).await {
         Ok(user) => {
@@ -87,8 +198,17 @@ async fn get_all_products(shared_data: web::Data<SharedData>, req: HttpRequest)
         }
     };
 
-    match product::get_products(pool, false).await {
-        Ok(products) => HttpResponse::Ok().json(products),
+    let text = query.q.trim();
+    if text.is_empty() {
+        return HttpResponse::BadRequest().json("Search terms are required");
+    }
+    let filter = SearchFilter {
+        text,
+        category_id: query.category_id,
+        available: query.available,
+    };
+    match search::search_products(pool, &filter).await {
+        Ok(hits) => HttpResponse::Ok().json(hits),
         Err(e) => {
             error!("{}", e);
             HttpResponse::InternalServerError().finish()
@@ -100,13 +220,16 @@ async fn get_all_products(shared_data: web::Data<SharedData>, req: HttpRequest)
 #[derive(Deserialize, Serialize, ToSchema)]
 struct NewProductForm {
     product_name: String,
-    price_per_unit: i32,
+    /// Price per user per year, with at most two decimals.
+    #[schema(example = "199.00")]
+    price_per_unit: String,
     short_description: String,
     image: Vec<u8>,
 }
 /// Create a new product.
 ///
-/// Availablity is set to false by default. Must be **PATCH**ed to `true` in a separate request.
+/// The product is created as a draft, which is not available. It is published in a separate request
+/// to `PUT /products/{product_id}/publishing`.
 #[utoipa::path(
     context_path = "/api/priv",
     post,
@@ -200,15 +323,19 @@ async fn create_product(
         None => return HttpResponse::InternalServerError().finish(),
     };
     let price_per_unit = match text_fields.get("price_per_unit") {
-        Some(price_per_unit) => match price_per_unit.parse::<f32>() {
+        Some(price_per_unit) => match price_per_unit.parse::<Money>() {
             Ok(price_per_unit) => price_per_unit,
-            Err(_) => return HttpResponse::BadRequest().body("Price must be a number."),
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
         },
         None => return HttpResponse::InternalServerError().finish(),
     };
-    if price_per_unit <= 0.0 {
+    if price_per_unit <= Money::ZERO {
         return HttpResponse::BadRequest().body("Price must be greater than 0.");
     }
+    if price_per_unit > MAX_PRICE {
+        return HttpResponse::BadRequest()
+            .body(format!("Price must not be more than {}.", MAX_PRICE));
+    }
     let short_description = match text_fields.get("short_description") {
         Some(short_description) => short_description,
         None => return HttpResponse::InternalServerError().finish(),
@@ -238,6 +365,7 @@ async fn create_product(
         &product_id,
         prod_name,
         price_per_unit,
+        Currency::default(),
         short_description,
         &file_name,
         false,
@@ -267,7 +395,9 @@ async fn create_product(
 #[derive(Deserialize, Serialize, ToSchema)]
 struct UpdateProductForm {
     product_name: String,
-    price_per_unit: i32,
+    /// Price per user per year, with at most two decimals.
+    #[schema(example = "199.00")]
+    price_per_unit: String,
     short_description: String,
     image: Option<Vec<u8>>,
 }
@@ -406,15 +536,19 @@ async fn update_product(
         None => return HttpResponse::InternalServerError().finish(),
     };
     let price_per_unit = match text_fields.get("price_per_unit") {
-        Some(price_per_unit) => match price_per_unit.parse::<f32>() {
+        Some(price_per_unit) => match price_per_unit.parse::<Money>() {
             Ok(price_per_unit) => price_per_unit,
-            Err(_) => return HttpResponse::BadRequest().json("Price must be a number."),
+            Err(e) => return HttpResponse::BadRequest().json(e.to_string()),
         },
         None => return HttpResponse::InternalServerError().finish(),
     };
-    if price_per_unit <= 0.0 {
+    if price_per_unit <= Money::ZERO {
         return HttpResponse::BadRequest().body("Price must be greater than 0.");
     }
+    if price_per_unit > MAX_PRICE {
+        return HttpResponse::BadRequest()
+            .body(format!("Price must not be more than {}.", MAX_PRICE));
+    }
 
     let short_description = match text_fields.get("short_description") {
         Some(short_description) => short_description,
@@ -429,6 +563,7 @@ async fn update_product(
         unupadted_product.product_id(),
         prod_name,
         price_per_unit,
+        unupadted_product.currency(),
         short_description,
         &new_image_path,
         unupadted_product.available(),
@@ -448,6 +583,9 @@ struct AvailibilityBody {
     available: bool,
 }
 /// Update the availability of a product.
+///
+/// Making a product available publishes it. Making it unavailable archives a published product,
+/// and makes a scheduled product a draft again.
 #[utoipa::path(
     context_path = "/api/priv",
     patch,
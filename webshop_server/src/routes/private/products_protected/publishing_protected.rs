@@ -0,0 +1,234 @@
+//! Endpoints for publishing products, and previewing them before they are published.
+
+use actix_web::{get, put, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    data_access::{
+        product::{
+            self,
+            description::{self, DescriptionComponent, ImageComponent, TextComponent},
+            publishing::{self, PublishStatus, Publishing},
+            Product,
+        },
+        user::Role,
+    },
+    utils::auth,
+    SharedData,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_publishing);
+    cfg.service(set_publishing);
+    cfg.service(preview_product);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        get_publishing,
+        set_publishing,
+        preview_product,
+    ),
+    components(
+        schemas(Publishing, PublishStatus, PublishingChange, ProductPreview, Product,
+            DescriptionComponent, ImageComponent, TextComponent)
+    ),
+    tags(
+        (name = "Product Publishing", description = "API endpoints for publishing products")
+    ),
+)]
+pub struct PublishingApiDoc;
+
+/// A new publish status of a product.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PublishingChange {
+    pub status: PublishStatus,
+    /// When to publish the product, required when scheduling it and not given otherwise.
+    pub publish_at: Option<DateTime<Utc>>,
+}
+
+/// The public page of a product, as it looks once published.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProductPreview {
+    pub product: Product,
+    pub publishing: Publishing,
+    pub descriptions: Vec<DescriptionComponent>,
+}
+
+/// Checks the user is an admin, returning the response to send if not.
+async fn validate_admin(shared_data: &SharedData, req: HttpRequest) -> Result<(), HttpResponse> {
+    match auth::validate_user(req, &shared_data.db_pool).await {
+        Ok(user) => {
+            if user.role != Role::Admin {
+                return Err(HttpResponse::Forbidden().finish());
+            }
+            Ok(())
+        }
+        Err(e) => match e {
+            auth::AuthError::Unauthorized => Err(HttpResponse::Unauthorized().finish()),
+            auth::AuthError::SqlxError(e) => {
+                error!("{}", e);
+                Err(HttpResponse::InternalServerError().finish())
+            }
+        },
+    }
+}
+
+/// Response for an error fetching or saving a product.
+fn product_error(e: sqlx::Error) -> HttpResponse {
+    match e {
+        sqlx::Error::RowNotFound => HttpResponse::NotFound().json("Product not found"),
+        e => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Get the publish status of a product.
+#[utoipa::path(
+    context_path = "/api/priv/products",
+    get,
+    tag = "Product Publishing",
+    responses(
+        (status = 200, description = "Publish status of the product", body = Publishing),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Product not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("product_id", description = "The id of the product"),
+    ),
+)]
+#[get("/{product_id}/publishing")]
+async fn get_publishing(
+    shared_data: web::Data<SharedData>,
+    product_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+
+    match publishing::get_publishing(&shared_data.db_pool, product_id.as_str()).await {
+        Ok(publishing) => HttpResponse::Ok().json(publishing),
+        Err(e) => product_error(e),
+    }
+}
+
+/// Set the publish status of a product.
+///
+/// A product can be made a draft, scheduled to be published at a time in the future,
+/// published right away or archived. A scheduled product is published by the server
+/// when its time comes. Only published products are available for purchase,
+/// and drafts and scheduled products are hidden from the public api.
+#[utoipa::path(
+    context_path = "/api/priv/products",
+    put,
+    tag = "Product Publishing",
+    responses(
+        (status = 200, description = "Publish status updated", body = Publishing),
+        (status = 400, description = "Bad Request - publish time missing, in the past or not scheduling"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Product not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("product_id", description = "The id of the product"),
+    ),
+    request_body(
+        description = "The new publish status",
+        content = PublishingChange,
+    ),
+)]
+#[put("/{product_id}/publishing")]
+async fn set_publishing(
+    shared_data: web::Data<SharedData>,
+    product_id: web::Path<String>,
+    body: web::Json<PublishingChange>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    match (body.status, body.publish_at) {
+        (PublishStatus::Scheduled, None) => {
+            return HttpResponse::BadRequest().json("Publish time is required when scheduling")
+        }
+        (PublishStatus::Scheduled, Some(publish_at)) if publish_at <= Utc::now() => {
+            return HttpResponse::BadRequest().json("Publish time must be in the future")
+        }
+        (PublishStatus::Scheduled, Some(_)) | (_, None) => (),
+        (_, Some(_)) => {
+            return HttpResponse::BadRequest().json("Publish time is only given when scheduling")
+        }
+    }
+
+    match publishing::set_publishing(
+        &shared_data.db_pool,
+        product_id.as_str(),
+        body.status,
+        body.publish_at,
+    )
+    .await
+    {
+        Ok(publishing) => HttpResponse::Ok().json(publishing),
+        Err(e) => product_error(e),
+    }
+}
+
+/// Preview the public page of a product.
+///
+/// Returns the product and its descriptions whatever its publish status,
+/// so admins can see how the page of a draft or scheduled product will look.
+#[utoipa::path(
+    context_path = "/api/priv/products",
+    get,
+    tag = "Product Publishing",
+    responses(
+        (status = 200, description = "The product page", body = ProductPreview),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Product not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("product_id", description = "The id of the product"),
+    ),
+)]
+#[get("/{product_id}/preview")]
+async fn preview_product(
+    shared_data: web::Data<SharedData>,
+    product_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let pool = &shared_data.db_pool;
+    let product = match product::get_product_by_id(pool, product_id.as_str()).await {
+        Ok(product) => product,
+        Err(e) => return product_error(e),
+    };
+    let publishing = match publishing::get_publishing(pool, product_id.as_str()).await {
+        Ok(publishing) => publishing,
+        Err(e) => return product_error(e),
+    };
+    let descriptions =
+        match description::get_product_description_components(pool, product_id.as_str()).await {
+            Ok(descriptions) => descriptions,
+            Err(e) => return product_error(e),
+        };
+
+    HttpResponse::Ok().json(ProductPreview {
+        product,
+        publishing,
+        descriptions,
+    })
+}
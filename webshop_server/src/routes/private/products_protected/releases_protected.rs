@@ -0,0 +1,506 @@
+//! Endpoints for managing the releases of products, and the files to download for them.
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use actix_multipart::Multipart;
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Responder};
+use futures::StreamExt;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    data_access::{
+        error_handling::PostgresDBError,
+        product::{
+            self,
+            release::{self, DownloadEvent, PartialRelease, Release, ReleaseDownload},
+        },
+        user::Role,
+    },
+    utils::auth,
+    SharedData,
+};
+
+/// Largest file that can be uploaded for a release.
+const MAX_RELEASE_FILE_SIZE: i64 = 1024 * 1024 * 1024 * 2; // 2 GB
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(create_release);
+    cfg.service(update_release);
+    cfg.service(delete_release);
+    cfg.service(upload_release_file);
+    cfg.service(release_downloads);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        create_release,
+        update_release,
+        delete_release,
+        upload_release_file,
+        release_downloads,
+    ),
+    components(
+        schemas(Release, ReleaseDownload, PartialRelease, DownloadEvent)
+    ),
+    tags(
+        (name = "Product Releases", description = "API endpoints for managing product releases")
+    ),
+)]
+pub struct ReleasesApiDoc;
+
+/// Checks the user is an admin, returning the response to send if not.
+async fn validate_admin(shared_data: &SharedData, req: HttpRequest) -> Result<(), HttpResponse> {
+    match auth::validate_user(req, &shared_data.db_pool).await {
+        Ok(user) => {
+            if user.role != Role::Admin {
+                return Err(HttpResponse::Forbidden().finish());
+            }
+            Ok(())
+        }
+        Err(e) => match e {
+            auth::AuthError::Unauthorized => Err(HttpResponse::Unauthorized().finish()),
+            auth::AuthError::SqlxError(e) => {
+                error!("{}", e);
+                Err(HttpResponse::InternalServerError().finish())
+            }
+        },
+    }
+}
+
+/// Trims the version and changelog and lowercases the checksum,
+/// returning why the release is invalid if it is.
+fn normalize(release: &mut PartialRelease) -> Result<(), &'static str> {
+    release.version = release.version.trim().to_string();
+    if release.version.is_empty() {
+        return Err("Version is required");
+    }
+    release.changelog = release.changelog.trim().to_string();
+    if let Some(download) = release.download.as_mut() {
+        download.file_name = download.file_name.trim().to_string();
+        download.sha256 = download.sha256.trim().to_ascii_lowercase();
+        if download.file_name.is_empty() {
+            return Err("File name is required");
+        }
+        if download.file_size < 0 {
+            return Err("File size must not be negative");
+        }
+        if download.sha256.len() != 64 || !download.sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err("Checksum must be a SHA-256 hash in hex");
+        }
+    }
+    Ok(())
+}
+
+/// Response for an error saving a release.
+fn save_error(e: sqlx::Error) -> HttpResponse {
+    match e {
+        sqlx::Error::RowNotFound => HttpResponse::NotFound().json("Release not found"),
+        sqlx::Error::Database(e) => match PostgresDBError::from_str(e) {
+            PostgresDBError::UniqueViolation => {
+                HttpResponse::Conflict().json("Product has a release with this version already")
+            }
+            _ => HttpResponse::InternalServerError().json("Internal Server Error"),
+        },
+        e => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Fetches a release of a product, returning the response to send if the product has no such release.
+async fn fetch_release(
+    shared_data: &SharedData,
+    product_id: &str,
+    release_id: i32,
+) -> Result<Release, HttpResponse> {
+    match release::get_release(&shared_data.db_pool, &release_id).await {
+        Ok(release) if release.product_id == product_id => Ok(release),
+        Ok(_) | Err(sqlx::Error::RowNotFound) => {
+            Err(HttpResponse::NotFound().json("Release not found"))
+        }
+        Err(e) => {
+            error!("{}", e);
+            Err(HttpResponse::InternalServerError().json("Internal Server Error"))
+        }
+    }
+}
+
+/// Deletes a file from file storage, logging if it cannot be deleted.
+async fn delete_stored_file(shared_data: &SharedData, storage_key: &str) {
+    if let Err(e) = shared_data.file_storage.delete(storage_key).await {
+        warn!("Could not delete stored file {}: {}", storage_key, e);
+    }
+}
+
+/// Form for uploading the file of a release.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct ReleaseFileForm {
+    file: Vec<u8>,
+}
+
+/// Receives the `file` field of an upload into a temporary file,
+/// returning its path and the download details of the file.
+/// Returns the response to send if the upload is invalid.
+async fn receive_file(mut payload: Multipart) -> Result<(PathBuf, ReleaseDownload), HttpResponse> {
+    while let Some(item) = payload.next().await {
+        let mut field = match item {
+            Ok(field) => field,
+            Err(e) => {
+                error!("{}", e);
+                return Err(HttpResponse::BadRequest().json("Couldnt extract multipart"));
+            }
+        };
+        if field.content_disposition().get_name() != Some("file") {
+            continue;
+        }
+        let file_name = match field.content_disposition().get_filename() {
+            Some(name) => sanitize_filename::sanitize(name.trim()).replace(' ', "-"),
+            None => return Err(HttpResponse::BadRequest().json("File name is required")),
+        };
+        if file_name.is_empty() {
+            return Err(HttpResponse::BadRequest().json("File name is required"));
+        }
+
+        let path = std::env::temp_dir().join(format!("upload-{}", uuid::Uuid::new_v4()));
+        let mut file = match fs::File::create(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Could not create {}: {}", path.display(), e);
+                return Err(HttpResponse::InternalServerError().json("Internal Server Error"));
+            }
+        };
+        let mut hasher = Sha256::new();
+        let mut file_size: i64 = 0;
+        while let Some(chunk) = field.next().await {
+            let failure = match chunk {
+                Ok(data) => {
+                    file_size += data.len() as i64;
+                    hasher.update(&data);
+                    if file_size > MAX_RELEASE_FILE_SIZE {
+                        Some(HttpResponse::PayloadTooLarge().json("File too large"))
+                    } else if let Err(e) = file.write_all(&data) {
+                        error!("Could not write {}: {}", path.display(), e);
+                        Some(HttpResponse::InternalServerError().json("Internal Server Error"))
+                    } else {
+                        None
+                    }
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    Some(HttpResponse::BadRequest().json("Couldnt extract multipart"))
+                }
+            };
+            if let Some(response) = failure {
+                remove_temp_file(&path);
+                return Err(response);
+            }
+        }
+        if file_size == 0 {
+            remove_temp_file(&path);
+            return Err(HttpResponse::BadRequest().json("File is empty"));
+        }
+        return Ok((
+            path,
+            ReleaseDownload {
+                file_name,
+                file_size,
+                sha256: hex::encode(hasher.finalize()),
+            },
+        ));
+    }
+    Err(HttpResponse::BadRequest().json("Missing data, expected 'file'"))
+}
+
+fn remove_temp_file(path: &Path) {
+    if let Err(e) = fs::remove_file(path) {
+        warn!("Could not remove {}: {}", path.display(), e);
+    }
+}
+
+/// Create a release of a product.
+///
+/// The release date is now if not given.
+#[utoipa::path(
+    context_path = "/api/priv/products",
+    post,
+    tag = "Product Releases",
+    responses(
+        (status = 201, description = "Release created", body = Release),
+        (status = 400, description = "Bad Request - no version or invalid download"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Product not found"),
+        (status = 409, description = "Conflict - product has a release with the version already"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("product_id", description = "The id of the product"),
+    ),
+    request_body(
+        description = "The release to create",
+        content = PartialRelease,
+    ),
+)]
+#[post("/{product_id}/releases")]
+async fn create_release(
+    shared_data: web::Data<SharedData>,
+    product_id: web::Path<String>,
+    body: web::Json<PartialRelease>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let mut new_release = body.into_inner();
+    if let Err(e) = normalize(&mut new_release) {
+        return HttpResponse::BadRequest().json(e);
+    }
+    let pool = &shared_data.db_pool;
+    match product::product_exists(pool, product_id.as_str()).await {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::NotFound().json("Product not found"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    }
+
+    match release::create_release(pool, product_id.as_str(), &new_release).await {
+        Ok(created) => HttpResponse::Created().json(created),
+        Err(e) => save_error(e),
+    }
+}
+
+/// Update a release of a product.
+///
+/// The release date is kept if not given. The download is removed if not given.
+/// The uploaded file of the release is deleted if the checksum of the download changes.
+#[utoipa::path(
+    context_path = "/api/priv/products",
+    put,
+    tag = "Product Releases",
+    responses(
+        (status = 200, description = "Release updated", body = Release),
+        (status = 400, description = "Bad Request - no version or invalid download"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Release not found"),
+        (status = 409, description = "Conflict - product has a release with the version already"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("product_id", description = "The id of the product"),
+        ("release_id", description = "The id of the release"),
+    ),
+    request_body(
+        description = "The new details of the release",
+        content = PartialRelease,
+    ),
+)]
+#[put("/{product_id}/releases/{release_id}")]
+async fn update_release(
+    shared_data: web::Data<SharedData>,
+    path: web::Path<(String, i32)>,
+    body: web::Json<PartialRelease>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let mut details = body.into_inner();
+    if let Err(e) = normalize(&mut details) {
+        return HttpResponse::BadRequest().json(e);
+    }
+    let (product_id, release_id) = path.into_inner();
+    let current = match fetch_release(&shared_data, &product_id, release_id).await {
+        Ok(current) => current,
+        Err(response) => return response,
+    };
+
+    match release::update_release(&shared_data.db_pool, &release_id, &details).await {
+        Ok(updated) => {
+            // the uploaded file is dropped when the download changes
+            if let (Some(key), None) = (&current.storage_key, &updated.storage_key) {
+                delete_stored_file(&shared_data, key).await;
+            }
+            HttpResponse::Ok().json(updated)
+        }
+        Err(e) => save_error(e),
+    }
+}
+
+/// Delete a release of a product, and its uploaded file.
+#[utoipa::path(
+    context_path = "/api/priv/products",
+    delete,
+    tag = "Product Releases",
+    responses(
+        (status = 204, description = "Release deleted"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Release not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("product_id", description = "The id of the product"),
+        ("release_id", description = "The id of the release"),
+    ),
+)]
+#[delete("/{product_id}/releases/{release_id}")]
+async fn delete_release(
+    shared_data: web::Data<SharedData>,
+    path: web::Path<(String, i32)>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let (product_id, release_id) = path.into_inner();
+    let current = match fetch_release(&shared_data, &product_id, release_id).await {
+        Ok(current) => current,
+        Err(response) => return response,
+    };
+
+    match release::delete_release(&shared_data.db_pool, &release_id).await {
+        Ok(true) => {
+            if let Some(key) = &current.storage_key {
+                delete_stored_file(&shared_data, key).await;
+            }
+            HttpResponse::NoContent().finish()
+        }
+        Ok(false) => HttpResponse::NotFound().json("Release not found"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Upload the file to download for a release of a product.
+///
+/// The file is sent in the multipart field `file`. The download of the release is set to the file,
+/// with its size and SHA-256 checksum, replacing the file uploaded before.
+/// Users holding a seat on a license covering the release can then download it.
+#[utoipa::path(
+    context_path = "/api/priv/products",
+    post,
+    tag = "Product Releases",
+    responses(
+        (status = 200, description = "File uploaded", body = Release),
+        (status = 400, description = "Bad Request - no file, or no file name"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Release not found"),
+        (status = 413, description = "Payload Too Large - file larger than 2 GB"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("product_id", description = "The id of the product"),
+        ("release_id", description = "The id of the release"),
+    ),
+    request_body(
+        content_type = "multipart/form-data",
+        description = "The file of the release",
+        content = inline(ReleaseFileForm),
+    ),
+)]
+#[post("/{product_id}/releases/{release_id}/file")]
+async fn upload_release_file(
+    shared_data: web::Data<SharedData>,
+    path: web::Path<(String, i32)>,
+    payload: Multipart,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let (product_id, release_id) = path.into_inner();
+    let current = match fetch_release(&shared_data, &product_id, release_id).await {
+        Ok(current) => current,
+        Err(response) => return response,
+    };
+    let (temp_path, download) = match receive_file(payload).await {
+        Ok(received) => received,
+        Err(response) => return response,
+    };
+
+    let storage_key = format!(
+        "releases/{}/{}/{}",
+        product_id, release_id, download.file_name
+    );
+    if let Err(e) = shared_data
+        .file_storage
+        .store(&storage_key, &temp_path)
+        .await
+    {
+        error!("Could not store {}: {}", storage_key, e);
+        remove_temp_file(&temp_path);
+        return HttpResponse::InternalServerError().json("Internal Server Error");
+    }
+
+    match release::set_release_file(&shared_data.db_pool, &release_id, &download, &storage_key)
+        .await
+    {
+        Ok(updated) => {
+            if let Some(key) = current.storage_key.filter(|key| *key != storage_key) {
+                delete_stored_file(&shared_data, &key).await;
+            }
+            HttpResponse::Ok().json(updated)
+        }
+        Err(e) => {
+            if current.storage_key.as_deref() != Some(storage_key.as_str()) {
+                delete_stored_file(&shared_data, &storage_key).await;
+            }
+            save_error(e)
+        }
+    }
+}
+
+/// Get the downloads of the file of a release, newest first.
+#[utoipa::path(
+    context_path = "/api/priv/products",
+    get,
+    tag = "Product Releases",
+    responses(
+        (status = 200, description = "Downloads of the release", body = Vec<DownloadEvent>),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Release not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("product_id", description = "The id of the product"),
+        ("release_id", description = "The id of the release"),
+    ),
+)]
+#[get("/{product_id}/releases/{release_id}/downloads")]
+async fn release_downloads(
+    shared_data: web::Data<SharedData>,
+    path: web::Path<(String, i32)>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = validate_admin(&shared_data, req).await {
+        return response;
+    }
+    let (product_id, release_id) = path.into_inner();
+    if let Err(response) = fetch_release(&shared_data, &product_id, release_id).await {
+        return response;
+    }
+
+    match release::get_downloads(&shared_data.db_pool, &release_id).await {
+        Ok(downloads) => HttpResponse::Ok().json(downloads),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! Endpoints for seat usage reports.
+//!
+//! Reports are built from the daily usage snapshots, per license or for all licenses of a company,
+//! and can be downloaded as CSV with `?format=csv`.
+
+use actix_web::{get, http::header, web, HttpRequest, HttpResponse, Responder};
+use chrono::{Duration, NaiveDate, Utc};
+use log::error;
+use serde::Deserialize;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    data_access::{
+        license::{
+            self,
+            usage::{self, CompanyUsage, LicenseUsage, UsagePoint},
+        },
+        user::{Role, User},
+    },
+    utils::auth,
+    SharedData,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(license_usage);
+    cfg.service(company_usage);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        license_usage,
+        company_usage,
+    ),
+    components(
+        schemas(LicenseUsage, CompanyUsage, UsagePoint, ReportFormat)
+    ),
+    tags(
+        (name = "Reports", description = "API endpoints for seat usage reports")
+    ),
+)]
+pub struct ReportsOpenApi;
+
+/// How many days a report covers if no start date is given.
+const DEFAULT_REPORT_DAYS: i64 = 90;
+
+#[derive(Deserialize, ToSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum ReportFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize, IntoParams)]
+struct ReportQuery {
+    /// First day of the report, defaults to 90 days before the last day.
+    from: Option<NaiveDate>,
+    /// Last day of the report, defaults to today.
+    to: Option<NaiveDate>,
+    /// Format of the report, defaults to json.
+    format: Option<ReportFormat>,
+}
+
+impl ReportQuery {
+    /// Returns the first and last day of the report, or None if the first day is after the last.
+    fn period(&self) -> Option<(NaiveDate, NaiveDate)> {
+        let to = self.to.unwrap_or_else(|| Utc::now().date_naive());
+        let from = self
+            .from
+            .unwrap_or_else(|| to - Duration::days(DEFAULT_REPORT_DAYS));
+        if from > to {
+            return None;
+        }
+        Some((from, to))
+    }
+
+    fn is_csv(&self) -> bool {
+        self.format == Some(ReportFormat::Csv)
+    }
+}
+
+/// Returns true if the user may see the reports of the company.
+fn can_view_reports(user: &User, company_id: i32) -> bool {
+    match user.role {
+        Role::Admin => true,
+        Role::CompanyItHead | Role::CompanyIt => user.company_id == company_id,
+        Role::Default => false,
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Renders the usage of licenses as CSV, one row per license and day.
+fn usage_csv(licenses: &[LicenseUsage]) -> String {
+    let mut csv = String::from("license_id,product_id,product,date,seats_used,amount\n");
+    for license in licenses {
+        for point in license.usage.iter() {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{}\n",
+                license.license_id,
+                csv_field(&license.product_id),
+                csv_field(&license.display_name),
+                point.date,
+                point.seats_used,
+                point.amount
+            ));
+        }
+    }
+    csv
+}
+
+/// Builds the response for a CSV report.
+fn csv_response(csv: String, file_name: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", file_name),
+        ))
+        .body(csv)
+}
+
+/// Get the seat usage history of a license.
+///
+/// Available to admins, and to IT staff of the company owning the license.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Reports",
+    responses(
+        (status = 200, description = "Seat usage per day, as JSON or CSV", body = LicenseUsage),
+        (status = 400, description = "Bad Request - invalid period"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "License not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("license_id", description = "The ID of the license"),
+        ReportQuery,
+    ),
+)]
+#[get("/licenses/{license_id}/usage")]
+async fn license_usage(
+    shared_data: web::Data<SharedData>,
+    license_id: web::Path<String>,
+    query: web::Query<ReportQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let license_id = match license_id.parse::<i32>() {
+        Ok(license_id) => license_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    let (from, to) = match query.period() {
+        Some(period) => period,
+        None => return HttpResponse::BadRequest().json("From date must not be after to date"),
+    };
+    let license = match license::get_license_by_id(pool, &license_id).await {
+        Ok(license) => license,
+        Err(e) => {
+            return match e {
+                sqlx::Error::RowNotFound => HttpResponse::NotFound().json("License not found"),
+                _ => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().json("Internal Server Error")
+                }
+            }
+        }
+    };
+    if !can_view_reports(&user, license.company_id) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    match usage::get_license_usage(pool, &license_id, from, to).await {
+        Ok(usage) => {
+            if query.is_csv() {
+                let file_name = format!("license_{}_usage_{}_{}.csv", license_id, from, to);
+                csv_response(usage_csv(&[usage]), &file_name)
+            } else {
+                HttpResponse::Ok().json(usage)
+            }
+        }
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Get the seat usage history of all licenses of a company.
+///
+/// The JSON report also contains the usage summed over all licenses per day.
+/// Available to admins, and to IT staff of the company.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Reports",
+    responses(
+        (status = 200, description = "Seat usage per license and day, as JSON or CSV", body = CompanyUsage),
+        (status = 400, description = "Bad Request - invalid period"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("company_id", description = "The ID of the company"),
+        ReportQuery,
+    ),
+)]
+#[get("/companies/{company_id}/usage")]
+async fn company_usage(
+    shared_data: web::Data<SharedData>,
+    company_id: web::Path<String>,
+    query: web::Query<ReportQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let company_id = match company_id.parse::<i32>() {
+        Ok(company_id) => company_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    if !can_view_reports(&user, company_id) {
+        return HttpResponse::Forbidden().finish();
+    }
+    let (from, to) = match query.period() {
+        Some(period) => period,
+        None => return HttpResponse::BadRequest().json("From date must not be after to date"),
+    };
+
+    match usage::get_company_usage(pool, &company_id, from, to).await {
+        Ok(usage) => {
+            if query.is_csv() {
+                let file_name = format!("company_{}_usage_{}_{}.csv", company_id, from, to);
+                csv_response(usage_csv(&usage.licenses), &file_name)
+            } else {
+                HttpResponse::Ok().json(usage)
+            }
+        }
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! Endpoints for trial licenses.
+//!
+//! Admins configure the trial offered for a product, a company's IT head starts it once
+//! per product, and later converts the trial license into a paid one by paying for it.
+
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Responder};
+use log::error;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+
+use super::licenses_protected::{
+    discounted, get_purchasable_license, license_order_response, order_error_response,
+    pricing_error_response,
+};
+use crate::{
+    data_access::{
+        license::trial::{self, CompanyTrial, ProductTrial, TrialError},
+        order, product,
+        user::Role,
+    },
+    pricing,
+    utils::auth,
+    SharedData,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(set_product_trial);
+    cfg.service(delete_product_trial);
+    cfg.service(start_trial);
+    cfg.service(company_trials);
+    cfg.service(convert_trial);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        set_product_trial,
+        delete_product_trial,
+        start_trial,
+        company_trials,
+        convert_trial,
+    ),
+    components(
+        schemas(ProductTrial, CompanyTrial, TrialSettings, TrialConversion)
+    ),
+    tags(
+        (name = "Trials", description = "API endpoints for trial licenses")
+    ),
+)]
+pub struct TrialsOpenApi;
+
+/// Trial settings of a product.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct TrialSettings {
+    /// How many days a trial lasts.
+    duration_days: i32,
+    /// How many seats a trial license has.
+    seats: i32,
+}
+
+/// Set the trial offered for a product.
+///
+/// Replaces the previous settings, trials already started are not affected.
+#[utoipa::path(
+    context_path = "/api/priv",
+    put,
+    tag = "Trials",
+    responses(
+        (status = 200, description = "Trial settings saved", body = ProductTrial),
+        (status = 400, description = "Bad Request - duration and seats must be greater than 0"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Product not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("product_id", description = "The id of the product"),
+    ),
+    request_body(
+        content_type = "application/json",
+        description = "Trial settings",
+        content = TrialSettings,
+    ),
+)]
+#[put("/products/{product_id}/trial")]
+async fn set_product_trial(
+    shared_data: web::Data<SharedData>,
+    product_id: web::Path<String>,
+    settings: web::Json<TrialSettings>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    match auth::validate_user(req, pool).await {
+        Ok(user) => {
+            if user.role != Role::Admin {
+                return HttpResponse::Forbidden().finish();
+            }
+        }
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    if settings.duration_days <= 0 || settings.seats <= 0 {
+        return HttpResponse::BadRequest().json("Duration and seats must be greater than 0");
+    }
+
+    match product::product_exists(pool, &product_id).await {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::NotFound().json("Product not found"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    }
+
+    match trial::set_product_trial(pool, &product_id, settings.duration_days, settings.seats).await
+    {
+        Ok(product_trial) => HttpResponse::Ok().json(product_trial),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Stop offering a trial for a product.
+#[utoipa::path(
+    context_path = "/api/priv",
+    delete,
+    tag = "Trials",
+    responses(
+        (status = 204, description = "Trial removed"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Product offers no trial"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("product_id", description = "The id of the product"),
+    ),
+)]
+#[delete("/products/{product_id}/trial")]
+async fn delete_product_trial(
+    shared_data: web::Data<SharedData>,
+    product_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    match auth::validate_user(req, pool).await {
+        Ok(user) => {
+            if user.role != Role::Admin {
+                return HttpResponse::Forbidden().finish();
+            }
+        }
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+
+    match trial::delete_product_trial(pool, &product_id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json("Product offers no trial"),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Start the trial of a product for your company.
+///
+/// Only a company IT head can start a trial, and only once per product.
+/// Creates a valid trial license with the seats and duration configured for the product.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Trials",
+    responses(
+        (status = 201, description = "Trial started", body = License),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden - only company IT heads can start trials"),
+        (status = 404, description = "Product offers no trial"),
+        (status = 409, description = "Conflict - trial already started by the company"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("product_id", description = "The id of the product"),
+    ),
+)]
+#[post("/products/{product_id}/trial/start")]
+async fn start_trial(
+    shared_data: web::Data<SharedData>,
+    product_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    if user.role != Role::CompanyItHead {
+        return HttpResponse::Forbidden().json("Only company IT heads can start trials");
+    }
+
+    match trial::start_trial(pool, &user.company_id, &product_id).await {
+        Ok(license) => HttpResponse::Created().json(license),
+        Err(e) => match e {
+            TrialError::NotOffered => HttpResponse::NotFound().json("Product offers no trial"),
+            TrialError::AlreadyStarted => HttpResponse::Conflict()
+                .json("Your company has already started a trial of this product"),
+            TrialError::SqlxError(e) => {
+                error!("{}", e);
+                HttpResponse::InternalServerError().json("Internal Server Error")
+            }
+        },
+    }
+}
+
+/// Get all trials started by a company.
+#[utoipa::path(
+    context_path = "/api/priv",
+    get,
+    tag = "Trials",
+    responses(
+        (status = 200, description = "List of trials started by the company", body = Vec<CompanyTrial>),
+        (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("company_id", description = "The ID of the company"),
+    ),
+)]
+#[get("/companies/{company_id}/trials")]
+async fn company_trials(
+    shared_data: web::Data<SharedData>,
+    company_id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let company_id = match company_id.parse::<i32>() {
+        Ok(company_id) => company_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    match user.role {
+        Role::Admin => (),
+        Role::CompanyItHead | Role::CompanyIt => {
+            if user.company_id != company_id {
+                return HttpResponse::Forbidden().finish();
+            }
+        }
+        Role::Default => return HttpResponse::Forbidden().finish(),
+    }
+
+    match trial::get_company_trials(pool, &company_id).await {
+        Ok(trials) => HttpResponse::Ok().json(trials),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+/// Paid license to convert a trial into.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct TrialConversion {
+    /// Number of seats of the paid license.
+    seats: i32,
+    /// Length of the paid license in years, defaults to 1.
+    term_years: Option<i32>,
+    /// Discount code to apply to the price.
+    discount_code: Option<String>,
+}
+
+/// Convert a trial license of your company into a paid license.
+///
+/// The server computes the price with the pricing engine, in the billing currency of your company,
+/// less the discount code given, if any, and creates the order paying for the license.
+/// Once the order is paid with the returned payment, the license is no longer a trial:
+/// it starts then and lasts for the term, with the seats paid for.
+/// The license keeps its ID, so all users assigned to the trial keep their seats.
+/// Only company IT staff can convert trials.
+#[utoipa::path(
+    context_path = "/api/priv",
+    post,
+    tag = "Trials",
+    responses(
+        (status = 200, description = "Conversion ordered", body = LicenseOrder),
+        (status = 400, description = "Bad Request - invalid number of seats or term, or discount code not valid for the conversion"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "License, product or discount code not found"),
+        (status = 409, description = "Conflict - license is not a valid trial or its conversion is being paid, more users are assigned than the seats, discount code used up, or product not sold in the billing currency"),
+        (status = 500, description = "Internal Server Error"),
+        (status = 502, description = "Payment provider error"),
+    ),
+    params(
+        ("license_id", description = "The ID of the trial license"),
+    ),
+    request_body(
+        content_type = "application/json",
+        description = "The paid license",
+        content = TrialConversion,
+    ),
+)]
+#[post("/licenses/{license_id}/convert")]
+async fn convert_trial(
+    shared_data: web::Data<SharedData>,
+    license_id: web::Path<String>,
+    conversion: web::Json<TrialConversion>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let user = match auth::validate_user(req, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            return match e {
+                auth::AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+                auth::AuthError::SqlxError(e) => {
+                    error!("{}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+    };
+    let license = match get_purchasable_license(&shared_data, &license_id, &user).await {
+        Ok(license) => license,
+        Err(response) => return response,
+    };
+
+    let price = match pricing::quote_for_company(
+        pool,
+        license.product_id(),
+        conversion.seats,
+        conversion.term_years.unwrap_or(1),
+        license.company_id,
+    )
+    .await
+    {
+        Ok(price) => price,
+        Err(e) => return pricing_error_response(e),
+    };
+    let price = match discounted(pool, price, &conversion.discount_code, license.company_id).await {
+        Ok(price) => price,
+        Err(e) => return pricing_error_response(e),
+    };
+
+    match order::convert_trial(
+        pool,
+        user.user_id,
+        &license.license_id(),
+        &price,
+        shared_data.checkout_timeout,
+    )
+    .await
+    {
+        Ok((license, order)) => license_order_response(&shared_data, license, order).await,
+        Err(e) => order_error_response(e),
+    }
+}
@@ -2,8 +2,13 @@
 use crate::{
     data_access::{
         self, company, error_handling, license,
-        user::{self, LicenseUser, PartialRegisterCompanyUser, Role, User, UserID, UserRole},
+        pagination::{PageQuery, SortOrder},
+        user::{
+            self, LicenseUser, PartialRegisterCompanyUser, Role, User, UserFilter, UserID,
+            UserRole, UserSort,
+        },
     },
+    routes::page_response,
     utils::{self, auth, email::SupportEmail},
     SharedData,
 };
@@ -61,7 +66,7 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 
     ),
     components(
-        schemas(User, Role, UserRole, LicenseUser, LicenseUsers, UserIDs, UserID)
+        schemas(User, Role, SortOrder, UserRole, LicenseUser, LicenseUsers, UserIDs, UserID)
     ),
     tags(
         (name = "Users", description = "API endpoints for users"),
@@ -71,18 +76,33 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 )]
 pub struct UserApiDoc;
 
+/// Get a page of the users.
+///
+/// Sorted by `user_id` if not given, or by `email`, `company_id` or `role`.
 #[utoipa::path(
     context_path = "/api/priv",
     tag = "Users",
     responses(
-    (status = 200, description = "List of all available users", body = Vec<User>),
+    (status = 200, description = "Page of the users", body = Vec<User>,
+        headers(
+            ("X-Total-Count" = i64, description = "Number of users matching the filters"),
+            ("Link" = String, description = "Links to the first, previous, next and last pages"),
+        ),
+    ),
+    (status = 400, description = "Invalid limit, cursor or sort field"),
     (status = 500, description = "Internal Server Error"),
-    )
+    ),
+    params(PageQuery, UserFilter),
 )]
 #[get("/users")]
-async fn users(shared_data: web::Data<SharedData>, req: HttpRequest) -> impl Responder {
+async fn users(
+    shared_data: web::Data<SharedData>,
+    page_query: web::Query<PageQuery>,
+    filter: web::Query<UserFilter>,
+    req: HttpRequest,
+) -> impl Responder {
     let pool = &shared_data.db_pool;
-    match auth::validate_user(req, pool).await {
+    match auth::validate_user(req.clone(), pool).await {
         Ok(user) => {
             if user.role != user::Role::Admin {
                 return HttpResponse::Forbidden().finish();
@@ -99,19 +119,17 @@ async fn users(shared_data: web::Data<SharedData>, req: HttpRequest) -> impl Res
         }
     };
 
-    let users = user::get_all_users(pool).await;
-
-    //error check
-    if users.is_err() {
-        return HttpResponse::InternalServerError().json("Internal Server Error");
-    }
-
-    //parse to json
-    if let Ok(users) = users {
-        return HttpResponse::Ok().json(users);
+    let page = match page_query.page::<UserSort>() {
+        Ok(page) => page,
+        Err(e) => return HttpResponse::BadRequest().json(e.to_string()),
+    };
+    match user::get_users(pool, &filter, &page).await {
+        Ok(all_users) => page_response(&req, all_users, &page),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
     }
-
-    HttpResponse::InternalServerError().json("Internal Server Error")
 }
 
 #[utoipa::path(
@@ -6,6 +6,8 @@ use actix_web::{get, web, Responder};
 
 pub mod auth;
 pub mod categories;
+pub mod downloads;
+pub mod payments;
 pub mod products;
 pub mod testimonials;
 
@@ -20,4 +22,6 @@ pub fn public(cfg: &mut web::ServiceConfig) {
     cfg.configure(products::configure);
     cfg.configure(auth::configure);
     cfg.configure(testimonials::configure);
+    cfg.configure(payments::configure);
+    cfg.configure(downloads::configure);
 }
@@ -235,7 +235,7 @@ async fn valid_verify(
     tag = "Auth",
     post,
     responses(
-        (status = 200, description = "User created"),
+        (status = 200, description = "User created, company users are assigned to the company's auto-assign licenses"),
         (status = 400, description = "Company name and address are required"),
         (status = 400, description = "Company name is required"),
         (status = 400, description = "Company address is required"),
@@ -284,7 +284,29 @@ async fn verify(
                                 .await;
 
                                 match user {
-                                    Ok(_v) => {
+                                    Ok(user) => {
+                                        // a failed auto-assignment should not fail the registration
+                                        match data_access::license::assignment::auto_assign_user(
+                                            pool, &user,
+                                        )
+                                        .await
+                                        {
+                                            Ok(result) => {
+                                                for failed in result.failed() {
+                                                    log::warn!(
+                                                        "Could not auto-assign user {} to license: {:?}",
+                                                        user.user_id,
+                                                        failed
+                                                    );
+                                                }
+                                            }
+                                            Err(e) => log::error!(
+                                                "Error auto-assigning user {}: {}",
+                                                user.user_id,
+                                                e
+                                            ),
+                                        }
+
                                         // delete invite
                                         let delete =
                                             data_access::user::delete_invite(&invite_id, pool)
@@ -1,21 +1,27 @@
 use actix_web::{get, web, HttpResponse, Responder};
+use log::error;
 use utoipa::OpenApi;
 
 use crate::{
-    data_access::category::{get_categories, get_category_by_id},
+    data_access::{
+        category::{get_categories, get_category_by_id},
+        product,
+    },
     SharedData,
 };
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(categories);
     cfg.service(category_by_id);
+    cfg.service(category_products);
 }
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         categories,
-        category_by_id
+        category_by_id,
+        category_products,
     ),
     tags(
         (name = "Category", description = "API endpoints for categories")
@@ -85,3 +91,48 @@ async fn category_by_id(
 
     HttpResponse::InternalServerError().json("Internal Server Error")
 }
+
+/// Get the available products in a category, ordered by name.
+#[utoipa::path(
+    context_path = "/api",
+    get,
+    tag = "Category",
+    responses(
+        (status = 200, description = "Products in the category", body = Vec<crate::data_access::product::Product>),
+        (status = 400, description = "Bad Request"),
+        (status = 404, description = "Category not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("category_id", description = "The id of the category"),
+    ),
+)]
+#[get("/categories/{category_id}/products")]
+async fn category_products(
+    shared_data: web::Data<SharedData>,
+    category_id: web::Path<String>,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let category_id = match category_id.parse::<i32>() {
+        Ok(category_id) => category_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    match get_category_by_id(pool, &category_id).await {
+        Ok(_) => (),
+        Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json("Category not found")
+        }
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    }
+
+    match product::get_products_by_category(pool, &category_id, true).await {
+        Ok(products) => HttpResponse::Ok().json(products),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
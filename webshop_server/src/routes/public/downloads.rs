@@ -0,0 +1,139 @@
+//! Downloads of release files through signed links.
+//!
+//! The links are made by `POST /api/priv/licenses/{license_id}/releases/{release_id}/download`
+//! for users holding a seat on the license, the signature proves the server made the link.
+
+use actix_files::NamedFile;
+use actix_web::{
+    get,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web, HttpRequest, HttpResponse, Responder,
+};
+use chrono::{TimeZone, Utc};
+use log::error;
+use serde::Deserialize;
+use utoipa::{IntoParams, OpenApi};
+
+use crate::{
+    data_access::product::release,
+    storage::{DownloadGrant, StorageError},
+    SharedData,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(download);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        download,
+    ),
+    tags(
+        (name = "Downloads", description = "API endpoints for downloading licensed releases")
+    ),
+)]
+pub struct DownloadsApiDoc;
+
+/// The signed query of a download link.
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct DownloadQuery {
+    /// The license the link was made for
+    license_id: i32,
+    /// The user the link was made for
+    user_id: i32,
+    /// When the link expires, in seconds since the Unix epoch
+    expires: i64,
+    /// HMAC-SHA256 of the link, in hex
+    signature: String,
+}
+
+/// Download the file of a release through a signed link.
+///
+/// The download is recorded for the license and user of the link.
+#[utoipa::path(
+    context_path = "/api",
+    get,
+    tag = "Downloads",
+    responses(
+        (status = 200, description = "The file of the release", content_type = "application/octet-stream"),
+        (status = 400, description = "Bad Request"),
+        (status = 403, description = "Forbidden - invalid or expired link"),
+        (status = 404, description = "Release or file not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(
+        ("release_id", description = "The ID of the release"),
+        DownloadQuery,
+    ),
+)]
+#[get("/downloads/{release_id}")]
+async fn download(
+    shared_data: web::Data<SharedData>,
+    release_id: web::Path<String>,
+    query: web::Query<DownloadQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let release_id = match release_id.parse::<i32>() {
+        Ok(release_id) => release_id,
+        Err(_) => return HttpResponse::BadRequest().json("Bad Request"),
+    };
+    let expires_at = match Utc.timestamp_opt(query.expires, 0).single() {
+        Some(expires_at) => expires_at,
+        None => return HttpResponse::Forbidden().json("Invalid download link"),
+    };
+    let grant = DownloadGrant {
+        release_id,
+        license_id: query.license_id,
+        user_id: query.user_id,
+        expires_at,
+    };
+    if !grant.verify(&shared_data.download_secret, &query.signature) {
+        return HttpResponse::Forbidden().json("Invalid download link");
+    }
+    if grant.is_expired() {
+        return HttpResponse::Forbidden().json("Download link has expired");
+    }
+
+    let pool = &shared_data.db_pool;
+    let release = match release::get_release(pool, &release_id).await {
+        Ok(release) => release,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json("Release not found"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    };
+    let (storage_key, file_name) = match (release.storage_key, release.download) {
+        (Some(storage_key), Some(download)) => (storage_key, download.file_name),
+        _ => return HttpResponse::NotFound().json("Release has no file to download"),
+    };
+    let file = match shared_data.file_storage.open(&storage_key).await {
+        Ok(file) => file,
+        Err(StorageError::NotFound) => return HttpResponse::NotFound().json("File not found"),
+        Err(e) => {
+            error!("Could not open {}: {}", storage_key, e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    };
+    let file = match NamedFile::from_file(file, &file_name) {
+        Ok(file) => file,
+        Err(e) => {
+            error!("Could not open {}: {}", storage_key, e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    };
+
+    if let Err(e) =
+        release::record_download(pool, &release_id, &grant.license_id, &grant.user_id).await
+    {
+        error!("{}", e);
+        return HttpResponse::InternalServerError().json("Internal Server Error");
+    }
+
+    file.set_content_disposition(ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(file_name)],
+    })
+    .into_response(&req)
+}
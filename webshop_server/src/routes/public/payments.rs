@@ -0,0 +1,207 @@
+//! Endpoints called by the payment provider, and by customers paying at the mock provider.
+
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    data_access::payment::{self, PaymentRecordError},
+    invoice,
+    payment::{PaymentError, PaymentIntent, PaymentStatus, WebhookEventType, SIGNATURE_HEADER},
+    SharedData,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(webhook);
+}
+
+/// Mounts the payment page of the mock provider, only when mock payments are enabled.
+pub fn configure_mock(cfg: &mut web::ServiceConfig) {
+    cfg.service(confirm_mock_payment);
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        webhook,
+        confirm_mock_payment,
+    ),
+    components(
+        schemas(MockConfirmation, PaymentIntent, PaymentStatus)
+    ),
+    tags(
+        (name = "Payments", description = "API endpoints for payments")
+    ),
+)]
+pub struct PaymentsApiDoc;
+
+/// Webhook callback of the payment provider, reporting the outcome of a payment.
+///
+/// The body must be signed by the provider, the signature is sent in the `Payment-Signature`
+/// header. A succeeded payment marks its order paid and applies it to the licenses,
+/// and the invoice of the order is emailed to the user who placed it.
+/// An outcome that has already been recorded is acknowledged again, so the provider can retry.
+#[utoipa::path(
+    context_path = "/api",
+    post,
+    tag = "Payments",
+    request_body(content = String, description = "Signed event from the payment provider"),
+    params(
+        ("Payment-Signature" = String, Header, description = "HMAC-SHA256 of the body, in hex"),
+    ),
+    responses(
+        (status = 200, description = "Event processed"),
+        (status = 400, description = "Invalid event"),
+        (status = 401, description = "Invalid signature"),
+        (status = 404, description = "Unknown payment"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+)]
+#[post("/payments/webhook")]
+async fn webhook(
+    shared_data: web::Data<SharedData>,
+    req: HttpRequest,
+    body: web::Bytes,
+) -> impl Responder {
+    let signature = match req
+        .headers()
+        .get(SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(signature) => signature,
+        None => return HttpResponse::Unauthorized().json("Missing signature"),
+    };
+    process_webhook(&shared_data, &body, signature).await
+}
+
+/// Verifies a webhook callback and records the payment outcome it reports.
+async fn process_webhook(
+    shared_data: &SharedData,
+    payload: &[u8],
+    signature: &str,
+) -> HttpResponse {
+    let provider = &shared_data.payment_provider;
+    let event = match provider.verify_webhook(payload, signature) {
+        Ok(event) => event,
+        Err(PaymentError::InvalidSignature) => {
+            warn!("Rejected payment webhook with an invalid signature");
+            return HttpResponse::Unauthorized().json("Invalid signature");
+        }
+        Err(e) => return HttpResponse::BadRequest().json(e.to_string()),
+    };
+
+    match payment::record_payment_outcome(&shared_data.db_pool, provider.name(), &event).await {
+        Ok(order_id) => {
+            info!(
+                "Payment {} of order {}: {:?}",
+                event.intent_id, order_id, event.event_type
+            );
+            if event.event_type == WebhookEventType::PaymentSucceeded {
+                // the provider only waits for the acknowledgement, not for the email
+                let pool = shared_data.db_pool.clone();
+                let mailer = shared_data.mailer.clone();
+                let seller_vat_number = shared_data.seller_vat_number.clone();
+                actix_web::rt::spawn(async move {
+                    invoice::send_order_confirmation(&pool, &mailer, &seller_vat_number, &order_id)
+                        .await;
+                });
+            }
+            HttpResponse::Ok().finish()
+        }
+        Err(PaymentRecordError::AlreadyProcessed) => HttpResponse::Ok().finish(),
+        Err(PaymentRecordError::UnknownIntent) => {
+            HttpResponse::NotFound().json("Unknown payment intent")
+        }
+        Err(PaymentRecordError::AmountMismatch) => {
+            error!(
+                "Payment {} does not match its order, amount {} {:?}",
+                event.intent_id, event.amount, event.currency
+            );
+            HttpResponse::BadRequest().json("Amount paid does not match the order")
+        }
+        Err(PaymentRecordError::OrderNotApplicable(e)) => {
+            error!(
+                "Paid order of payment {} cannot be applied: {}",
+                event.intent_id, e
+            );
+            HttpResponse::Conflict().json(e.to_string())
+        }
+        Err(PaymentRecordError::OrderNotPending(order_id)) => {
+            error!(
+                "Payment {} succeeded for order {}, which no longer waits for payment, refund it",
+                event.intent_id, order_id
+            );
+            HttpResponse::Ok().finish()
+        }
+        Err(PaymentRecordError::SqlxError(e)) => {
+            error!("Error recording payment: {}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct MockConfirmation {
+    /// Client secret of the payment intent.
+    client_secret: String,
+    /// Whether the payment goes through, true if not given.
+    succeed: Option<bool>,
+}
+
+/// Pay a payment intent at the mock payment provider.
+///
+/// Stands in for the payment page of a real provider, only available when mock payments
+/// are enabled for development. The outcome is reported through the same signed webhook callback.
+#[utoipa::path(
+    context_path = "/api",
+    post,
+    tag = "Payments",
+    request_body = MockConfirmation,
+    params(
+        ("intent_id", description = "The id of the payment intent"),
+    ),
+    responses(
+        (status = 200, description = "Payment processed"),
+        (status = 404, description = "Unknown payment intent"),
+        (status = 409, description = "Payment intent already paid or declined"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+)]
+#[post("/payments/mock/{intent_id}/confirm")]
+async fn confirm_mock_payment(
+    shared_data: web::Data<SharedData>,
+    intent_id: web::Path<String>,
+    confirmation: web::Json<MockConfirmation>,
+) -> impl Responder {
+    let mock = match &shared_data.mock_payments {
+        Some(mock) => mock,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    if let Err(e) = mock.authorize(&intent_id, &confirmation.client_secret) {
+        return mock_error_response(e);
+    }
+    let outcome = if confirmation.succeed.unwrap_or(true) {
+        mock.pay(&intent_id)
+    } else {
+        mock.decline(&intent_id)
+    };
+    if let Err(e) = outcome {
+        return mock_error_response(e);
+    }
+    match mock.signed_webhook(&intent_id) {
+        Ok((payload, signature)) => process_webhook(&shared_data, &payload, &signature).await,
+        Err(e) => mock_error_response(e),
+    }
+}
+
+fn mock_error_response(e: PaymentError) -> HttpResponse {
+    match e {
+        PaymentError::UnknownIntent => HttpResponse::NotFound().json(e.to_string()),
+        PaymentError::InvalidState => HttpResponse::Conflict().json(e.to_string()),
+        e => {
+            error!("Error at mock payment provider: {}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
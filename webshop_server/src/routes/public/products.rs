@@ -1,17 +1,39 @@
-use actix_web::{get, web, HttpResponse, Responder};
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
 use log::error;
-use utoipa::OpenApi;
+use serde::Deserialize;
+use utoipa::{IntoParams, OpenApi};
 
 pub mod descriptions;
 
 use crate::{
-    data_access::product::{self, Product},
+    data_access::{
+        license::trial::{self, ProductTrial},
+        pagination::{PageQuery, SortOrder},
+        product::{
+            self,
+            price_list::{self, PriceList},
+            price_tier::{self, PriceTier},
+            publishing,
+            release::{self, Release, ReleaseDownload},
+            search::{self, SearchFilter, SearchHit},
+            Product, ProductFilter, ProductSort,
+        },
+    },
+    money::Currency,
+    pricing::{self, AppliedDiscount, PriceQuote, PricingError},
+    routes::page_response,
     SharedData,
 };
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(all_available_products);
+    cfg.service(search_products);
     cfg.service(product_by_id);
+    cfg.service(product_trial);
+    cfg.service(product_prices);
+    cfg.service(product_price_tiers);
+    cfg.service(product_quote);
+    cfg.service(product_releases);
     cfg.service(web::scope("/products").configure(descriptions::configure));
 }
 
@@ -19,10 +41,16 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 #[openapi(
     paths(
         all_available_products,
+        search_products,
         product_by_id,
+        product_trial,
+        product_prices,
+        product_price_tiers,
+        product_quote,
+        product_releases,
     ),
     components(
-        schemas(Product)
+        schemas(Product, SortOrder, SearchHit, ProductTrial, PriceList, PriceTier, PriceQuote, AppliedDiscount, Currency, Release, ReleaseDownload)
     ),
     tags(
         (name = "Products", description = "Api endpoints for products"),
@@ -30,21 +58,50 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 )]
 pub struct ProductsApiDoc;
 
-/// Get all available products
+#[derive(Deserialize, IntoParams)]
+struct ProductsQuery {
+    /// Only products in this currency
+    currency: Option<Currency>,
+}
+
+/// Get a page of the available products
+///
+/// Sorted by `display_name` if not given, or by `price_per_user` or `product_id`.
 #[utoipa::path(
     context_path = "/api",
     get,
     tag = "Products",
     responses(
-    (status = 200, description = "List of all available products", body = Vec<Product>),
-    (status = 500, description = "Internal Server Error"),
-)
+        (status = 200, description = "Page of the available products", body = Vec<Product>,
+            headers(
+                ("X-Total-Count" = i64, description = "Number of products matching the filters"),
+                ("Link" = String, description = "Links to the first, previous, next and last pages"),
+            ),
+        ),
+        (status = 400, description = "Invalid limit, cursor or sort field"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(PageQuery, ProductsQuery),
 )]
 #[get("/products")]
-async fn all_available_products(shared_data: web::Data<SharedData>) -> impl Responder {
+async fn all_available_products(
+    shared_data: web::Data<SharedData>,
+    page_query: web::Query<PageQuery>,
+    query: web::Query<ProductsQuery>,
+    req: HttpRequest,
+) -> impl Responder {
     let pool = &shared_data.db_pool;
-    match product::get_products(pool, true).await {
-        Ok(products) => HttpResponse::Ok().json(products),
+    let page = match page_query.page::<ProductSort>() {
+        Ok(page) => page,
+        Err(e) => return HttpResponse::BadRequest().json(e.to_string()),
+    };
+    let filter = ProductFilter {
+        available: Some(true),
+        currency: query.currency,
+        status: None,
+    };
+    match product::get_products(pool, &filter, &page).await {
+        Ok(products) => page_response(&req, products, &page),
         Err(e) => {
             error!("Error: {}", e);
             HttpResponse::InternalServerError().json("Internal Server Error")
@@ -52,7 +109,55 @@ async fn all_available_products(shared_data: web::Data<SharedData>) -> impl Resp
     }
 }
 
+#[derive(Deserialize, IntoParams)]
+struct SearchQuery {
+    /// Search terms, in web search syntax, e.g. `tax -legal` or `"business process"`
+    q: String,
+    /// Only products in this category
+    category_id: Option<i32>,
+}
+
+/// Search available products
+///
+/// Searches the name, short description and description of the products,
+/// best match first. Matches in the name rank highest.
+#[utoipa::path(
+    context_path = "/api",
+    get,
+    tag = "Products",
+    responses(
+        (status = 200, description = "Products matching the search, best match first", body = Vec<SearchHit>),
+        (status = 400, description = "No search terms"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    params(SearchQuery),
+)]
+#[get("/products/search")]
+async fn search_products(
+    shared_data: web::Data<SharedData>,
+    query: web::Query<SearchQuery>,
+) -> impl Responder {
+    let text = query.q.trim();
+    if text.is_empty() {
+        return HttpResponse::BadRequest().json("Search terms are required");
+    }
+    let filter = SearchFilter {
+        text,
+        category_id: query.category_id,
+        available: Some(true),
+    };
+    match search::search_products(&shared_data.db_pool, &filter).await {
+        Ok(hits) => HttpResponse::Ok().json(hits),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
 /// Get a specific product by id
+///
+/// Drafts and scheduled products are not found, archived products are found but not available.
 #[utoipa::path (
     context_path = "/api",
     get,
@@ -73,6 +178,14 @@ async fn product_by_id(
     product_id: web::Path<String>,
 ) -> impl Responder {
     let pool = &shared_data.db_pool;
+    match publishing::is_public(pool, product_id.as_str()).await {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::NotFound().json("Product not found"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    }
     let product = product::get_product_by_id(pool, product_id.as_str()).await;
 
     match product {
@@ -83,3 +196,268 @@ async fn product_by_id(
         },
     }
 }
+
+/// Get the trial offered for a product
+#[utoipa::path (
+    context_path = "/api",
+    get,
+    tag = "Products",
+    responses(
+        (status = 200, description = "Returns the trial offered for the product", body = ProductTrial),
+        (status = 404, description = "Product not found or offers no trial"),
+        (status = 500, description = "Internal Server Error"),
+        ),
+    params(
+        ("product_id", description = "The id of the product"),
+        )
+    )
+]
+#[get("/products/{product_id}/trial")]
+async fn product_trial(
+    shared_data: web::Data<SharedData>,
+    product_id: web::Path<String>,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    match publishing::is_public(pool, product_id.as_str()).await {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::NotFound().json("Product not found"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    }
+    match trial::get_product_trial(pool, product_id.as_str()).await {
+        Ok(product_trial) => HttpResponse::Ok().json(product_trial),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => HttpResponse::NotFound().json("Product offers no trial"),
+            _ => {
+                error!("{}", e);
+                HttpResponse::InternalServerError().json("Internal Server Error")
+            }
+        },
+    }
+}
+
+/// Get the price lists of a product, one for each currency it is sold in
+///
+/// The price list in the currency of the product comes first.
+#[utoipa::path (
+    context_path = "/api",
+    get,
+    tag = "Products",
+    responses(
+        (status = 200, description = "Price lists of the product", body = Vec<PriceList>),
+        (status = 404, description = "Product not found"),
+        (status = 500, description = "Internal Server Error"),
+        ),
+    params(
+        ("product_id", description = "The id of the product"),
+        )
+    )
+]
+#[get("/products/{product_id}/prices")]
+async fn product_prices(
+    shared_data: web::Data<SharedData>,
+    product_id: web::Path<String>,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    match publishing::is_public(pool, product_id.as_str()).await {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::NotFound().json("Product not found"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    }
+    let product = match product::get_product_by_id(pool, product_id.as_str()).await {
+        Ok(product) => product,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json("Product not found"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    };
+    match price_list::get_price_lists(pool, &product).await {
+        Ok(price_lists) => HttpResponse::Ok().json(price_lists),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+struct PriceTiersQuery {
+    /// Currency of the price list, defaults to the currency of the product
+    currency: Option<Currency>,
+}
+
+/// Get the volume pricing tiers of a product
+#[utoipa::path (
+    context_path = "/api",
+    get,
+    tag = "Products",
+    responses(
+        (status = 200, description = "Price tiers of the product, ordered by smallest number of seats", body = Vec<PriceTier>),
+        (status = 404, description = "Product not found"),
+        (status = 500, description = "Internal Server Error"),
+        ),
+    params(
+        ("product_id", description = "The id of the product"),
+        PriceTiersQuery,
+        )
+    )
+]
+#[get("/products/{product_id}/price_tiers")]
+async fn product_price_tiers(
+    shared_data: web::Data<SharedData>,
+    product_id: web::Path<String>,
+    query: web::Query<PriceTiersQuery>,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    match publishing::is_public(pool, product_id.as_str()).await {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::NotFound().json("Product not found"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    }
+    let currency = match query.currency {
+        Some(currency) => currency,
+        None => match product::get_product_by_id(pool, product_id.as_str()).await {
+            Ok(product) => product.currency(),
+            Err(sqlx::Error::RowNotFound) => {
+                return HttpResponse::NotFound().json("Product not found")
+            }
+            Err(e) => {
+                error!("{}", e);
+                return HttpResponse::InternalServerError().json("Internal Server Error");
+            }
+        },
+    };
+    match price_tier::get_price_tiers(pool, product_id.as_str(), currency).await {
+        Ok(tiers) => HttpResponse::Ok().json(tiers),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+struct QuoteQuery {
+    /// Number of seats
+    seats: i32,
+    /// Length of the license in years, defaults to 1
+    term_years: Option<i32>,
+    /// Discount code to apply to the price
+    discount_code: Option<String>,
+    /// Currency to price in, defaults to the currency of the product.
+    /// Companies buy in their billing currency
+    currency: Option<Currency>,
+}
+
+/// Get the price of a number of seats of a product
+///
+/// A discount code given is applied to the price. Whether it is restricted to some companies
+/// is only checked at purchase.
+#[utoipa::path (
+    context_path = "/api",
+    get,
+    tag = "Products",
+    responses(
+        (status = 200, description = "Price computed by the server", body = PriceQuote),
+        (status = 400, description = "Invalid number of seats or term, product not sold in the currency, or discount code not valid for the product"),
+        (status = 404, description = "Product or discount code not found"),
+        (status = 409, description = "Discount code used up"),
+        (status = 500, description = "Internal Server Error"),
+        ),
+    params(
+        ("product_id", description = "The id of the product"),
+        QuoteQuery,
+        )
+    )
+]
+#[get("/products/{product_id}/quote")]
+async fn product_quote(
+    shared_data: web::Data<SharedData>,
+    product_id: web::Path<String>,
+    query: web::Query<QuoteQuery>,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    let term_years = query.term_years.unwrap_or(1);
+    let quote = match (
+        pricing::quote(
+            pool,
+            product_id.as_str(),
+            query.seats,
+            term_years,
+            query.currency,
+        )
+        .await,
+        query.discount_code.as_deref().map(str::trim),
+    ) {
+        (Ok(quote), Some(code)) if !code.is_empty() => {
+            pricing::apply_discount(pool, quote, code, None).await
+        }
+        (quote, _) => quote,
+    };
+    match quote {
+        Ok(quote) => HttpResponse::Ok().json(quote),
+        Err(e) => match e {
+            PricingError::ProductNotFound | PricingError::DiscountNotFound => {
+                HttpResponse::NotFound().json(e.to_string())
+            }
+            PricingError::InvalidSeats
+            | PricingError::InvalidTerm
+            | PricingError::DiscountNotValidNow
+            | PricingError::DiscountNotApplicable
+            | PricingError::EmptyCart
+            | PricingError::NotSoldInCurrency => HttpResponse::BadRequest().json(e.to_string()),
+            PricingError::DiscountUsedUp => HttpResponse::Conflict().json(e.to_string()),
+            PricingError::SqlxError(e) => {
+                error!("{}", e);
+                HttpResponse::InternalServerError().json("Internal Server Error")
+            }
+        },
+    }
+}
+
+/// Get the releases of a product, newest first
+#[utoipa::path (
+    context_path = "/api",
+    get,
+    tag = "Products",
+    responses(
+        (status = 200, description = "Releases of the product, newest first", body = Vec<Release>),
+        (status = 404, description = "Product not found"),
+        (status = 500, description = "Internal Server Error"),
+        ),
+    params(
+        ("product_id", description = "The id of the product"),
+        )
+    )
+]
+#[get("/products/{product_id}/releases")]
+async fn product_releases(
+    shared_data: web::Data<SharedData>,
+    product_id: web::Path<String>,
+) -> impl Responder {
+    let pool = &shared_data.db_pool;
+    match publishing::is_public(pool, product_id.as_str()).await {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::NotFound().json("Product not found"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    }
+    match release::get_releases(pool, product_id.as_str()).await {
+        Ok(releases) => HttpResponse::Ok().json(releases),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("Internal Server Error")
+        }
+    }
+}
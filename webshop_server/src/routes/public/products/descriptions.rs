@@ -1,4 +1,7 @@
-use crate::{data_access::product, SharedData};
+use crate::{
+    data_access::product::{self, publishing},
+    SharedData,
+};
 use actix_web::{get, web, HttpResponse, Responder};
 
 use log::error;
@@ -49,31 +52,25 @@ async fn get_product_descriptions(
     product_id: web::Path<String>,
 ) -> impl Responder {
     let pool = &shared_data.db_pool;
+    // drafts and scheduled products are not found
+    match publishing::is_public(pool, product_id.as_str()).await {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::NotFound().json("Product not found"),
+        Err(e) => {
+            error!("Error while checking if product is public: {}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    }
     let descriptions =
         product::description::get_product_description_components(pool, product_id.as_str()).await;
 
-    let descriptions = match descriptions {
-        Ok(descriptions) => descriptions,
+    match descriptions {
+        Ok(descriptions) => HttpResponse::Ok().json(descriptions),
         Err(e) => {
             error!("Error while getting product descriptions: {}", e);
-            return HttpResponse::InternalServerError().json("Internal Server Error");
-        }
-    };
-
-    if descriptions.is_empty() {
-        let is_valid = match product::product_exists(pool, product_id.as_str()).await {
-            Ok(is_valid) => is_valid,
-            Err(e) => {
-                error!("Error while checking if product exists: {}", e);
-                return HttpResponse::InternalServerError().json("Internal Server Error");
-            }
-        };
-        if !is_valid {
-            return HttpResponse::NotFound().json("Product not found");
+            HttpResponse::InternalServerError().json("Internal Server Error")
         }
     }
-
-    HttpResponse::Ok().json(descriptions)
 }
 
 /// Get a specific description component for a specific product
@@ -99,6 +96,14 @@ async fn get_product_description_component_by_id(
 ) -> impl Responder {
     let pool = &shared_data.db_pool;
     let (product_id, component_id) = path.into_inner();
+    match publishing::is_public(pool, product_id.as_str()).await {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::NotFound().json("Product or description not found"),
+        Err(e) => {
+            error!("Error while checking if product is public: {}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    }
     let description = product::description::get_description_component_checked(
         pool,
         product_id.as_str(),
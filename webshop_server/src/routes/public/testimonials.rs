@@ -1,5 +1,8 @@
 use crate::{
-    data_access::testimonial::{self, Testimonial},
+    data_access::{
+        product::publishing,
+        testimonial::{self, Testimonial},
+    },
     SharedData,
 };
 
@@ -32,6 +35,7 @@ pub struct TestimonialsOpenApi;
   tag = "Testimonials",
   responses(
   (status = 200, description = "List of all testimonials for a product", body = Vec<Testimonial>),
+  (status = 404, description = "Product not found"),
   (status = 500, description = "Internal Server Error"),
 ),
   params(
@@ -44,6 +48,14 @@ async fn get_testimonials_by_product(
     product_id: web::Path<String>,
 ) -> impl Responder {
     let pool = &shared_data.db_pool;
+    match publishing::is_public(pool, &product_id).await {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::NotFound().json("Product not found"),
+        Err(e) => {
+            error!("Error while checking if product is public: {}", e);
+            return HttpResponse::InternalServerError().json("Internal Server Error");
+        }
+    }
     let testimonials = testimonial::get_testimonials_by_product(pool, &product_id).await;
     match testimonials {
         Ok(testimonials) => HttpResponse::Ok().json(testimonials),
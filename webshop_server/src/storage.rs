@@ -0,0 +1,202 @@
+//! File storage for release downloads, and signed links to download them.
+//!
+//! Files are stored through a [`FileStorage`] under a key, e.g.
+//! `releases/proflex_tax_solutions/3/proflex_tax_solutions-3.0.0.zip`.
+//! [`LocalStorage`] keeps them in a directory on the server.
+//!
+//! Downloads are served through short-lived links signed with HMAC-SHA256, so a link only works
+//! for the user and license it was made for, and only until it expires.
+
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// How long a download link works after it is made.
+pub const DOWNLOAD_LINK_MINUTES: i64 = 5;
+
+#[derive(Debug)]
+pub enum StorageError {
+    /// No file is stored under the key.
+    NotFound,
+    /// The key is empty or points outside the storage.
+    InvalidKey,
+    Io(io::Error),
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StorageError::NotFound => write!(f, "File not found"),
+            StorageError::InvalidKey => write!(f, "Invalid storage key"),
+            StorageError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => StorageError::NotFound,
+            _ => StorageError::Io(e),
+        }
+    }
+}
+
+/// Where uploaded files are stored.
+pub trait FileStorage: Send + Sync {
+    /// Moves a file into the storage under a key, replacing the file stored under it.
+    fn store<'a>(
+        &'a self,
+        key: &'a str,
+        source: &'a Path,
+    ) -> BoxFuture<'a, Result<(), StorageError>>;
+
+    /// Opens the file stored under a key, for sending it to a client.
+    fn open<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<fs::File, StorageError>>;
+
+    /// Deletes the file stored under a key, if there is one.
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), StorageError>>;
+}
+
+/// Stores files in a directory on the server, a key being the path of its file in the directory.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Path of the file of a key, the key must not leave the directory.
+    fn path(&self, key: &str) -> Result<PathBuf, StorageError> {
+        let key = Path::new(key);
+        if key.as_os_str().is_empty()
+            || !key
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(StorageError::InvalidKey);
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+impl FileStorage for LocalStorage {
+    fn store<'a>(
+        &'a self,
+        key: &'a str,
+        source: &'a Path,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            let source = source.to_path_buf();
+            actix_web::web::block(move || -> io::Result<()> {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                // a rename fails across file systems, copy the file then
+                if fs::rename(&source, &path).is_err() {
+                    fs::copy(&source, &path)?;
+                    fs::remove_file(&source)?;
+                }
+                Ok(())
+            })
+            .await
+            .map_err(|e| StorageError::Io(io::Error::other(e)))??;
+            Ok(())
+        })
+    }
+
+    fn open<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<fs::File, StorageError>> {
+        Box::pin(async move { Ok(fs::File::open(self.path(key)?)?) })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            match fs::remove_file(self.path(key)?) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(StorageError::Io(e)),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+/// What a download link gives access to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DownloadGrant {
+    pub release_id: i32,
+    pub license_id: i32,
+    pub user_id: i32,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl DownloadGrant {
+    /// A grant that expires a few minutes from now.
+    pub fn new(release_id: i32, license_id: i32, user_id: i32) -> Self {
+        let expires_at = Utc::now() + Duration::minutes(DOWNLOAD_LINK_MINUTES);
+        // whole seconds, as they are in the link
+        let expires_at = Utc
+            .timestamp_opt(expires_at.timestamp(), 0)
+            .single()
+            .unwrap_or(expires_at);
+        Self {
+            release_id,
+            license_id,
+            user_id,
+            expires_at,
+        }
+    }
+
+    fn message(&self) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            self.release_id,
+            self.license_id,
+            self.user_id,
+            self.expires_at.timestamp()
+        )
+    }
+
+    /// Signs the grant with HMAC-SHA256, returning the signature as hex.
+    pub fn sign(&self, secret: &[u8]) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+        mac.update(self.message().as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Checks the signature of the grant, in constant time.
+    pub fn verify(&self, secret: &[u8], signature: &str) -> bool {
+        let signature = match hex::decode(signature.trim()) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+        mac.update(self.message().as_bytes());
+        mac.verify_slice(&signature).is_ok()
+    }
+
+    /// Path and query of the link to download the release with the grant.
+    pub fn link(&self, secret: &[u8]) -> String {
+        format!(
+            "/api/downloads/{}?license_id={}&user_id={}&expires={}&signature={}",
+            self.release_id,
+            self.license_id,
+            self.user_id,
+            self.expires_at.timestamp(),
+            self.sign(secret)
+        )
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Utc::now() > self.expires_at
+    }
+}
@@ -0,0 +1,283 @@
+//! Tax rules for orders.
+//!
+//! ProFlex sells from Norway, so the tax on an order depends on where the company is billed:
+//! - Norwegian companies pay Norwegian VAT.
+//! - EU companies with a VAT number pay no VAT, they account for it themselves (reverse charge).
+//! - EU companies without a VAT number pay the VAT of their own country.
+//! - Companies anywhere else pay no VAT, the sale is an export.
+//!
+//! Companies without a billing address are taxed as Norwegian companies.
+//! Rates are in basis points, 2500 is 25%.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Country the seller is established in.
+pub const SELLER_COUNTRY: &str = "NO";
+
+/// Standard VAT rates of the EU member states, by ISO 3166-1 alpha-2 code.
+const EU_VAT_RATES: [(&str, i32); 27] = [
+    ("AT", 2000),
+    ("BE", 2100),
+    ("BG", 2000),
+    ("CY", 1900),
+    ("CZ", 2100),
+    ("DE", 1900),
+    ("DK", 2500),
+    ("EE", 2400),
+    ("ES", 2100),
+    ("FI", 2550),
+    ("FR", 2000),
+    ("GR", 2400),
+    ("HR", 2500),
+    ("HU", 2700),
+    ("IE", 2300),
+    ("IT", 2200),
+    ("LT", 2100),
+    ("LU", 1700),
+    ("LV", 2100),
+    ("MT", 1800),
+    ("NL", 2100),
+    ("PL", 2300),
+    ("PT", 2300),
+    ("RO", 2100),
+    ("SE", 2500),
+    ("SI", 2200),
+    ("SK", 2300),
+];
+/// Standard VAT rate of the seller's country.
+const SELLER_VAT_RATE: i32 = 2500;
+
+/// Why an order is taxed the way it is.
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[sqlx(type_name = "tax_treatment_enum", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum TaxTreatment {
+    /// VAT of the seller's country.
+    Domestic,
+    /// VAT of the customer's EU country, for customers without a VAT number.
+    EuConsumer,
+    /// No VAT, the EU customer accounts for it.
+    ReverseCharge,
+    /// No VAT, the customer is outside the EU and the seller's country.
+    Export,
+}
+
+impl TaxTreatment {
+    /// Note printed on invoices, explaining why no VAT is charged.
+    pub fn invoice_note(&self) -> Option<&'static str> {
+        match self {
+            TaxTreatment::Domestic | TaxTreatment::EuConsumer => None,
+            TaxTreatment::ReverseCharge => {
+                Some("Reverse charge: VAT to be accounted for by the recipient.")
+            }
+            TaxTreatment::Export => Some("Export of services, no VAT charged."),
+        }
+    }
+}
+
+/// Tax to charge on an order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TaxRule {
+    pub treatment: TaxTreatment,
+    /// Rate in basis points.
+    pub rate: i32,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TaxError {
+    /// The country is not an ISO 3166-1 alpha-2 code.
+    InvalidCountry,
+    /// The VAT number is not a VAT number of the country.
+    InvalidVatNumber,
+}
+
+impl std::fmt::Display for TaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TaxError::InvalidCountry => write!(f, "Country must be a two letter country code"),
+            TaxError::InvalidVatNumber => {
+                write!(f, "VAT number must start with the prefix of the country")
+            }
+        }
+    }
+}
+
+fn eu_vat_rate(country: &str) -> Option<i32> {
+    EU_VAT_RATES
+        .iter()
+        .find(|(code, _)| *code == country)
+        .map(|(_, rate)| *rate)
+}
+
+/// Returns the tax to charge a company billed in `country`,
+/// or in the seller's country if it has no billing address.
+pub fn tax_rule(country: Option<&str>, vat_number: Option<&str>) -> TaxRule {
+    let country = country.unwrap_or(SELLER_COUNTRY);
+    if country == SELLER_COUNTRY {
+        return TaxRule {
+            treatment: TaxTreatment::Domestic,
+            rate: SELLER_VAT_RATE,
+        };
+    }
+    match (eu_vat_rate(country), vat_number) {
+        (Some(_), Some(_)) => TaxRule {
+            treatment: TaxTreatment::ReverseCharge,
+            rate: 0,
+        },
+        (Some(rate), None) => TaxRule {
+            treatment: TaxTreatment::EuConsumer,
+            rate,
+        },
+        (None, _) => TaxRule {
+            treatment: TaxTreatment::Export,
+            rate: 0,
+        },
+    }
+}
+
+/// Checks a country code, returning it in upper case.
+pub fn normalize_country(country: &str) -> Result<String, TaxError> {
+    let country = country.trim().to_uppercase();
+    if country.len() == 2 && country.chars().all(|c| c.is_ascii_uppercase()) {
+        Ok(country)
+    } else {
+        Err(TaxError::InvalidCountry)
+    }
+}
+
+/// Checks a VAT number of a country, returning it in upper case without spaces,
+/// dots or dashes. The number must start with the prefix of the country,
+/// which is the country code, except `EL` for Greece.
+pub fn normalize_vat_number(country: &str, vat_number: &str) -> Result<String, TaxError> {
+    let vat_number: String = vat_number
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '.' | '-'))
+        .collect::<String>()
+        .to_uppercase();
+    let prefix = if country == "GR" { "EL" } else { country };
+    let number = vat_number
+        .strip_prefix(prefix)
+        .ok_or(TaxError::InvalidVatNumber)?;
+    if (2..=13).contains(&number.len()) && number.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Ok(vat_number)
+    } else {
+        Err(TaxError::InvalidVatNumber)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn norwegian_companies_pay_norwegian_vat() {
+        let domestic = TaxRule {
+            treatment: TaxTreatment::Domestic,
+            rate: 2500,
+        };
+        assert_eq!(tax_rule(Some("NO"), None), domestic);
+        assert_eq!(tax_rule(Some("NO"), Some("NO999999999MVA")), domestic);
+        // without a billing address
+        assert_eq!(tax_rule(None, None), domestic);
+    }
+
+    #[test]
+    fn eu_companies_with_a_vat_number_are_reverse_charged() {
+        let rule = tax_rule(Some("DE"), Some("DE123456789"));
+        assert_eq!(rule.treatment, TaxTreatment::ReverseCharge);
+        assert_eq!(rule.rate, 0);
+        assert!(rule.treatment.invoice_note().is_some());
+    }
+
+    #[test]
+    fn eu_companies_without_a_vat_number_pay_their_own_vat() {
+        assert_eq!(
+            tax_rule(Some("DE"), None),
+            TaxRule {
+                treatment: TaxTreatment::EuConsumer,
+                rate: 1900,
+            }
+        );
+        assert_eq!(tax_rule(Some("HU"), None).rate, 2700);
+        assert_eq!(tax_rule(Some("LU"), None).rate, 1700);
+        assert_eq!(tax_rule(Some("FI"), None).rate, 2550);
+        assert!(TaxTreatment::EuConsumer.invoice_note().is_none());
+    }
+
+    #[test]
+    fn companies_outside_the_eu_pay_no_vat() {
+        for (country, vat_number) in [("US", None), ("GB", Some("GB123456789")), ("CH", None)] {
+            assert_eq!(
+                tax_rule(Some(country), vat_number),
+                TaxRule {
+                    treatment: TaxTreatment::Export,
+                    rate: 0,
+                }
+            );
+        }
+        assert!(TaxTreatment::Export.invoice_note().is_some());
+    }
+
+    #[test]
+    fn looks_up_the_rate_of_every_member_state() {
+        for (country, rate) in EU_VAT_RATES {
+            assert_eq!(eu_vat_rate(country), Some(rate));
+            assert!((1_000..=3_000).contains(&rate), "{}", country);
+        }
+        assert_eq!(eu_vat_rate("NO"), None);
+        assert_eq!(eu_vat_rate("de"), None);
+        assert_eq!(eu_vat_rate(""), None);
+    }
+
+    #[test]
+    fn normalizes_country_codes() {
+        assert_eq!(normalize_country(" no ").unwrap(), "NO");
+        assert_eq!(normalize_country("De").unwrap(), "DE");
+        for invalid in ["", "N", "NOR", "N1", "Ø"] {
+            assert_eq!(
+                normalize_country(invalid),
+                Err(TaxError::InvalidCountry),
+                "{:?}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn normalizes_vat_numbers() {
+        assert_eq!(
+            normalize_vat_number("DE", "de 123.456-789").unwrap(),
+            "DE123456789"
+        );
+        assert_eq!(
+            normalize_vat_number("NO", "NO999999999MVA").unwrap(),
+            "NO999999999MVA"
+        );
+        // Greece uses EL
+        assert_eq!(
+            normalize_vat_number("GR", "EL123456789").unwrap(),
+            "EL123456789"
+        );
+        assert_eq!(
+            normalize_vat_number("GR", "GR123456789"),
+            Err(TaxError::InvalidVatNumber)
+        );
+        assert_eq!(
+            normalize_vat_number("DE", "FR123456789"),
+            Err(TaxError::InvalidVatNumber)
+        );
+        assert_eq!(
+            normalize_vat_number("DE", "DE1"),
+            Err(TaxError::InvalidVatNumber)
+        );
+        assert_eq!(
+            normalize_vat_number("DE", "DE12345678901234"),
+            Err(TaxError::InvalidVatNumber)
+        );
+        assert_eq!(
+            normalize_vat_number("DE", "DE1234_5678"),
+            Err(TaxError::InvalidVatNumber)
+        );
+    }
+}
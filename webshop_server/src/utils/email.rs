@@ -1,5 +1,6 @@
+use chrono::{DateTime, Utc};
 use lettre::{
-    message::{header::ContentType, Mailbox},
+    message::{header::ContentType, Attachment, Mailbox, MultiPart, SinglePart},
     Message, SmtpTransport, Transport,
 };
 
@@ -54,11 +55,91 @@ impl SupportEmail {
     }
 }
 
+/// Reminder sent to a company's IT heads before their trial license expires.
+pub struct TrialReminderEmail {
+    pub product: String,
+    pub end_date: DateTime<Utc>,
+}
+
+/// A license listed in a [`LicenseExpiryEmail`].
+pub struct ExpiringLicenseInfo {
+    pub product: String,
+    pub end_date: DateTime<Utc>,
+    pub amount: i32,
+    pub used_seats: i64,
+}
+
+/// Reminder sent to a company's IT heads before their licenses expire.
+pub struct LicenseExpiryEmail {
+    pub days_before: i32,
+    pub licenses: Vec<ExpiringLicenseInfo>,
+}
+
+/// Notice sent to a company's IT heads when the renewal of a subscription was not paid in time.
+pub struct RenewalFailedEmail {
+    pub product: String,
+    /// When the license is invalidated if the renewal is still not paid.
+    pub grace_until: DateTime<Utc>,
+}
+
+/// Confirmation of a paid order, with its invoice attached.
+pub struct OrderConfirmationEmail {
+    pub invoice_number: String,
+    /// Total with its currency, e.g. `1500.00 USD`.
+    pub total: String,
+    /// Description of each license in the order.
+    pub products: Vec<String>,
+    pub invoice_file_name: String,
+    /// The invoice as PDF.
+    pub invoice: Vec<u8>,
+}
+
+/// Quote sent to the IT heads of a company, with the quote attached.
+#[derive(Clone)]
+pub struct QuoteEmail {
+    pub quote_number: String,
+    /// Total with its currency, e.g. `45000.00 USD`.
+    pub total: String,
+    pub valid_until: DateTime<Utc>,
+    pub quote_file_name: String,
+    /// The quote as PDF.
+    pub quote: Vec<u8>,
+}
+
+/// What happened to a purchase request, see [`PurchaseRequestEmail`].
+#[derive(Clone)]
+pub enum PurchaseRequestUpdate {
+    /// Sent to the IT heads of the company, a user asked them to buy a license.
+    Submitted {
+        requested_by: String,
+        comment: Option<String>,
+    },
+    /// Sent to the user who asked, an IT head bought the license.
+    Approved { note: Option<String> },
+    /// Sent to the user who asked, an IT head turned the request down.
+    Rejected { note: Option<String> },
+}
+
+/// Notification about a purchase request of a company user.
+#[derive(Clone)]
+pub struct PurchaseRequestEmail {
+    pub product: String,
+    pub seats: i32,
+    pub term_years: i32,
+    pub update: PurchaseRequestUpdate,
+}
+
 pub enum EmailType {
     RegisterUser,
     RegisterUserCompany,
     ResetPassword,
     Support(SupportEmail),
+    TrialReminder(TrialReminderEmail),
+    LicenseExpiry(LicenseExpiryEmail),
+    OrderConfirmation(OrderConfirmationEmail),
+    PurchaseRequest(PurchaseRequestEmail),
+    RenewalFailed(RenewalFailedEmail),
+    Quote(QuoteEmail),
     Other(OtherEmail),
 }
 
@@ -87,10 +168,15 @@ pub async fn send_email(email: Email, mailer: &SmtpTransport) -> Result<(), Mail
     };
 
     match mailer.send(&email) {
-        Ok(_) => println!("Email sent successfully!"),
-        Err(e) => panic!("Could not send email: {e:?}"),
-    };
-    Ok(())
+        Ok(_) => {
+            log::info!("Email sent successfully!");
+            Ok(())
+        }
+        Err(e) => {
+            log::error!("Could not send email: {e:?}");
+            Err(MailError::SendError)
+        }
+    }
 }
 
 fn generate_email(email: Email) -> Result<Message, MailError> {
@@ -104,9 +190,15 @@ fn generate_email(email: Email) -> Result<Message, MailError> {
         Err(_) => return Err(MailError::InvalidRecipient),
     };
 
-    let invite_code = match email.invite_code {
-        Some(invite_code) => invite_code,
-        None => return Err(MailError::InvalidBody),
+    // only the invite emails need an invite code
+    let invite_code = match email.mail_type {
+        EmailType::RegisterUser | EmailType::RegisterUserCompany | EmailType::ResetPassword => {
+            match email.invite_code {
+                Some(invite_code) => invite_code,
+                None => return Err(MailError::InvalidBody),
+            }
+        }
+        _ => String::new(),
     };
 
     let email: Result<Message, lettre::error::Error> = match email.mail_type {
@@ -158,6 +250,123 @@ fn generate_email(email: Email) -> Result<Message, MailError> {
                 .unwrap();
             Ok(email)
         }
+        EmailType::TrialReminder(trial_email) => {
+            let email_template =
+                trial_reminder_template(&trial_email.product, &trial_email.end_date);
+            let email = Message::builder()
+                .from(from)
+                .to(to)
+                .subject(format!(
+                    "Your ProFlex trial of {} is about to expire",
+                    trial_email.product
+                ))
+                .header(ContentType::TEXT_HTML)
+                .body(email_template)
+                .unwrap();
+            Ok(email)
+        }
+        EmailType::LicenseExpiry(expiry_email) => {
+            let email_template = license_expiry_template(&expiry_email.licenses);
+            let subject = match expiry_email.days_before {
+                1 => "Your ProFlex licenses expire within a day".to_string(),
+                days => format!("Your ProFlex licenses expire within {} days", days),
+            };
+            let email = Message::builder()
+                .from(from)
+                .to(to)
+                .subject(subject)
+                .header(ContentType::TEXT_HTML)
+                .body(email_template)
+                .unwrap();
+            Ok(email)
+        }
+        EmailType::OrderConfirmation(order_email) => {
+            let email_template = order_confirmation_template(
+                &order_email.invoice_number,
+                &order_email.total,
+                &order_email.products,
+            );
+            let invoice = Attachment::new(order_email.invoice_file_name).body(
+                order_email.invoice,
+                ContentType::parse("application/pdf").unwrap(),
+            );
+            let email = Message::builder()
+                .from(from)
+                .to(to)
+                .subject(format!(
+                    "Your ProFlex order {} is confirmed",
+                    order_email.invoice_number
+                ))
+                .multipart(
+                    MultiPart::mixed()
+                        .singlepart(SinglePart::html(email_template))
+                        .singlepart(invoice),
+                )
+                .unwrap();
+            Ok(email)
+        }
+        EmailType::PurchaseRequest(request_email) => {
+            let subject = match request_email.update {
+                PurchaseRequestUpdate::Submitted { .. } => {
+                    format!("New request to buy {} on ProFlex", request_email.product)
+                }
+                PurchaseRequestUpdate::Approved { .. } => {
+                    format!("Your request for {} was approved", request_email.product)
+                }
+                PurchaseRequestUpdate::Rejected { .. } => {
+                    format!("Your request for {} was rejected", request_email.product)
+                }
+            };
+            let email_template = purchase_request_template(&subject, &request_email);
+            let email = Message::builder()
+                .from(from)
+                .to(to)
+                .subject(subject)
+                .header(ContentType::TEXT_HTML)
+                .body(email_template)
+                .unwrap();
+            Ok(email)
+        }
+        EmailType::RenewalFailed(renewal_email) => {
+            let email_template =
+                renewal_failed_template(&renewal_email.product, &renewal_email.grace_until);
+            let email = Message::builder()
+                .from(from)
+                .to(to)
+                .subject(format!(
+                    "Your ProFlex subscription of {} could not be renewed",
+                    renewal_email.product
+                ))
+                .header(ContentType::TEXT_HTML)
+                .body(email_template)
+                .unwrap();
+            Ok(email)
+        }
+        EmailType::Quote(quote_email) => {
+            let email_template = quote_template(
+                &quote_email.quote_number,
+                &quote_email.total,
+                &quote_email.valid_until,
+            );
+            let quote = Attachment::new(quote_email.quote_file_name).body(
+                quote_email.quote,
+                ContentType::parse("application/pdf").unwrap(),
+            );
+            let email = Message::builder()
+                .from(from)
+                .to(to)
+                .subject(format!(
+                    "Your quote {} from ProFlex",
+                    quote_email.quote_number
+                ))
+                .multipart(
+                    MultiPart::mixed()
+                        .singlepart(SinglePart::html(email_template))
+                        .singlepart(quote),
+                )
+                .unwrap();
+            Ok(email)
+        }
         EmailType::Other(other_email) => {
             let email_template = other_template(other_email.clone());
             let email = Message::builder()
@@ -343,6 +552,322 @@ fn support_template(subject: &str, product: &str, message: &str) -> String {
     email_template
 }
 
+fn trial_reminder_template(product: &str, end_date: &DateTime<Utc>) -> String {
+    let end_date = end_date.format("%d.%m.%Y");
+    let email_template = format!(
+        r#"
+        <!DOCTYPE html>
+        <html lang="en">
+
+        <head>
+            <meta charset="UTF-8">
+            <title>Your ProFlex trial of {product} is about to expire</title>
+        </head>
+
+        <style>
+            p {{
+                margin-top: 2em;
+                margin-bottom: 2em;
+            }}
+        </style>
+
+        <body style=" font-family: sans-serif">
+            <div style="padding: 1em;">
+                <h1 style="font-size: 1.2em">Dear Customer,</h1>
+                <p>Your company's trial of <b>{product}</b> expires on <b>{end_date}</b>.
+                    After that, your users will lose access to the product.
+                </p>
+                <p>To keep using {product}, convert the trial into a paid license from your
+                    <a href="https://group04.web-tek.ninja/profile">ProFlex profile</a>.
+                    All users you have given access to will keep their seats.
+                </p>
+                <p><b>Best regards,<br>
+                    The ProFlex Team</b></p>
+            </div>
+        </body>
+
+        </html>
+    "#
+    );
+
+    email_template
+}
+
+fn renewal_failed_template(product: &str, grace_until: &DateTime<Utc>) -> String {
+    let grace_until = grace_until.format("%d.%m.%Y");
+    let email_template = format!(
+        r#"
+        <!DOCTYPE html>
+        <html lang="en">
+
+        <head>
+            <meta charset="UTF-8">
+            <title>Your ProFlex subscription of {product} could not be renewed</title>
+        </head>
+
+        <style>
+            p {{
+                margin-top: 2em;
+                margin-bottom: 2em;
+            }}
+        </style>
+
+        <body style=" font-family: sans-serif">
+            <div style="padding: 1em;">
+                <h1 style="font-size: 1.2em">Dear Customer,</h1>
+                <p>We could not charge the renewal of your company's subscription of
+                    <b>{product}</b>. Your users keep access to the product until <b>{grace_until}</b>.
+                </p>
+                <p>To keep using {product}, pay the renewal from your
+                    <a href="https://group04.web-tek.ninja/profile">ProFlex profile</a>
+                    before then. Otherwise the license will be deactivated.
+                </p>
+                <p><b>Best regards,<br>
+                    The ProFlex Team</b></p>
+            </div>
+        </body>
+
+        </html>
+    "#
+    );
+
+    email_template
+}
+
+fn license_expiry_template(licenses: &[ExpiringLicenseInfo]) -> String {
+    let rows = licenses
+        .iter()
+        .map(|license| {
+            format!(
+                r#"<tr>
+                        <td style="padding: 0.3em 1em 0.3em 0;">{product}</td>
+                        <td style="padding: 0.3em 1em 0.3em 0;">{end_date}</td>
+                        <td style="padding: 0.3em 1em 0.3em 0;">{used_seats} of {amount}</td>
+                    </tr>"#,
+                product = license.product,
+                end_date = license.end_date.format("%d.%m.%Y"),
+                used_seats = license.used_seats,
+                amount = license.amount,
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let email_template = format!(
+        r#"
+        <!DOCTYPE html>
+        <html lang="en">
+
+        <head>
+            <meta charset="UTF-8">
+            <title>Your ProFlex licenses are about to expire</title>
+        </head>
+
+        <style>
+            p {{
+                margin-top: 2em;
+                margin-bottom: 2em;
+            }}
+        </style>
+
+        <body style=" font-family: sans-serif">
+            <div style="padding: 1em;">
+                <h1 style="font-size: 1.2em">Dear Customer,</h1>
+                <p>The following licenses of your company are about to expire.
+                    After they expire, your users will lose access to the products.
+                </p>
+                <table style="text-align: left; border-collapse: collapse;">
+                    <tr>
+                        <th style="padding: 0.3em 1em 0.3em 0;">Product</th>
+                        <th style="padding: 0.3em 1em 0.3em 0;">Expires</th>
+                        <th style="padding: 0.3em 1em 0.3em 0;">Seats in use</th>
+                    </tr>
+                    {rows}
+                </table>
+                <p>To keep using the products, renew the licenses from your
+                    <a href="https://group04.web-tek.ninja/profile">ProFlex profile</a>.
+                </p>
+                <p><b>Best regards,<br>
+                    The ProFlex Team</b></p>
+            </div>
+        </body>
+
+        </html>
+    "#
+    );
+
+    email_template
+}
+
+fn order_confirmation_template(invoice_number: &str, total: &str, products: &[String]) -> String {
+    let rows = products
+        .iter()
+        .map(|product| format!("<li>{}</li>", product))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let email_template = format!(
+        r#"
+        <!DOCTYPE html>
+        <html lang="en">
+
+        <head>
+            <meta charset="UTF-8">
+            <title>Your ProFlex order {invoice_number} is confirmed</title>
+        </head>
+
+        <style>
+            p {{
+                margin-top: 2em;
+                margin-bottom: 2em;
+            }}
+        </style>
+
+        <body style=" font-family: sans-serif">
+            <div style="padding: 1em;">
+                <h1 style="font-size: 1.2em">Dear Customer,</h1>
+                <p>Thank you for your purchase! We have received your payment of {total}
+                    for order {invoice_number}:
+                </p>
+                <ul>
+                    {rows}
+                </ul>
+                <p>The invoice is attached to this e-mail. You can also download it, and see your
+                    licenses, from your <a href="https://group04.web-tek.ninja/profile">ProFlex profile</a>.
+                </p>
+                <p><b>Best regards,<br>
+                    The ProFlex Team</b></p>
+            </div>
+        </body>
+
+        </html>
+    "#
+    );
+
+    email_template
+}
+
+fn quote_template(quote_number: &str, total: &str, valid_until: &DateTime<Utc>) -> String {
+    let valid_until = valid_until.format("%d.%m.%Y");
+    let email_template = format!(
+        r#"
+        <!DOCTYPE html>
+        <html lang="en">
+
+        <head>
+            <meta charset="UTF-8">
+            <title>Your quote {quote_number} from ProFlex</title>
+        </head>
+
+        <style>
+            p {{
+                margin-top: 2em;
+                margin-bottom: 2em;
+            }}
+        </style>
+
+        <body style=" font-family: sans-serif">
+            <div style="padding: 1em;">
+                <h1 style="font-size: 1.2em">Dear Customer,</h1>
+                <p>Thank you for your interest in ProFlex. Please find attached our quote
+                    {quote_number} for your company, at a total of {total} before VAT.
+                </p>
+                <p>The quote is valid until <b>{valid_until}</b>. To accept it, open it from your
+                    <a href="https://group04.web-tek.ninja/profile">ProFlex profile</a>.
+                    The licenses are created as soon as you accept.
+                </p>
+                <p><b>Best regards,<br>
+                    The ProFlex Team</b></p>
+            </div>
+        </body>
+
+        </html>
+    "#
+    );
+
+    email_template
+}
+
+/// Escapes text written by users, so it is shown as is in an email.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn purchase_request_template(subject: &str, email: &PurchaseRequestEmail) -> String {
+    let license = format!(
+        "{} seat(s) of <b>{}</b> for {} year(s)",
+        email.seats,
+        escape_html(&email.product),
+        email.term_years
+    );
+    let (message, note) = match &email.update {
+        PurchaseRequestUpdate::Submitted {
+            requested_by,
+            comment,
+        } => (
+            format!(
+                "{} has asked you to buy {}. Approve or reject the request from your \
+                <a href=\"https://group04.web-tek.ninja/profile\">ProFlex profile</a>.",
+                escape_html(requested_by),
+                license
+            ),
+            comment,
+        ),
+        PurchaseRequestUpdate::Approved { note } => (
+            format!(
+                "Your request for {} has been approved, and the license has been bought. \
+                It can be used as soon as it is paid.",
+                license
+            ),
+            note,
+        ),
+        PurchaseRequestUpdate::Rejected { note } => (
+            format!("Your request for {} has been rejected.", license),
+            note,
+        ),
+    };
+    let note = match note {
+        Some(note) => format!("<p><i>{}</i></p>", escape_html(note)),
+        None => String::new(),
+    };
+
+    let email_template = format!(
+        r#"
+        <!DOCTYPE html>
+        <html lang="en">
+
+        <head>
+            <meta charset="UTF-8">
+            <title>{subject}</title>
+        </head>
+
+        <style>
+            p {{
+                margin-top: 2em;
+                margin-bottom: 2em;
+            }}
+        </style>
+
+        <body style=" font-family: sans-serif">
+            <div style="padding: 1em;">
+                <h1 style="font-size: 1.2em">Hello,</h1>
+                <p>{message}</p>
+                {note}
+                <p><b>Best regards,<br>
+                    The ProFlex Team</b></p>
+            </div>
+        </body>
+
+        </html>
+    "#
+    );
+
+    email_template
+}
+
 fn other_template(email: OtherEmail) -> String {
     let email_template = format!(
         r#"